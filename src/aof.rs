@@ -0,0 +1,120 @@
+use std::fs::{File, OpenOptions};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::log::log_warning;
+use crate::parser::{finish_streaming, parse_redis_value, RedisValue};
+use crate::Result;
+
+/// Interval between two fsyncs with appendfsync everysec
+const EVERYSEC_INTERVAL: Duration = Duration::from_secs(1);
+
+/// When the append only file is fsynced, same options as redis
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum AppendFsync {
+    /// After every write, the safest and slowest
+    Always,
+    /// At most once per second, at most one second of writes can be lost
+    Everysec,
+    /// Never, the OS decides when the data is flushed
+    No,
+}
+
+/// Append only file: the write commands are appended in the RESP format and replayed
+/// at startup
+#[derive(Debug)]
+pub struct Aof {
+    file: File,
+    pub fsync: AppendFsync,
+    last_fsync: Instant,
+    /// Whether some writes were not fsynced yet
+    has_unsynced_writes: bool,
+}
+
+impl Aof {
+    pub fn open<P: AsRef<Path>>(path: P, fsync: AppendFsync) -> Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            file,
+            fsync,
+            last_fsync: Instant::now(),
+            has_unsynced_writes: false,
+        })
+    }
+
+    /// Appends the command to the file. It is only fsynced right away with
+    /// appendfsync always
+    pub fn append(&mut self, redis_value: &RedisValue) -> Result<()> {
+        redis_value.write_to(&mut self.file)?;
+        match self.fsync {
+            AppendFsync::Always => {
+                self.file.sync_data()?;
+                self.last_fsync = Instant::now();
+            }
+            AppendFsync::Everysec => self.has_unsynced_writes = true,
+            AppendFsync::No => {}
+        }
+        Ok(())
+    }
+
+    /// Called on every tick of the main loop: with appendfsync everysec, fsyncs the file
+    /// if there are unsynced writes and the last fsync is at least one second old.
+    /// Returns whether the file was fsynced
+    pub fn fsync_if_due(&mut self, now: Instant) -> Result<bool> {
+        if self.fsync != AppendFsync::Everysec
+            || !self.has_unsynced_writes
+            || now.duration_since(self.last_fsync) < EVERYSEC_INTERVAL
+        {
+            return Ok(false);
+        }
+        self.file.sync_data()?;
+        self.last_fsync = now;
+        self.has_unsynced_writes = false;
+        Ok(true)
+    }
+
+    /// Reads the commands stored in the file, in order. Like redis with
+    /// aof-load-truncated, a command cut by a crash at the end of the file is ignored.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Vec<RedisValue>> {
+        let content = std::fs::read(path)?;
+        let mut input = content.as_slice();
+        let mut redis_values = Vec::new();
+        while !input.is_empty() {
+            let Some((rest, redis_value)) = finish_streaming(parse_redis_value(input))? else {
+                log_warning!("Ignoring the truncated command at the end of the AOF");
+                break;
+            };
+            input = rest;
+            redis_values.push(redis_value);
+        }
+        Ok(redis_values)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_everysec_fsync() -> Result<()> {
+        let path = std::env::temp_dir().join(format!("test-{}.aof", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let mut aof = Aof::open(&path, AppendFsync::Everysec)?;
+        let now = Instant::now();
+        // nothing to fsync yet
+        assert!(!aof.fsync_if_due(now + EVERYSEC_INTERVAL)?);
+
+        let command = RedisValue::array_of_bulkstrings_from("SET a 1");
+        aof.append(&command)?;
+        aof.append(&command)?;
+        assert!(!aof.fsync_if_due(aof.last_fsync + Duration::from_millis(500))?);
+        assert!(aof.fsync_if_due(aof.last_fsync + EVERYSEC_INTERVAL)?);
+        // the writes were fsynced at once
+        assert!(!aof.fsync_if_due(aof.last_fsync + EVERYSEC_INTERVAL)?);
+
+        assert_eq!(Aof::load(&path)?, vec![command.clone(), command]);
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+}
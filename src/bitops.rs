@@ -0,0 +1,102 @@
+/// Position of the first bit set to `bit` in bytes[start..=end] (byte indices, negative
+/// values count from the end). Bits are numbered from the most significant bit of the
+/// first byte. Returns -1 when not found, except when looking for a 0 in a string full
+/// of 1s without an explicit end: the string is then considered padded with zeros.
+pub fn bitpos(bytes: &[u8], bit: u8, start: Option<i64>, end: Option<i64>) -> i64 {
+    let len = bytes.len() as i64;
+    if len == 0 {
+        return if bit == 1 { -1 } else { 0 };
+    }
+
+    let normalize = |index: i64| {
+        if index < 0 {
+            (len + index).max(0)
+        } else {
+            index
+        }
+    };
+    let range_start = normalize(start.unwrap_or(0));
+    let range_end = normalize(end.unwrap_or(len - 1)).min(len - 1);
+    if range_start > range_end {
+        return -1;
+    }
+
+    for (i, byte) in bytes[range_start as usize..=range_end as usize]
+        .iter()
+        .enumerate()
+    {
+        // we always look for a 1 so we flip the byte when looking for a 0
+        let byte = if bit == 1 { *byte } else { !*byte };
+        if byte != 0 {
+            return (range_start + i as i64) * 8 + byte.leading_zeros() as i64;
+        }
+    }
+
+    if bit == 0 && end.is_none() {
+        (range_end + 1) * 8
+    } else {
+        -1
+    }
+}
+
+/// Applies the bitwise operation (and, or, xor, not) byte by byte across the sources.
+/// Shorter sources are considered padded with zeros. Not only uses the first source.
+pub fn bitop(op: &str, sources: &[Vec<u8>]) -> Vec<u8> {
+    let len = sources.iter().map(|source| source.len()).max().unwrap_or(0);
+    let byte_at = |source: &Vec<u8>, i: usize| source.get(i).copied().unwrap_or(0);
+
+    (0..len)
+        .map(|i| {
+            let mut bytes = sources.iter().map(|source| byte_at(source, i));
+            let first = bytes.next().unwrap_or(0);
+            match op {
+                "and" => bytes.fold(first, |acc, byte| acc & byte),
+                "or" => bytes.fold(first, |acc, byte| acc | byte),
+                "xor" => bytes.fold(first, |acc, byte| acc ^ byte),
+                "not" => !first,
+                _ => unreachable!("Operation should have been validated"),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bitpos() {
+        let bytes = [0xff, 0xf0, 0x00];
+        assert_eq!(bitpos(&bytes, 0, None, None), 12);
+        assert_eq!(bitpos(&bytes, 1, None, None), 0);
+        assert_eq!(bitpos(&bytes, 1, Some(2), None), -1);
+        assert_eq!(bitpos(&bytes, 1, Some(1), None), 8);
+        assert_eq!(bitpos(&bytes, 0, Some(-1), None), 16);
+        assert_eq!(bitpos(&bytes, 1, Some(2), Some(1)), -1);
+    }
+
+    #[test]
+    fn test_bitpos_all_ones() {
+        let bytes = [0xff, 0xff, 0xff];
+        // without an explicit end, the string is considered padded with zeros
+        assert_eq!(bitpos(&bytes, 0, None, None), 24);
+        assert_eq!(bitpos(&bytes, 0, Some(1), None), 24);
+        assert_eq!(bitpos(&bytes, 0, Some(0), Some(-1)), -1);
+    }
+
+    #[test]
+    fn test_bitpos_empty() {
+        assert_eq!(bitpos(&[], 0, None, None), 0);
+        assert_eq!(bitpos(&[], 1, None, None), -1);
+    }
+
+    #[test]
+    fn test_bitop() {
+        let sources = vec![vec![0b1100_1100, 0xff], vec![0b1010_1010]];
+        assert_eq!(bitop("and", &sources), vec![0b1000_1000, 0x00]);
+        assert_eq!(bitop("or", &sources), vec![0b1110_1110, 0xff]);
+        assert_eq!(bitop("xor", &sources), vec![0b0110_0110, 0xff]);
+        assert_eq!(bitop("not", &sources[..1]), vec![0b0011_0011, 0x00]);
+        assert!(bitop("and", &[vec![], vec![]]).is_empty());
+    }
+}
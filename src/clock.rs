@@ -0,0 +1,104 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+#[cfg(test)]
+use std::{cell::Cell, time::Duration};
+
+/// Source of the current time for the expirations, the stream ids and the idle times
+/// of the pending stream entries
+pub trait Clock {
+    fn now(&self) -> Instant;
+    /// Current unix time in milliseconds
+    fn unix_time_ms(&self) -> u64;
+}
+
+/// Time of the system, used by the server
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn unix_time_ms(&self) -> u64 {
+        let since_epoch = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("time should not go backward");
+        since_epoch.as_millis() as u64
+    }
+}
+
+thread_local! {
+    /// The server runs on a single thread and each test on its own, so a test can replace
+    /// the clock without affecting the others
+    static CLOCK: RefCell<Rc<dyn Clock>> = RefCell::new(Rc::new(SystemClock));
+}
+
+/// Current time according to the clock of the thread
+pub fn now() -> Instant {
+    CLOCK.with(|clock| clock.borrow().now())
+}
+
+/// Current unix time in milliseconds according to the clock of the thread
+pub fn unix_time_ms() -> u64 {
+    CLOCK.with(|clock| clock.borrow().unix_time_ms())
+}
+
+/// Clock that only moves when it is advanced, so that the tests of the expirations don't
+/// depend on sleeps
+#[cfg(test)]
+pub struct MockClock {
+    instant: Cell<Instant>,
+    unix_time_ms: Cell<u64>,
+}
+
+#[cfg(test)]
+impl MockClock {
+    /// Replaces the clock of the current thread with a mock starting at the current time
+    pub fn install() -> Rc<Self> {
+        let mock = Rc::new(Self {
+            instant: Cell::new(Instant::now()),
+            unix_time_ms: Cell::new(SystemClock.unix_time_ms()),
+        });
+        CLOCK.with(|clock| *clock.borrow_mut() = mock.clone());
+        mock
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        self.instant.set(self.instant.get() + duration);
+        self.unix_time_ms
+            .set(self.unix_time_ms.get() + duration.as_millis() as u64);
+    }
+}
+
+#[cfg(test)]
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        self.instant.get()
+    }
+
+    fn unix_time_ms(&self) -> u64 {
+        self.unix_time_ms.get()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mock_clock() {
+        let mock = MockClock::install();
+        let (start, start_ms) = (now(), unix_time_ms());
+        assert_eq!(now(), start);
+
+        mock.advance(Duration::from_millis(1500));
+        assert_eq!(now() - start, Duration::from_millis(1500));
+        assert_eq!(unix_time_ms() - start_ms, 1500);
+
+        // the other threads keep the system clock
+        let system_now = std::thread::spawn(unix_time_ms).join().unwrap();
+        assert!(system_now < start_ms + 1500);
+    }
+}
@@ -1,7 +1,16 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
 
-use crate::db::{RedisDb, ValueType};
-use crate::parser::RedisValue;
+use mio::Token;
+
+use crate::clock::{self, unix_time_ms};
+use crate::command_table::{self, CommandMetadata};
+use crate::db::{EncodingThresholds, RedisDb, SetOperation, ValueType};
+use crate::geo::{self, GeoOrigin, GeoSearchOptions, Unit};
+use crate::parser::{lossy_string, RedisValue};
+use crate::pubsub::SubscriptionKind;
+use crate::sorted_set::{format_score, ZAddOptions, ZAddOutcome};
+use crate::stream::{ClaimOptions, PendingRange, StreamId, StreamTrim};
 use crate::{Error, Result};
 
 /// Purpose of this enum is to convert a given redis value to
@@ -9,11 +18,63 @@ use crate::{Error, Result};
 /// It only handles Arrays.
 #[derive(Debug, Clone)]
 pub enum RedisCommand {
-    Ping,
+    /// Replies PONG, or the message if there is one
+    Ping(Option<String>),
     Echo(String),
-    Set(String, String, Option<u64>),
+    /// Negotiates the protocol version of the connection, 2 or 3
+    Hello(Option<u8>),
+    Client(ClientSubcommand),
+    Set(String, Vec<u8>, SetOptions),
     Get(String),
+    /// Returns the value and deletes the key
+    GetDel(String),
+    /// Returns the value and updates its expiration if there is one
+    GetEx(String, Option<Expiry>),
+    Del(Vec<String>),
+    /// Values of the keys, nil for the missing keys and the keys that are not strings
+    MGet(Vec<String>),
+    /// Sets all the key value pairs at once
+    MSet(Vec<(String, Vec<u8>)>),
+    /// Appends the value to the string
+    Append(String, Vec<u8>),
+    /// key, offset and value written at the offset
+    SetRange(String, i64, Vec<u8>),
+    /// Length of the string, 0 if the key does not exist
+    Strlen(String),
+    /// key, start and end included, negative indices count from the end
+    GetRange(String, i64, i64),
+    /// key, bit offset and bit
+    SetBit(String, i64, i64),
+    /// key and unix time in seconds at which it expires
+    ExpireAt(String, u64),
+    /// key and unix time in milliseconds at which it expires
+    PExpireAt(String, u64),
+    /// Counts the keys that exist, a key given several times is counted each time
+    Exists(Vec<String>),
+    DbSize,
+    /// Saves the rdb to dir/dbfilename
+    Save,
+    /// Same as SAVE since the server is single threaded, only the reply differs
+    BgSave,
+    /// Selects the database of the connection
+    Select(usize),
+    /// Copies the source key to the destination key, optionally in another database
+    Copy {
+        source: String,
+        destination: String,
+        destination_db: Option<usize>,
+        replace: bool,
+    },
     Incr(String),
+    Decr(String),
+    IncrBy(String, i64),
+    DecrBy(String, i64),
+    /// Replies with the new value as a bulk string
+    IncrByFloat(String, f64),
+    /// key, field and increment
+    HIncrBy(String, String, i64),
+    /// key, field and increment
+    HIncrByFloat(String, String, f64),
     Info(String),
     /// All replconfs except for GETACK *
     ReplConf,
@@ -23,547 +84,6771 @@ pub enum RedisCommand {
     Psync,
     /// Wait for nb_replicas with a timeout is ms
     Wait(u64, u64),
+    /// Glob-style pattern of the parameters
     ConfigGet(String),
+    /// Pairs of parameter and value
+    ConfigSet(Vec<(String, String)>),
+    /// Replies of a standalone server, for the clients probing the cluster on connect
+    Cluster(ClusterSubcommand),
     Keys(String),
+    /// Incremental iteration over the keys, see RedisDb::scan
+    Scan {
+        cursor: u64,
+        pattern: Option<String>,
+        count: usize,
+        type_name: Option<String>,
+    },
     Type(String),
+    Command(CommandSubcommand),
     Xadd {
         key: String,
         stream_id: String,
         store: HashMap<String, String>,
+        trim: Option<StreamTrim>,
+        /// The stream is not created if it does not exist
+        no_mk_stream: bool,
     },
     Xrange {
         key: String,
         stream_id_start: String,
         stream_id_end: String,
     },
+    /// Removes entries from a stream
+    Xdel {
+        key: String,
+        ids: Vec<String>,
+    },
+    /// Same as Xrange from the highest id, with the end first
+    Xrevrange {
+        key: String,
+        stream_id_end: String,
+        stream_id_start: String,
+    },
     Xread {
         block: Option<u64>,
         key_offset_pairs: Vec<(String, String)>,
     },
+    XGroup(XGroupSubcommand),
+    XInfo(XInfoSubcommand),
+    XReadGroup {
+        group: String,
+        consumer: String,
+        count: Option<usize>,
+        no_ack: bool,
+        key_offset_pairs: Vec<(String, String)>,
+    },
+    XAck {
+        key: String,
+        group: String,
+        stream_ids: Vec<String>,
+    },
+    XClaim {
+        key: String,
+        group: String,
+        consumer: String,
+        stream_ids: Vec<String>,
+        options: ClaimOptions,
+    },
+    /// Summary form without range, extended form otherwise
+    XPending {
+        key: String,
+        group: String,
+        range: Option<PendingRange>,
+    },
+    /// Pushes the values to the head of the list
+    LPush(String, Vec<Vec<u8>>),
+    /// Pushes the values to the tail of the list
+    RPush(String, Vec<Vec<u8>>),
+    /// Pops one element from the head of the list, or an array of up to count elements
+    LPop(String, Option<usize>),
+    /// Same as LPOP from the tail of the list
+    RPop(String, Option<usize>),
+    LLen(String),
+    /// key, start and stop, negative indices counting from the tail
+    LRange(String, i64, i64),
+    /// Pops from the head of the first non empty list, blocking until the timeout
+    /// (forever if zero) if they are all empty
+    BLPop(Vec<String>, Duration),
+    /// Same as BLPOP from the tail of the lists
+    BRPop(Vec<String>, Duration),
+    /// key, element, rank and count
+    LPos(String, String, Option<i64>, Option<usize>),
+    /// key and optional count with the withvalues flag
+    HRandField(String, Option<(i64, bool)>),
+    /// Sets the (field, value) pairs of a hash
+    HSet(String, Vec<(String, Vec<u8>)>),
+    /// key and field
+    HGet(String, String),
+    /// Removes fields from a hash
+    HDel(String, Vec<String>),
+    /// Flat array of the fields and values of a hash
+    HGetAll(String),
+    HKeys(String),
+    HVals(String),
+    HLen(String),
+    /// Values of the fields of a hash, nil for the missing fields
+    HMGet(String, Vec<String>),
+    /// Sets the expiration in seconds of fields of a hash
+    HExpire(String, u64, Vec<String>),
+    /// Time to live in seconds of fields of a hash
+    HTtl(String, Vec<String>),
+    /// key and optional count with the withscores flag
+    ZRandMember(String, Option<(i64, bool)>),
+    /// key, flags and (score, member) pairs
+    ZAdd(String, ZAddOptions, Vec<(f64, String)>),
+    /// Score of a member of a sorted set
+    ZScore(String, String),
+    /// Rank of a member of a sorted set, starting from the lowest score
+    ZRank(String, String),
+    /// key, start and stop ranks and the withscores flag
+    ZRange(String, i64, i64, bool),
+    /// Removes members from a sorted set
+    ZRem(String, Vec<String>),
+    /// key and (longitude, latitude, member) triplets, stored in a sorted set
+    GeoAdd(String, Vec<(f64, f64, String)>),
+    /// Positions of members of a geospatial index
+    GeoPos(String, Vec<String>),
+    /// Distance between two members of a geospatial index
+    GeoDist(String, String, String, Unit),
+    /// Members of a geospatial index within a radius
+    GeoSearch(String, GeoSearchOptions),
+    /// Adds elements to a HyperLogLog
+    PFAdd(String, Vec<String>),
+    /// Estimated cardinality of the union of the HyperLogLogs of the keys
+    PFCount(Vec<String>),
+    /// Merges HyperLogLogs into the destination
+    PFMerge(String, Vec<String>),
+    /// Adds members to a set
+    SAdd(String, Vec<String>),
+    /// Removes members from a set
+    SRem(String, Vec<String>),
+    SMembers(String),
+    /// A random member, or random members following random::sample for the count
+    SRandMember(String, Option<i64>),
+    /// Removes and returns a random member, or up to count distinct random members
+    SPop(String, Option<usize>),
+    /// Members of the intersection of the sets of the keys
+    SInter(Vec<String>),
+    /// Members of the union of the sets of the keys
+    SUnion(Vec<String>),
+    /// Members of the first set that are in none of the other sets
+    SDiff(Vec<String>),
+    /// destination and keys, same as SINTER with the result stored at destination
+    SInterStore(String, Vec<String>),
+    /// destination and keys, same as SUNION with the result stored at destination
+    SUnionStore(String, Vec<String>),
+    /// destination and keys, same as SDIFF with the result stored at destination
+    SDiffStore(String, Vec<String>),
+    /// key, bit, start and end
+    BitPos(String, u8, Option<i64>, Option<i64>),
+    /// operation, destination key and source keys
+    BitOp(String, String, Vec<String>),
+    Debug(DebugSubcommand),
+    Object(ObjectSubcommand),
+    /// HELP subcommand of a container command such as CONFIG or DEBUG
+    Help(String),
+    /// Channels (or patterns) to subscribe to
+    Subscribe(SubscriptionKind, Vec<String>),
+    /// Unsubscribes from all the channels (or patterns) if none is given
+    Unsubscribe(SubscriptionKind, Vec<String>),
+    PubSub(PubSubSubcommand),
+    /// channel and message
+    Publish(String, String),
+    /// shard channel and message
+    SPublish(String, String),
     Multi,
     Exec,
     Discard,
+    /// Keys that abort the next transaction of the connection if they are modified
+    Watch(Vec<String>),
+    Unwatch,
+}
+
+#[derive(Debug, Clone)]
+pub enum ClientSubcommand {
+    /// Id of the connection
+    Id,
+}
+
+#[derive(Debug, Clone)]
+pub enum XInfoSubcommand {
+    /// Length, last generated id, number of groups and first and last entries of the
+    /// stream
+    Stream(String),
+}
+
+#[derive(Debug, Clone)]
+pub enum XGroupSubcommand {
+    Create {
+        key: String,
+        group: String,
+        stream_id: String,
+        mk_stream: bool,
+    },
+    Destroy {
+        key: String,
+        group: String,
+    },
+    CreateConsumer {
+        key: String,
+        group: String,
+        consumer: String,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub enum CommandSubcommand {
+    /// Metadata of each of the given commands
+    Info(Vec<String>),
+    /// Documentation of the given commands, or of all the commands if none is given
+    Docs(Vec<String>),
+    /// Names of all the commands
+    List,
+    /// Number of commands in the table
+    Count,
+    /// Keys of the full command given as arguments
+    GetKeys(Vec<String>),
+    /// Keys of the full command given as arguments, with their access flags
+    GetKeysAndFlags(Vec<String>),
+}
+
+#[derive(Debug, Clone)]
+pub enum PubSubSubcommand {
+    /// Active channels, optionally filtered with a glob-style pattern
+    Channels(Option<String>),
+    /// Number of subscribers of each channel
+    NumSub(Vec<String>),
+    /// Number of patterns subscribed to
+    NumPat,
+}
+
+#[derive(Debug, Clone)]
+pub enum ClusterSubcommand {
+    /// State of the cluster, which is disabled
+    Info,
+    /// Id of the node
+    MyId,
+    /// Slots served by each node, none in standalone mode
+    Slots,
+    /// Shards of the cluster, none in standalone mode
+    Shards,
+}
+
+#[derive(Debug, Clone)]
+pub enum DebugSubcommand {
+    /// The reply is deferred by the given duration without blocking the event loop
+    Sleep(Duration),
+    /// Enables or disables the periodic eviction of expired keys
+    SetActiveExpire(bool),
+    /// Removes all the keys of all the databases
+    FlushAll,
+    /// Low level information about the value of the key
+    Object(String),
+    /// Subcommand we don't implement, accepted with --debug-lenient
+    Unknown(String),
+}
+
+#[derive(Debug, Clone)]
+pub enum ObjectSubcommand {
+    /// Internal representation of the value of the key
+    Encoding(String),
+}
+
+/// Expiration given to SET or GETEX
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Expiry {
+    /// In seconds
+    Ex(u64),
+    /// In milliseconds
+    Px(u64),
+    /// Unix time in seconds
+    ExAt(u64),
+    /// Unix time in milliseconds
+    PxAt(u64),
+    /// Removes the expiration, only for GETEX
+    Persist,
+}
+
+impl Expiry {
+    /// Milliseconds before the key expires, 0 if the unix time is already past and None
+    /// if the key no longer expires
+    pub fn remaining_ms(&self) -> Option<u64> {
+        match *self {
+            Self::Ex(seconds) => Some(seconds.saturating_mul(1000)),
+            Self::Px(ms) => Some(ms),
+            Self::ExAt(seconds) => {
+                Some(seconds.saturating_mul(1000).saturating_sub(unix_time_ms()))
+            }
+            Self::PxAt(ms) => Some(ms.saturating_sub(unix_time_ms())),
+            Self::Persist => None,
+        }
+    }
+
+    fn is_zero(&self) -> bool {
+        matches!(
+            self,
+            Self::Ex(0) | Self::Px(0) | Self::ExAt(0) | Self::PxAt(0)
+        )
+    }
+}
+
+/// Options of SET
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct SetOptions {
+    pub expiry: Option<Expiry>,
+    /// Only sets the key if it does not exist
+    pub nx: bool,
+    /// Only sets the key if it already exists
+    pub xx: bool,
+    /// Replies with the old value instead of OK
+    pub get: bool,
+    /// Keeps the expiration of the existing key
+    pub keepttl: bool,
+    /// More than one of EX, PX, EXAT and PXAT was given
+    pub several_expiries: bool,
+}
+
+impl SetOptions {
+    /// Same errors as redis when the options can not be used together
+    pub fn incompatibility(&self) -> Option<Error> {
+        if (self.nx && self.xx) || (self.keepttl && self.expiry.is_some()) || self.several_expiries
+        {
+            Some(Error::SyntaxError)
+        } else if self.expiry.is_some_and(|expiry| expiry.is_zero()) {
+            Some(Error::InvalidExpireTime("set"))
+        } else {
+            None
+        }
+    }
 }
 
 impl TryFrom<&RedisValue> for RedisCommand {
     type Error = Error;
 
+    /// Looks the command up in the command table, checks its arity and hands the
+    /// arguments to the parser registered for it
     fn try_from(redis_value: &RedisValue) -> Result<Self> {
-        match redis_value.clone() {
-            RedisValue::Array(nb_elements, arr) => {
-                let (command, args) = arr.split_first().ok_or_else(|| Error::EmptyCommand)?;
-
-                match command {
-                    RedisValue::BulkString(_, val) => {
-                        // we could add check on size
-                        match val.to_lowercase().as_ref() {
-                            "ping" => {
-                                if nb_elements != 1 {
-                                    return Err(Error::InvalidRedisValue(redis_value.clone()));
-                                }
-                                Ok(Self::Ping)
-                            }
+        let RedisValue::Array(nb_elements, values) = redis_value else {
+            return Err(Error::InvalidRedisValue(redis_value.clone()));
+        };
+        let (command, args) = values.split_first().ok_or(Error::EmptyCommand)?;
+        let RedisValue::BulkString(_, name) = command else {
+            return Err(Error::InvalidRedisValue(redis_value.clone()));
+        };
+        let name = lossy_string(name);
+        let metadata = command_table::lookup(&name)
+            .ok_or_else(|| Error::unknown_command(name.clone(), args))?;
+        if !metadata.accepts(*nb_elements) {
+            return Err(Error::WrongArity(metadata.name.to_string()));
+        }
 
-                            "echo" => {
-                                if nb_elements != 2 {
-                                    Err(Error::InvalidRedisValue(redis_value.clone()))
-                                } else {
-                                    match &args[0] {
-                                        RedisValue::BulkString(_, val) => {
-                                            Ok(RedisCommand::Echo(val.clone()))
-                                        }
-                                        _ => Err(Error::InvalidRedisValue(redis_value.clone())),
-                                    }
-                                }
-                            }
-                            "set" => {
-                                if nb_elements != 3 && nb_elements != 5 {
-                                    Err(Error::InvalidRedisValue(redis_value.clone()))
-                                } else {
-                                    match (&args[0], &args[1]) {
-                                        (
-                                            RedisValue::BulkString(_, key),
-                                            RedisValue::BulkString(_, value),
-                                        ) => {
-                                            let px = {
-                                                if nb_elements == 5 {
-                                                    match (&args[2], &args[3]) {
-                                                        (
-                                                            RedisValue::BulkString(_, px_id),
-                                                            RedisValue::BulkString(_, px_ms),
-                                                        ) => {
-                                                            if px_id.to_lowercase() != "px" {
-                                                                return Err(
-                                                                    Error::InvalidRedisValue(
-                                                                        redis_value.clone(),
-                                                                    ),
-                                                                );
-                                                            }
-                                                            Some(px_ms.parse()?)
-                                                        }
-                                                        _ => Err(Error::InvalidRedisValue(
-                                                            redis_value.clone(),
-                                                        ))?,
-                                                    }
-                                                } else {
-                                                    None
-                                                }
-                                            };
-
-                                            Ok(RedisCommand::Set(key.clone(), value.clone(), px))
-                                        }
-                                        _ => Err(Error::InvalidRedisValue(redis_value.clone())),
-                                    }
-                                }
-                            }
+        // HELP subcommand of the container commands
+        let is_help = args.len() == 1
+            && args[0]
+                .inner_string()
+                .is_ok_and(|x| x.eq_ignore_ascii_case("help"));
+        if is_help && !help_lines(metadata.name).is_empty() {
+            return Ok(RedisCommand::Help(metadata.name.to_string()));
+        }
 
-                            "get" => {
-                                if nb_elements != 2 {
-                                    Err(Error::InvalidRedisValue(redis_value.clone()))
-                                } else {
-                                    match &args[0] {
-                                        RedisValue::BulkString(_, key) => {
-                                            Ok(RedisCommand::Get(key.clone()))
-                                        }
-                                        _ => Err(Error::InvalidRedisValue(redis_value.clone())),
-                                    }
-                                }
-                            }
-                            "incr" => {
-                                if nb_elements != 2 {
-                                    Err(Error::InvalidRedisValue(redis_value.clone()))
-                                } else {
-                                    match &args[0] {
-                                        RedisValue::BulkString(_, key) => {
-                                            Ok(RedisCommand::Incr(key.clone()))
-                                        }
-                                        _ => Err(Error::InvalidRedisValue(redis_value.clone())),
-                                    }
-                                }
-                            }
-                            "info" => {
-                                if nb_elements != 2 {
-                                    Err(Error::InvalidRedisValue(redis_value.clone()))
-                                } else {
-                                    match &args[0] {
-                                        RedisValue::BulkString(_, info_cmd) => {
-                                            Ok(RedisCommand::Info(info_cmd.clone()))
-                                        }
-                                        _ => Err(Error::InvalidRedisValue(redis_value.clone())),
-                                    }
-                                }
-                            }
-                            "replconf" => {
-                                if nb_elements != 3 {
-                                    Err(Error::InvalidRedisValue(redis_value.clone()))
-                                } else if let ("GETACK", "*") = (
-                                    args[0].inner_string()?.as_ref(),
-                                    args[1].inner_string()?.as_ref(),
-                                ) {
-                                    // this is actually what the master sends the replica
-                                    Ok(RedisCommand::ReplConfGetAck)
-                                } else {
-                                    Ok(RedisCommand::ReplConf)
-                                }
-                                // } else if let ("listening-port", port) =
-                                //     (args[0].inner_string()?.as_ref(), args[1].inner_string()?)
-                                // {
-                                //     let replica_port: u16 = port.parse()?;
-                                //     Ok(RedisCommand::ReplConfListeningPort(replica_port))
-                                // } else {
-                                //     Ok(RedisCommand::ReplConfCapa)
-                                // }
-                            }
-                            "psync" => Ok(RedisCommand::Psync),
-                            "wait" => {
-                                if nb_elements != 3 {
-                                    Err(Error::InvalidRedisValue(redis_value.clone()))
-                                } else {
-                                    match (&args[0], &args[1]) {
-                                        (
-                                            RedisValue::BulkString(_, nb_replica),
-                                            RedisValue::BulkString(_, timeout),
-                                        ) => {
-                                            let nb_replica = nb_replica.parse()?;
-                                            let timeout = timeout.parse()?;
-
-                                            Ok(RedisCommand::Wait(nb_replica, timeout))
-                                        }
-                                        _ => Err(Error::InvalidRedisValue(redis_value.clone())),
-                                    }
-                                }
-                            }
-                            "config" => {
-                                if nb_elements != 3 {
-                                    Err(Error::InvalidRedisValue(redis_value.clone()))
-                                } else {
-                                    match (&args[0], &args[1]) {
-                                        (
-                                            RedisValue::BulkString(_, get),
-                                            RedisValue::BulkString(_, val),
-                                        ) => {
-                                            if get.to_lowercase() != "get" {
-                                                return Err(Error::InvalidRedisValue(
-                                                    redis_value.clone(),
-                                                ));
-                                            }
-
-                                            Ok(RedisCommand::ConfigGet(val.to_string()))
-                                        }
-                                        _ => Err(Error::InvalidRedisValue(redis_value.clone())),
-                                    }
-                                }
-                            }
-                            "keys" => {
-                                if nb_elements != 2 {
-                                    Err(Error::InvalidRedisValue(redis_value.clone()))
-                                } else {
-                                    match &args[0] {
-                                        RedisValue::BulkString(_, pat) => {
-                                            Ok(RedisCommand::Keys(pat.clone()))
-                                        }
-                                        _ => Err(Error::InvalidRedisValue(redis_value.clone())),
-                                    }
-                                }
-                            }
-                            "type" => {
-                                if nb_elements != 2 {
-                                    Err(Error::InvalidRedisValue(redis_value.clone()))
-                                } else {
-                                    match &args[0] {
-                                        RedisValue::BulkString(_, key) => {
-                                            Ok(RedisCommand::Type(key.clone()))
-                                        }
-                                        _ => Err(Error::InvalidRedisValue(redis_value.clone())),
-                                    }
-                                }
-                            }
+        (metadata.parse)(metadata.name, args, redis_value)
+    }
+}
 
-                            "xadd" => {
-                                if nb_elements < 5 || nb_elements % 2 != 1 {
-                                    Err(Error::InvalidRedisValue(redis_value.clone()))
-                                } else {
-                                    let args_as_strings = get_strings_from_bulkstrings(args)
-                                        .map_err(|_| {
-                                            Error::InvalidRedisValue(redis_value.clone())
-                                        })?;
-
-                                    let key = args_as_strings[0].clone();
-                                    let stream_id = args_as_strings[1].clone();
-                                    let mut store = HashMap::new();
-                                    let mut i = 2;
-                                    while i < nb_elements - 1 {
-                                        store.insert(
-                                            args_as_strings[i].clone(),
-                                            args_as_strings[i + 1].clone(),
-                                        );
-                                        i += 2;
-                                    }
-                                    Ok(RedisCommand::Xadd {
-                                        key,
-                                        stream_id,
-                                        store,
-                                    })
-                                }
-                            }
+// Parsers of the commands, registered in the command table. They receive the lowercase
+// name of the command, which lets several commands share a parser, its arguments and the
+// whole value for the errors.
 
-                            "xrange" => {
-                                if nb_elements != 4 {
-                                    Err(Error::InvalidRedisValue(redis_value.clone()))
-                                } else {
-                                    let args_as_strings = get_strings_from_bulkstrings(args)
-                                        .map_err(|_| {
-                                            Error::InvalidRedisValue(redis_value.clone())
-                                        })?;
-
-                                    let key = args_as_strings[0].clone();
-                                    let stream_id_start = args_as_strings[1].clone();
-                                    let stream_id_end = args_as_strings[2].clone();
-                                    Ok(RedisCommand::Xrange {
-                                        key,
-                                        stream_id_start,
-                                        stream_id_end,
-                                    })
-                                }
-                            }
+pub fn parse_ping(
+    _name: &str,
+    args: &[RedisValue],
+    redis_value: &RedisValue,
+) -> Result<RedisCommand> {
+    let args_as_strings = get_strings_from_bulkstrings(args)
+        .map_err(|_| Error::InvalidRedisValue(redis_value.clone()))?;
+    match &args_as_strings[..] {
+        [] => Ok(RedisCommand::Ping(None)),
+        [message] => Ok(RedisCommand::Ping(Some(message.clone()))),
+        _ => Err(Error::InvalidRedisValue(redis_value.clone())),
+    }
+}
 
-                            "xread" => {
-                                if nb_elements < 4 || nb_elements % 2 != 0 {
-                                    Err(Error::InvalidRedisValue(redis_value.clone()))
-                                } else {
-                                    let args_as_strings = get_strings_from_bulkstrings(args)
-                                        .map_err(|_| {
-                                            Error::InvalidRedisValue(redis_value.clone())
-                                        })?;
-
-                                    let mut i = 0;
-                                    let mut block = None;
-                                    // handle block
-                                    if args_as_strings[0].to_lowercase() == "block" {
-                                        if nb_elements < 6 {
-                                            Err(Error::InvalidRedisValue(redis_value.clone()))?
-                                        }
-                                        block = Some(args_as_strings[1].parse::<u64>()?);
-                                        i = 2;
-                                    }
-
-                                    if args_as_strings[i] != "streams" {
-                                        Err(Error::InvalidRedisValue(redis_value.clone()))?
-                                    }
-
-                                    let offset = (nb_elements - 2 - i) / 2;
-
-                                    let mut key_offset_pairs = Vec::new();
-
-                                    i += 1;
-                                    while i + offset < args_as_strings.len() {
-                                        key_offset_pairs.push((
-                                            args_as_strings[i].clone(),
-                                            args_as_strings[i + offset].clone(),
-                                        ));
-                                        i += 1;
-                                    }
-
-                                    Ok(RedisCommand::Xread {
-                                        block,
-                                        key_offset_pairs,
-                                    })
-                                }
-                            }
+pub fn parse_hello(
+    _name: &str,
+    args: &[RedisValue],
+    redis_value: &RedisValue,
+) -> Result<RedisCommand> {
+    let args_as_strings = get_strings_from_bulkstrings(args)
+        .map_err(|_| Error::InvalidRedisValue(redis_value.clone()))?;
+    match &args_as_strings[..] {
+        [] => Ok(RedisCommand::Hello(None)),
+        [protover] => Ok(RedisCommand::Hello(Some(protover.parse()?))),
+        _ => Err(Error::InvalidRedisValue(redis_value.clone())),
+    }
+}
 
-                            "multi" => {
-                                if nb_elements != 1 {
-                                    return Err(Error::InvalidRedisValue(redis_value.clone()));
-                                }
-                                Ok(Self::Multi)
-                            }
-                            "exec" => {
-                                if nb_elements != 1 {
-                                    return Err(Error::InvalidRedisValue(redis_value.clone()));
-                                }
-                                Ok(Self::Exec)
-                            }
-                            "discard" => {
-                                if nb_elements != 1 {
-                                    return Err(Error::InvalidRedisValue(redis_value.clone()));
-                                }
-                                Ok(Self::Discard)
-                            }
-                            _ => Err(Error::InvalidRedisValue(redis_value.clone())),
-                        }
+pub fn parse_client(
+    _name: &str,
+    args: &[RedisValue],
+    redis_value: &RedisValue,
+) -> Result<RedisCommand> {
+    let args_as_strings = get_strings_from_bulkstrings(args)
+        .map_err(|_| Error::InvalidRedisValue(redis_value.clone()))?;
+    match &args_as_strings[..] {
+        [subcommand] if subcommand.eq_ignore_ascii_case("id") => {
+            Ok(RedisCommand::Client(ClientSubcommand::Id))
+        }
+        _ => Err(Error::InvalidRedisValue(redis_value.clone())),
+    }
+}
+
+pub fn parse_echo(
+    _name: &str,
+    args: &[RedisValue],
+    redis_value: &RedisValue,
+) -> Result<RedisCommand> {
+    let nb_elements = args.len() + 1;
+    if nb_elements != 2 {
+        Err(Error::InvalidRedisValue(redis_value.clone()))
+    } else {
+        match &args[0] {
+            RedisValue::BulkString(_, val) => Ok(RedisCommand::Echo(lossy_string(val))),
+            _ => Err(Error::InvalidRedisValue(redis_value.clone())),
+        }
+    }
+}
+
+pub fn parse_set(
+    _name: &str,
+    args: &[RedisValue],
+    redis_value: &RedisValue,
+) -> Result<RedisCommand> {
+    match args {
+        [RedisValue::BulkString(_, key), RedisValue::BulkString(_, value), rest @ ..] => {
+            let rest = get_strings_from_bulkstrings(rest)
+                .map_err(|_| Error::InvalidRedisValue(redis_value.clone()))?;
+            let mut options = SetOptions::default();
+            let mut rest = rest.iter();
+            while let Some(option) = rest.next() {
+                let option = option.to_lowercase();
+                let expiry: fn(u64) -> Expiry = match option.as_str() {
+                    "nx" => {
+                        options.nx = true;
+                        continue;
                     }
-                    _ => Err(Error::InvalidRedisValue(redis_value.clone())),
-                }
+                    "xx" => {
+                        options.xx = true;
+                        continue;
+                    }
+                    "get" => {
+                        options.get = true;
+                        continue;
+                    }
+                    "keepttl" => {
+                        options.keepttl = true;
+                        continue;
+                    }
+                    "ex" => Expiry::Ex,
+                    "px" => Expiry::Px,
+                    "exat" => Expiry::ExAt,
+                    "pxat" => Expiry::PxAt,
+                    _ => Err(Error::InvalidRedisValue(redis_value.clone()))?,
+                };
+                let time = rest
+                    .next()
+                    .ok_or_else(|| Error::InvalidRedisValue(redis_value.clone()))?;
+                options.several_expiries |= options.expiry.is_some();
+                options.expiry = Some(expiry(time.parse()?));
             }
+            Ok(RedisCommand::Set(lossy_string(key), value.clone(), options))
+        }
+        _ => Err(Error::InvalidRedisValue(redis_value.clone())),
+    }
+}
+
+pub fn parse_get(
+    _name: &str,
+    args: &[RedisValue],
+    redis_value: &RedisValue,
+) -> Result<RedisCommand> {
+    let nb_elements = args.len() + 1;
+    if nb_elements != 2 {
+        Err(Error::InvalidRedisValue(redis_value.clone()))
+    } else {
+        match &args[0] {
+            RedisValue::BulkString(_, key) => Ok(RedisCommand::Get(lossy_string(key))),
             _ => Err(Error::InvalidRedisValue(redis_value.clone())),
         }
     }
 }
 
-impl RedisCommand {
-    /// Whether the command should be forwarded to the other replicas.
-    /// Only commands that write to the underlying db are concerned
-    pub fn should_forward_to_replicas(&self) -> bool {
-        matches!(self, Self::Set(_, _, _))
+pub fn parse_getdel(
+    _name: &str,
+    args: &[RedisValue],
+    redis_value: &RedisValue,
+) -> Result<RedisCommand> {
+    let args_as_strings = get_strings_from_bulkstrings(args)
+        .map_err(|_| Error::InvalidRedisValue(redis_value.clone()))?;
+    match &args_as_strings[..] {
+        [key] => Ok(RedisCommand::GetDel(key.clone())),
+        _ => Err(Error::InvalidRedisValue(redis_value.clone())),
     }
+}
 
-    /// Executes command and returns a RedisValue on success
-    pub fn execute(&self, db: &mut RedisDb) -> Result<RedisValue> {
-        match self {
-            Self::Ping => Ok(RedisValue::SimpleString("PONG".to_string())),
-            Self::Echo(x) => Ok(RedisValue::SimpleString(x.clone())),
-            Self::Set(key, value, px) => {
-                db.set(key.clone(), ValueType::String(value.clone()), *px);
-                Ok(RedisValue::SimpleString("OK".to_string()))
-            }
-            Self::Get(key) => {
-                let val = db.get(key);
-                match val {
-                    Some(val) => match val {
-                        ValueType::String(val) => Ok(RedisValue::SimpleString(val)),
-                        _ => todo!("Implement get for other types"),
-                    },
+pub fn parse_getex(
+    _name: &str,
+    args: &[RedisValue],
+    redis_value: &RedisValue,
+) -> Result<RedisCommand> {
+    let args_as_strings = get_strings_from_bulkstrings(args)
+        .map_err(|_| Error::InvalidRedisValue(redis_value.clone()))?;
+    match &args_as_strings[..] {
+        [key] => Ok(RedisCommand::GetEx(key.clone(), None)),
+        [key, option] if option.eq_ignore_ascii_case("persist") => {
+            Ok(RedisCommand::GetEx(key.clone(), Some(Expiry::Persist)))
+        }
+        [key, option, time] => {
+            let time = time.parse::<u64>()?;
+            let expiry = match option.to_lowercase().as_str() {
+                "ex" => Expiry::Ex(time),
+                "px" => Expiry::Px(time),
+                "exat" => Expiry::ExAt(time),
+                "pxat" => Expiry::PxAt(time),
+                _ => Err(Error::InvalidRedisValue(redis_value.clone()))?,
+            };
+            Ok(RedisCommand::GetEx(key.clone(), Some(expiry)))
+        }
+        _ => Err(Error::InvalidRedisValue(redis_value.clone())),
+    }
+}
 
-                    None => Ok(RedisValue::NullBulkString),
-                }
-            }
-            Self::Incr(key) => match db.incr(key) {
-                Ok(val) => Ok(RedisValue::Integer(val)),
-                Err(_) => Ok(RedisValue::SimpleError(
-                    "ERR value is not an integer or out of range".to_string(),
-                )),
-            },
-            Self::Info(x) => match x.as_str() {
-                "replication" => {
-                    let answer = db.info.to_string();
+pub fn parse_del(
+    _name: &str,
+    args: &[RedisValue],
+    redis_value: &RedisValue,
+) -> Result<RedisCommand> {
+    let nb_elements = args.len() + 1;
+    if nb_elements < 2 {
+        Err(Error::InvalidRedisValue(redis_value.clone()))
+    } else {
+        let keys = get_strings_from_bulkstrings(args)
+            .map_err(|_| Error::InvalidRedisValue(redis_value.clone()))?;
+        Ok(RedisCommand::Del(keys))
+    }
+}
 
-                    Ok(RedisValue::BulkString(answer.len(), answer))
-                }
-                _ => Err(Error::InvalidRedisCommand(self.clone())),
-            },
-            Self::ReplConf => Ok(RedisValue::SimpleString("OK".to_string())),
-            Self::ReplConfGetAck => {
-                let answer = format!("REPLCONF ACK {}", db.processed_bytes);
+pub fn parse_append(
+    _name: &str,
+    args: &[RedisValue],
+    redis_value: &RedisValue,
+) -> Result<RedisCommand> {
+    match args {
+        [RedisValue::BulkString(_, key), RedisValue::BulkString(_, value)] => {
+            Ok(RedisCommand::Append(lossy_string(key), value.clone()))
+        }
+        _ => Err(Error::InvalidRedisValue(redis_value.clone())),
+    }
+}
 
-                Ok(RedisValue::array_of_bulkstrings_from(&answer))
-            }
-            Self::Psync => {
-                let master_replid = db.info.master_replid.clone();
-                Ok(RedisValue::SimpleString(format!(
-                    "FULLRESYNC {} 0",
-                    master_replid
-                )))
-            }
-            Self::Wait(_, _) => {
-                // Wait should not be executed in a standard way
-                // It should instead modify the db state
-                todo!()
-            }
-            Self::ConfigGet(val) => match val.as_str() {
-                "dir" => Ok(RedisValue::array_of_bulkstrings_from(&format!(
-                    "dir {}",
-                    db.info.dir
-                ))),
-                "dbfilename" => Ok(RedisValue::array_of_bulkstrings_from(&format!(
-                    "dbfilename {}",
-                    db.info.dbfilename
-                ))),
-                _ => Err(Error::InvalidRedisCommand(self.clone())),
-            },
-            RedisCommand::Keys(pat) => {
-                let keys = db.keys(pat);
-                let joined_keys = keys.join(" ");
-                Ok(RedisValue::array_of_bulkstrings_from(&joined_keys))
-            }
+pub fn parse_setrange(
+    _name: &str,
+    args: &[RedisValue],
+    redis_value: &RedisValue,
+) -> Result<RedisCommand> {
+    match args {
+        [RedisValue::BulkString(_, key), RedisValue::BulkString(_, offset), RedisValue::BulkString(_, value)] => {
+            Ok(RedisCommand::SetRange(
+                lossy_string(key),
+                lossy_string(offset).parse()?,
+                value.clone(),
+            ))
+        }
+        _ => Err(Error::InvalidRedisValue(redis_value.clone())),
+    }
+}
 
-            Self::Type(key) => {
-                let val = db.get(key);
-                match val {
-                    Some(val) => match val {
-                        ValueType::String(_) => Ok(RedisValue::SimpleString("string".to_string())),
-                        ValueType::Stream(_) => Ok(RedisValue::SimpleString("stream".to_string())),
-                    },
+pub fn parse_strlen(
+    _name: &str,
+    args: &[RedisValue],
+    redis_value: &RedisValue,
+) -> Result<RedisCommand> {
+    let args_as_strings = get_strings_from_bulkstrings(args)
+        .map_err(|_| Error::InvalidRedisValue(redis_value.clone()))?;
+    match &args_as_strings[..] {
+        [key] => Ok(RedisCommand::Strlen(key.clone())),
+        _ => Err(Error::InvalidRedisValue(redis_value.clone())),
+    }
+}
 
-                    None => Ok(RedisValue::SimpleString("none".to_string())),
-                }
-            }
+pub fn parse_getrange(
+    _name: &str,
+    args: &[RedisValue],
+    redis_value: &RedisValue,
+) -> Result<RedisCommand> {
+    let args_as_strings = get_strings_from_bulkstrings(args)
+        .map_err(|_| Error::InvalidRedisValue(redis_value.clone()))?;
+    match &args_as_strings[..] {
+        [key, start, end] => Ok(RedisCommand::GetRange(
+            key.clone(),
+            start.parse()?,
+            end.parse()?,
+        )),
+        _ => Err(Error::InvalidRedisValue(redis_value.clone())),
+    }
+}
 
-            Self::Xadd {
-                key,
-                stream_id,
-                store,
-            } => {
-                let stream_id = db.xadd(key, stream_id, store.clone());
-                match stream_id {
-                    Ok(stream_id) => Ok(RedisValue::bulkstring_from(&stream_id)),
-                    Err(Error::InvalidStreamId{should_be_greater_than:_, got}) => match got.as_ref() {
-                        "0-0" => Ok(RedisValue::SimpleError(
-                            "ERR The ID specified in XADD must be greater than 0-0".to_string(),
-                        )),
-                        _ => Ok(RedisValue::SimpleError(
-                            "ERR The ID specified in XADD is equal or smaller than the target stream top item".to_string()
-                        )),
-                    },
-                    Err(_) => Err(Error::InvalidRedisCommand(self.clone())),
-                }
-            }
-            Self::Xrange {
-                key,
-                stream_id_start,
-                stream_id_end,
-            } => {
-                let res = db.xrange(key, stream_id_start, stream_id_end)?;
+pub fn parse_setbit(
+    _name: &str,
+    args: &[RedisValue],
+    redis_value: &RedisValue,
+) -> Result<RedisCommand> {
+    let args_as_strings = get_strings_from_bulkstrings(args)
+        .map_err(|_| Error::InvalidRedisValue(redis_value.clone()))?;
+    match &args_as_strings[..] {
+        [key, offset, bit] => Ok(RedisCommand::SetBit(
+            key.clone(),
+            offset.parse()?,
+            bit.parse()?,
+        )),
+        _ => Err(Error::InvalidRedisValue(redis_value.clone())),
+    }
+}
 
-                let intermediate = res
-                    .iter()
-                    .map(|(id, store)| {
-                        (
-                            RedisValue::bulkstring_from(id),
-                            RedisValue::array_of_bulkstrings_from(
-                                &store
-                                    .iter()
-                                    .map(|(k, v)| format!("{} {}", k, v))
-                                    .collect::<Vec<_>>()
-                                    .join(" "),
-                            ),
-                        )
-                    })
-                    .map(|(id, store)| RedisValue::Array(2, vec![id, store]))
-                    .collect::<Vec<_>>();
+pub fn parse_expireat(
+    name: &str,
+    args: &[RedisValue],
+    redis_value: &RedisValue,
+) -> Result<RedisCommand> {
+    let args_as_strings = get_strings_from_bulkstrings(args)
+        .map_err(|_| Error::InvalidRedisValue(redis_value.clone()))?;
+    match &args_as_strings[..] {
+        [key, time] if name == "expireat" => Ok(RedisCommand::ExpireAt(key.clone(), time.parse()?)),
+        [key, time] => Ok(RedisCommand::PExpireAt(key.clone(), time.parse()?)),
+        _ => Err(Error::InvalidRedisValue(redis_value.clone())),
+    }
+}
+
+pub fn parse_mget(
+    _name: &str,
+    args: &[RedisValue],
+    redis_value: &RedisValue,
+) -> Result<RedisCommand> {
+    let keys = get_strings_from_bulkstrings(args)
+        .map_err(|_| Error::InvalidRedisValue(redis_value.clone()))?;
+    if keys.is_empty() {
+        Err(Error::InvalidRedisValue(redis_value.clone()))
+    } else {
+        Ok(RedisCommand::MGet(keys))
+    }
+}
 
-                Ok(RedisValue::Array(intermediate.len(), intermediate))
+pub fn parse_mset(
+    _name: &str,
+    args: &[RedisValue],
+    redis_value: &RedisValue,
+) -> Result<RedisCommand> {
+    let nb_elements = args.len() + 1;
+    if nb_elements < 3 || nb_elements % 2 == 0 {
+        Err(Error::InvalidRedisValue(redis_value.clone()))?
+    }
+    let pairs = args
+        .chunks(2)
+        .map(|pair| match pair {
+            [RedisValue::BulkString(_, key), RedisValue::BulkString(_, value)] => {
+                Ok((lossy_string(key), value.clone()))
             }
-            Self::Xread {
-                block: _,
-                key_offset_pairs,
-            } => {
-                let comb = key_offset_pairs
-                    .iter()
-                    .map(|(key, stream_id_start)| {
-                        let intermediate = db
-                            .xread(key, stream_id_start)
-                            .unwrap_or_default()
-                            .iter()
-                            .map(|(id, store)| {
-                                (
-                                    RedisValue::bulkstring_from(id),
-                                    RedisValue::array_of_bulkstrings_from(
-                                        &store
-                                            .iter()
-                                            .map(|(k, v)| format!("{} {}", k, v))
-                                            .collect::<Vec<_>>()
-                                            .join(" "),
-                                    ),
-                                )
-                            })
-                            .map(|(id, store)| RedisValue::Array(2, vec![id, store]))
-                            .collect::<Vec<_>>();
+            _ => Err(Error::InvalidRedisValue(redis_value.clone())),
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok(RedisCommand::MSet(pairs))
+}
 
-                        if intermediate.is_empty() {
-                            RedisValue::Array(1, vec![RedisValue::bulkstring_from(key)])
-                        } else {
-                            let key_and_intermediate =
-                                RedisValue::Array(intermediate.len(), intermediate);
-                            RedisValue::Array(
-                                2,
-                                vec![RedisValue::bulkstring_from(key), key_and_intermediate],
-                            )
-                        }
-                    })
-                    .collect::<Vec<_>>();
+pub fn parse_exists(
+    _name: &str,
+    args: &[RedisValue],
+    redis_value: &RedisValue,
+) -> Result<RedisCommand> {
+    let nb_elements = args.len() + 1;
+    if nb_elements < 2 {
+        Err(Error::InvalidRedisValue(redis_value.clone()))
+    } else {
+        let keys = get_strings_from_bulkstrings(args)
+            .map_err(|_| Error::InvalidRedisValue(redis_value.clone()))?;
+        Ok(RedisCommand::Exists(keys))
+    }
+}
 
-                if comb.iter().all(|el| matches!(el, RedisValue::Array(1, _))) {
-                    Ok(RedisValue::NullBulkString)
-                } else {
-                    Ok(RedisValue::Array(comb.len(), comb))
-                }
-            }
+pub fn parse_select(
+    _name: &str,
+    args: &[RedisValue],
+    redis_value: &RedisValue,
+) -> Result<RedisCommand> {
+    let args_as_strings = get_strings_from_bulkstrings(args)
+        .map_err(|_| Error::InvalidRedisValue(redis_value.clone()))?;
+    match &args_as_strings[..] {
+        [index] => Ok(RedisCommand::Select(index.parse()?)),
+        _ => Err(Error::InvalidRedisValue(redis_value.clone())),
+    }
+}
 
-            Self::Multi => {
-                // multi should not be executed in a standard way
-                todo!()
-            }
-            Self::Exec => {
-                // exec should not be executed in a standard way
-                todo!()
-            }
-            Self::Discard => {
-                // discard should not be executed in a standard way
-                todo!()
+pub fn parse_copy(
+    _name: &str,
+    args: &[RedisValue],
+    redis_value: &RedisValue,
+) -> Result<RedisCommand> {
+    let args_as_strings = get_strings_from_bulkstrings(args)
+        .map_err(|_| Error::InvalidRedisValue(redis_value.clone()))?;
+    let [source, destination, options @ ..] = &args_as_strings[..] else {
+        return Err(Error::InvalidRedisValue(redis_value.clone()));
+    };
+    let mut destination_db = None;
+    let mut replace = false;
+    let mut options = options.iter();
+    while let Some(option) = options.next() {
+        match option.to_lowercase().as_str() {
+            "db" => {
+                let index = options
+                    .next()
+                    .ok_or_else(|| Error::InvalidRedisValue(redis_value.clone()))?;
+                destination_db = Some(index.parse()?);
             }
+            "replace" => replace = true,
+            _ => Err(Error::InvalidRedisValue(redis_value.clone()))?,
         }
     }
+    Ok(RedisCommand::Copy {
+        source: source.clone(),
+        destination: destination.clone(),
+        destination_db,
+        replace,
+    })
 }
 
-pub fn get_strings_from_bulkstrings(args: &[RedisValue]) -> Result<Vec<String>> {
-    args.iter()
-        .map(|el| {
-            if let RedisValue::BulkString(_, val) = el {
-                Ok(val.clone())
-            } else {
-                Err(Error::InvalidRedisValue(el.clone()))
-            }
-        })
+pub fn parse_dbsize(
+    _name: &str,
+    args: &[RedisValue],
+    redis_value: &RedisValue,
+) -> Result<RedisCommand> {
+    let nb_elements = args.len() + 1;
+    if nb_elements != 1 {
+        return Err(Error::InvalidRedisValue(redis_value.clone()));
+    }
+    Ok(RedisCommand::DbSize)
+}
+
+pub fn parse_save(
+    name: &str,
+    args: &[RedisValue],
+    redis_value: &RedisValue,
+) -> Result<RedisCommand> {
+    let args_as_strings = get_strings_from_bulkstrings(args)
+        .map_err(|_| Error::InvalidRedisValue(redis_value.clone()))?;
+    match (name, &args_as_strings[..]) {
+        ("save", []) => Ok(RedisCommand::Save),
+        // there is never a save in progress to wait for
+        ("bgsave", []) => Ok(RedisCommand::BgSave),
+        ("bgsave", [schedule]) if schedule.eq_ignore_ascii_case("schedule") => {
+            Ok(RedisCommand::BgSave)
+        }
+        _ => Err(Error::InvalidRedisValue(redis_value.clone())),
+    }
+}
+
+pub fn parse_incr(
+    _name: &str,
+    args: &[RedisValue],
+    redis_value: &RedisValue,
+) -> Result<RedisCommand> {
+    let nb_elements = args.len() + 1;
+    if nb_elements != 2 {
+        Err(Error::InvalidRedisValue(redis_value.clone()))
+    } else {
+        match &args[0] {
+            RedisValue::BulkString(_, key) => Ok(RedisCommand::Incr(lossy_string(key))),
+            _ => Err(Error::InvalidRedisValue(redis_value.clone())),
+        }
+    }
+}
+
+pub fn parse_increment(
+    name: &str,
+    args: &[RedisValue],
+    redis_value: &RedisValue,
+) -> Result<RedisCommand> {
+    let args_as_strings = get_strings_from_bulkstrings(args)
+        .map_err(|_| Error::InvalidRedisValue(redis_value.clone()))?;
+    match (name, &args_as_strings[..]) {
+        ("decr", [key]) => Ok(RedisCommand::Decr(key.clone())),
+        ("incrby", [key, increment]) => Ok(RedisCommand::IncrBy(key.clone(), increment.parse()?)),
+        ("decrby", [key, decrement]) => Ok(RedisCommand::DecrBy(key.clone(), decrement.parse()?)),
+        ("incrbyfloat", [key, increment]) => {
+            Ok(RedisCommand::IncrByFloat(key.clone(), increment.parse()?))
+        }
+        _ => Err(Error::InvalidRedisValue(redis_value.clone())),
+    }
+}
+
+pub fn parse_hincrby(
+    name: &str,
+    args: &[RedisValue],
+    redis_value: &RedisValue,
+) -> Result<RedisCommand> {
+    let args_as_strings = get_strings_from_bulkstrings(args)
+        .map_err(|_| Error::InvalidRedisValue(redis_value.clone()))?;
+    match (name, &args_as_strings[..]) {
+        ("hincrby", [key, field, increment]) => Ok(RedisCommand::HIncrBy(
+            key.clone(),
+            field.clone(),
+            increment.parse()?,
+        )),
+        ("hincrbyfloat", [key, field, increment]) => Ok(RedisCommand::HIncrByFloat(
+            key.clone(),
+            field.clone(),
+            increment.parse()?,
+        )),
+        _ => Err(Error::InvalidRedisValue(redis_value.clone())),
+    }
+}
+
+pub fn parse_info(
+    _name: &str,
+    args: &[RedisValue],
+    redis_value: &RedisValue,
+) -> Result<RedisCommand> {
+    let nb_elements = args.len() + 1;
+    if nb_elements != 2 {
+        Err(Error::InvalidRedisValue(redis_value.clone()))
+    } else {
+        match &args[0] {
+            RedisValue::BulkString(_, info_cmd) => Ok(RedisCommand::Info(lossy_string(info_cmd))),
+            _ => Err(Error::InvalidRedisValue(redis_value.clone())),
+        }
+    }
+}
+
+pub fn parse_replconf(
+    _name: &str,
+    args: &[RedisValue],
+    redis_value: &RedisValue,
+) -> Result<RedisCommand> {
+    let nb_elements = args.len() + 1;
+    if nb_elements != 3 {
+        Err(Error::InvalidRedisValue(redis_value.clone()))
+    } else if let ("GETACK", "*") = (
+        args[0].inner_string()?.as_ref(),
+        args[1].inner_string()?.as_ref(),
+    ) {
+        // this is actually what the master sends the replica
+        Ok(RedisCommand::ReplConfGetAck)
+    } else {
+        Ok(RedisCommand::ReplConf)
+    }
+    // } else if let ("listening-port", port) =
+    //     (args[0].inner_string()?.as_ref(), args[1].inner_string()?)
+    // {
+    //     let replica_port: u16 = port.parse()?;
+    //     Ok(RedisCommand::ReplConfListeningPort(replica_port))
+    // } else {
+    //     Ok(RedisCommand::ReplConfCapa)
+    // }
+}
+
+pub fn parse_psync(
+    _name: &str,
+    _args: &[RedisValue],
+    _redis_value: &RedisValue,
+) -> Result<RedisCommand> {
+    Ok(RedisCommand::Psync)
+}
+
+pub fn parse_wait(
+    _name: &str,
+    args: &[RedisValue],
+    redis_value: &RedisValue,
+) -> Result<RedisCommand> {
+    let nb_elements = args.len() + 1;
+    if nb_elements != 3 {
+        Err(Error::InvalidRedisValue(redis_value.clone()))
+    } else {
+        match (&args[0], &args[1]) {
+            (RedisValue::BulkString(_, nb_replica), RedisValue::BulkString(_, timeout)) => {
+                let nb_replica = lossy_string(nb_replica).parse()?;
+                let timeout = lossy_string(timeout).parse()?;
+
+                Ok(RedisCommand::Wait(nb_replica, timeout))
+            }
+            _ => Err(Error::InvalidRedisValue(redis_value.clone())),
+        }
+    }
+}
+
+pub fn parse_config(
+    _name: &str,
+    args: &[RedisValue],
+    redis_value: &RedisValue,
+) -> Result<RedisCommand> {
+    let args_as_strings = get_strings_from_bulkstrings(args)
+        .map_err(|_| Error::InvalidRedisValue(redis_value.clone()))?;
+    let (subcommand, rest) = args_as_strings
+        .split_first()
+        .ok_or_else(|| Error::InvalidRedisValue(redis_value.clone()))?;
+
+    match (subcommand.to_lowercase().as_ref(), rest) {
+        ("get", [parameter]) => Ok(RedisCommand::ConfigGet(parameter.to_lowercase())),
+        ("set", [_, ..]) if rest.len() % 2 == 0 => Ok(RedisCommand::ConfigSet(
+            rest.chunks_exact(2)
+                .map(|pair| (pair[0].to_lowercase(), pair[1].clone()))
+                .collect(),
+        )),
+        ("set", _) => Err(Error::UnknownConfigParameter(
+            rest.first().cloned().unwrap_or_default(),
+        )),
+        _ => Err(Error::InvalidRedisValue(redis_value.clone())),
+    }
+}
+
+pub fn parse_cluster(
+    _name: &str,
+    args: &[RedisValue],
+    redis_value: &RedisValue,
+) -> Result<RedisCommand> {
+    let args_as_strings = get_strings_from_bulkstrings(args)
+        .map_err(|_| Error::InvalidRedisValue(redis_value.clone()))?;
+
+    let subcommand = match &args_as_strings[..] {
+        [subcommand] => match subcommand.to_lowercase().as_ref() {
+            "info" => ClusterSubcommand::Info,
+            "myid" => ClusterSubcommand::MyId,
+            "slots" => ClusterSubcommand::Slots,
+            "shards" => ClusterSubcommand::Shards,
+            _ => Err(Error::InvalidRedisValue(redis_value.clone()))?,
+        },
+        _ => Err(Error::InvalidRedisValue(redis_value.clone()))?,
+    };
+    Ok(RedisCommand::Cluster(subcommand))
+}
+
+pub fn parse_command(
+    _name: &str,
+    args: &[RedisValue],
+    redis_value: &RedisValue,
+) -> Result<RedisCommand> {
+    let args_as_strings = get_strings_from_bulkstrings(args)
+        .map_err(|_| Error::InvalidRedisValue(redis_value.clone()))?;
+    let (subcommand, rest) = args_as_strings
+        .split_first()
+        .ok_or_else(|| Error::InvalidRedisValue(redis_value.clone()))?;
+
+    match subcommand.to_lowercase().as_ref() {
+        "info" => Ok(RedisCommand::Command(CommandSubcommand::Info(
+            rest.to_vec(),
+        ))),
+        "docs" => Ok(RedisCommand::Command(CommandSubcommand::Docs(
+            rest.to_vec(),
+        ))),
+        "list" if rest.is_empty() => Ok(RedisCommand::Command(CommandSubcommand::List)),
+        "count" if rest.is_empty() => Ok(RedisCommand::Command(CommandSubcommand::Count)),
+        "getkeys" if !rest.is_empty() => Ok(RedisCommand::Command(CommandSubcommand::GetKeys(
+            rest.to_vec(),
+        ))),
+        "getkeysandflags" if !rest.is_empty() => Ok(RedisCommand::Command(
+            CommandSubcommand::GetKeysAndFlags(rest.to_vec()),
+        )),
+        _ => Err(Error::InvalidRedisValue(redis_value.clone())),
+    }
+}
+
+pub fn parse_keys(
+    _name: &str,
+    args: &[RedisValue],
+    redis_value: &RedisValue,
+) -> Result<RedisCommand> {
+    let nb_elements = args.len() + 1;
+    if nb_elements != 2 {
+        Err(Error::InvalidRedisValue(redis_value.clone()))
+    } else {
+        match &args[0] {
+            RedisValue::BulkString(_, pat) => Ok(RedisCommand::Keys(lossy_string(pat))),
+            _ => Err(Error::InvalidRedisValue(redis_value.clone())),
+        }
+    }
+}
+
+pub fn parse_scan(
+    _name: &str,
+    args: &[RedisValue],
+    redis_value: &RedisValue,
+) -> Result<RedisCommand> {
+    let args_as_strings = get_strings_from_bulkstrings(args)
+        .map_err(|_| Error::InvalidRedisValue(redis_value.clone()))?;
+    let [cursor, options @ ..] = &args_as_strings[..] else {
+        return Err(Error::InvalidRedisValue(redis_value.clone()));
+    };
+    let cursor = cursor.parse()?;
+    let mut pattern = None;
+    let mut count = 10;
+    let mut type_name = None;
+    for option in options.chunks(2) {
+        match option {
+            [name, value] if name.eq_ignore_ascii_case("match") => pattern = Some(value.clone()),
+            [name, value] if name.eq_ignore_ascii_case("count") => count = value.parse()?,
+            [name, value] if name.eq_ignore_ascii_case("type") => {
+                type_name = Some(value.to_lowercase())
+            }
+            _ => Err(Error::InvalidRedisValue(redis_value.clone()))?,
+        }
+    }
+    Ok(RedisCommand::Scan {
+        cursor,
+        pattern,
+        count,
+        type_name,
+    })
+}
+
+pub fn parse_type(
+    _name: &str,
+    args: &[RedisValue],
+    redis_value: &RedisValue,
+) -> Result<RedisCommand> {
+    let nb_elements = args.len() + 1;
+    if nb_elements != 2 {
+        Err(Error::InvalidRedisValue(redis_value.clone()))
+    } else {
+        match &args[0] {
+            RedisValue::BulkString(_, key) => Ok(RedisCommand::Type(lossy_string(key))),
+            _ => Err(Error::InvalidRedisValue(redis_value.clone())),
+        }
+    }
+}
+
+pub fn parse_xadd(
+    _name: &str,
+    args: &[RedisValue],
+    redis_value: &RedisValue,
+) -> Result<RedisCommand> {
+    let args_as_strings = get_strings_from_bulkstrings(args)
+        .map_err(|_| Error::InvalidRedisValue(redis_value.clone()))?;
+    let [key, rest @ ..] = &args_as_strings[..] else {
+        return Err(Error::InvalidRedisValue(redis_value.clone()));
+    };
+
+    let (no_mk_stream, rest) = match rest {
+        [option, rest @ ..] if option.eq_ignore_ascii_case("nomkstream") => (true, rest),
+        rest => (false, rest),
+    };
+
+    // MAXLEN [~|=] <count> comes before the id
+    let (trim, rest) = match rest {
+        [option, operator, max_len, rest @ ..]
+            if option.eq_ignore_ascii_case("maxlen") && (operator == "~" || operator == "=") =>
+        {
+            let trim = StreamTrim {
+                max_len: max_len.parse()?,
+                approximate: operator == "~",
+            };
+            (Some(trim), rest)
+        }
+        [option, max_len, rest @ ..] if option.eq_ignore_ascii_case("maxlen") => {
+            let trim = StreamTrim {
+                max_len: max_len.parse()?,
+                approximate: false,
+            };
+            (Some(trim), rest)
+        }
+        rest => (None, rest),
+    };
+
+    let [stream_id, pairs @ ..] = rest else {
+        return Err(Error::InvalidRedisValue(redis_value.clone()));
+    };
+    if pairs.is_empty() || pairs.len() % 2 != 0 {
+        return Err(Error::InvalidRedisValue(redis_value.clone()));
+    }
+    let store = pairs
+        .chunks(2)
+        .map(|pair| (pair[0].clone(), pair[1].clone()))
+        .collect();
+    Ok(RedisCommand::Xadd {
+        key: key.clone(),
+        stream_id: stream_id.clone(),
+        store,
+        trim,
+        no_mk_stream,
+    })
+}
+
+pub fn parse_xdel(
+    _name: &str,
+    args: &[RedisValue],
+    redis_value: &RedisValue,
+) -> Result<RedisCommand> {
+    let args_as_strings = get_strings_from_bulkstrings(args)
+        .map_err(|_| Error::InvalidRedisValue(redis_value.clone()))?;
+    match &args_as_strings[..] {
+        [key, ids @ ..] if !ids.is_empty() => Ok(RedisCommand::Xdel {
+            key: key.clone(),
+            ids: ids.to_vec(),
+        }),
+        _ => Err(Error::InvalidRedisValue(redis_value.clone())),
+    }
+}
+
+pub fn parse_xrange(
+    _name: &str,
+    args: &[RedisValue],
+    redis_value: &RedisValue,
+) -> Result<RedisCommand> {
+    let nb_elements = args.len() + 1;
+    if nb_elements != 4 {
+        Err(Error::InvalidRedisValue(redis_value.clone()))
+    } else {
+        let args_as_strings = get_strings_from_bulkstrings(args)
+            .map_err(|_| Error::InvalidRedisValue(redis_value.clone()))?;
+
+        let key = args_as_strings[0].clone();
+        let stream_id_start = args_as_strings[1].clone();
+        let stream_id_end = args_as_strings[2].clone();
+        Ok(RedisCommand::Xrange {
+            key,
+            stream_id_start,
+            stream_id_end,
+        })
+    }
+}
+
+pub fn parse_xrevrange(
+    _name: &str,
+    args: &[RedisValue],
+    redis_value: &RedisValue,
+) -> Result<RedisCommand> {
+    let args_as_strings = get_strings_from_bulkstrings(args)
+        .map_err(|_| Error::InvalidRedisValue(redis_value.clone()))?;
+    match &args_as_strings[..] {
+        [key, stream_id_end, stream_id_start] => Ok(RedisCommand::Xrevrange {
+            key: key.clone(),
+            stream_id_end: stream_id_end.clone(),
+            stream_id_start: stream_id_start.clone(),
+        }),
+        _ => Err(Error::InvalidRedisValue(redis_value.clone())),
+    }
+}
+
+pub fn parse_xread(
+    _name: &str,
+    args: &[RedisValue],
+    redis_value: &RedisValue,
+) -> Result<RedisCommand> {
+    let nb_elements = args.len() + 1;
+    if nb_elements < 4 || nb_elements % 2 != 0 {
+        Err(Error::InvalidRedisValue(redis_value.clone()))
+    } else {
+        let args_as_strings = get_strings_from_bulkstrings(args)
+            .map_err(|_| Error::InvalidRedisValue(redis_value.clone()))?;
+
+        let mut i = 0;
+        let mut block = None;
+        // handle block
+        if args_as_strings[0].to_lowercase() == "block" {
+            if nb_elements < 6 {
+                Err(Error::InvalidRedisValue(redis_value.clone()))?
+            }
+            block = Some(args_as_strings[1].parse::<u64>()?);
+            i = 2;
+        }
+
+        if args_as_strings[i] != "streams" {
+            Err(Error::InvalidRedisValue(redis_value.clone()))?
+        }
+
+        let offset = (nb_elements - 2 - i) / 2;
+
+        let mut key_offset_pairs = Vec::new();
+
+        i += 1;
+        while i + offset < args_as_strings.len() {
+            key_offset_pairs.push((
+                args_as_strings[i].clone(),
+                args_as_strings[i + offset].clone(),
+            ));
+            i += 1;
+        }
+
+        Ok(RedisCommand::Xread {
+            block,
+            key_offset_pairs,
+        })
+    }
+}
+
+pub fn parse_xgroup(
+    _name: &str,
+    args: &[RedisValue],
+    redis_value: &RedisValue,
+) -> Result<RedisCommand> {
+    let nb_elements = args.len() + 1;
+    if nb_elements < 4 {
+        Err(Error::InvalidRedisValue(redis_value.clone()))
+    } else {
+        let args_as_strings = get_strings_from_bulkstrings(args)
+            .map_err(|_| Error::InvalidRedisValue(redis_value.clone()))?;
+
+        let key = args_as_strings[1].clone();
+        let group = args_as_strings[2].clone();
+        let subcommand = match (args_as_strings[0].to_lowercase().as_ref(), nb_elements) {
+            ("create", 5) => XGroupSubcommand::Create {
+                key,
+                group,
+                stream_id: args_as_strings[3].clone(),
+                mk_stream: false,
+            },
+            ("create", 6) if args_as_strings[4].to_lowercase() == "mkstream" => {
+                XGroupSubcommand::Create {
+                    key,
+                    group,
+                    stream_id: args_as_strings[3].clone(),
+                    mk_stream: true,
+                }
+            }
+            ("destroy", 4) => XGroupSubcommand::Destroy { key, group },
+            ("createconsumer", 5) => XGroupSubcommand::CreateConsumer {
+                key,
+                group,
+                consumer: args_as_strings[3].clone(),
+            },
+            _ => Err(Error::InvalidRedisValue(redis_value.clone()))?,
+        };
+        Ok(RedisCommand::XGroup(subcommand))
+    }
+}
+
+pub fn parse_xinfo(
+    _name: &str,
+    args: &[RedisValue],
+    redis_value: &RedisValue,
+) -> Result<RedisCommand> {
+    let args_as_strings = get_strings_from_bulkstrings(args)
+        .map_err(|_| Error::InvalidRedisValue(redis_value.clone()))?;
+    match &args_as_strings[..] {
+        [subcommand, key] if subcommand.eq_ignore_ascii_case("stream") => {
+            Ok(RedisCommand::XInfo(XInfoSubcommand::Stream(key.clone())))
+        }
+        _ => Err(Error::InvalidRedisValue(redis_value.clone())),
+    }
+}
+
+pub fn parse_xreadgroup(
+    _name: &str,
+    args: &[RedisValue],
+    redis_value: &RedisValue,
+) -> Result<RedisCommand> {
+    let nb_elements = args.len() + 1;
+    let args_as_strings = get_strings_from_bulkstrings(args)
+        .map_err(|_| Error::InvalidRedisValue(redis_value.clone()))?;
+    if nb_elements < 7 || args_as_strings[0].to_lowercase() != "group" {
+        Err(Error::InvalidRedisValue(redis_value.clone()))?
+    }
+
+    let group = args_as_strings[1].clone();
+    let consumer = args_as_strings[2].clone();
+    let mut count = None;
+    let mut no_ack = false;
+    let mut i = 3;
+    loop {
+        match args_as_strings.get(i).map(|x| x.to_lowercase()) {
+            Some(x) if x == "count" => {
+                let value = args_as_strings
+                    .get(i + 1)
+                    .ok_or_else(|| Error::InvalidRedisValue(redis_value.clone()))?;
+                count = Some(value.parse::<usize>()?);
+                i += 2;
+            }
+            Some(x) if x == "noack" => {
+                no_ack = true;
+                i += 1;
+            }
+            Some(x) if x == "streams" => {
+                i += 1;
+                break;
+            }
+            _ => Err(Error::InvalidRedisValue(redis_value.clone()))?,
+        }
+    }
+
+    let keys_and_ids = &args_as_strings[i..];
+    if keys_and_ids.is_empty() || keys_and_ids.len() % 2 != 0 {
+        Err(Error::InvalidRedisValue(redis_value.clone()))?
+    }
+    let (keys, stream_ids) = keys_and_ids.split_at(keys_and_ids.len() / 2);
+    let key_offset_pairs = keys
+        .iter()
+        .cloned()
+        .zip(stream_ids.iter().cloned())
+        .collect();
+
+    Ok(RedisCommand::XReadGroup {
+        group,
+        consumer,
+        count,
+        no_ack,
+        key_offset_pairs,
+    })
+}
+
+pub fn parse_xack(
+    _name: &str,
+    args: &[RedisValue],
+    redis_value: &RedisValue,
+) -> Result<RedisCommand> {
+    let nb_elements = args.len() + 1;
+    if nb_elements < 4 {
+        Err(Error::InvalidRedisValue(redis_value.clone()))
+    } else {
+        let args_as_strings = get_strings_from_bulkstrings(args)
+            .map_err(|_| Error::InvalidRedisValue(redis_value.clone()))?;
+
+        Ok(RedisCommand::XAck {
+            key: args_as_strings[0].clone(),
+            group: args_as_strings[1].clone(),
+            stream_ids: args_as_strings[2..].to_vec(),
+        })
+    }
+}
+
+pub fn parse_xclaim(
+    _name: &str,
+    args: &[RedisValue],
+    redis_value: &RedisValue,
+) -> Result<RedisCommand> {
+    let nb_elements = args.len() + 1;
+    let args_as_strings = get_strings_from_bulkstrings(args)
+        .map_err(|_| Error::InvalidRedisValue(redis_value.clone()))?;
+    if nb_elements < 6 {
+        Err(Error::InvalidRedisValue(redis_value.clone()))?
+    }
+
+    let key = args_as_strings[0].clone();
+    let group = args_as_strings[1].clone();
+    let consumer = args_as_strings[2].clone();
+    let mut options = ClaimOptions {
+        min_idle: args_as_strings[3].parse::<u64>()?,
+        ..Default::default()
+    };
+
+    // ids come first, then the options
+    let mut stream_ids = Vec::new();
+    let mut i = 4;
+    while i < args_as_strings.len() {
+        let value = args_as_strings.get(i + 1);
+        match args_as_strings[i].to_lowercase().as_ref() {
+            "idle" | "time" | "retrycount" if value.is_none() => {
+                Err(Error::InvalidRedisValue(redis_value.clone()))?
+            }
+            "idle" => {
+                options.idle = value.map(|x| x.parse()).transpose()?;
+                i += 1;
+            }
+            "time" => {
+                let unix_time_ms = value.unwrap().parse::<u64>()?;
+                options.idle = Some(clock::unix_time_ms().saturating_sub(unix_time_ms));
+                i += 1;
+            }
+            "retrycount" => {
+                options.retry_count = value.map(|x| x.parse()).transpose()?;
+                i += 1;
+            }
+            "force" => options.force = true,
+            "justid" => options.just_id = true,
+            _ if stream_ids.len() == i - 4 => stream_ids.push(args_as_strings[i].clone()),
+            _ => Err(Error::InvalidRedisValue(redis_value.clone()))?,
+        }
+        i += 1;
+    }
+    if stream_ids.is_empty() {
+        Err(Error::InvalidRedisValue(redis_value.clone()))?
+    }
+
+    Ok(RedisCommand::XClaim {
+        key,
+        group,
+        consumer,
+        stream_ids,
+        options,
+    })
+}
+
+pub fn parse_xpending(
+    _name: &str,
+    args: &[RedisValue],
+    redis_value: &RedisValue,
+) -> Result<RedisCommand> {
+    let nb_elements = args.len() + 1;
+    let args_as_strings = get_strings_from_bulkstrings(args)
+        .map_err(|_| Error::InvalidRedisValue(redis_value.clone()))?;
+    if nb_elements < 3 {
+        Err(Error::InvalidRedisValue(redis_value.clone()))?
+    }
+
+    let key = args_as_strings[0].clone();
+    let group = args_as_strings[1].clone();
+    let mut rest = &args_as_strings[2..];
+    let range = if rest.is_empty() {
+        None
+    } else {
+        let mut min_idle = None;
+        if rest[0].to_lowercase() == "idle" && rest.len() > 1 {
+            min_idle = Some(rest[1].parse::<u64>()?);
+            rest = &rest[2..];
+        }
+        if rest.len() != 3 && rest.len() != 4 {
+            Err(Error::InvalidRedisValue(redis_value.clone()))?
+        }
+        Some(PendingRange {
+            min_idle,
+            stream_id_start: rest[0].clone(),
+            stream_id_end: rest[1].clone(),
+            count: rest[2].parse::<usize>()?,
+            consumer: rest.get(3).cloned(),
+        })
+    };
+
+    Ok(RedisCommand::XPending { key, group, range })
+}
+
+pub fn parse_subscribe(
+    name: &str,
+    args: &[RedisValue],
+    redis_value: &RedisValue,
+) -> Result<RedisCommand> {
+    let names = get_strings_from_bulkstrings(args)
+        .map_err(|_| Error::InvalidRedisValue(redis_value.clone()))?;
+    let kind = match name.chars().next() {
+        Some('p') => SubscriptionKind::Pattern,
+        Some('s') if name != "subscribe" => SubscriptionKind::Shard,
+        _ => SubscriptionKind::Channel,
+    };
+
+    if name.ends_with("unsubscribe") {
+        Ok(RedisCommand::Unsubscribe(kind, names))
+    } else if names.is_empty() {
+        Err(Error::InvalidRedisValue(redis_value.clone()))
+    } else {
+        Ok(RedisCommand::Subscribe(kind, names))
+    }
+}
+
+pub fn parse_publish(
+    name: &str,
+    args: &[RedisValue],
+    redis_value: &RedisValue,
+) -> Result<RedisCommand> {
+    let nb_elements = args.len() + 1;
+    if nb_elements != 3 {
+        Err(Error::InvalidRedisValue(redis_value.clone()))
+    } else {
+        let args_as_strings = get_strings_from_bulkstrings(args)
+            .map_err(|_| Error::InvalidRedisValue(redis_value.clone()))?;
+        let channel = args_as_strings[0].clone();
+        let message = args_as_strings[1].clone();
+        if name == "publish" {
+            Ok(RedisCommand::Publish(channel, message))
+        } else {
+            Ok(RedisCommand::SPublish(channel, message))
+        }
+    }
+}
+
+pub fn parse_object(
+    _name: &str,
+    args: &[RedisValue],
+    redis_value: &RedisValue,
+) -> Result<RedisCommand> {
+    let args_as_strings = get_strings_from_bulkstrings(args)
+        .map_err(|_| Error::InvalidRedisValue(redis_value.clone()))?;
+    match &args_as_strings[..] {
+        [subcommand, key] if subcommand.eq_ignore_ascii_case("encoding") => Ok(
+            RedisCommand::Object(ObjectSubcommand::Encoding(key.clone())),
+        ),
+        _ => Err(Error::InvalidRedisValue(redis_value.clone())),
+    }
+}
+
+pub fn parse_pubsub(
+    _name: &str,
+    args: &[RedisValue],
+    redis_value: &RedisValue,
+) -> Result<RedisCommand> {
+    let args_as_strings = get_strings_from_bulkstrings(args)
+        .map_err(|_| Error::InvalidRedisValue(redis_value.clone()))?;
+    let (subcommand, rest) = args_as_strings
+        .split_first()
+        .ok_or_else(|| Error::InvalidRedisValue(redis_value.clone()))?;
+
+    let subcommand = match subcommand.to_lowercase().as_ref() {
+        "channels" if rest.len() <= 1 => PubSubSubcommand::Channels(rest.first().cloned()),
+        "numsub" => PubSubSubcommand::NumSub(rest.to_vec()),
+        "numpat" if rest.is_empty() => PubSubSubcommand::NumPat,
+        _ => Err(Error::InvalidRedisValue(redis_value.clone()))?,
+    };
+    Ok(RedisCommand::PubSub(subcommand))
+}
+
+pub fn parse_push(
+    name: &str,
+    args: &[RedisValue],
+    redis_value: &RedisValue,
+) -> Result<RedisCommand> {
+    match args {
+        [RedisValue::BulkString(_, key), values @ ..] if !values.is_empty() => {
+            let values = values
+                .iter()
+                .map(|value| match value {
+                    RedisValue::BulkString(_, value) => Ok(value.clone()),
+                    _ => Err(Error::InvalidRedisValue(redis_value.clone())),
+                })
+                .collect::<Result<Vec<_>>>()?;
+            let key = lossy_string(key);
+            if name == "lpush" {
+                Ok(RedisCommand::LPush(key, values))
+            } else {
+                Ok(RedisCommand::RPush(key, values))
+            }
+        }
+        _ => Err(Error::InvalidRedisValue(redis_value.clone())),
+    }
+}
+
+pub fn parse_pop(
+    name: &str,
+    args: &[RedisValue],
+    redis_value: &RedisValue,
+) -> Result<RedisCommand> {
+    let args_as_strings = get_strings_from_bulkstrings(args)
+        .map_err(|_| Error::InvalidRedisValue(redis_value.clone()))?;
+    let (key, count) = match &args_as_strings[..] {
+        [key] => (key.clone(), None),
+        [key, count] => (key.clone(), Some(count.parse::<usize>()?)),
+        _ => Err(Error::InvalidRedisValue(redis_value.clone()))?,
+    };
+    if name == "lpop" {
+        Ok(RedisCommand::LPop(key, count))
+    } else {
+        Ok(RedisCommand::RPop(key, count))
+    }
+}
+
+pub fn parse_llen(
+    _name: &str,
+    args: &[RedisValue],
+    redis_value: &RedisValue,
+) -> Result<RedisCommand> {
+    let args_as_strings = get_strings_from_bulkstrings(args)
+        .map_err(|_| Error::InvalidRedisValue(redis_value.clone()))?;
+    match &args_as_strings[..] {
+        [key] => Ok(RedisCommand::LLen(key.clone())),
+        _ => Err(Error::InvalidRedisValue(redis_value.clone())),
+    }
+}
+
+pub fn parse_lrange(
+    _name: &str,
+    args: &[RedisValue],
+    redis_value: &RedisValue,
+) -> Result<RedisCommand> {
+    let args_as_strings = get_strings_from_bulkstrings(args)
+        .map_err(|_| Error::InvalidRedisValue(redis_value.clone()))?;
+    match &args_as_strings[..] {
+        [key, start, stop] => Ok(RedisCommand::LRange(
+            key.clone(),
+            start.parse::<i64>()?,
+            stop.parse::<i64>()?,
+        )),
+        _ => Err(Error::InvalidRedisValue(redis_value.clone())),
+    }
+}
+
+pub fn parse_blocking_pop(
+    name: &str,
+    args: &[RedisValue],
+    redis_value: &RedisValue,
+) -> Result<RedisCommand> {
+    let args_as_strings = get_strings_from_bulkstrings(args)
+        .map_err(|_| Error::InvalidRedisValue(redis_value.clone()))?;
+    match &args_as_strings[..] {
+        [keys @ .., timeout] if !keys.is_empty() => {
+            let timeout = timeout.parse::<f64>()?;
+            if !timeout.is_finite() || timeout < 0.0 {
+                Err(Error::InvalidRedisValue(redis_value.clone()))?
+            }
+            let timeout = Duration::from_secs_f64(timeout);
+            if name == "blpop" {
+                Ok(RedisCommand::BLPop(keys.to_vec(), timeout))
+            } else {
+                Ok(RedisCommand::BRPop(keys.to_vec(), timeout))
+            }
+        }
+        _ => Err(Error::InvalidRedisValue(redis_value.clone())),
+    }
+}
+
+pub fn parse_lpos(
+    _name: &str,
+    args: &[RedisValue],
+    redis_value: &RedisValue,
+) -> Result<RedisCommand> {
+    let nb_elements = args.len() + 1;
+    if nb_elements < 3 || nb_elements % 2 != 1 {
+        Err(Error::InvalidRedisValue(redis_value.clone()))
+    } else {
+        let args_as_strings = get_strings_from_bulkstrings(args)
+            .map_err(|_| Error::InvalidRedisValue(redis_value.clone()))?;
+
+        let key = args_as_strings[0].clone();
+        let element = args_as_strings[1].clone();
+        let mut rank = None;
+        let mut count = None;
+        let mut i = 2;
+        while i < args_as_strings.len() {
+            match args_as_strings[i].to_lowercase().as_ref() {
+                "rank" => rank = Some(args_as_strings[i + 1].parse::<i64>()?),
+                "count" => count = Some(args_as_strings[i + 1].parse::<usize>()?),
+                _ => Err(Error::InvalidRedisValue(redis_value.clone()))?,
+            }
+            i += 2;
+        }
+
+        Ok(RedisCommand::LPos(key, element, rank, count))
+    }
+}
+
+pub fn parse_hset(
+    _name: &str,
+    args: &[RedisValue],
+    redis_value: &RedisValue,
+) -> Result<RedisCommand> {
+    let nb_elements = args.len() + 1;
+    if nb_elements < 4 || nb_elements % 2 != 0 {
+        Err(Error::InvalidRedisValue(redis_value.clone()))?
+    }
+    let RedisValue::BulkString(_, key) = &args[0] else {
+        Err(Error::InvalidRedisValue(redis_value.clone()))?
+    };
+    let pairs = args[1..]
+        .chunks(2)
+        .map(|pair| match pair {
+            [RedisValue::BulkString(_, field), RedisValue::BulkString(_, value)] => {
+                Ok((lossy_string(field), value.clone()))
+            }
+            _ => Err(Error::InvalidRedisValue(redis_value.clone())),
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok(RedisCommand::HSet(lossy_string(key), pairs))
+}
+
+pub fn parse_hash_command(
+    name: &str,
+    args: &[RedisValue],
+    redis_value: &RedisValue,
+) -> Result<RedisCommand> {
+    let args_as_strings = get_strings_from_bulkstrings(args)
+        .map_err(|_| Error::InvalidRedisValue(redis_value.clone()))?;
+    match (name, &args_as_strings[..]) {
+        ("hget", [key, field]) => Ok(RedisCommand::HGet(key.clone(), field.clone())),
+        ("hdel", [key, fields @ ..]) if !fields.is_empty() => {
+            Ok(RedisCommand::HDel(key.clone(), fields.to_vec()))
+        }
+        ("hgetall", [key]) => Ok(RedisCommand::HGetAll(key.clone())),
+        ("hkeys", [key]) => Ok(RedisCommand::HKeys(key.clone())),
+        ("hvals", [key]) => Ok(RedisCommand::HVals(key.clone())),
+        ("hlen", [key]) => Ok(RedisCommand::HLen(key.clone())),
+        _ => Err(Error::InvalidRedisValue(redis_value.clone())),
+    }
+}
+
+pub fn parse_hmget(
+    _name: &str,
+    args: &[RedisValue],
+    redis_value: &RedisValue,
+) -> Result<RedisCommand> {
+    let args_as_strings = get_strings_from_bulkstrings(args)
+        .map_err(|_| Error::InvalidRedisValue(redis_value.clone()))?;
+    match &args_as_strings[..] {
+        [key, fields @ ..] if !fields.is_empty() => {
+            Ok(RedisCommand::HMGet(key.clone(), fields.to_vec()))
+        }
+        _ => Err(Error::InvalidRedisValue(redis_value.clone())),
+    }
+}
+
+pub fn parse_hrandfield(
+    _name: &str,
+    args: &[RedisValue],
+    redis_value: &RedisValue,
+) -> Result<RedisCommand> {
+    let nb_elements = args.len() + 1;
+    if !(2..=4).contains(&nb_elements) {
+        Err(Error::InvalidRedisValue(redis_value.clone()))
+    } else {
+        let args_as_strings = get_strings_from_bulkstrings(args)
+            .map_err(|_| Error::InvalidRedisValue(redis_value.clone()))?;
+
+        let key = args_as_strings[0].clone();
+        let count = match args_as_strings.get(1) {
+            None => None,
+            Some(count) => {
+                let with_values = match args_as_strings.get(2) {
+                    None => false,
+                    Some(x) if x.to_lowercase() == "withvalues" => true,
+                    Some(_) => Err(Error::InvalidRedisValue(redis_value.clone()))?,
+                };
+                Some((count.parse::<i64>()?, with_values))
+            }
+        };
+
+        Ok(RedisCommand::HRandField(key, count))
+    }
+}
+
+pub fn parse_hexpire(
+    _name: &str,
+    args: &[RedisValue],
+    redis_value: &RedisValue,
+) -> Result<RedisCommand> {
+    let args_as_strings = get_strings_from_bulkstrings(args)
+        .map_err(|_| Error::InvalidRedisValue(redis_value.clone()))?;
+    match &args_as_strings[..] {
+        [key, seconds, fields @ ..] => Ok(RedisCommand::HExpire(
+            key.clone(),
+            seconds.parse()?,
+            parse_fields_argument(fields)
+                .ok_or_else(|| Error::InvalidRedisValue(redis_value.clone()))?,
+        )),
+        _ => Err(Error::InvalidRedisValue(redis_value.clone())),
+    }
+}
+
+pub fn parse_httl(
+    _name: &str,
+    args: &[RedisValue],
+    redis_value: &RedisValue,
+) -> Result<RedisCommand> {
+    let args_as_strings = get_strings_from_bulkstrings(args)
+        .map_err(|_| Error::InvalidRedisValue(redis_value.clone()))?;
+    match &args_as_strings[..] {
+        [key, fields @ ..] => Ok(RedisCommand::HTtl(
+            key.clone(),
+            parse_fields_argument(fields)
+                .ok_or_else(|| Error::InvalidRedisValue(redis_value.clone()))?,
+        )),
+        _ => Err(Error::InvalidRedisValue(redis_value.clone())),
+    }
+}
+
+pub fn parse_set_command(
+    name: &str,
+    args: &[RedisValue],
+    redis_value: &RedisValue,
+) -> Result<RedisCommand> {
+    let args_as_strings = get_strings_from_bulkstrings(args)
+        .map_err(|_| Error::InvalidRedisValue(redis_value.clone()))?;
+    match (name, &args_as_strings[..]) {
+        ("sadd", [key, members @ ..]) if !members.is_empty() => {
+            Ok(RedisCommand::SAdd(key.clone(), members.to_vec()))
+        }
+        ("srem", [key, members @ ..]) if !members.is_empty() => {
+            Ok(RedisCommand::SRem(key.clone(), members.to_vec()))
+        }
+        ("smembers", [key]) => Ok(RedisCommand::SMembers(key.clone())),
+        ("srandmember", [key]) => Ok(RedisCommand::SRandMember(key.clone(), None)),
+        ("srandmember", [key, count]) => Ok(RedisCommand::SRandMember(
+            key.clone(),
+            Some(count.parse::<i64>()?),
+        )),
+        ("spop", [key]) => Ok(RedisCommand::SPop(key.clone(), None)),
+        ("spop", [key, count]) => Ok(RedisCommand::SPop(
+            key.clone(),
+            Some(count.parse::<usize>()?),
+        )),
+        ("sinter", keys) if !keys.is_empty() => Ok(RedisCommand::SInter(keys.to_vec())),
+        ("sunion", keys) if !keys.is_empty() => Ok(RedisCommand::SUnion(keys.to_vec())),
+        ("sdiff", keys) if !keys.is_empty() => Ok(RedisCommand::SDiff(keys.to_vec())),
+        ("sinterstore", [destination, keys @ ..]) if !keys.is_empty() => Ok(
+            RedisCommand::SInterStore(destination.clone(), keys.to_vec()),
+        ),
+        ("sunionstore", [destination, keys @ ..]) if !keys.is_empty() => Ok(
+            RedisCommand::SUnionStore(destination.clone(), keys.to_vec()),
+        ),
+        ("sdiffstore", [destination, keys @ ..]) if !keys.is_empty() => {
+            Ok(RedisCommand::SDiffStore(destination.clone(), keys.to_vec()))
+        }
+        _ => Err(Error::InvalidRedisValue(redis_value.clone())),
+    }
+}
+
+pub fn parse_geoadd(
+    _name: &str,
+    args: &[RedisValue],
+    redis_value: &RedisValue,
+) -> Result<RedisCommand> {
+    let args_as_strings = get_strings_from_bulkstrings(args)
+        .map_err(|_| Error::InvalidRedisValue(redis_value.clone()))?;
+    let [key, triplets @ ..] = &args_as_strings[..] else {
+        return Err(Error::InvalidRedisValue(redis_value.clone()));
+    };
+    if triplets.is_empty() || triplets.len() % 3 != 0 {
+        return Err(Error::InvalidRedisValue(redis_value.clone()));
+    }
+    let triplets = triplets
+        .chunks(3)
+        .map(|triplet| {
+            let (lon, lat) = (triplet[0].parse()?, triplet[1].parse()?);
+            geo::validate(lon, lat)?;
+            Ok((lon, lat, triplet[2].clone()))
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok(RedisCommand::GeoAdd(key.clone(), triplets))
+}
+
+pub fn parse_geopos(
+    _name: &str,
+    args: &[RedisValue],
+    redis_value: &RedisValue,
+) -> Result<RedisCommand> {
+    let args_as_strings = get_strings_from_bulkstrings(args)
+        .map_err(|_| Error::InvalidRedisValue(redis_value.clone()))?;
+    match &args_as_strings[..] {
+        [key, members @ ..] => Ok(RedisCommand::GeoPos(key.clone(), members.to_vec())),
+        _ => Err(Error::InvalidRedisValue(redis_value.clone())),
+    }
+}
+
+pub fn parse_geodist(
+    _name: &str,
+    args: &[RedisValue],
+    redis_value: &RedisValue,
+) -> Result<RedisCommand> {
+    let args_as_strings = get_strings_from_bulkstrings(args)
+        .map_err(|_| Error::InvalidRedisValue(redis_value.clone()))?;
+    let (key, first, second, unit) = match &args_as_strings[..] {
+        [key, first, second] => (key, first, second, Unit::Meters),
+        [key, first, second, unit] => (key, first, second, Unit::parse(unit)?),
+        _ => return Err(Error::InvalidRedisValue(redis_value.clone())),
+    };
+    Ok(RedisCommand::GeoDist(
+        key.clone(),
+        first.clone(),
+        second.clone(),
+        unit,
+    ))
+}
+
+pub fn parse_geosearch(
+    _name: &str,
+    args: &[RedisValue],
+    redis_value: &RedisValue,
+) -> Result<RedisCommand> {
+    let args_as_strings = get_strings_from_bulkstrings(args)
+        .map_err(|_| Error::InvalidRedisValue(redis_value.clone()))?;
+    let [key, options @ ..] = &args_as_strings[..] else {
+        return Err(Error::InvalidRedisValue(redis_value.clone()));
+    };
+
+    let mut origin = None;
+    let mut radius = None;
+    let mut descending = false;
+    let mut count = None;
+    let mut with_dist = false;
+    let mut with_coord = false;
+    let mut options = options.iter();
+    let mut next = || {
+        options
+            .next()
+            .ok_or_else(|| Error::InvalidRedisValue(redis_value.clone()))
+    };
+    while let Ok(option) = next() {
+        match option.to_lowercase().as_str() {
+            "frommember" => origin = Some(GeoOrigin::Member(next()?.clone())),
+            "fromlonlat" => origin = Some(GeoOrigin::LonLat(next()?.parse()?, next()?.parse()?)),
+            "byradius" => radius = Some((next()?.parse()?, Unit::parse(next()?)?)),
+            "asc" => descending = false,
+            "desc" => descending = true,
+            "count" => count = Some(next()?.parse()?),
+            "withdist" => with_dist = true,
+            "withcoord" => with_coord = true,
+            _ => return Err(Error::InvalidRedisValue(redis_value.clone())),
+        }
+    }
+
+    let (Some(origin), Some((radius, unit))) = (origin, radius) else {
+        return Err(Error::InvalidRedisValue(redis_value.clone()));
+    };
+    Ok(RedisCommand::GeoSearch(
+        key.clone(),
+        GeoSearchOptions {
+            origin,
+            radius,
+            unit,
+            descending,
+            count,
+            with_dist,
+            with_coord,
+        },
+    ))
+}
+
+pub fn parse_hyperloglog_command(
+    name: &str,
+    args: &[RedisValue],
+    redis_value: &RedisValue,
+) -> Result<RedisCommand> {
+    let args_as_strings = get_strings_from_bulkstrings(args)
+        .map_err(|_| Error::InvalidRedisValue(redis_value.clone()))?;
+    match (name, &args_as_strings[..]) {
+        ("pfadd", [key, elements @ ..]) => Ok(RedisCommand::PFAdd(key.clone(), elements.to_vec())),
+        ("pfcount", keys) if !keys.is_empty() => Ok(RedisCommand::PFCount(keys.to_vec())),
+        ("pfmerge", [destination, keys @ ..]) => {
+            Ok(RedisCommand::PFMerge(destination.clone(), keys.to_vec()))
+        }
+        _ => Err(Error::InvalidRedisValue(redis_value.clone())),
+    }
+}
+
+pub fn parse_zadd(
+    _name: &str,
+    args: &[RedisValue],
+    redis_value: &RedisValue,
+) -> Result<RedisCommand> {
+    let args_as_strings = get_strings_from_bulkstrings(args)
+        .map_err(|_| Error::InvalidRedisValue(redis_value.clone()))?;
+    let (key, mut rest) = args_as_strings
+        .split_first()
+        .ok_or_else(|| Error::InvalidRedisValue(redis_value.clone()))?;
+
+    // the flags come before the score member pairs
+    let mut options = ZAddOptions::default();
+    while let Some((flag, others)) = rest.split_first() {
+        match flag.to_lowercase().as_str() {
+            "nx" => options.nx = true,
+            "xx" => options.xx = true,
+            "gt" => options.gt = true,
+            "lt" => options.lt = true,
+            "ch" => options.ch = true,
+            "incr" => options.incr = true,
+            _ => break,
+        }
+        rest = others;
+    }
+
+    if rest.is_empty() || rest.len() % 2 != 0 {
+        Err(Error::InvalidRedisValue(redis_value.clone()))?
+    }
+    let pairs = rest
+        .chunks(2)
+        .map(|pair| match pair[0].parse::<f64>() {
+            Ok(score) if !score.is_nan() => Ok((score, pair[1].clone())),
+            _ => Err(Error::InvalidRedisValue(redis_value.clone())),
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok(RedisCommand::ZAdd(key.clone(), options, pairs))
+}
+
+pub fn parse_zrandmember(
+    _name: &str,
+    args: &[RedisValue],
+    redis_value: &RedisValue,
+) -> Result<RedisCommand> {
+    let nb_elements = args.len() + 1;
+    if !(2..=4).contains(&nb_elements) {
+        Err(Error::InvalidRedisValue(redis_value.clone()))
+    } else {
+        let args_as_strings = get_strings_from_bulkstrings(args)
+            .map_err(|_| Error::InvalidRedisValue(redis_value.clone()))?;
+
+        let key = args_as_strings[0].clone();
+        let count = match args_as_strings.get(1) {
+            None => None,
+            Some(count) => {
+                let with_scores = match args_as_strings.get(2) {
+                    None => false,
+                    Some(x) if x.to_lowercase() == "withscores" => true,
+                    Some(_) => Err(Error::InvalidRedisValue(redis_value.clone()))?,
+                };
+                Some((count.parse::<i64>()?, with_scores))
+            }
+        };
+
+        Ok(RedisCommand::ZRandMember(key, count))
+    }
+}
+
+pub fn parse_zscore(
+    name: &str,
+    args: &[RedisValue],
+    redis_value: &RedisValue,
+) -> Result<RedisCommand> {
+    let args_as_strings = get_strings_from_bulkstrings(args)
+        .map_err(|_| Error::InvalidRedisValue(redis_value.clone()))?;
+    match (name, &args_as_strings[..]) {
+        ("zscore", [key, member]) => Ok(RedisCommand::ZScore(key.clone(), member.clone())),
+        ("zrank", [key, member]) => Ok(RedisCommand::ZRank(key.clone(), member.clone())),
+        _ => Err(Error::InvalidRedisValue(redis_value.clone())),
+    }
+}
+
+pub fn parse_zrange(
+    _name: &str,
+    args: &[RedisValue],
+    redis_value: &RedisValue,
+) -> Result<RedisCommand> {
+    let args_as_strings = get_strings_from_bulkstrings(args)
+        .map_err(|_| Error::InvalidRedisValue(redis_value.clone()))?;
+    let (key, start, stop, with_scores) = match &args_as_strings[..] {
+        [key, start, stop] => (key, start, stop, false),
+        [key, start, stop, option] if option.eq_ignore_ascii_case("withscores") => {
+            (key, start, stop, true)
+        }
+        _ => return Err(Error::InvalidRedisValue(redis_value.clone())),
+    };
+    Ok(RedisCommand::ZRange(
+        key.clone(),
+        start.parse()?,
+        stop.parse()?,
+        with_scores,
+    ))
+}
+
+pub fn parse_zrem(
+    _name: &str,
+    args: &[RedisValue],
+    redis_value: &RedisValue,
+) -> Result<RedisCommand> {
+    let args_as_strings = get_strings_from_bulkstrings(args)
+        .map_err(|_| Error::InvalidRedisValue(redis_value.clone()))?;
+    match &args_as_strings[..] {
+        [key, members @ ..] if !members.is_empty() => {
+            Ok(RedisCommand::ZRem(key.clone(), members.to_vec()))
+        }
+        _ => Err(Error::InvalidRedisValue(redis_value.clone())),
+    }
+}
+
+pub fn parse_bitpos(
+    _name: &str,
+    args: &[RedisValue],
+    redis_value: &RedisValue,
+) -> Result<RedisCommand> {
+    let nb_elements = args.len() + 1;
+    if !(3..=6).contains(&nb_elements) {
+        Err(Error::InvalidRedisValue(redis_value.clone()))
+    } else {
+        let args_as_strings = get_strings_from_bulkstrings(args)
+            .map_err(|_| Error::InvalidRedisValue(redis_value.clone()))?;
+
+        let key = args_as_strings[0].clone();
+        let bit = args_as_strings[1].parse::<u8>()?;
+        let start = match args_as_strings.get(2) {
+            None => None,
+            Some(start) => Some(start.parse::<i64>()?),
+        };
+        let end = match args_as_strings.get(3) {
+            None => None,
+            Some(end) => Some(end.parse::<i64>()?),
+        };
+        // Only the BYTE unit is supported
+        if let Some(unit) = args_as_strings.get(4) {
+            if unit.to_lowercase() != "byte" {
+                Err(Error::InvalidRedisValue(redis_value.clone()))?
+            }
+        }
+
+        Ok(RedisCommand::BitPos(key, bit, start, end))
+    }
+}
+
+pub fn parse_bitop(
+    _name: &str,
+    args: &[RedisValue],
+    redis_value: &RedisValue,
+) -> Result<RedisCommand> {
+    let nb_elements = args.len() + 1;
+    if nb_elements < 4 {
+        Err(Error::InvalidRedisValue(redis_value.clone()))
+    } else {
+        let args_as_strings = get_strings_from_bulkstrings(args)
+            .map_err(|_| Error::InvalidRedisValue(redis_value.clone()))?;
+
+        Ok(RedisCommand::BitOp(
+            args_as_strings[0].to_lowercase(),
+            args_as_strings[1].clone(),
+            args_as_strings[2..].to_vec(),
+        ))
+    }
+}
+
+pub fn parse_debug(
+    _name: &str,
+    args: &[RedisValue],
+    redis_value: &RedisValue,
+) -> Result<RedisCommand> {
+    let nb_elements = args.len() + 1;
+    if nb_elements < 2 {
+        Err(Error::InvalidRedisValue(redis_value.clone()))
+    } else {
+        let args_as_strings = get_strings_from_bulkstrings(args)
+            .map_err(|_| Error::InvalidRedisValue(redis_value.clone()))?;
+
+        match args_as_strings[0].to_lowercase().as_ref() {
+            "sleep" => {
+                if nb_elements != 3 {
+                    Err(Error::InvalidRedisValue(redis_value.clone()))?
+                }
+                let seconds = args_as_strings[1].parse::<f64>()?;
+                let duration = Duration::try_from_secs_f64(seconds)
+                    .map_err(|_| Error::InvalidRedisValue(redis_value.clone()))?;
+                Ok(RedisCommand::Debug(DebugSubcommand::Sleep(duration)))
+            }
+            "flushall" if nb_elements == 2 => Ok(RedisCommand::Debug(DebugSubcommand::FlushAll)),
+            "object" if nb_elements == 3 => Ok(RedisCommand::Debug(DebugSubcommand::Object(
+                args_as_strings[1].clone(),
+            ))),
+            "set-active-expire" => {
+                let enabled = match &args_as_strings[1..] {
+                    [flag] if flag == "0" => false,
+                    [flag] if flag == "1" => true,
+                    _ => Err(Error::InvalidRedisValue(redis_value.clone()))?,
+                };
+                Ok(RedisCommand::Debug(DebugSubcommand::SetActiveExpire(
+                    enabled,
+                )))
+            }
+            _ => Ok(RedisCommand::Debug(DebugSubcommand::Unknown(
+                args_as_strings[0].clone(),
+            ))),
+        }
+    }
+}
+
+pub fn parse_multi(
+    _name: &str,
+    args: &[RedisValue],
+    redis_value: &RedisValue,
+) -> Result<RedisCommand> {
+    let nb_elements = args.len() + 1;
+    if nb_elements != 1 {
+        return Err(Error::InvalidRedisValue(redis_value.clone()));
+    }
+    Ok(RedisCommand::Multi)
+}
+
+pub fn parse_exec(
+    _name: &str,
+    args: &[RedisValue],
+    redis_value: &RedisValue,
+) -> Result<RedisCommand> {
+    let nb_elements = args.len() + 1;
+    if nb_elements != 1 {
+        return Err(Error::InvalidRedisValue(redis_value.clone()));
+    }
+    Ok(RedisCommand::Exec)
+}
+
+pub fn parse_watch(
+    _name: &str,
+    args: &[RedisValue],
+    redis_value: &RedisValue,
+) -> Result<RedisCommand> {
+    let args_as_strings = get_strings_from_bulkstrings(args)
+        .map_err(|_| Error::InvalidRedisValue(redis_value.clone()))?;
+    if args_as_strings.is_empty() {
+        return Err(Error::InvalidRedisValue(redis_value.clone()));
+    }
+    Ok(RedisCommand::Watch(args_as_strings))
+}
+
+pub fn parse_unwatch(
+    _name: &str,
+    args: &[RedisValue],
+    redis_value: &RedisValue,
+) -> Result<RedisCommand> {
+    let nb_elements = args.len() + 1;
+    if nb_elements != 1 {
+        return Err(Error::InvalidRedisValue(redis_value.clone()));
+    }
+    Ok(RedisCommand::Unwatch)
+}
+
+pub fn parse_discard(
+    _name: &str,
+    args: &[RedisValue],
+    redis_value: &RedisValue,
+) -> Result<RedisCommand> {
+    let nb_elements = args.len() + 1;
+    if nb_elements != 1 {
+        return Err(Error::InvalidRedisValue(redis_value.clone()));
+    }
+    Ok(RedisCommand::Discard)
+}
+
+impl RedisCommand {
+    /// Whether the command should be forwarded to the other replicas.
+    /// Only commands that write to the underlying db are concerned
+    /// Name of the command in the command table. The match is exhaustive so that new
+    /// commands can't be added without a name.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Ping(_) => "ping",
+            Self::Echo(_) => "echo",
+            Self::Hello(_) => "hello",
+            Self::Client(_) => "client",
+            Self::Set(_, _, _) => "set",
+            Self::Get(_) => "get",
+            Self::GetDel(_) => "getdel",
+            Self::GetEx(_, _) => "getex",
+            Self::Del(_) => "del",
+            Self::MGet(_) => "mget",
+            Self::MSet(_) => "mset",
+            Self::Append(_, _) => "append",
+            Self::SetRange(_, _, _) => "setrange",
+            Self::Strlen(_) => "strlen",
+            Self::GetRange(_, _, _) => "getrange",
+            Self::SetBit(_, _, _) => "setbit",
+            Self::ExpireAt(_, _) => "expireat",
+            Self::PExpireAt(_, _) => "pexpireat",
+            Self::Exists(_) => "exists",
+            Self::DbSize => "dbsize",
+            Self::Save => "save",
+            Self::BgSave => "bgsave",
+            Self::Select(_) => "select",
+            Self::Copy { .. } => "copy",
+            Self::Incr(_) => "incr",
+            Self::Decr(_) => "decr",
+            Self::IncrBy(_, _) => "incrby",
+            Self::DecrBy(_, _) => "decrby",
+            Self::IncrByFloat(_, _) => "incrbyfloat",
+            Self::HIncrBy(_, _, _) => "hincrby",
+            Self::HIncrByFloat(_, _, _) => "hincrbyfloat",
+            Self::Info(_) => "info",
+            Self::ReplConf | Self::ReplConfGetAck => "replconf",
+            Self::Psync => "psync",
+            Self::Wait(_, _) => "wait",
+            Self::ConfigGet(_) | Self::ConfigSet(_) => "config",
+            Self::Cluster(_) => "cluster",
+            Self::Keys(_) => "keys",
+            Self::Scan { .. } => "scan",
+            Self::Type(_) => "type",
+            Self::Command(_) => "command",
+            Self::Xadd { .. } => "xadd",
+            Self::Xdel { .. } => "xdel",
+            Self::Xrange { .. } => "xrange",
+            Self::Xrevrange { .. } => "xrevrange",
+            Self::Xread { .. } => "xread",
+            Self::XGroup(_) => "xgroup",
+            Self::XInfo(_) => "xinfo",
+            Self::XReadGroup { .. } => "xreadgroup",
+            Self::XAck { .. } => "xack",
+            Self::XClaim { .. } => "xclaim",
+            Self::XPending { .. } => "xpending",
+            Self::LPush(_, _) => "lpush",
+            Self::RPush(_, _) => "rpush",
+            Self::LPop(_, _) => "lpop",
+            Self::RPop(_, _) => "rpop",
+            Self::LLen(_) => "llen",
+            Self::LRange(_, _, _) => "lrange",
+            Self::BLPop(_, _) => "blpop",
+            Self::BRPop(_, _) => "brpop",
+            Self::LPos(_, _, _, _) => "lpos",
+            Self::HRandField(_, _) => "hrandfield",
+            Self::HSet(_, _) => "hset",
+            Self::HGet(_, _) => "hget",
+            Self::HDel(_, _) => "hdel",
+            Self::HGetAll(_) => "hgetall",
+            Self::HKeys(_) => "hkeys",
+            Self::HVals(_) => "hvals",
+            Self::HLen(_) => "hlen",
+            Self::HMGet(_, _) => "hmget",
+            Self::HExpire(_, _, _) => "hexpire",
+            Self::HTtl(_, _) => "httl",
+            Self::ZRandMember(_, _) => "zrandmember",
+            Self::ZAdd(_, _, _) => "zadd",
+            Self::ZScore(_, _) => "zscore",
+            Self::ZRank(_, _) => "zrank",
+            Self::ZRange(_, _, _, _) => "zrange",
+            Self::ZRem(_, _) => "zrem",
+            Self::GeoAdd(_, _) => "geoadd",
+            Self::GeoPos(_, _) => "geopos",
+            Self::GeoDist(_, _, _, _) => "geodist",
+            Self::GeoSearch(_, _) => "geosearch",
+            Self::PFAdd(_, _) => "pfadd",
+            Self::PFCount(_) => "pfcount",
+            Self::PFMerge(_, _) => "pfmerge",
+            Self::SAdd(_, _) => "sadd",
+            Self::SRem(_, _) => "srem",
+            Self::SMembers(_) => "smembers",
+            Self::SRandMember(_, _) => "srandmember",
+            Self::SPop(_, _) => "spop",
+            Self::SInter(_) => "sinter",
+            Self::SUnion(_) => "sunion",
+            Self::SDiff(_) => "sdiff",
+            Self::SInterStore(_, _) => "sinterstore",
+            Self::SUnionStore(_, _) => "sunionstore",
+            Self::SDiffStore(_, _) => "sdiffstore",
+            Self::BitPos(_, _, _, _) => "bitpos",
+            Self::BitOp(_, _, _) => "bitop",
+            Self::Debug(_) => "debug",
+            Self::Object(_) => "object",
+            // the help of a container command is one of its subcommands
+            Self::Help(_) => "help",
+            Self::Subscribe(kind, _) => kind.subscribe_name(),
+            Self::Unsubscribe(kind, _) => kind.unsubscribe_name(),
+            Self::PubSub(_) => "pubsub",
+            Self::Publish(_, _) => "publish",
+            Self::SPublish(_, _) => "spublish",
+            Self::Multi => "multi",
+            Self::Exec => "exec",
+            Self::Discard => "discard",
+            Self::Watch(_) => "watch",
+            Self::Unwatch => "unwatch",
+        }
+    }
+
+    pub fn should_forward_to_replicas(&self) -> bool {
+        matches!(
+            self,
+            Self::Set(_, _, _)
+                | Self::GetDel(_)
+                // GETEX only modifies the key when it changes its expiration
+                | Self::GetEx(_, Some(_))
+                | Self::Del(_)
+                | Self::Copy { .. }
+                | Self::HSet(_, _)
+                | Self::HDel(_, _)
+                | Self::HExpire(_, _, _)
+                | Self::ZAdd(_, _, _)
+                | Self::ZRem(_, _)
+                | Self::GeoAdd(_, _)
+                | Self::PFAdd(_, _)
+                | Self::PFMerge(_, _)
+                | Self::MSet(_)
+                | Self::Incr(_)
+                | Self::Decr(_)
+                | Self::IncrBy(_, _)
+                | Self::DecrBy(_, _)
+                | Self::IncrByFloat(_, _)
+                | Self::HIncrBy(_, _, _)
+                | Self::HIncrByFloat(_, _, _)
+                | Self::Append(_, _)
+                | Self::SetRange(_, _, _)
+                | Self::SetBit(_, _, _)
+                | Self::ExpireAt(_, _)
+                | Self::PExpireAt(_, _)
+                | Self::LPush(_, _)
+                | Self::RPush(_, _)
+                | Self::LPop(_, _)
+                | Self::RPop(_, _)
+                // the pops of BLPOP and BRPOP are propagated as LPOP and RPOP
+                | Self::BitOp(_, _, _)
+                | Self::SAdd(_, _)
+                | Self::SRem(_, _)
+                // the members removed by SPOP are propagated as SREM, as the replicas
+                // would pick other ones
+                | Self::SInterStore(_, _)
+                | Self::SUnionStore(_, _)
+                | Self::SDiffStore(_, _)
+                | Self::Xdel { .. }
+                | Self::XGroup(_)
+                | Self::XReadGroup { .. }
+                | Self::XAck { .. }
+                | Self::XClaim { .. }
+        )
+    }
+
+    /// HELLO, WATCH and the pub/sub commands depend on the connection (and the pub/sub ones
+    /// reply with one message per channel), so they are executed separately.
+    /// Returns None for other commands.
+    pub fn execute_for_connection(
+        &self,
+        db: &mut RedisDb,
+        token: Token,
+    ) -> Option<Vec<RedisValue>> {
+        let confirmation = |kind_name: &str, name: Option<&str>, count: usize| {
+            let name = match name {
+                Some(name) => RedisValue::bulkstring_from(name),
+                None => RedisValue::NullBulkString,
+            };
+            RedisValue::Array(
+                3,
+                vec![
+                    RedisValue::bulkstring_from(kind_name),
+                    name,
+                    RedisValue::Integer(count as i64),
+                ],
+            )
+        };
+
+        match self {
+            Self::Client(ClientSubcommand::Id) => Some(vec![RedisValue::Integer(token.0 as i64)]),
+            Self::Watch(keys) => {
+                db.watch(token, keys);
+                Some(vec![RedisValue::SimpleString("OK".to_string())])
+            }
+            Self::Unwatch => {
+                db.unwatch(token);
+                Some(vec![RedisValue::SimpleString("OK".to_string())])
+            }
+            Self::Select(index) => {
+                let reply = match db.select_for_connection(token, *index) {
+                    Ok(()) => RedisValue::SimpleString("OK".to_string()),
+                    Err(e) => RedisValue::SimpleError(e.to_string()),
+                };
+                Some(vec![reply])
+            }
+            Self::Hello(protover) => {
+                let reply = match protover {
+                    Some(protover) if *protover != 2 && *protover != 3 => {
+                        RedisValue::SimpleError("NOPROTO unsupported protocol version".to_string())
+                    }
+                    _ => {
+                        if let Some(protover) = protover {
+                            db.set_protocol(token, *protover);
+                        }
+                        let role = if db.is_replica() { "replica" } else { "master" };
+                        // a map, sent as a flat array of fields and values to RESP2
+                        // connections
+                        let fields = [
+                            ("server", RedisValue::bulkstring_from("redis")),
+                            ("version", RedisValue::bulkstring_from("7.4.0")),
+                            ("proto", RedisValue::Integer(db.protocol(token) as i64)),
+                            ("id", RedisValue::Integer(token.0 as i64)),
+                            ("mode", RedisValue::bulkstring_from("standalone")),
+                            ("role", RedisValue::bulkstring_from(role)),
+                            ("modules", RedisValue::Array(0, vec![])),
+                        ];
+                        RedisValue::Map(
+                            fields
+                                .into_iter()
+                                .map(|(field, value)| (RedisValue::bulkstring_from(field), value))
+                                .collect(),
+                        )
+                    }
+                };
+                Some(vec![reply])
+            }
+            Self::Subscribe(kind, names) => Some(
+                names
+                    .iter()
+                    .map(|name| {
+                        let count = db.pubsub.subscribe(token, *kind, name);
+                        confirmation(kind.subscribe_name(), Some(name), count)
+                    })
+                    .collect(),
+            ),
+            Self::Unsubscribe(kind, names) => {
+                let names = if names.is_empty() {
+                    db.pubsub.subscriptions(token, *kind)
+                } else {
+                    names.clone()
+                };
+                if names.is_empty() {
+                    let count = db.pubsub.subscription_count(token, *kind);
+                    return Some(vec![confirmation(kind.unsubscribe_name(), None, count)]);
+                }
+
+                Some(
+                    names
+                        .iter()
+                        .map(|name| {
+                            let count = db.pubsub.unsubscribe(token, *kind, name);
+                            confirmation(kind.unsubscribe_name(), Some(name), count)
+                        })
+                        .collect(),
+                )
+            }
+            _ => None,
+        }
+    }
+
+    /// Executes command and returns a RedisValue on success
+    pub fn execute(&self, db: &mut RedisDb) -> Result<RedisValue> {
+        match self {
+            Self::Ping(None) => Ok(RedisValue::SimpleString("PONG".to_string())),
+            Self::Ping(Some(message)) => Ok(RedisValue::bulkstring_from(message)),
+            Self::Echo(x) => Ok(RedisValue::SimpleString(x.clone())),
+            Self::Set(key, value, options) => {
+                if let Some(error) = options.incompatibility() {
+                    return Err(error);
+                }
+                let (exists, old_value) = match db.get(key) {
+                    Some(ValueType::String(old_value)) => (true, Some(old_value)),
+                    // without GET, SET overwrites values of any type
+                    Some(_) if options.get => return Err(Error::WrongTypeOperation),
+                    Some(_) => (true, None),
+                    None => (false, None),
+                };
+                let skipped = (options.nx && exists) || (options.xx && !exists);
+                if !skipped {
+                    let value = ValueType::String(value.clone());
+                    if options.keepttl {
+                        db.set_keepttl(key.clone(), value);
+                    } else {
+                        // an absolute time already past leaves a key that is expired right away
+                        let px = options.expiry.and_then(|expiry| expiry.remaining_ms());
+                        db.set(key.clone(), value, px);
+                    }
+                }
+                Ok(if options.get {
+                    old_value.map_or(RedisValue::NullBulkString, |old_value| {
+                        RedisValue::bulkstring_from_bytes(&old_value)
+                    })
+                } else if skipped {
+                    RedisValue::NullBulkString
+                } else {
+                    RedisValue::SimpleString("OK".to_string())
+                })
+            }
+            Self::Get(key) => {
+                let val = db.get(key);
+                match val {
+                    Some(val) => match val {
+                        ValueType::String(val) => Ok(RedisValue::bulkstring_from_bytes(&val)),
+                        _ => Err(Error::WrongTypeOperation),
+                    },
+
+                    None => Ok(RedisValue::NullBulkString),
+                }
+            }
+            Self::GetDel(key) => match db.get(key) {
+                Some(ValueType::String(val)) => {
+                    db.del(std::slice::from_ref(key));
+                    Ok(RedisValue::bulkstring_from_bytes(&val))
+                }
+                Some(_) => Err(Error::WrongTypeOperation),
+                None => Ok(RedisValue::NullBulkString),
+            },
+            Self::GetEx(key, expiry) => match db.get(key) {
+                Some(ValueType::String(val)) => {
+                    if let Some(expiry) = expiry {
+                        if expiry.is_zero() {
+                            return Err(Error::InvalidExpireTime("getex"));
+                        }
+                        // an absolute time already past leaves a key that is expired right away
+                        db.set_expiry(key, expiry.remaining_ms());
+                    }
+                    Ok(RedisValue::bulkstring_from_bytes(&val))
+                }
+                Some(_) => Err(Error::WrongTypeOperation),
+                None => Ok(RedisValue::NullBulkString),
+            },
+            Self::Del(keys) => Ok(RedisValue::Integer(db.del(keys) as i64)),
+            Self::Exists(keys) => Ok(RedisValue::Integer(db.exists(keys))),
+            // missing values are RESP3 nulls, null bulk strings for RESP2 connections
+            Self::MGet(keys) => {
+                let values = keys
+                    .iter()
+                    .map(|key| match db.get(key) {
+                        Some(ValueType::String(val)) => RedisValue::bulkstring_from_bytes(&val),
+                        _ => RedisValue::Null,
+                    })
+                    .collect::<Vec<_>>();
+                Ok(RedisValue::Array(values.len(), values))
+            }
+            Self::MSet(pairs) => {
+                for (key, value) in pairs {
+                    db.set(key.clone(), ValueType::String(value.clone()), None);
+                }
+                Ok(RedisValue::SimpleString("OK".to_string()))
+            }
+            Self::HSet(key, pairs) => Ok(RedisValue::Integer(db.hset(key, pairs)? as i64)),
+            Self::HGet(key, field) => Ok(db
+                .hget(key, field)?
+                .map(|value| RedisValue::bulkstring_from_bytes(&value))
+                .unwrap_or(RedisValue::NullBulkString)),
+            Self::HDel(key, fields) => Ok(RedisValue::Integer(db.hdel(key, fields)? as i64)),
+            Self::HGetAll(key) => {
+                let values = db
+                    .hgetall(key)?
+                    .iter()
+                    .flat_map(|(field, value)| {
+                        [
+                            RedisValue::bulkstring_from(field),
+                            RedisValue::bulkstring_from_bytes(value),
+                        ]
+                    })
+                    .collect::<Vec<_>>();
+                Ok(RedisValue::Array(values.len(), values))
+            }
+            Self::HKeys(key) => {
+                let fields = db
+                    .hgetall(key)?
+                    .iter()
+                    .map(|(field, _)| RedisValue::bulkstring_from(field))
+                    .collect::<Vec<_>>();
+                Ok(RedisValue::Array(fields.len(), fields))
+            }
+            Self::HVals(key) => {
+                let values = db
+                    .hgetall(key)?
+                    .iter()
+                    .map(|(_, value)| RedisValue::bulkstring_from_bytes(value))
+                    .collect::<Vec<_>>();
+                Ok(RedisValue::Array(values.len(), values))
+            }
+            Self::HLen(key) => Ok(RedisValue::Integer(db.hlen(key)? as i64)),
+            Self::HIncrBy(key, field, increment) => {
+                Ok(RedisValue::Integer(db.hincrby(key, field, *increment)?))
+            }
+            Self::HIncrByFloat(key, field, increment) => Ok(RedisValue::bulkstring_from(
+                &format_score(db.hincrbyfloat(key, field, *increment)?),
+            )),
+            Self::HMGet(key, fields) => {
+                let hash = match db.get(key) {
+                    Some(ValueType::Hash(hash)) => Some(hash),
+                    None => None,
+                    Some(_) => return Err(Error::WrongTypeOperation),
+                };
+                let values = fields
+                    .iter()
+                    .map(
+                        |field| match hash.as_ref().and_then(|hash| hash.get(field)) {
+                            Some(value) => RedisValue::bulkstring_from_bytes(value),
+                            None => RedisValue::Null,
+                        },
+                    )
+                    .collect::<Vec<_>>();
+                Ok(RedisValue::Array(values.len(), values))
+            }
+            Self::ExpireAt(key, unix_time) => Ok(RedisValue::Integer(
+                db.expire_at(key, unix_time.saturating_mul(1000)) as i64,
+            )),
+            Self::PExpireAt(key, unix_time_ms) => {
+                Ok(RedisValue::Integer(db.expire_at(key, *unix_time_ms) as i64))
+            }
+            Self::Append(key, value) => Ok(RedisValue::Integer(db.append(key, value)? as i64)),
+            Self::Strlen(key) => match db.strlen(key) {
+                Ok(len) => Ok(RedisValue::Integer(len as i64)),
+                Err(e) => Err(e),
+            },
+            Self::GetRange(key, start, end) => match db.getrange(key, *start, *end) {
+                Ok(bytes) => Ok(RedisValue::bulkstring_from_bytes(&bytes)),
+                Err(e) => Err(e),
+            },
+            Self::SetRange(key, offset, value) => {
+                if *offset < 0 {
+                    return Ok(RedisValue::SimpleError(
+                        "ERR offset is out of range".to_string(),
+                    ));
+                }
+                Ok(RedisValue::Integer(
+                    db.setrange(key, *offset as usize, value)? as i64,
+                ))
+            }
+            Self::SetBit(key, offset, bit) => {
+                if *offset < 0 {
+                    return Ok(RedisValue::SimpleError(
+                        Error::BitOffsetOutOfRange.to_string(),
+                    ));
+                }
+                if *bit != 0 && *bit != 1 {
+                    return Ok(RedisValue::SimpleError(
+                        "ERR bit is not an integer or out of range".to_string(),
+                    ));
+                }
+                let previous = db.setbit(key, *offset as u64, *bit == 1)?;
+                Ok(RedisValue::Integer(previous as i64))
+            }
+            Self::DbSize => Ok(RedisValue::Integer(db.dbsize() as i64)),
+            Self::Save => match db.save() {
+                Ok(()) => Ok(RedisValue::SimpleString("OK".to_string())),
+                Err(e) => Ok(RedisValue::SimpleError(format!("ERR {}", e))),
+            },
+            Self::BgSave => match db.save() {
+                Ok(()) => Ok(RedisValue::SimpleString(
+                    "Background saving started".to_string(),
+                )),
+                Err(e) => Ok(RedisValue::SimpleError(format!("ERR {}", e))),
+            },
+            // Only for the commands executed without connection, such as in transactions
+            Self::Select(index) => {
+                db.select(*index)?;
+                Ok(RedisValue::SimpleString("OK".to_string()))
+            }
+            Self::Copy {
+                source,
+                destination,
+                destination_db,
+                replace,
+            } => Ok(RedisValue::Integer(
+                db.copy(source, destination, *destination_db, *replace)? as i64,
+            )),
+            Self::Incr(key) => Ok(RedisValue::Integer(db.incr(key)?)),
+            Self::Decr(key) => Ok(RedisValue::Integer(db.decr(key)?)),
+            Self::IncrBy(key, increment) => Ok(RedisValue::Integer(db.incrby(key, *increment)?)),
+            Self::DecrBy(key, decrement) => Ok(RedisValue::Integer(db.decrby(key, *decrement)?)),
+            Self::IncrByFloat(key, increment) => Ok(RedisValue::bulkstring_from(&format_score(
+                db.incrbyfloat(key, *increment)?,
+            ))),
+            Self::Info(x) => match x.as_str() {
+                "replication" => {
+                    let answer = db.info.to_string();
+
+                    Ok(RedisValue::VerbatimString("txt".to_string(), answer))
+                }
+                "keyspace" => Ok(RedisValue::VerbatimString(
+                    "txt".to_string(),
+                    db.keyspace_info(),
+                )),
+                _ => Err(Error::InvalidRedisCommand(Box::new(self.clone()))),
+            },
+            Self::ReplConf => Ok(RedisValue::SimpleString("OK".to_string())),
+            Self::ReplConfGetAck => {
+                let answer = format!("REPLCONF ACK {}", db.processed_bytes);
+
+                Ok(RedisValue::array_of_bulkstrings_from(&answer))
+            }
+            Self::Psync => {
+                // The rdb is sent right after this reply, in the same event, so no write can
+                // happen between the offset we report and the dataset the replica receives
+                Ok(RedisValue::SimpleString(format!(
+                    "FULLRESYNC {} {}",
+                    db.info.master_replid, db.info.master_repl_offset
+                )))
+            }
+            Self::Wait(_, _) => {
+                // Wait should not be executed in a standard way
+                // It should instead modify the db state
+                todo!()
+            }
+            Self::ConfigGet(pattern) => {
+                let values = db
+                    .info
+                    .config_get_matching(pattern)
+                    .into_iter()
+                    .flat_map(|(parameter, value)| {
+                        [
+                            RedisValue::bulkstring_from(parameter),
+                            RedisValue::bulkstring_from(&value),
+                        ]
+                    })
+                    .collect::<Vec<_>>();
+                Ok(RedisValue::Array(values.len(), values))
+            }
+            Self::ConfigSet(pairs) => {
+                // the parameters are all set or none is
+                let mut info = db.info.clone();
+                for (parameter, value) in pairs {
+                    info.config_set(parameter, value)?;
+                }
+                db.info = info;
+                Ok(RedisValue::SimpleString("OK".to_string()))
+            }
+            Self::Cluster(subcommand) => match subcommand {
+                ClusterSubcommand::Info => Ok(RedisValue::VerbatimString(
+                    "txt".to_string(),
+                    [
+                        "cluster_enabled:0",
+                        "cluster_state:ok",
+                        "cluster_slots_assigned:0",
+                        "cluster_slots_ok:0",
+                        "cluster_slots_pfail:0",
+                        "cluster_slots_fail:0",
+                        "cluster_known_nodes:1",
+                        "cluster_size:0",
+                        "cluster_current_epoch:0",
+                        "cluster_my_epoch:0",
+                    ]
+                    .iter()
+                    .map(|line| format!("{}\r\n", line))
+                    .collect(),
+                )),
+                // there is no run id, the replication id identifies the server as well
+                ClusterSubcommand::MyId => Ok(RedisValue::bulkstring_from(&db.info.master_replid)),
+                ClusterSubcommand::Slots | ClusterSubcommand::Shards => {
+                    Ok(RedisValue::Array(0, Vec::new()))
+                }
+            },
+            RedisCommand::Keys(pat) => match db.keys(pat) {
+                Ok(keys) => {
+                    let keys = keys
+                        .iter()
+                        .map(|key| RedisValue::bulkstring_from(key))
+                        .collect::<Vec<_>>();
+                    Ok(RedisValue::Array(keys.len(), keys))
+                }
+                Err(e) => Err(e),
+            },
+
+            Self::Type(key) => match db.get(key) {
+                Some(val) => Ok(RedisValue::SimpleString(val.name().to_string())),
+                None => Ok(RedisValue::SimpleString("none".to_string())),
+            },
+            Self::Scan {
+                cursor,
+                pattern,
+                count,
+                type_name,
+            } => {
+                let (next_cursor, keys) =
+                    db.scan(*cursor, pattern.as_deref(), *count, type_name.as_deref());
+                let keys = keys
+                    .iter()
+                    .map(|key| RedisValue::bulkstring_from(key))
+                    .collect::<Vec<_>>();
+                Ok(RedisValue::Array(
+                    2,
+                    vec![
+                        RedisValue::bulkstring_from(&next_cursor.to_string()),
+                        RedisValue::Array(keys.len(), keys),
+                    ],
+                ))
+            }
+
+            Self::Xadd {
+                key,
+                stream_id,
+                store,
+                trim,
+                no_mk_stream,
+            } => {
+                let stream_id =
+                    db.xadd(key, stream_id, store.clone(), trim.as_ref(), *no_mk_stream);
+                match stream_id {
+                    Ok(Some(stream_id)) => Ok(RedisValue::bulkstring_from(&stream_id)),
+                    Ok(None) => Ok(RedisValue::NullBulkString),
+                    Err(Error::InvalidStreamId{should_be_greater_than:_, got}) => match got.as_ref() {
+                        "0-0" => Ok(RedisValue::SimpleError(
+                            "ERR The ID specified in XADD must be greater than 0-0".to_string(),
+                        )),
+                        _ => Ok(RedisValue::SimpleError(
+                            "ERR The ID specified in XADD is equal or smaller than the target stream top item".to_string()
+                        )),
+                    },
+                    Err(_) => Err(Error::InvalidRedisCommand(Box::new(self.clone()))),
+                }
+            }
+            Self::Xdel { key, ids } => Ok(RedisValue::Integer(db.xdel(key, ids)? as i64)),
+            Self::Xrange {
+                key,
+                stream_id_start,
+                stream_id_end,
+            } => {
+                let res = db.xrange(key, stream_id_start, stream_id_end)?;
+                Ok(stream_entries_to_redis_value(&res))
+            }
+            Self::Xrevrange {
+                key,
+                stream_id_end,
+                stream_id_start,
+            } => {
+                let res = db.xrevrange(key, stream_id_end, stream_id_start)?;
+                Ok(stream_entries_to_redis_value(&res))
+            }
+            Self::Xread {
+                block: _,
+                key_offset_pairs,
+            } => {
+                let comb = key_offset_pairs
+                    .iter()
+                    .map(|(key, stream_id_start)| {
+                        let entries = db.xread(key, stream_id_start).unwrap_or_default();
+
+                        if entries.is_empty() {
+                            RedisValue::Array(1, vec![RedisValue::bulkstring_from(key)])
+                        } else {
+                            RedisValue::Array(
+                                2,
+                                vec![
+                                    RedisValue::bulkstring_from(key),
+                                    stream_entries_to_redis_value(&entries),
+                                ],
+                            )
+                        }
+                    })
+                    .collect::<Vec<_>>();
+
+                if comb.iter().all(|el| matches!(el, RedisValue::Array(1, _))) {
+                    Ok(RedisValue::NullBulkString)
+                } else {
+                    Ok(RedisValue::Array(comb.len(), comb))
+                }
+            }
+
+            Self::XGroup(subcommand) => match subcommand {
+                XGroupSubcommand::Create {
+                    key,
+                    group,
+                    stream_id,
+                    mk_stream,
+                } => match db.xgroup_create(key, group, stream_id, *mk_stream) {
+                    Ok(()) => Ok(RedisValue::SimpleString("OK".to_string())),
+                    Err(e) => Err(e),
+                },
+                XGroupSubcommand::Destroy { key, group } => {
+                    Ok(RedisValue::Integer(db.xgroup_destroy(key, group)? as i64))
+                }
+                XGroupSubcommand::CreateConsumer {
+                    key,
+                    group,
+                    consumer,
+                } => match db.xgroup_create_consumer(key, group, consumer) {
+                    Ok(created) => Ok(RedisValue::Integer(created as i64)),
+                    Err(e) => Err(e),
+                },
+            },
+            Self::XInfo(XInfoSubcommand::Stream(key)) => {
+                let info = db.xinfo_stream(key)?;
+                let entry = |entry: Option<(String, HashMap<String, String>)>| match entry {
+                    Some((id, store)) => stream_entry_to_redis_value(&id, &store),
+                    None => RedisValue::Null,
+                };
+                Ok(RedisValue::Map(vec![
+                    (
+                        RedisValue::bulkstring_from("length"),
+                        RedisValue::Integer(info.length as i64),
+                    ),
+                    (
+                        RedisValue::bulkstring_from("last-generated-id"),
+                        RedisValue::bulkstring_from(&info.last_generated_id.to_string()),
+                    ),
+                    (
+                        RedisValue::bulkstring_from("groups"),
+                        RedisValue::Integer(info.groups as i64),
+                    ),
+                    (
+                        RedisValue::bulkstring_from("first-entry"),
+                        entry(info.first_entry),
+                    ),
+                    (
+                        RedisValue::bulkstring_from("last-entry"),
+                        entry(info.last_entry),
+                    ),
+                ]))
+            }
+            Self::XReadGroup {
+                group,
+                consumer,
+                count,
+                no_ack,
+                key_offset_pairs,
+            } => {
+                let mut comb = Vec::new();
+                for (key, stream_id_start) in key_offset_pairs {
+                    let entries =
+                        db.xreadgroup(key, group, consumer, stream_id_start, *count, *no_ack)?;
+                    comb.push((key, stream_id_start, entries));
+                }
+
+                // When only new entries are requested and there are none, the reply is nil
+                if comb.iter().all(|(_, stream_id_start, entries)| {
+                    *stream_id_start == ">" && entries.is_empty()
+                }) {
+                    return Ok(RedisValue::NullBulkString);
+                }
+
+                let comb = comb
+                    .iter()
+                    .map(|(key, _, entries)| {
+                        RedisValue::Array(
+                            2,
+                            vec![
+                                RedisValue::bulkstring_from(key),
+                                stream_entries_to_redis_value(entries),
+                            ],
+                        )
+                    })
+                    .collect::<Vec<_>>();
+                Ok(RedisValue::Array(comb.len(), comb))
+            }
+            Self::XAck {
+                key,
+                group,
+                stream_ids,
+            } => Ok(RedisValue::Integer(db.xack(key, group, stream_ids)? as i64)),
+
+            Self::XClaim {
+                key,
+                group,
+                consumer,
+                stream_ids,
+                options,
+            } => match db.xclaim(key, group, consumer, stream_ids, options) {
+                Ok(claimed) if options.just_id => {
+                    let ids = claimed
+                        .iter()
+                        .map(|(stream_id, _)| RedisValue::bulkstring_from(stream_id))
+                        .collect::<Vec<_>>();
+                    Ok(RedisValue::Array(ids.len(), ids))
+                }
+                Ok(claimed) => Ok(stream_entries_to_redis_value(&claimed)),
+                Err(e) => Err(e),
+            },
+            Self::XPending { key, group, range } => match range {
+                None => db.xpending_summary(key, group).map(|summary| {
+                    if summary.count == 0 {
+                        return RedisValue::Array(
+                            4,
+                            vec![
+                                RedisValue::Integer(0),
+                                RedisValue::NullBulkString,
+                                RedisValue::NullBulkString,
+                                RedisValue::NullBulkString,
+                            ],
+                        );
+                    }
+                    let consumers = summary
+                        .consumers
+                        .iter()
+                        .map(|(consumer, count)| {
+                            RedisValue::Array(
+                                2,
+                                vec![
+                                    RedisValue::bulkstring_from(consumer),
+                                    RedisValue::bulkstring_from(&count.to_string()),
+                                ],
+                            )
+                        })
+                        .collect::<Vec<_>>();
+                    let id_to_redis_value = |stream_id: Option<StreamId>| {
+                        stream_id
+                            .map(|x| RedisValue::bulkstring_from(&x.to_string()))
+                            .unwrap_or(RedisValue::NullBulkString)
+                    };
+                    RedisValue::Array(
+                        4,
+                        vec![
+                            RedisValue::Integer(summary.count as i64),
+                            id_to_redis_value(summary.min_id),
+                            id_to_redis_value(summary.max_id),
+                            RedisValue::Array(consumers.len(), consumers),
+                        ],
+                    )
+                }),
+                Some(range) => db.xpending_entries(key, group, range).map(|entries| {
+                    let entries = entries
+                        .iter()
+                        .map(|(stream_id, consumer, idle, delivery_count)| {
+                            RedisValue::Array(
+                                4,
+                                vec![
+                                    RedisValue::bulkstring_from(stream_id),
+                                    RedisValue::bulkstring_from(consumer),
+                                    RedisValue::Integer(*idle as i64),
+                                    RedisValue::Integer(*delivery_count as i64),
+                                ],
+                            )
+                        })
+                        .collect::<Vec<_>>();
+                    RedisValue::Array(entries.len(), entries)
+                }),
+            },
+
+            Self::LPush(key, values) | Self::RPush(key, values) => {
+                let from_left = matches!(self, Self::LPush(_, _));
+                match db.push(key, values, from_left) {
+                    Ok(len) => Ok(RedisValue::Integer(len as i64)),
+                    Err(e) => Err(e),
+                }
+            }
+            Self::LPop(key, count) | Self::RPop(key, count) => {
+                let from_left = matches!(self, Self::LPop(_, _));
+                match db.pop_count(key, from_left, count.unwrap_or(1)) {
+                    Ok(Some(elements)) if count.is_some() => {
+                        let elements = elements
+                            .iter()
+                            .map(|element| RedisValue::bulkstring_from_bytes(element))
+                            .collect::<Vec<_>>();
+                        Ok(RedisValue::Array(elements.len(), elements))
+                    }
+                    Ok(Some(elements)) => Ok(elements
+                        .first()
+                        .map(|element| RedisValue::bulkstring_from_bytes(element))
+                        .unwrap_or(RedisValue::NullBulkString)),
+                    Ok(None) => Ok(RedisValue::NullBulkString),
+                    Err(e) => Err(e),
+                }
+            }
+            Self::LLen(key) => match db.llen(key) {
+                Ok(len) => Ok(RedisValue::Integer(len as i64)),
+                Err(e) => Err(e),
+            },
+            Self::LRange(key, start, stop) => match db.lrange(key, *start, *stop) {
+                Ok(elements) => {
+                    let elements = elements
+                        .iter()
+                        .map(|element| RedisValue::bulkstring_from_bytes(element))
+                        .collect::<Vec<_>>();
+                    Ok(RedisValue::Array(elements.len(), elements))
+                }
+                Err(e) => Err(e),
+            },
+            // Within a transaction, BLPOP and BRPOP don't block. The blocking case is
+            // handled by the connection handler.
+            Self::BLPop(keys, _) | Self::BRPop(keys, _) => {
+                let from_left = matches!(self, Self::BLPop(_, _));
+                match db.pop_first(keys, from_left) {
+                    Ok(Some((key, element))) => Ok(RedisValue::Array(
+                        2,
+                        vec![
+                            RedisValue::bulkstring_from(&key),
+                            RedisValue::bulkstring_from_bytes(&element),
+                        ],
+                    )),
+                    Ok(None) => Ok(RedisValue::NullBulkString),
+                    Err(e) => Err(e),
+                }
+            }
+            Self::LPos(key, element, rank, count) => {
+                let rank = rank.unwrap_or(1);
+                if rank == 0 {
+                    return Ok(RedisValue::SimpleError(
+                        "ERR RANK can't be zero: use 1 to start from the first match, 2 from the second ... or use negative to start from the end of the list".to_string(),
+                    ));
+                }
+
+                let positions = db.lpos(key, element.as_bytes(), rank, count.unwrap_or(1))?;
+                match count {
+                    None => Ok(positions
+                        .first()
+                        .map(|&pos| RedisValue::Integer(pos as i64))
+                        .unwrap_or(RedisValue::NullBulkString)),
+                    Some(_) => {
+                        let positions = positions
+                            .into_iter()
+                            .map(|pos| RedisValue::Integer(pos as i64))
+                            .collect::<Vec<_>>();
+                        Ok(RedisValue::Array(positions.len(), positions))
+                    }
+                }
+            }
+
+            Self::HExpire(key, seconds, fields) => match db.hexpire(key, *seconds, fields) {
+                Ok(statuses) => Ok(integers_to_redis_value(&statuses)),
+                Err(e) => Err(e),
+            },
+            Self::HTtl(key, fields) => match db.httl(key, fields) {
+                Ok(ttls) => Ok(integers_to_redis_value(&ttls)),
+                Err(e) => Err(e),
+            },
+            Self::HRandField(key, count) => match count {
+                None => {
+                    let picked = db.hrandfield(key, 1)?;
+                    Ok(picked
+                        .first()
+                        .map(|(field, _)| RedisValue::bulkstring_from(field))
+                        .unwrap_or(RedisValue::NullBulkString))
+                }
+                Some((count, with_values)) => {
+                    let picked = db
+                        .hrandfield(key, *count)?
+                        .iter()
+                        .flat_map(|(field, value)| {
+                            if *with_values {
+                                vec![
+                                    RedisValue::bulkstring_from(field),
+                                    RedisValue::bulkstring_from_bytes(value),
+                                ]
+                            } else {
+                                vec![RedisValue::bulkstring_from(field)]
+                            }
+                        })
+                        .collect::<Vec<_>>();
+                    Ok(RedisValue::Array(picked.len(), picked))
+                }
+            },
+
+            Self::ZRandMember(key, count) => match count {
+                None => {
+                    let picked = db.zrandmember(key, 1)?;
+                    Ok(picked
+                        .first()
+                        .map(|(member, _)| RedisValue::bulkstring_from(member))
+                        .unwrap_or(RedisValue::NullBulkString))
+                }
+                Some((count, with_scores)) => {
+                    let picked = db
+                        .zrandmember(key, *count)?
+                        .iter()
+                        .flat_map(|(member, score)| {
+                            if *with_scores {
+                                vec![
+                                    RedisValue::bulkstring_from(member),
+                                    // converted to a bulk string for RESP2 connections
+                                    RedisValue::Double(*score),
+                                ]
+                            } else {
+                                vec![RedisValue::bulkstring_from(member)]
+                            }
+                        })
+                        .collect::<Vec<_>>();
+                    Ok(RedisValue::Array(picked.len(), picked))
+                }
+            },
+
+            Self::ZScore(key, member) => Ok(db
+                .zscore(key, member)?
+                .map(RedisValue::Double)
+                .unwrap_or(RedisValue::NullBulkString)),
+            Self::ZRank(key, member) => Ok(db
+                .zrank(key, member)?
+                .map(|rank| RedisValue::Integer(rank as i64))
+                .unwrap_or(RedisValue::NullBulkString)),
+            Self::ZRange(key, start, stop, with_scores) => {
+                let values = db
+                    .zrange(key, *start, *stop)?
+                    .iter()
+                    .flat_map(|(member, score)| {
+                        if *with_scores {
+                            vec![
+                                RedisValue::bulkstring_from(member),
+                                RedisValue::Double(*score),
+                            ]
+                        } else {
+                            vec![RedisValue::bulkstring_from(member)]
+                        }
+                    })
+                    .collect::<Vec<_>>();
+                Ok(RedisValue::Array(values.len(), values))
+            }
+            Self::ZRem(key, members) => Ok(RedisValue::Integer(db.zrem(key, members)? as i64)),
+            Self::ZAdd(key, options, pairs) => {
+                if let Some(error) = options.incompatibility(pairs.len()) {
+                    return Ok(RedisValue::SimpleError(error.to_string()));
+                }
+                match db.zadd(key, options, pairs) {
+                    // with INCR, the reply is the new score or nil if the flags prevented
+                    // the increment
+                    Ok(outcomes) if options.incr => Ok(match outcomes[0] {
+                        ZAddOutcome::Added(score)
+                        | ZAddOutcome::Updated(score)
+                        | ZAddOutcome::Unchanged(score) => RedisValue::Double(score),
+                        ZAddOutcome::Skipped => RedisValue::NullBulkString,
+                    }),
+                    Ok(outcomes) => {
+                        let count = outcomes
+                            .iter()
+                            .filter(|outcome| match outcome {
+                                ZAddOutcome::Added(_) => true,
+                                ZAddOutcome::Updated(_) => options.ch,
+                                _ => false,
+                            })
+                            .count();
+                        Ok(RedisValue::Integer(count as i64))
+                    }
+                    Err(e) => Err(e),
+                }
+            }
+
+            Self::BitPos(key, bit, start, end) => {
+                if *bit > 1 {
+                    return Ok(RedisValue::SimpleError(
+                        "ERR The bit argument must be 1 or 0.".to_string(),
+                    ));
+                }
+                Ok(RedisValue::Integer(db.bitpos(key, *bit, *start, *end)?))
+            }
+
+            Self::GeoAdd(key, triplets) => {
+                let pairs = triplets
+                    .iter()
+                    .map(|(lon, lat, member)| (geo::encode(*lon, *lat), member.clone()))
+                    .collect::<Vec<_>>();
+                let added = db
+                    .zadd(key, &ZAddOptions::default(), &pairs)?
+                    .iter()
+                    .filter(|outcome| matches!(outcome, ZAddOutcome::Added(_)))
+                    .count();
+                Ok(RedisValue::Integer(added as i64))
+            }
+            Self::GeoPos(key, members) => {
+                let positions = members
+                    .iter()
+                    .map(|member| {
+                        Ok(db
+                            .zscore(key, member)?
+                            .map(|score| position_to_redis_value(geo::decode(score)))
+                            .unwrap_or(RedisValue::Null))
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                Ok(RedisValue::Array(positions.len(), positions))
+            }
+            Self::GeoDist(key, first, second, unit) => {
+                match (db.zscore(key, first)?, db.zscore(key, second)?) {
+                    (Some(first), Some(second)) => {
+                        let distance = geo::distance(geo::decode(first), geo::decode(second));
+                        Ok(RedisValue::bulkstring_from(&format!(
+                            "{:.4}",
+                            distance / unit.meters()
+                        )))
+                    }
+                    _ => Ok(RedisValue::NullBulkString),
+                }
+            }
+            Self::GeoSearch(key, options) => {
+                let members = db.zrange(key, 0, -1)?;
+                if members.is_empty() {
+                    return Ok(RedisValue::Array(0, vec![]));
+                }
+                let center = match &options.origin {
+                    GeoOrigin::LonLat(lon, lat) => (*lon, *lat),
+                    GeoOrigin::Member(member) => {
+                        geo::decode(db.zscore(key, member)?.ok_or(Error::GeoMemberMissing)?)
+                    }
+                };
+                let mut matches =
+                    geo::search(&members, center, options.radius * options.unit.meters());
+                if options.descending {
+                    matches.reverse();
+                }
+                if let Some(count) = options.count {
+                    matches.truncate(count);
+                }
+
+                let values = matches
+                    .iter()
+                    .map(|found| {
+                        let member = RedisValue::bulkstring_from(&found.member);
+                        if !options.with_dist && !options.with_coord {
+                            return member;
+                        }
+                        let mut item = vec![member];
+                        if options.with_dist {
+                            item.push(RedisValue::bulkstring_from(&format!(
+                                "{:.4}",
+                                found.distance / options.unit.meters()
+                            )));
+                        }
+                        if options.with_coord {
+                            item.push(position_to_redis_value(found.position));
+                        }
+                        RedisValue::Array(item.len(), item)
+                    })
+                    .collect::<Vec<_>>();
+                Ok(RedisValue::Array(values.len(), values))
+            }
+            Self::PFAdd(key, elements) => Ok(RedisValue::Integer(db.pfadd(key, elements)? as i64)),
+            Self::PFCount(keys) => Ok(RedisValue::Integer(db.pfcount(keys)? as i64)),
+            Self::PFMerge(destination, keys) => {
+                db.pfmerge(destination, keys)?;
+                Ok(RedisValue::SimpleString("OK".to_string()))
+            }
+            Self::SAdd(key, members) => Ok(RedisValue::Integer(db.sadd(key, members)? as i64)),
+            Self::SRem(key, members) => Ok(RedisValue::Integer(db.srem(key, members)? as i64)),
+            Self::SMembers(key) => Ok(members_to_redis_value(db.smembers(key)?)),
+            Self::SRandMember(key, None) => Ok(db
+                .srandmember(key, 1)?
+                .first()
+                .map(|member| RedisValue::bulkstring_from(member))
+                .unwrap_or(RedisValue::NullBulkString)),
+            Self::SRandMember(key, Some(count)) => {
+                let members = db
+                    .srandmember(key, *count)?
+                    .iter()
+                    .map(|member| RedisValue::bulkstring_from(member))
+                    .collect::<Vec<_>>();
+                Ok(RedisValue::Array(members.len(), members))
+            }
+            Self::SPop(key, None) => Ok(db
+                .spop(key, 1)?
+                .first()
+                .map(|member| RedisValue::bulkstring_from(member))
+                .unwrap_or(RedisValue::NullBulkString)),
+            Self::SPop(key, Some(count)) => {
+                let members = db
+                    .spop(key, *count)?
+                    .iter()
+                    .map(|member| RedisValue::bulkstring_from(member))
+                    .collect::<Vec<_>>();
+                Ok(RedisValue::Array(members.len(), members))
+            }
+            Self::SInter(keys) => Ok(members_to_redis_value(
+                db.set_operation(SetOperation::Inter, keys)?,
+            )),
+            Self::SUnion(keys) => Ok(members_to_redis_value(
+                db.set_operation(SetOperation::Union, keys)?,
+            )),
+            Self::SDiff(keys) => Ok(members_to_redis_value(
+                db.set_operation(SetOperation::Diff, keys)?,
+            )),
+            Self::SInterStore(destination, keys) => Ok(RedisValue::Integer(
+                db.set_operation_store(SetOperation::Inter, destination, keys)? as i64,
+            )),
+            Self::SUnionStore(destination, keys) => Ok(RedisValue::Integer(
+                db.set_operation_store(SetOperation::Union, destination, keys)? as i64,
+            )),
+            Self::SDiffStore(destination, keys) => Ok(RedisValue::Integer(db.set_operation_store(
+                SetOperation::Diff,
+                destination,
+                keys,
+            )? as i64)),
+            Self::BitOp(op, dest, srckeys) => {
+                match op.as_str() {
+                    "and" | "or" | "xor" => {}
+                    "not" => {
+                        if srckeys.len() != 1 {
+                            return Ok(RedisValue::SimpleError(
+                                "ERR BITOP NOT must be called with a single source key."
+                                    .to_string(),
+                            ));
+                        }
+                    }
+                    _ => return Ok(RedisValue::SimpleError("ERR syntax error".to_string())),
+                }
+                Ok(RedisValue::Integer(db.bitop(op, dest, srckeys)? as i64))
+            }
+
+            Self::Command(subcommand) => match subcommand {
+                CommandSubcommand::Info(names) => {
+                    let infos = names
+                        .iter()
+                        .map(|name| match command_table::lookup(name) {
+                            Some(metadata) => command_info_to_redis_value(metadata),
+                            None => RedisValue::NullBulkString,
+                        })
+                        .collect::<Vec<_>>();
+                    Ok(RedisValue::Array(infos.len(), infos))
+                }
+                CommandSubcommand::Docs(names) => {
+                    // as in redis, unknown commands are left out. The reply is a map
+                    // flattened to an array since there is no RESP3 map yet
+                    let metadatas = if names.is_empty() {
+                        command_table::COMMAND_TABLE.iter().collect::<Vec<_>>()
+                    } else {
+                        names
+                            .iter()
+                            .filter_map(|name| command_table::lookup(name))
+                            .collect()
+                    };
+                    let docs = metadatas
+                        .into_iter()
+                        .flat_map(|metadata| {
+                            [
+                                RedisValue::bulkstring_from(metadata.name),
+                                command_docs_to_redis_value(metadata),
+                            ]
+                        })
+                        .collect::<Vec<_>>();
+                    Ok(RedisValue::Array(docs.len(), docs))
+                }
+                CommandSubcommand::List => {
+                    let names = command_table::COMMAND_TABLE
+                        .iter()
+                        .map(|metadata| RedisValue::bulkstring_from(metadata.name))
+                        .collect::<Vec<_>>();
+                    Ok(RedisValue::Array(names.len(), names))
+                }
+                CommandSubcommand::Count => Ok(RedisValue::Integer(
+                    command_table::COMMAND_TABLE.len() as i64,
+                )),
+                CommandSubcommand::GetKeys(args) => {
+                    let keys = command_keys(args)?
+                        .into_iter()
+                        .map(|(key, _)| RedisValue::bulkstring_from(key))
+                        .collect::<Vec<_>>();
+                    Ok(RedisValue::Array(keys.len(), keys))
+                }
+                CommandSubcommand::GetKeysAndFlags(args) => {
+                    let keys = command_keys(args)?
+                        .into_iter()
+                        .map(|(key, flags)| {
+                            let flags = flags
+                                .iter()
+                                .map(|flag| RedisValue::SimpleString(flag.to_string()))
+                                .collect::<Vec<_>>();
+                            RedisValue::Array(
+                                2,
+                                vec![
+                                    RedisValue::bulkstring_from(key),
+                                    RedisValue::Array(flags.len(), flags),
+                                ],
+                            )
+                        })
+                        .collect::<Vec<_>>();
+                    Ok(RedisValue::Array(keys.len(), keys))
+                }
+            },
+            // the messages are deferred replies of the subscribers, written to their
+            // connections by the main loop at the end of the current iteration
+            Self::Publish(channel, message) => {
+                Ok(RedisValue::Integer(db.publish(channel, message) as i64))
+            }
+            Self::SPublish(shard_channel, message) => {
+                // Messages are sent to the subscribers by the main loop, as pushes that
+                // are converted to arrays for RESP2 connections
+                let redis_value = RedisValue::Push(
+                    3,
+                    vec![
+                        RedisValue::bulkstring_from("smessage"),
+                        RedisValue::bulkstring_from(shard_channel),
+                        RedisValue::bulkstring_from(message),
+                    ],
+                );
+                let subscribers = db.pubsub.shard_subscribers(shard_channel);
+                for token in subscribers.iter() {
+                    db.defer_reply(*token, Instant::now(), redis_value.clone());
+                }
+                Ok(RedisValue::Integer(subscribers.len() as i64))
+            }
+            Self::PubSub(subcommand) => match subcommand {
+                PubSubSubcommand::Channels(pattern) => {
+                    let channels = db
+                        .pubsub
+                        .channels(pattern.as_deref())
+                        .iter()
+                        .map(|channel| RedisValue::bulkstring_from(channel))
+                        .collect::<Vec<_>>();
+                    Ok(RedisValue::Array(channels.len(), channels))
+                }
+                PubSubSubcommand::NumSub(channels) => {
+                    let values = channels
+                        .iter()
+                        .flat_map(|channel| {
+                            [
+                                RedisValue::bulkstring_from(channel),
+                                RedisValue::Integer(db.pubsub.numsub(channel) as i64),
+                            ]
+                        })
+                        .collect::<Vec<_>>();
+                    Ok(RedisValue::Array(values.len(), values))
+                }
+                PubSubSubcommand::NumPat => Ok(RedisValue::Integer(db.pubsub.numpat() as i64)),
+            },
+            Self::Debug(subcommand) => debug_dispatch(subcommand, db),
+            Self::Object(ObjectSubcommand::Encoding(key)) => match db.get(key) {
+                Some(value) => Ok(RedisValue::bulkstring_from(
+                    value.encoding(&db.info.encoding_thresholds),
+                )),
+                None => Ok(RedisValue::NullBulkString),
+            },
+
+            Self::Help(container) => {
+                let lines = help_lines(container)
+                    .iter()
+                    .map(|line| RedisValue::SimpleString(line.to_string()))
+                    .collect::<Vec<_>>();
+                Ok(RedisValue::Array(lines.len(), lines))
+            }
+            Self::Hello(_) | Self::Client(_) | Self::Subscribe(_, _) | Self::Unsubscribe(_, _) => {
+                Ok(RedisValue::SimpleError(
+                    "ERR this command is not allowed in this context".to_string(),
+                ))
+            }
+            Self::Multi => {
+                // multi should not be executed in a standard way
+                todo!()
+            }
+            Self::Exec => {
+                // exec should not be executed in a standard way
+                todo!()
+            }
+            Self::Discard => {
+                // discard should not be executed in a standard way
+                todo!()
+            }
+            // WATCH is refused within a transaction, UNWATCH does nothing there
+            Self::Watch(_) => Ok(RedisValue::SimpleError(
+                "ERR WATCH inside MULTI is not allowed".to_string(),
+            )),
+            Self::Unwatch => Ok(RedisValue::SimpleString("OK".to_string())),
+        }
+    }
+}
+
+/// [name, arity, [flags], first_key, last_key, key_step] as returned by COMMAND INFO
+fn command_info_to_redis_value(metadata: &CommandMetadata) -> RedisValue {
+    let flags = metadata
+        .flags
+        .iter()
+        .map(|flag| RedisValue::SimpleString(flag.to_string()))
+        .collect::<Vec<_>>();
+    RedisValue::Array(
+        6,
+        vec![
+            RedisValue::bulkstring_from(metadata.name),
+            RedisValue::Integer(metadata.arity),
+            RedisValue::Array(flags.len(), flags),
+            RedisValue::Integer(metadata.first_key),
+            RedisValue::Integer(metadata.last_key),
+            RedisValue::Integer(metadata.key_step),
+        ],
+    )
+}
+
+/// Keys of a full command, name included, with their access flags, as found by COMMAND
+/// GETKEYS from the key positions of the command table
+fn command_keys(args: &[String]) -> Result<Vec<(&String, &'static [&'static str])>> {
+    let metadata = command_table::lookup(&args[0]).ok_or(Error::InvalidCommandSpecified)?;
+    if !metadata.accepts(args.len()) {
+        return Err(Error::InvalidNumberOfArgumentsSpecified);
+    }
+    let positions = metadata.key_positions(args);
+    if positions.is_empty() {
+        return Err(Error::NoKeyArguments);
+    }
+    Ok(positions
+        .into_iter()
+        .enumerate()
+        .map(|(index, position)| (&args[position], metadata.key_flags(index)))
+        .collect())
+}
+
+/// Converts the documentation of a command to the flattened map
+/// [summary, <summary>, arity, <arity>]
+fn command_docs_to_redis_value(metadata: &CommandMetadata) -> RedisValue {
+    RedisValue::Array(
+        4,
+        vec![
+            RedisValue::bulkstring_from("summary"),
+            RedisValue::bulkstring_from(metadata.summary),
+            RedisValue::bulkstring_from("arity"),
+            RedisValue::Integer(metadata.arity),
+        ],
+    )
+}
+
+/// Converts stream entries to an array of [id, [field, value, ...]]
+fn stream_entries_to_redis_value(entries: &[(String, HashMap<String, String>)]) -> RedisValue {
+    let entries = entries
+        .iter()
+        .map(|(id, store)| stream_entry_to_redis_value(id, store))
+        .collect::<Vec<_>>();
+    RedisValue::Array(entries.len(), entries)
+}
+
+/// Converts a stream entry to [id, [field, value, ...]]
+fn stream_entry_to_redis_value(id: &str, store: &HashMap<String, String>) -> RedisValue {
+    let store = store
+        .iter()
+        .flat_map(|(k, v)| {
+            [
+                RedisValue::bulkstring_from(k),
+                RedisValue::bulkstring_from(v),
+            ]
+        })
+        .collect::<Vec<_>>();
+    RedisValue::Array(
+        2,
+        vec![
+            RedisValue::bulkstring_from(id),
+            RedisValue::Array(store.len(), store),
+        ],
+    )
+}
+
+/// Lines returned by the HELP subcommand of the container commands
+fn help_lines(container: &str) -> &'static [&'static str] {
+    match container {
+        "config" => &[
+            "CONFIG <subcommand> [<arg> [value] [opt] ...]. Subcommands are:",
+            "GET <pattern>",
+            "    Return parameters matching the glob-like <pattern> and their values.",
+            "SET <directive> <value>",
+            "    Set the configuration <directive> to <value>.",
+            "HELP",
+            "    Print this help.",
+        ],
+        "cluster" => &[
+            "CLUSTER <subcommand> [<arg> [value] [opt] ...]. Subcommands are:",
+            "INFO",
+            "    Return information about the cluster.",
+            "MYID",
+            "    Return the node id.",
+            "SHARDS",
+            "    Return information about slot range mappings and the nodes associated with",
+            "    them.",
+            "SLOTS",
+            "    Return information about slots range mappings. Each range is made of:",
+            "    start, end, master and replicas IP addresses, ports and ids",
+            "HELP",
+            "    Print this help.",
+        ],
+        "command" => &[
+            "COMMAND <subcommand> [<arg> [value] [opt] ...]. Subcommands are:",
+            "INFO [<command-name> ...]",
+            "    Return details about multiple Redis commands.",
+            "LIST",
+            "    Return a list of all commands in this Redis server.",
+            "COUNT",
+            "    Return the total number of commands in this Redis server.",
+            "GETKEYS <full-command>",
+            "    Return the keys from a full Redis command.",
+            "GETKEYSANDFLAGS <full-command>",
+            "    Return the keys and the access flags from a full Redis command.",
+            "HELP",
+            "    Print this help.",
+        ],
+        "debug" => &[
+            "DEBUG <subcommand> [<arg> [value] [opt] ...]. Subcommands are:",
+            "SLEEP <seconds>",
+            "    Reply after <seconds>, which can be fractional, without blocking the server.",
+            "FLUSHALL",
+            "    Synchronously remove all the keys of all the databases.",
+            "OBJECT <key>",
+            "    Show low level info about the `key` and associated value.",
+            "SET-ACTIVE-EXPIRE <0|1>",
+            "    Setting it to 0 disables expiring keys in background when they are not",
+            "    accessed (otherwise the Redis behavior). Setting it to 1 reenables back the",
+            "    default.",
+            "HELP",
+            "    Print this help.",
+        ],
+        "object" => &[
+            "OBJECT <subcommand> [<arg> [value] [opt] ...]. Subcommands are:",
+            "ENCODING <key>",
+            "    Return the kind of internal representation used in order to store the value",
+            "    associated with a <key>.",
+            "HELP",
+            "    Print this help.",
+        ],
+        "pubsub" => &[
+            "PUBSUB <subcommand> [<arg> [value] [opt] ...]. Subcommands are:",
+            "CHANNELS [<pattern>]",
+            "    Return the currently active channels matching a <pattern> (default: '*').",
+            "NUMPAT",
+            "    Return number of subscriptions to patterns.",
+            "NUMSUB [<channel> ...]",
+            "    Return the number of subscribers for the specified channels, excluding",
+            "    pattern subscriptions(default: no channels).",
+            "HELP",
+            "    Print this help.",
+        ],
+        "client" => &[
+            "CLIENT <subcommand> [<arg> [value] [opt] ...]. Subcommands are:",
+            "ID",
+            "    Return the ID of the current connection.",
+            "HELP",
+            "    Print this help.",
+        ],
+        "xinfo" => &[
+            "XINFO <subcommand> [<arg> [value] [opt] ...]. Subcommands are:",
+            "STREAM <key>",
+            "    Show information about the stream.",
+            "HELP",
+            "    Print this help.",
+        ],
+        "xgroup" => &[
+            "XGROUP <subcommand> [<arg> [value] [opt] ...]. Subcommands are:",
+            "CREATE <key> <groupname> <id|$> [MKSTREAM]",
+            "    Create a new consumer group. Options are:",
+            "    * MKSTREAM",
+            "      Create the empty stream if it does not exist.",
+            "CREATECONSUMER <key> <groupname> <consumer>",
+            "    Create a new consumer in the specified group.",
+            "DESTROY <key> <groupname>",
+            "    Remove the specified group.",
+            "HELP",
+            "    Print this help.",
+        ],
+        _ => &[],
+    }
+}
+
+/// Executes the DEBUG subcommands
+fn debug_dispatch(subcommand: &DebugSubcommand, db: &mut RedisDb) -> Result<RedisValue> {
+    match subcommand {
+        // The deferred reply is set up in handle_connection. If we end up here (for
+        // instance inside a transaction), we can not defer so we answer directly
+        DebugSubcommand::Sleep(_) => Ok(RedisValue::SimpleString("OK".to_string())),
+        DebugSubcommand::SetActiveExpire(enabled) => {
+            db.info.active_expire = *enabled;
+            Ok(RedisValue::SimpleString("OK".to_string()))
+        }
+        DebugSubcommand::FlushAll => {
+            db.flushall();
+            Ok(RedisValue::SimpleString("OK".to_string()))
+        }
+        DebugSubcommand::Object(key) => match db.get(key) {
+            Some(value) => Ok(RedisValue::SimpleString(debug_object(
+                &value,
+                &db.info.encoding_thresholds,
+            ))),
+            None => Ok(RedisValue::SimpleError("ERR no such key".to_string())),
+        },
+        // Conformance suites probe many DEBUG subcommands, they can be
+        // acknowledged without doing anything
+        DebugSubcommand::Unknown(_) if db.info.debug_lenient => {
+            Ok(RedisValue::SimpleString("OK".to_string()))
+        }
+        DebugSubcommand::Unknown(subcommand) => Ok(RedisValue::SimpleError(format!(
+            "ERR unknown subcommand '{}'. Try DEBUG HELP.",
+            subcommand
+        ))),
+    }
+}
+
+/// Same fields as the DEBUG OBJECT of redis. Conformance suites parse them, in particular
+/// the quicklist fields of lists, but the values are only approximations: there is no
+/// allocation address, no LRU and a list is a single node.
+fn debug_object(value: &ValueType, thresholds: &EncodingThresholds) -> String {
+    let serialized_length = match value {
+        ValueType::String(val) => val.len(),
+        ValueType::List(list) => list.iter().map(|element| element.len()).sum(),
+        _ => 0,
+    };
+    let mut debug = format!(
+        "Value at:0x0 refcount:1 encoding:{} serializedlength:{} lru:0 lru_seconds_idle:0",
+        value.encoding(thresholds),
+        serialized_length
+    );
+    if let ValueType::List(list) = value {
+        debug += &format!(
+            " ql_nodes:1 ql_avg_node:{:.2} ql_listpack_max:-2 ql_compressed:0 ql_uncompressed_size:{}",
+            list.len() as f64,
+            serialized_length
+        );
+    }
+    debug
+}
+
+/// Parses the `FIELDS numfields field [field ...]` arguments of the hash field
+/// expiration commands
+fn parse_fields_argument(args: &[String]) -> Option<Vec<String>> {
+    match args {
+        [fields, numfields, rest @ ..] if fields.eq_ignore_ascii_case("fields") => {
+            let numfields = numfields.parse::<usize>().ok()?;
+            (numfields > 0 && numfields == rest.len()).then(|| rest.to_vec())
+        }
+        _ => None,
+    }
+}
+
+fn integers_to_redis_value(values: &[i64]) -> RedisValue {
+    let values = values
+        .iter()
+        .map(|&x| RedisValue::Integer(x))
+        .collect::<Vec<_>>();
+    RedisValue::Array(values.len(), values)
+}
+
+/// Longitude and latitude, as returned by GEOPOS and GEOSEARCH WITHCOORD
+fn position_to_redis_value((lon, lat): (f64, f64)) -> RedisValue {
+    RedisValue::Array(
+        2,
+        vec![
+            RedisValue::bulkstring_from(&lon.to_string()),
+            RedisValue::bulkstring_from(&lat.to_string()),
+        ],
+    )
+}
+
+/// Array of the members of a set, in no particular order
+fn members_to_redis_value(members: HashSet<String>) -> RedisValue {
+    let members = members
+        .iter()
+        .map(|member| RedisValue::bulkstring_from(member))
+        .collect::<Vec<_>>();
+    RedisValue::Array(members.len(), members)
+}
+
+pub fn get_strings_from_bulkstrings(args: &[RedisValue]) -> Result<Vec<String>> {
+    args.iter()
+        .map(|el| {
+            if let RedisValue::BulkString(_, val) = el {
+                Ok(lossy_string(val))
+            } else {
+                Err(Error::InvalidRedisValue(el.clone()))
+            }
+        })
         // NOTE: transforms a vec of result into result of vec
         .collect::<Result<Vec<_>>>()
 }
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashSet, VecDeque};
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    use super::*;
+    use crate::clock::MockClock;
+    use crate::db::{ConnectionState, DbInfo};
+    use crate::hyperloglog::HyperLogLog;
+    use crate::rdb::Rdb;
+    use crate::sorted_set::SortedSet;
+
+    fn setup_db() -> RedisDb {
+        let db_info = DbInfo::build("master", 6379, "/tmp/redis-files", "dump.rdb");
+        RedisDb::build(db_info, ConnectionState::Ready)
+    }
+
+    fn execute(db: &mut RedisDb, input: &str) -> Result<RedisValue> {
+        let redis_value = RedisValue::array_of_bulkstrings_from(input);
+        db.execute_command(&RedisCommand::try_from(&redis_value)?)
+    }
+
+    fn integers(values: &[i64]) -> RedisValue {
+        let values = values
+            .iter()
+            .map(|&x| RedisValue::Integer(x))
+            .collect::<Vec<_>>();
+        RedisValue::Array(values.len(), values)
+    }
+
+    #[test]
+    fn test_push_pop() -> Result<()> {
+        let mut db = setup_db();
+        assert_eq!(execute(&mut db, "LPUSH l a b")?, RedisValue::Integer(2));
+        assert_eq!(execute(&mut db, "RPUSH l c")?, RedisValue::Integer(3));
+        assert_eq!(
+            execute(&mut db, "LPOP l")?,
+            RedisValue::bulkstring_from("b")
+        );
+        assert_eq!(
+            execute(&mut db, "RPOP l")?,
+            RedisValue::bulkstring_from("c")
+        );
+        // BLPOP doesn't block when executed in a transaction
+        assert_eq!(
+            execute(&mut db, "BLPOP other l 0")?,
+            RedisValue::array_of_bulkstrings_from("l a")
+        );
+        assert_eq!(execute(&mut db, "BRPOP l 0.5")?, RedisValue::NullBulkString);
+        assert_eq!(
+            execute(&mut db, "TYPE l")?,
+            RedisValue::SimpleString("none".into())
+        );
+
+        execute(&mut db, "SET s a")?;
+        assert!(matches!(
+            execute(&mut db, "LPUSH s a")?,
+            RedisValue::SimpleError(e) if e.starts_with("WRONGTYPE")
+        ));
+        assert!(execute(&mut db, "BLPOP l -1").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_pop_count() -> Result<()> {
+        let mut db = setup_db();
+        execute(&mut db, "RPUSH l a b c d e")?;
+        assert_eq!(
+            execute(&mut db, "LPOP l 2")?,
+            RedisValue::array_of_bulkstrings_from("a b")
+        );
+        assert_eq!(
+            execute(&mut db, "RPOP l 2")?,
+            RedisValue::array_of_bulkstrings_from("e d")
+        );
+        assert_eq!(execute(&mut db, "LPOP l 0")?, RedisValue::Array(0, vec![]));
+        assert_eq!(
+            execute(&mut db, "RPOP l 10")?,
+            RedisValue::array_of_bulkstrings_from("c")
+        );
+        assert_eq!(execute(&mut db, "EXISTS l")?, RedisValue::Integer(0));
+        assert_eq!(execute(&mut db, "LPOP l 2")?, RedisValue::NullBulkString);
+        assert!(execute(&mut db, "LPOP l -1").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_llen_lrange() -> Result<()> {
+        let mut db = setup_db();
+        assert_eq!(execute(&mut db, "LLEN l")?, RedisValue::Integer(0));
+        assert_eq!(
+            execute(&mut db, "LRANGE l 0 -1")?,
+            RedisValue::Array(0, vec![])
+        );
+
+        execute(&mut db, "RPUSH l a b c d")?;
+        assert_eq!(execute(&mut db, "LLEN l")?, RedisValue::Integer(4));
+        assert_eq!(
+            execute(&mut db, "LRANGE l 0 -1")?,
+            RedisValue::array_of_bulkstrings_from("a b c d")
+        );
+        assert_eq!(
+            execute(&mut db, "LRANGE l 1 2")?,
+            RedisValue::array_of_bulkstrings_from("b c")
+        );
+        assert_eq!(
+            execute(&mut db, "LRANGE l -3 -2")?,
+            RedisValue::array_of_bulkstrings_from("b c")
+        );
+        assert_eq!(
+            execute(&mut db, "LRANGE l -100 100")?,
+            RedisValue::array_of_bulkstrings_from("a b c d")
+        );
+        assert_eq!(
+            execute(&mut db, "LRANGE l 3 1")?,
+            RedisValue::Array(0, vec![])
+        );
+        assert_eq!(
+            execute(&mut db, "LRANGE l 5 10")?,
+            RedisValue::Array(0, vec![])
+        );
+
+        execute(&mut db, "SET s a")?;
+        for command in ["LLEN s", "LRANGE s 0 -1", "LPOP s 2"] {
+            assert!(matches!(
+                execute(&mut db, command)?,
+                RedisValue::SimpleError(e) if e.starts_with("WRONGTYPE")
+            ));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_push_order() -> Result<()> {
+        let mut db = setup_db();
+        let list = |db: &mut RedisDb, key: &str| match db.get(key) {
+            Some(ValueType::List(list)) => list
+                .into_iter()
+                .map(|element| String::from_utf8(element).unwrap())
+                .collect::<Vec<_>>(),
+            _ => panic!("{} should be a list", key),
+        };
+
+        // each value is pushed to the head in turn
+        execute(&mut db, "LPUSH l a b c")?;
+        assert_eq!(list(&mut db, "l"), ["c", "b", "a"]);
+        execute(&mut db, "LPUSH l d e")?;
+        assert_eq!(list(&mut db, "l"), ["e", "d", "c", "b", "a"]);
+
+        execute(&mut db, "RPUSH r a b c")?;
+        assert_eq!(list(&mut db, "r"), ["a", "b", "c"]);
+        execute(&mut db, "RPUSH r d e")?;
+        assert_eq!(list(&mut db, "r"), ["a", "b", "c", "d", "e"]);
+
+        execute(&mut db, "LPUSH r x y")?;
+        assert_eq!(list(&mut db, "r"), ["y", "x", "a", "b", "c", "d", "e"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_lpos() -> Result<()> {
+        let mut db = setup_db();
+        let list = "a b c 1 2 3 c c"
+            .split_whitespace()
+            .map(|el| el.as_bytes().to_vec());
+        db.set(
+            "mylist".to_string(),
+            ValueType::List(VecDeque::from_iter(list)),
+            None,
+        );
+
+        assert_eq!(execute(&mut db, "LPOS mylist c")?, RedisValue::Integer(2));
+        assert_eq!(
+            execute(&mut db, "LPOS mylist c RANK 2")?,
+            RedisValue::Integer(6)
+        );
+        assert_eq!(
+            execute(&mut db, "LPOS mylist z")?,
+            RedisValue::NullBulkString
+        );
+        assert_eq!(
+            execute(&mut db, "LPOS missing c")?,
+            RedisValue::NullBulkString
+        );
+        assert_eq!(execute(&mut db, "LPOS missing c COUNT 2")?, integers(&[]));
+        assert!(matches!(
+            execute(&mut db, "LPOS mylist c RANK 0")?,
+            RedisValue::SimpleError(_)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_lpos_negative_rank() -> Result<()> {
+        let mut db = setup_db();
+        let list = "a b c 1 2 3 c c"
+            .split_whitespace()
+            .map(|el| el.as_bytes().to_vec());
+        db.set(
+            "mylist".to_string(),
+            ValueType::List(VecDeque::from_iter(list)),
+            None,
+        );
+
+        assert_eq!(
+            execute(&mut db, "LPOS mylist c RANK -1")?,
+            RedisValue::Integer(7)
+        );
+        assert_eq!(
+            execute(&mut db, "LPOS mylist c RANK -3")?,
+            RedisValue::Integer(2)
+        );
+        assert_eq!(
+            execute(&mut db, "LPOS mylist c RANK -4")?,
+            RedisValue::NullBulkString
+        );
+        assert_eq!(
+            execute(&mut db, "LPOS mylist c RANK -1 COUNT 2")?,
+            integers(&[7, 6])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_lpos_count_zero() -> Result<()> {
+        let mut db = setup_db();
+        let list = "a b c 1 2 3 c c"
+            .split_whitespace()
+            .map(|el| el.as_bytes().to_vec());
+        db.set(
+            "mylist".to_string(),
+            ValueType::List(VecDeque::from_iter(list)),
+            None,
+        );
+
+        assert_eq!(
+            execute(&mut db, "LPOS mylist c COUNT 0")?,
+            integers(&[2, 6, 7])
+        );
+        assert_eq!(
+            execute(&mut db, "LPOS mylist c RANK 2 COUNT 0")?,
+            integers(&[6, 7])
+        );
+        assert_eq!(
+            execute(&mut db, "LPOS mylist c RANK -1 COUNT 0")?,
+            integers(&[7, 6, 2])
+        );
+        Ok(())
+    }
+
+    fn setup_hash(db: &RedisDb) {
+        let hash = HashMap::from([
+            ("f1".to_string(), b"v1".to_vec()),
+            ("f2".to_string(), b"v2".to_vec()),
+            ("f3".to_string(), b"v3".to_vec()),
+        ]);
+        db.set(
+            "myhash".to_string(),
+            ValueType::Hash(hash.into_iter().collect()),
+            None,
+        );
+    }
+
+    fn bulkstrings(redis_value: RedisValue) -> Vec<String> {
+        match redis_value {
+            RedisValue::Array(_, values) | RedisValue::Push(_, values) => {
+                values.iter().map(|x| x.inner_string().unwrap()).collect()
+            }
+            _ => panic!("Expected an array"),
+        }
+    }
+
+    #[test]
+    fn test_mget_nil_encoding() -> Result<()> {
+        let mut db = setup_db();
+        execute(&mut db, "SET a 1")?;
+        let reply = execute(&mut db, "MGET a missing")?;
+        assert_eq!(
+            db.encode_for(Token(20), reply.clone()).to_bytes(),
+            b"*2\r\n$1\r\n1\r\n$-1\r\n"
+        );
+        db.set_protocol(Token(20), 3);
+        assert_eq!(
+            db.encode_for(Token(20), reply).to_bytes(),
+            b"*2\r\n$1\r\n1\r\n_\r\n"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_mset() -> Result<()> {
+        let mut db = setup_db();
+        setup_hash(&db);
+        assert_eq!(
+            execute(&mut db, "MSET a 1 b 2 myhash 3")?,
+            RedisValue::SimpleString("OK".to_string())
+        );
+        // MGET never fails on the type of a key
+        execute(&mut db, "LPUSH l x")?;
+        assert_eq!(
+            execute(&mut db, "MGET a b myhash l missing")?,
+            RedisValue::Array(
+                5,
+                vec![
+                    RedisValue::bulkstring_from("1"),
+                    RedisValue::bulkstring_from("2"),
+                    RedisValue::bulkstring_from("3"),
+                    RedisValue::Null,
+                    RedisValue::Null,
+                ]
+            )
+        );
+
+        let parse =
+            |input: &str| RedisCommand::try_from(&RedisValue::array_of_bulkstrings_from(input));
+        assert!(parse("MSET a 1 b").is_err());
+        assert!(parse("MSET a 1 b 2")?.should_forward_to_replicas());
+        Ok(())
+    }
+
+    #[test]
+    fn test_hash_commands() -> Result<()> {
+        let mut db = setup_db();
+        assert_eq!(
+            execute(&mut db, "HSET h f1 v1 f2 v2")?,
+            RedisValue::Integer(2)
+        );
+        // only the new fields are counted
+        assert_eq!(
+            execute(&mut db, "HSET h f2 x f3 v3")?,
+            RedisValue::Integer(1)
+        );
+        assert_eq!(
+            execute(&mut db, "TYPE h")?,
+            RedisValue::SimpleString("hash".into())
+        );
+        assert_eq!(
+            execute(&mut db, "HGET h f2")?,
+            RedisValue::bulkstring_from("x")
+        );
+        assert_eq!(execute(&mut db, "HGET h f4")?, RedisValue::NullBulkString);
+        assert_eq!(
+            execute(&mut db, "HGET missing f1")?,
+            RedisValue::NullBulkString
+        );
+        assert_eq!(execute(&mut db, "HLEN h")?, RedisValue::Integer(3));
+
+        let mut pairs = bulkstrings(execute(&mut db, "HGETALL h")?)
+            .chunks(2)
+            .map(|pair| (pair[0].clone(), pair[1].clone()))
+            .collect::<Vec<_>>();
+        pairs.sort();
+        assert_eq!(
+            pairs,
+            [("f1", "v1"), ("f2", "x"), ("f3", "v3")]
+                .map(|(field, value)| (field.to_string(), value.to_string()))
+        );
+        let mut fields = bulkstrings(execute(&mut db, "HKEYS h")?);
+        fields.sort();
+        assert_eq!(fields, ["f1", "f2", "f3"]);
+        let mut values = bulkstrings(execute(&mut db, "HVALS h")?);
+        values.sort();
+        assert_eq!(values, ["v1", "v3", "x"]);
+
+        assert_eq!(execute(&mut db, "HDEL h f1 f4")?, RedisValue::Integer(1));
+        assert_eq!(execute(&mut db, "HDEL h f2 f3")?, RedisValue::Integer(2));
+        assert_eq!(execute(&mut db, "EXISTS h")?, RedisValue::Integer(0));
+        assert_eq!(execute(&mut db, "HGETALL h")?, RedisValue::Array(0, vec![]));
+        assert_eq!(execute(&mut db, "HLEN h")?, RedisValue::Integer(0));
+
+        execute(&mut db, "SET s a")?;
+        for command in ["HSET s f v", "HGET s f", "HDEL s f", "HGETALL s", "HLEN s"] {
+            assert!(matches!(
+                execute(&mut db, command)?,
+                RedisValue::SimpleError(e) if e.starts_with("WRONGTYPE")
+            ));
+        }
+        assert!(execute(&mut db, "HSET h f").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_hincrby() -> Result<()> {
+        let mut db = setup_db();
+        assert_eq!(execute(&mut db, "HINCRBY h f 5")?, RedisValue::Integer(5));
+        assert_eq!(execute(&mut db, "HINCRBY h f -7")?, RedisValue::Integer(-2));
+        assert_eq!(
+            execute(&mut db, "HGET h f")?,
+            RedisValue::bulkstring_from("-2")
+        );
+        assert_eq!(
+            execute(&mut db, "HINCRBYFLOAT h g 10.5")?,
+            RedisValue::bulkstring_from("10.5")
+        );
+        assert_eq!(
+            execute(&mut db, "HINCRBYFLOAT h f 0.5")?,
+            RedisValue::bulkstring_from("-1.5")
+        );
+
+        // the expiration of the field is kept
+        execute(&mut db, "HEXPIRE h 100 FIELDS 1 g")?;
+        execute(&mut db, "HINCRBYFLOAT h g 1")?;
+        assert_eq!(execute(&mut db, "HTTL h FIELDS 1 g")?, integers(&[100]));
+
+        execute(&mut db, "HSET h s abc")?;
+        assert_eq!(
+            execute(&mut db, "HINCRBY h s 1")?,
+            RedisValue::SimpleError(Error::NotAnInteger.to_string())
+        );
+        assert_eq!(
+            execute(&mut db, "HINCRBY h f 1")?,
+            RedisValue::SimpleError(Error::NotAnInteger.to_string())
+        );
+        assert_eq!(
+            execute(&mut db, "HINCRBYFLOAT h s 1")?,
+            RedisValue::SimpleError(Error::NotAFloat.to_string())
+        );
+        execute(&mut db, "HSET h max 9223372036854775807")?;
+        assert_eq!(
+            execute(&mut db, "HINCRBY h max 1")?,
+            RedisValue::SimpleError(Error::IncrOverflow.to_string())
+        );
+
+        execute(&mut db, "SET s 1")?;
+        assert!(matches!(
+            execute(&mut db, "HINCRBY s f 1")?,
+            RedisValue::SimpleError(e) if e.starts_with("WRONGTYPE")
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_sorted_set_commands() -> Result<()> {
+        let mut db = setup_db();
+        assert_eq!(
+            execute(&mut db, "ZADD z 2 b 1 a 3 c 2 ab")?,
+            RedisValue::Integer(4)
+        );
+        assert_eq!(execute(&mut db, "ZSCORE z b")?, RedisValue::Double(2.0));
+        assert_eq!(execute(&mut db, "ZSCORE z d")?, RedisValue::NullBulkString);
+        assert_eq!(execute(&mut db, "ZRANK z b")?, RedisValue::Integer(2));
+        assert_eq!(execute(&mut db, "ZRANK y b")?, RedisValue::NullBulkString);
+        assert_eq!(
+            execute(&mut db, "ZRANGE z 1 -2")?,
+            RedisValue::array_of_bulkstrings_from("ab b")
+        );
+        assert_eq!(
+            execute(&mut db, "ZRANGE z -1 10 WITHSCORES")?,
+            RedisValue::Array(
+                2,
+                vec![RedisValue::bulkstring_from("c"), RedisValue::Double(3.0)]
+            )
+        );
+        assert_eq!(
+            execute(&mut db, "ZRANGE z 3 1")?,
+            RedisValue::Array(0, vec![])
+        );
+
+        assert_eq!(execute(&mut db, "ZREM z a d c")?, RedisValue::Integer(2));
+        assert_eq!(execute(&mut db, "ZRANK z b")?, RedisValue::Integer(1));
+        assert_eq!(execute(&mut db, "ZREM z ab b")?, RedisValue::Integer(2));
+        assert_eq!(execute(&mut db, "EXISTS z")?, RedisValue::Integer(0));
+
+        execute(&mut db, "SET s a")?;
+        assert!(matches!(
+            execute(&mut db, "ZSCORE s a")?,
+            RedisValue::SimpleError(e) if e.starts_with("WRONGTYPE")
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_geo_commands() -> Result<()> {
+        let mut db = setup_db();
+        assert_eq!(
+            execute(
+                &mut db,
+                "GEOADD Sicily 13.361389 38.115556 Palermo 15.087269 37.502669 Catania"
+            )?,
+            RedisValue::Integer(2)
+        );
+        assert_eq!(
+            execute(&mut db, "TYPE Sicily")?,
+            RedisValue::SimpleString("zset".to_string())
+        );
+        assert_eq!(
+            execute(&mut db, "GEODIST Sicily Palermo Catania")?,
+            RedisValue::bulkstring_from("166274.1516")
+        );
+        assert_eq!(
+            execute(&mut db, "GEODIST Sicily Palermo Catania KM")?,
+            RedisValue::bulkstring_from("166.2742")
+        );
+        assert_eq!(
+            execute(&mut db, "GEODIST Sicily Palermo Rome")?,
+            RedisValue::NullBulkString
+        );
+        let RedisValue::Array(2, positions) = execute(&mut db, "GEOPOS Sicily Palermo Rome")?
+        else {
+            panic!("GEOPOS should return one position per member");
+        };
+        assert_eq!(
+            positions,
+            vec![
+                RedisValue::array_of_bulkstrings_from("13.361389338970184 38.1155563954963"),
+                RedisValue::Null
+            ]
+        );
+
+        assert_eq!(
+            execute(
+                &mut db,
+                "GEOSEARCH Sicily FROMLONLAT 15 37 BYRADIUS 200 km ASC"
+            )?,
+            RedisValue::array_of_bulkstrings_from("Catania Palermo")
+        );
+        assert_eq!(
+            execute(&mut db, "GEOSEARCH Sicily FROMLONLAT 15 37 BYRADIUS 100 km")?,
+            RedisValue::array_of_bulkstrings_from("Catania")
+        );
+        assert_eq!(
+            execute(
+                &mut db,
+                "GEOSEARCH Sicily FROMMEMBER Palermo BYRADIUS 200 km DESC COUNT 1 WITHDIST"
+            )?,
+            RedisValue::Array(
+                1,
+                vec![RedisValue::array_of_bulkstrings_from("Catania 166.2742")]
+            )
+        );
+        assert_eq!(
+            execute(&mut db, "GEOSEARCH Sicily FROMMEMBER Rome BYRADIUS 200 km")?,
+            RedisValue::SimpleError("ERR could not decode requested zset member".to_string())
+        );
+        assert_eq!(
+            execute(&mut db, "GEOSEARCH Italy FROMMEMBER Rome BYRADIUS 200 km")?,
+            RedisValue::Array(0, vec![])
+        );
+
+        // rejected when parsing
+        let parse_error = |command: &str| {
+            RedisCommand::try_from(&RedisValue::array_of_bulkstrings_from(command))
+                .err()
+                .map(|e| e.to_string())
+        };
+        assert_eq!(
+            parse_error("GEOADD Sicily 1 100 a").as_deref(),
+            Some("ERR invalid longitude,latitude pair 1.000000,100.000000")
+        );
+        assert_eq!(
+            parse_error("GEODIST Sicily Palermo Catania yd").as_deref(),
+            Some("ERR unsupported unit provided. please use M, KM, FT, MI")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_hyperloglog_commands() -> Result<()> {
+        let mut db = setup_db();
+        assert_eq!(execute(&mut db, "PFADD h1")?, RedisValue::Integer(1));
+        assert_eq!(execute(&mut db, "PFADD h1")?, RedisValue::Integer(0));
+        assert_eq!(
+            execute(&mut db, "PFADD h1 a b c d")?,
+            RedisValue::Integer(1)
+        );
+        assert_eq!(execute(&mut db, "PFADD h1 a b")?, RedisValue::Integer(0));
+        assert_eq!(execute(&mut db, "PFCOUNT h1")?, RedisValue::Integer(4));
+        assert_eq!(execute(&mut db, "PFADD h2 c d e")?, RedisValue::Integer(1));
+        assert_eq!(
+            execute(&mut db, "PFCOUNT h1 h2 h3")?,
+            RedisValue::Integer(5)
+        );
+
+        assert_eq!(
+            execute(&mut db, "PFMERGE h3 h1 h2")?,
+            RedisValue::SimpleString("OK".to_string())
+        );
+        assert_eq!(execute(&mut db, "PFCOUNT h3")?, RedisValue::Integer(5));
+        assert_eq!(
+            execute(&mut db, "TYPE h3")?,
+            RedisValue::SimpleString("string".to_string())
+        );
+
+        execute(&mut db, "SET s a")?;
+        assert_eq!(
+            execute(&mut db, "PFADD s a")?,
+            RedisValue::SimpleError(
+                "WRONGTYPE Key is not a valid HyperLogLog string value.".to_string()
+            )
+        );
+        // a dense register with a value that can't be reached by PFADD
+        let mut corrupted = HyperLogLog::new().to_bytes();
+        corrupted[20] = 0xff;
+        db.set("corrupted".to_string(), ValueType::String(corrupted), None);
+        assert_eq!(
+            execute(&mut db, "PFCOUNT corrupted")?,
+            RedisValue::SimpleError(
+                "WRONGTYPE Key is not a valid HyperLogLog string value.".to_string()
+            )
+        );
+        execute(&mut db, "SADD set a")?;
+        assert!(matches!(
+            execute(&mut db, "PFCOUNT set")?,
+            RedisValue::SimpleError(e) if e.starts_with("WRONGTYPE Operation")
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_set_algebra() -> Result<()> {
+        let mut db = setup_db();
+        let members = |db: &mut RedisDb, command: &str| -> Result<Vec<String>> {
+            let mut members = bulkstrings(execute(db, command)?);
+            members.sort();
+            Ok(members)
+        };
+
+        assert_eq!(execute(&mut db, "SADD s1 a b c d")?, RedisValue::Integer(4));
+        assert_eq!(execute(&mut db, "SADD s1 a e")?, RedisValue::Integer(1));
+        execute(&mut db, "SADD s2 c d f")?;
+        execute(&mut db, "SADD s3 d")?;
+        assert_eq!(
+            execute(&mut db, "TYPE s1")?,
+            RedisValue::SimpleString("set".into())
+        );
+        assert_eq!(members(&mut db, "SMEMBERS s1")?, ["a", "b", "c", "d", "e"]);
+
+        assert_eq!(members(&mut db, "SINTER s1 s2")?, ["c", "d"]);
+        assert_eq!(members(&mut db, "SINTER s1 s2 s3")?, ["d"]);
+        assert_eq!(members(&mut db, "SUNION s2 s3")?, ["c", "d", "f"]);
+        assert_eq!(members(&mut db, "SDIFF s1 s2 s3")?, ["a", "b", "e"]);
+        assert_eq!(members(&mut db, "SDIFF s3 s1")?, Vec::<String>::new());
+
+        // missing keys are empty sets
+        assert_eq!(members(&mut db, "SINTER s1 missing")?, Vec::<String>::new());
+        assert_eq!(members(&mut db, "SUNION missing s3")?, ["d"]);
+        assert_eq!(members(&mut db, "SDIFF missing s1")?, Vec::<String>::new());
+
+        assert_eq!(
+            execute(&mut db, "SINTERSTORE dest s1 s2")?,
+            RedisValue::Integer(2)
+        );
+        assert_eq!(members(&mut db, "SMEMBERS dest")?, ["c", "d"]);
+        assert_eq!(
+            execute(&mut db, "SUNIONSTORE dest s2 s3")?,
+            RedisValue::Integer(3)
+        );
+        assert_eq!(
+            execute(&mut db, "SDIFFSTORE dest s1 s2")?,
+            RedisValue::Integer(3)
+        );
+        assert_eq!(members(&mut db, "SMEMBERS dest")?, ["a", "b", "e"]);
+        // an empty result deletes the destination
+        assert_eq!(
+            execute(&mut db, "SINTERSTORE dest s1 missing")?,
+            RedisValue::Integer(0)
+        );
+        assert_eq!(execute(&mut db, "EXISTS dest")?, RedisValue::Integer(0));
+
+        execute(&mut db, "SET str a")?;
+        for command in [
+            "SADD str a",
+            "SREM str a",
+            "SRANDMEMBER str",
+            "SPOP str",
+            "SMEMBERS str",
+            "SINTER s1 str",
+            "SDIFFSTORE d s1 str",
+        ] {
+            assert!(matches!(
+                execute(&mut db, command)?,
+                RedisValue::SimpleError(e) if e.starts_with("WRONGTYPE")
+            ));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_srem() -> Result<()> {
+        let mut db = setup_db();
+        execute(&mut db, "SADD s a b c")?;
+        assert_eq!(execute(&mut db, "SREM s a d")?, RedisValue::Integer(1));
+        assert_eq!(execute(&mut db, "SREM missing a")?, RedisValue::Integer(0));
+        // the set is deleted with its last member
+        assert_eq!(execute(&mut db, "SREM s b c")?, RedisValue::Integer(2));
+        assert_eq!(execute(&mut db, "EXISTS s")?, RedisValue::Integer(0));
+        Ok(())
+    }
+
+    #[test]
+    fn test_srandmember() -> Result<()> {
+        let mut db = setup_db();
+        execute(&mut db, "SADD s a b c")?;
+
+        let mut seen = HashSet::new();
+        for _ in 0..200 {
+            seen.insert(execute(&mut db, "SRANDMEMBER s")?.inner_string()?);
+        }
+        assert_eq!(seen.len(), 3);
+
+        let members = bulkstrings(execute(&mut db, "SRANDMEMBER s 5")?);
+        assert_eq!(members.iter().collect::<HashSet<_>>().len(), 3);
+        assert_eq!(bulkstrings(execute(&mut db, "SRANDMEMBER s -5")?).len(), 5);
+
+        assert_eq!(
+            execute(&mut db, "SRANDMEMBER missing")?,
+            RedisValue::NullBulkString
+        );
+        assert_eq!(
+            execute(&mut db, "SRANDMEMBER missing 2")?,
+            RedisValue::Array(0, vec![])
+        );
+        assert_eq!(
+            execute(&mut db, "SRANDMEMBER s -9223372036854775808")?,
+            RedisValue::SimpleError("ERR value is out of range".to_string())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_spop() -> Result<()> {
+        let mut db = setup_db();
+        execute(&mut db, "SADD s a b c d")?;
+
+        let popped = execute(&mut db, "SPOP s")?.inner_string()?;
+        let mut members = bulkstrings(execute(&mut db, "SMEMBERS s")?);
+        assert_eq!(members.len(), 3);
+        assert!(!members.contains(&popped));
+
+        let popped = bulkstrings(execute(&mut db, "SPOP s 2")?);
+        assert_eq!(popped.iter().collect::<HashSet<_>>().len(), 2);
+        members.retain(|member| !popped.contains(member));
+        assert_eq!(bulkstrings(execute(&mut db, "SMEMBERS s")?), members);
+
+        // the set is deleted with its last member
+        assert_eq!(bulkstrings(execute(&mut db, "SPOP s 5")?), members);
+        assert_eq!(execute(&mut db, "EXISTS s")?, RedisValue::Integer(0));
+        assert_eq!(execute(&mut db, "SPOP s")?, RedisValue::NullBulkString);
+        assert_eq!(execute(&mut db, "SPOP s 2")?, RedisValue::Array(0, vec![]));
+        assert!(execute(&mut db, "SPOP s -1").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_hmget() -> Result<()> {
+        let mut db = setup_db();
+        setup_hash(&db);
+        assert_eq!(
+            execute(&mut db, "HMGET myhash f1 missing f3")?,
+            RedisValue::Array(
+                3,
+                vec![
+                    RedisValue::bulkstring_from("v1"),
+                    RedisValue::Null,
+                    RedisValue::bulkstring_from("v3"),
+                ]
+            )
+        );
+        assert_eq!(
+            execute(&mut db, "HMGET missing f1")?,
+            RedisValue::Array(1, vec![RedisValue::Null])
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_hrandfield() -> Result<()> {
+        let mut db = setup_db();
+        setup_hash(&db);
+
+        // all fields should eventually be picked
+        let mut seen = HashSet::new();
+        for _ in 0..200 {
+            seen.insert(execute(&mut db, "HRANDFIELD myhash")?.inner_string()?);
+        }
+        assert_eq!(seen.len(), 3);
+
+        assert_eq!(
+            execute(&mut db, "HRANDFIELD missing")?,
+            RedisValue::NullBulkString
+        );
+        assert_eq!(execute(&mut db, "HRANDFIELD missing 2")?, integers(&[]));
+        Ok(())
+    }
+
+    #[test]
+    fn test_hexpire_httl() -> Result<()> {
+        let mut db = setup_db();
+        setup_hash(&db);
+
+        assert_eq!(
+            execute(&mut db, "HEXPIRE myhash 100 FIELDS 2 f1 missing")?,
+            integers(&[1, -2])
+        );
+        assert_eq!(
+            execute(&mut db, "HTTL myhash FIELDS 3 f1 f2 missing")?,
+            integers(&[100, -1, -2])
+        );
+        assert_eq!(
+            execute(&mut db, "HEXPIRE myhash 0 FIELDS 1 f2")?,
+            integers(&[2])
+        );
+        assert_eq!(
+            execute(&mut db, "HTTL missing FIELDS 1 f1")?,
+            integers(&[-2])
+        );
+        assert!(
+            RedisCommand::try_from(&RedisValue::array_of_bulkstrings_from(
+                "HTTL myhash FIELDS 2 f1"
+            ))
+            .is_err()
+        );
+
+        // the key is deleted with its last field
+        execute(&mut db, "HEXPIRE myhash 0 FIELDS 2 f1 f3")?;
+        assert_eq!(
+            execute(&mut db, "TYPE myhash")?,
+            RedisValue::SimpleString("none".to_string())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_hrandfield_count() -> Result<()> {
+        let mut db = setup_db();
+        setup_hash(&db);
+
+        let fields = bulkstrings(execute(&mut db, "HRANDFIELD myhash 2")?);
+        assert_eq!(fields.iter().collect::<HashSet<_>>().len(), 2);
+
+        let fields = bulkstrings(execute(&mut db, "HRANDFIELD myhash 10")?);
+        assert_eq!(fields.len(), 3);
+        assert_eq!(fields.iter().collect::<HashSet<_>>().len(), 3);
+
+        let fields = bulkstrings(execute(&mut db, "HRANDFIELD myhash -10")?);
+        assert_eq!(fields.len(), 10);
+
+        let fields = bulkstrings(execute(&mut db, "HRANDFIELD myhash -4 WITHVALUES")?);
+        assert_eq!(fields.len(), 8);
+        for pair in fields.chunks(2) {
+            assert_eq!(pair[0].replace('f', "v"), pair[1]);
+        }
+
+        for count in ["-9223372036854775808", "-9223372036854775807"] {
+            assert_eq!(
+                execute(&mut db, &format!("HRANDFIELD myhash {count}"))?,
+                RedisValue::SimpleError("ERR value is out of range".to_string())
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_zadd() -> Result<()> {
+        let mut db = setup_db();
+        assert_eq!(execute(&mut db, "ZADD z 1 a 2 b")?, RedisValue::Integer(2));
+        // only the new members are counted unless CH is given
+        assert_eq!(execute(&mut db, "ZADD z 3 a 1 c")?, RedisValue::Integer(1));
+        assert_eq!(
+            execute(&mut db, "ZADD z CH 4 a 1 c")?,
+            RedisValue::Integer(1)
+        );
+        assert_eq!(execute(&mut db, "ZADD z XX 1 new")?, RedisValue::Integer(0));
+        assert_eq!(
+            execute(&mut db, "ZADD missing XX 1 a")?,
+            RedisValue::Integer(0)
+        );
+        assert!(db.get("missing").is_none());
+        assert!(matches!(
+            execute(&mut db, "ZADD z NX XX 1 a")?,
+            RedisValue::SimpleError(_)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_zadd_incr() -> Result<()> {
+        let mut db = setup_db();
+        assert_eq!(
+            execute(&mut db, "ZADD z INCR 1.5 a")?,
+            RedisValue::Double(1.5)
+        );
+        assert_eq!(
+            execute(&mut db, "ZADD z INCR 2 a")?,
+            RedisValue::Double(3.5)
+        );
+
+        // nil when NX or XX prevent the increment
+        assert_eq!(
+            execute(&mut db, "ZADD z NX INCR 1 a")?,
+            RedisValue::NullBulkString
+        );
+        assert_eq!(
+            execute(&mut db, "ZADD z XX INCR 1 b")?,
+            RedisValue::NullBulkString
+        );
+        assert_eq!(
+            execute(&mut db, "ZADD z XX INCR 1 a")?,
+            RedisValue::Double(4.5)
+        );
+        assert_eq!(
+            execute(&mut db, "ZADD z NX INCR 1 b")?,
+            RedisValue::Double(1.0)
+        );
+
+        // a single pair is allowed
+        assert!(matches!(
+            execute(&mut db, "ZADD z INCR 1 a 2 b")?,
+            RedisValue::SimpleError(_)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_zrandmember() -> Result<()> {
+        let mut db = setup_db();
+        let mut sorted_set = SortedSet::new();
+        sorted_set.insert("one", 1.0);
+        sorted_set.insert("two", 2.0);
+        sorted_set.insert("three", 3.5);
+        db.set("myzset".to_string(), ValueType::SortedSet(sorted_set), None);
+
+        let mut seen = HashSet::new();
+        for _ in 0..200 {
+            seen.insert(execute(&mut db, "ZRANDMEMBER myzset")?.inner_string()?);
+        }
+        assert_eq!(seen.len(), 3);
+
+        let members = bulkstrings(execute(&mut db, "ZRANDMEMBER myzset 5")?);
+        assert_eq!(members.iter().collect::<HashSet<_>>().len(), 3);
+
+        let members = bulkstrings(execute(&mut db, "ZRANDMEMBER myzset -5")?);
+        assert_eq!(members.len(), 5);
+
+        let members = bulkstrings(execute(&mut db, "ZRANDMEMBER myzset -6 WITHSCORES")?);
+        assert_eq!(members.len(), 12);
+        assert_eq!(
+            execute(&mut db, "ZRANDMEMBER myzset -9223372036854775808")?,
+            RedisValue::SimpleError("ERR value is out of range".to_string())
+        );
+        for pair in members.chunks(2) {
+            let expected = match pair[0].as_str() {
+                "one" => "1",
+                "two" => "2",
+                _ => "3.5",
+            };
+            assert_eq!(pair[1], expected);
+        }
+
+        // scores are doubles on RESP3 connections and bulk strings on RESP2 ones
+        let mut sorted_set = SortedSet::new();
+        sorted_set.insert("one", 1.5);
+        db.set("single".to_string(), ValueType::SortedSet(sorted_set), None);
+        let reply = execute(&mut db, "ZRANDMEMBER single 1 WITHSCORES")?;
+        assert_eq!(reply.to_bytes(), b"*2\r\n$3\r\none\r\n,1.5\r\n");
+        assert_eq!(
+            db.encode_for(Token(20), reply).to_bytes(),
+            b"*2\r\n$3\r\none\r\n$3\r\n1.5\r\n"
+        );
+
+        assert_eq!(
+            execute(&mut db, "ZRANDMEMBER missing")?,
+            RedisValue::NullBulkString
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_bitpos() -> Result<()> {
+        let mut db = setup_db();
+        execute(&mut db, "SET mykey ab")?;
+
+        // 'a' is 0b01100001
+        assert_eq!(execute(&mut db, "BITPOS mykey 1")?, RedisValue::Integer(1));
+        assert_eq!(execute(&mut db, "BITPOS mykey 0")?, RedisValue::Integer(0));
+        // 'b' is 0b01100010
+        assert_eq!(
+            execute(&mut db, "BITPOS mykey 1 1")?,
+            RedisValue::Integer(9)
+        );
+        assert_eq!(
+            execute(&mut db, "BITPOS mykey 1 -1 -1 BYTE")?,
+            RedisValue::Integer(9)
+        );
+        assert_eq!(
+            execute(&mut db, "BITPOS missing 0")?,
+            RedisValue::Integer(0)
+        );
+        assert_eq!(
+            execute(&mut db, "BITPOS missing 1")?,
+            RedisValue::Integer(-1)
+        );
+        assert!(matches!(
+            execute(&mut db, "BITPOS mykey 2")?,
+            RedisValue::SimpleError(_)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_xdel() -> Result<()> {
+        let mut db = setup_db();
+        execute(&mut db, "XADD s 1-1 a 1")?;
+        execute(&mut db, "XADD s 1-2 a 2")?;
+        assert_eq!(execute(&mut db, "XDEL s 1-1 1-3")?, RedisValue::Integer(1));
+        assert_eq!(execute(&mut db, "XDEL s 1-1")?, RedisValue::Integer(0));
+        assert_eq!(
+            execute(&mut db, "XDEL missing 1-1")?,
+            RedisValue::Integer(0)
+        );
+        assert!(matches!(
+            execute(&mut db, "XRANGE s - +")?,
+            RedisValue::Array(1, _)
+        ));
+
+        execute(&mut db, "SET string a")?;
+        assert!(matches!(
+            execute(&mut db, "XDEL string 1-1")?,
+            RedisValue::SimpleError(e) if e.starts_with("WRONGTYPE")
+        ));
+        assert!(
+            RedisCommand::try_from(&RedisValue::array_of_bulkstrings_from("XDEL s 1-1"))?
+                .should_forward_to_replicas()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_xadd_maxlen() -> Result<()> {
+        let mut db = setup_db();
+        execute(&mut db, "XADD s 1 a 1")?;
+        execute(&mut db, "XADD s 2 a 2")?;
+        assert_eq!(
+            execute(&mut db, "XADD s MAXLEN 2 3 a 3")?,
+            RedisValue::bulkstring_from("3-0")
+        );
+        execute(&mut db, "XADD s maxlen ~ 2 4 a 4")?;
+        execute(&mut db, "XADD s MAXLEN = 3 5 a 5")?;
+        let RedisValue::Array(3, entries) = execute(&mut db, "XRANGE s - +")? else {
+            panic!("the stream should have been trimmed to 3 entries");
+        };
+        assert!(
+            matches!(&entries[0], RedisValue::Array(2, entry) if entry[0] == RedisValue::bulkstring_from("3-0"))
+        );
+
+        // the id is required after the option
+        assert!(
+            RedisCommand::try_from(&RedisValue::array_of_bulkstrings_from(
+                "XADD s MAXLEN 2 a 1"
+            ))
+            .is_err()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_xadd_nomkstream() -> Result<()> {
+        let mut db = setup_db();
+        assert_eq!(
+            execute(&mut db, "XADD s NOMKSTREAM MAXLEN 1 * a 1")?,
+            RedisValue::NullBulkString
+        );
+        assert_eq!(execute(&mut db, "EXISTS s")?, RedisValue::Integer(0));
+
+        execute(&mut db, "XADD s 1 a 1")?;
+        assert_eq!(
+            execute(&mut db, "XADD s nomkstream MAXLEN 1 2 a 2")?,
+            RedisValue::bulkstring_from("2-0")
+        );
+        assert!(matches!(
+            execute(&mut db, "XRANGE s - +")?,
+            RedisValue::Array(1, _)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_xadd_bare_timestamp() -> Result<()> {
+        let mut db = setup_db();
+        assert_eq!(
+            execute(&mut db, "XADD s 5 a 1")?,
+            RedisValue::bulkstring_from("5-0")
+        );
+        assert_eq!(
+            execute(&mut db, "XADD s 5 a 2")?,
+            RedisValue::bulkstring_from("5-1")
+        );
+        assert_eq!(
+            execute(&mut db, "XADD s 5-* a 3")?,
+            RedisValue::bulkstring_from("5-2")
+        );
+        assert_eq!(
+            execute(&mut db, "XADD s 4 a 4")?,
+            RedisValue::SimpleError(
+                "ERR The ID specified in XADD is equal or smaller than the target stream top item"
+                    .to_string()
+            )
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_incr_decr() -> Result<()> {
+        let mut db = setup_db();
+        assert_eq!(execute(&mut db, "INCR a")?, RedisValue::Integer(1));
+        assert_eq!(execute(&mut db, "INCRBY a 10")?, RedisValue::Integer(11));
+        assert_eq!(execute(&mut db, "DECR a")?, RedisValue::Integer(10));
+        assert_eq!(execute(&mut db, "DECRBY a -5")?, RedisValue::Integer(15));
+        assert_eq!(execute(&mut db, "DECRBY b 3")?, RedisValue::Integer(-3));
+        assert_eq!(
+            execute(&mut db, "GET a")?,
+            RedisValue::bulkstring_from("15")
+        );
+
+        let not_an_integer = RedisValue::SimpleError(Error::NotAnInteger.to_string());
+        execute(&mut db, "SET s abc")?;
+        assert_eq!(execute(&mut db, "INCR s")?, not_an_integer);
+        execute(&mut db, "SET f 1.5")?;
+        assert_eq!(execute(&mut db, "DECR f")?, not_an_integer);
+
+        let overflow = RedisValue::SimpleError(Error::IncrOverflow.to_string());
+        execute(&mut db, &format!("SET max {}", i64::MAX))?;
+        assert_eq!(execute(&mut db, "INCR max")?, overflow);
+        assert_eq!(
+            execute(&mut db, &format!("DECRBY a {}", i64::MIN))?,
+            overflow
+        );
+        assert_eq!(
+            execute(&mut db, "GET max")?,
+            RedisValue::bulkstring_from(&i64::MAX.to_string())
+        );
+
+        execute(&mut db, "LPUSH l x")?;
+        assert_eq!(
+            execute(&mut db, "INCRBY l 1")?,
+            RedisValue::SimpleError(Error::WrongTypeOperation.to_string())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_incrbyfloat() -> Result<()> {
+        let mut db = setup_db();
+        assert_eq!(
+            execute(&mut db, "INCRBYFLOAT a 10.5")?,
+            RedisValue::bulkstring_from("10.5")
+        );
+        assert_eq!(
+            execute(&mut db, "INCRBYFLOAT a 0.1")?,
+            RedisValue::bulkstring_from("10.6")
+        );
+        // no trailing zeros nor exponent
+        assert_eq!(
+            execute(&mut db, "INCRBYFLOAT a -5.6")?,
+            RedisValue::bulkstring_from("5")
+        );
+        assert_eq!(
+            execute(&mut db, "INCRBYFLOAT a 2.0e3")?,
+            RedisValue::bulkstring_from("2005")
+        );
+        assert_eq!(
+            execute(&mut db, "GET a")?,
+            RedisValue::bulkstring_from("2005")
+        );
+        // integers can be incremented afterwards
+        assert_eq!(execute(&mut db, "INCR a")?, RedisValue::Integer(2006));
+
+        execute(&mut db, "SET s abc")?;
+        assert_eq!(
+            execute(&mut db, "INCRBYFLOAT s 1")?,
+            RedisValue::SimpleError(Error::NotAFloat.to_string())
+        );
+        assert_eq!(
+            execute(&mut db, "INCRBYFLOAT a inf")?,
+            RedisValue::SimpleError(Error::NanOrInfinity.to_string())
+        );
+        assert_eq!(
+            execute(&mut db, "GET a")?,
+            RedisValue::bulkstring_from("2006")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_strlen_getrange() -> Result<()> {
+        let mut db = setup_db();
+        execute(&mut db, "SET s hello")?;
+        assert_eq!(execute(&mut db, "STRLEN s")?, RedisValue::Integer(5));
+        assert_eq!(execute(&mut db, "STRLEN missing")?, RedisValue::Integer(0));
+
+        let getrange = |db: &mut RedisDb, input: &str| execute(db, &format!("GETRANGE {}", input));
+        assert_eq!(
+            getrange(&mut db, "s 1 3")?,
+            RedisValue::bulkstring_from("ell")
+        );
+        assert_eq!(
+            getrange(&mut db, "s 0 -1")?,
+            RedisValue::bulkstring_from("hello")
+        );
+        assert_eq!(
+            getrange(&mut db, "s -3 -2")?,
+            RedisValue::bulkstring_from("ll")
+        );
+        assert_eq!(
+            getrange(&mut db, "s 3 100")?,
+            RedisValue::bulkstring_from("lo")
+        );
+        assert_eq!(
+            getrange(&mut db, "s -100 1")?,
+            RedisValue::bulkstring_from("he")
+        );
+        assert_eq!(getrange(&mut db, "s 3 1")?, RedisValue::bulkstring_from(""));
+        assert_eq!(
+            getrange(&mut db, "s -1 -3")?,
+            RedisValue::bulkstring_from("")
+        );
+        assert_eq!(
+            getrange(&mut db, "missing 0 -1")?,
+            RedisValue::bulkstring_from("")
+        );
+
+        execute(&mut db, "XADD stream 1-1 a b")?;
+        let wrong_type = RedisValue::SimpleError(Error::WrongTypeOperation.to_string());
+        assert_eq!(execute(&mut db, "STRLEN stream")?, wrong_type);
+        assert_eq!(getrange(&mut db, "stream 0 -1")?, wrong_type);
+        assert_eq!(execute(&mut db, "APPEND stream a")?, wrong_type);
+        assert_eq!(execute(&mut db, "SETRANGE stream 0 a")?, wrong_type);
+        Ok(())
+    }
+
+    #[test]
+    fn test_append_setrange_setbit() -> Result<()> {
+        let mut db = setup_db();
+        assert_eq!(execute(&mut db, "APPEND s ab")?, RedisValue::Integer(2));
+        assert_eq!(execute(&mut db, "APPEND s c")?, RedisValue::Integer(3));
+        // the string is padded with zeros up to the offset
+        assert_eq!(execute(&mut db, "SETRANGE s 5 xy")?, RedisValue::Integer(7));
+        assert_eq!(
+            execute(&mut db, "GET s")?,
+            RedisValue::bulkstring_from("abc\0\0xy")
+        );
+        // an empty value does not create the key
+        assert_eq!(db.setrange("missing", 3, b"")?, 0);
+        assert!(db.get("missing").is_none());
+
+        // 'a' is 0b01100001
+        assert_eq!(execute(&mut db, "SETBIT s 6 1")?, RedisValue::Integer(0));
+        assert_eq!(execute(&mut db, "SETBIT s 7 0")?, RedisValue::Integer(1));
+        assert_eq!(execute(&mut db, "SETBIT bits 9 1")?, RedisValue::Integer(0));
+        assert_eq!(
+            execute(&mut db, "GET bits")?,
+            RedisValue::bulkstring_from("\0\x40")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_string_growth_limit() -> Result<()> {
+        let mut db = setup_db();
+        db.info.proto_max_bulk_len = 4;
+        let too_long = RedisValue::SimpleError(Error::StringTooLong.to_string());
+
+        execute(&mut db, "APPEND s abc")?;
+        assert_eq!(execute(&mut db, "APPEND s de")?, too_long);
+        assert_eq!(execute(&mut db, "SETRANGE s 3 xy")?, too_long);
+        assert_eq!(execute(&mut db, "SETRANGE s 3 x")?, RedisValue::Integer(4));
+        assert_eq!(
+            execute(&mut db, "SETBIT s 32 1")?,
+            RedisValue::SimpleError(Error::BitOffsetOutOfRange.to_string())
+        );
+        assert_eq!(execute(&mut db, "SETBIT s 31 1")?, RedisValue::Integer(0));
+        // nothing is written when the limit is exceeded
+        assert_eq!(
+            execute(&mut db, "GET s")?,
+            RedisValue::bulkstring_from("abcy")
+        );
+        assert_eq!(execute(&mut db, "SETRANGE big 10 x")?, too_long);
+        assert!(db.get("big").is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_bgsave() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("test-bgsave-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let info = DbInfo::build("master", 6379, dir.to_str().unwrap(), "dump.rdb");
+        let mut db = RedisDb::build(info, ConnectionState::Ready);
+        execute(&mut db, "SET a 1")?;
+        execute(&mut db, "SET b 2 PXAT 4102444800000")?;
+        assert_eq!(
+            execute(&mut db, "BGSAVE")?,
+            RedisValue::SimpleString("Background saving started".to_string())
+        );
+
+        // the expiration in ms is encoded after the 0xFC opcode, before the type and key
+        let bytes = std::fs::read(dir.join("dump.rdb"))?;
+        let position = bytes
+            .windows(2)
+            .position(|window| window == b"\x01b")
+            .expect("the key b should be saved");
+        assert_eq!(bytes[position - 10], 0xFC);
+        let expiration = u64::from_le_bytes(bytes[position - 9..position - 1].try_into().unwrap());
+        assert!(expiration.abs_diff(4102444800000) < 1000, "{}", expiration);
+
+        let mut loaded = setup_db();
+        loaded.load_rdb(&Rdb::new(dir.join("dump.rdb"))?);
+        assert_eq!(
+            execute(&mut loaded, "GET a")?,
+            RedisValue::bulkstring_from("1")
+        );
+        assert_eq!(
+            execute(&mut loaded, "GET b")?,
+            RedisValue::bulkstring_from("2")
+        );
+
+        // sorted sets and streams are skipped, the save still succeeds
+        execute(&mut db, "ZADD zset 1 one")?;
+        execute(&mut db, "XADD stream 1-1 f v")?;
+        // the writes are counted when they are propagated, which execute doesn't do
+        db.dirty = 2;
+        assert_eq!(
+            execute(&mut db, "SAVE")?,
+            RedisValue::SimpleString("OK".to_string())
+        );
+        assert_eq!(db.dirty, 0);
+        let mut loaded = setup_db();
+        loaded.load_rdb(&Rdb::new(dir.join("dump.rdb"))?);
+        assert_eq!(
+            execute(&mut loaded, "GET a")?,
+            RedisValue::bulkstring_from("1")
+        );
+        assert_eq!(
+            execute(&mut loaded, "EXISTS zset stream")?,
+            RedisValue::Integer(0)
+        );
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_info_keyspace() -> Result<()> {
+        let mut db = setup_db();
+        assert_eq!(
+            execute(&mut db, "INFO keyspace")?,
+            RedisValue::VerbatimString("txt".to_string(), "# Keyspace\r\n".to_string())
+        );
+
+        execute(&mut db, "SET a 1")?;
+        execute(&mut db, "SET b 1 PX 10000")?;
+        execute(&mut db, "SET c 1 PX 20000")?;
+        let RedisValue::VerbatimString(_, info) = execute(&mut db, "INFO keyspace")? else {
+            panic!("INFO should return a verbatim string");
+        };
+        let line = info
+            .strip_prefix("# Keyspace\r\ndb0:keys=3,expires=2,avg_ttl=")
+            .and_then(|rest| rest.strip_suffix("\r\n"))
+            .unwrap_or_else(|| panic!("unexpected keyspace info {:?}", info));
+        let avg_ttl = line.parse::<u64>()?;
+        assert!((14000..=15000).contains(&avg_ttl), "{}", avg_ttl);
+        Ok(())
+    }
+
+    #[test]
+    fn test_expireat() -> Result<()> {
+        let mut db = setup_db();
+        execute(&mut db, "SET a 1")?;
+        execute(&mut db, "SET b 1")?;
+        let in_one_hour = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("time should not go backward")
+            + Duration::from_secs(3600);
+
+        assert_eq!(
+            execute(&mut db, &format!("EXPIREAT a {}", in_one_hour.as_secs()))?,
+            RedisValue::Integer(1)
+        );
+        assert_eq!(execute(&mut db, "GET a")?, RedisValue::bulkstring_from("1"));
+        assert_eq!(
+            execute(
+                &mut db,
+                &format!("PEXPIREAT missing {}", in_one_hour.as_millis())
+            )?,
+            RedisValue::Integer(0)
+        );
+
+        // a time in the past deletes the key
+        assert_eq!(
+            execute(&mut db, "PEXPIREAT b 1000")?,
+            RedisValue::Integer(1)
+        );
+        assert_eq!(execute(&mut db, "GET b")?, RedisValue::NullBulkString);
+        assert_eq!(db.dbsize(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_bitop() -> Result<()> {
+        let mut db = setup_db();
+        execute(&mut db, "SET key1 abc")?;
+        execute(&mut db, "SET key2 a")?;
+
+        assert_eq!(
+            execute(&mut db, "BITOP AND dest key1 key2")?,
+            RedisValue::Integer(3)
+        );
+        // 'a' & 'a' = 'a', then the shorter key is padded with zeros
+        assert_eq!(
+            execute(&mut db, "GET dest")?,
+            RedisValue::bulkstring_from("a\0\0")
+        );
+
+        assert_eq!(
+            execute(&mut db, "BITOP OR dest key1 key2 missing")?,
+            RedisValue::Integer(3)
+        );
+        assert_eq!(
+            execute(&mut db, "GET dest")?,
+            RedisValue::bulkstring_from("abc")
+        );
+
+        assert_eq!(
+            execute(&mut db, "BITOP XOR dest missing")?,
+            RedisValue::Integer(0)
+        );
+        assert_eq!(execute(&mut db, "GET dest")?, RedisValue::NullBulkString);
+
+        assert!(matches!(
+            execute(&mut db, "BITOP NOT dest key1 key2")?,
+            RedisValue::SimpleError(_)
+        ));
+        assert!(matches!(
+            execute(&mut db, "BITOP NAND dest key1 key2")?,
+            RedisValue::SimpleError(_)
+        ));
+
+        let mut sorted_set = SortedSet::new();
+        sorted_set.insert("one", 1.0);
+        db.set("myzset".to_string(), ValueType::SortedSet(sorted_set), None);
+        assert!(matches!(
+            execute(&mut db, "BITOP AND dest key1 myzset")?,
+            RedisValue::SimpleError(e) if e.starts_with("WRONGTYPE")
+        ));
+        Ok(())
+    }
+
+    fn entry_ids(redis_value: &RedisValue) -> Vec<String> {
+        // [[key, [[id, [fields]], ...]]] for a single key
+        match redis_value {
+            RedisValue::Array(_, streams) => match &streams[0] {
+                RedisValue::Array(_, key_and_entries) => match &key_and_entries[1] {
+                    RedisValue::Array(_, entries) => entries
+                        .iter()
+                        .map(|entry| match entry {
+                            RedisValue::Array(_, id_and_fields) => {
+                                id_and_fields[0].inner_string().unwrap()
+                            }
+                            _ => panic!("Expected an array"),
+                        })
+                        .collect(),
+                    _ => panic!("Expected an array"),
+                },
+                _ => panic!("Expected an array"),
+            },
+            _ => panic!("Expected an array"),
+        }
+    }
+
+    #[test]
+    fn test_consumer_group_commands() -> Result<()> {
+        let mut db = setup_db();
+        assert!(matches!(
+            execute(&mut db, "XGROUP CREATE mystream group $")?,
+            RedisValue::SimpleError(_)
+        ));
+        assert_eq!(
+            execute(&mut db, "XGROUP CREATE mystream group $ MKSTREAM")?,
+            RedisValue::SimpleString("OK".to_string())
+        );
+        assert_eq!(
+            execute(&mut db, "XGROUP CREATE mystream group $")?,
+            RedisValue::SimpleError("BUSYGROUP Consumer Group name already exists".to_string())
+        );
+        assert_eq!(
+            execute(&mut db, "XGROUP CREATECONSUMER mystream group alice")?,
+            RedisValue::Integer(1)
+        );
+        assert_eq!(
+            execute(&mut db, "XGROUP CREATECONSUMER mystream group alice")?,
+            RedisValue::Integer(0)
+        );
+
+        execute(&mut db, "XADD mystream 1-0 a 1")?;
+        execute(&mut db, "XADD mystream 2-0 b 2")?;
+        execute(&mut db, "XADD mystream 3-0 c 3")?;
+
+        let res = execute(
+            &mut db,
+            "XREADGROUP GROUP group alice COUNT 2 STREAMS mystream >",
+        )?;
+        assert_eq!(entry_ids(&res), vec!["1-0", "2-0"]);
+        let res = execute(&mut db, "XREADGROUP GROUP group bob STREAMS mystream >")?;
+        assert_eq!(entry_ids(&res), vec!["3-0"]);
+        assert_eq!(
+            execute(&mut db, "XREADGROUP GROUP group bob STREAMS mystream >")?,
+            RedisValue::NullBulkString
+        );
+
+        assert_eq!(
+            execute(&mut db, "XACK mystream group 1-0 3-0 4-0")?,
+            RedisValue::Integer(2)
+        );
+        let res = execute(&mut db, "XREADGROUP GROUP group alice STREAMS mystream 0")?;
+        assert_eq!(entry_ids(&res), vec!["2-0"]);
+        let res = execute(&mut db, "XREADGROUP GROUP group bob STREAMS mystream 0")?;
+        assert!(entry_ids(&res).is_empty());
+
+        assert!(matches!(
+            execute(&mut db, "XREADGROUP GROUP missing bob STREAMS mystream >")?,
+            RedisValue::SimpleError(_)
+        ));
+        assert_eq!(
+            execute(&mut db, "XGROUP DESTROY mystream group")?,
+            RedisValue::Integer(1)
+        );
+        assert_eq!(
+            execute(&mut db, "XACK mystream group 2-0")?,
+            RedisValue::Integer(0)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_xpending() -> Result<()> {
+        let mut db = setup_db();
+        execute(&mut db, "XGROUP CREATE mystream group $ MKSTREAM")?;
+        assert_eq!(
+            execute(&mut db, "XPENDING mystream group")?,
+            RedisValue::Array(
+                4,
+                vec![
+                    RedisValue::Integer(0),
+                    RedisValue::NullBulkString,
+                    RedisValue::NullBulkString,
+                    RedisValue::NullBulkString,
+                ]
+            )
+        );
+
+        execute(&mut db, "XADD mystream 1-0 a 1")?;
+        execute(&mut db, "XADD mystream 2-0 b 2")?;
+        execute(&mut db, "XADD mystream 3-0 c 3")?;
+        execute(
+            &mut db,
+            "XREADGROUP GROUP group bob COUNT 1 STREAMS mystream >",
+        )?;
+        execute(&mut db, "XREADGROUP GROUP group alice STREAMS mystream >")?;
+
+        let consumer_count = |consumer: &str, count: &str| {
+            RedisValue::Array(
+                2,
+                vec![
+                    RedisValue::bulkstring_from(consumer),
+                    RedisValue::bulkstring_from(count),
+                ],
+            )
+        };
+        assert_eq!(
+            execute(&mut db, "XPENDING mystream group")?,
+            RedisValue::Array(
+                4,
+                vec![
+                    RedisValue::Integer(3),
+                    RedisValue::bulkstring_from("1-0"),
+                    RedisValue::bulkstring_from("3-0"),
+                    RedisValue::Array(
+                        2,
+                        vec![consumer_count("alice", "2"), consumer_count("bob", "1")]
+                    ),
+                ]
+            )
+        );
+
+        match execute(&mut db, "XPENDING mystream group - + 10 alice")? {
+            RedisValue::Array(2, entries) => match &entries[0] {
+                RedisValue::Array(4, entry) => {
+                    assert_eq!(entry[0], RedisValue::bulkstring_from("2-0"));
+                    assert_eq!(entry[1], RedisValue::bulkstring_from("alice"));
+                    assert_eq!(entry[3], RedisValue::Integer(1));
+                }
+                _ => panic!("Expected a pending entry"),
+            },
+            _ => panic!("Expected two pending entries"),
+        }
+        assert_eq!(
+            execute(&mut db, "XPENDING mystream group IDLE 100000 - + 10")?,
+            RedisValue::Array(0, vec![])
+        );
+        assert!(matches!(
+            execute(&mut db, "XPENDING mystream missing")?,
+            RedisValue::SimpleError(_)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_xclaim() -> Result<()> {
+        let mut db = setup_db();
+        execute(&mut db, "XGROUP CREATE mystream group $ MKSTREAM")?;
+        execute(&mut db, "XADD mystream 1-0 a 1")?;
+        execute(&mut db, "XADD mystream 2-0 b 2")?;
+        execute(&mut db, "XREADGROUP GROUP group alice STREAMS mystream >")?;
+
+        let res = execute(&mut db, "XCLAIM mystream group bob 3600000 1-0")?;
+        assert_eq!(res, RedisValue::Array(0, vec![]));
+
+        let res = execute(&mut db, "XCLAIM mystream group bob 0 1-0")?;
+        assert_eq!(
+            entry_ids(&RedisValue::Array(
+                1,
+                vec![RedisValue::Array(
+                    2,
+                    vec![RedisValue::bulkstring_from("mystream"), res]
+                )]
+            )),
+            vec!["1-0"]
+        );
+
+        let res = execute(&mut db, "XCLAIM mystream group bob 0 2-0 JUSTID")?;
+        assert_eq!(
+            res,
+            RedisValue::Array(1, vec![RedisValue::bulkstring_from("2-0")])
+        );
+
+        let res = execute(&mut db, "XPENDING mystream group - + 10 bob")?;
+        match res {
+            RedisValue::Array(2, entries) => {
+                // claimed normally: delivery count incremented, with JUSTID: unchanged
+                assert!(
+                    matches!(&entries[0], RedisValue::Array(4, entry) if entry[3] == RedisValue::Integer(2))
+                );
+                assert!(
+                    matches!(&entries[1], RedisValue::Array(4, entry) if entry[3] == RedisValue::Integer(1))
+                );
+            }
+            _ => panic!("Expected two pending entries for bob"),
+        }
+        assert!(matches!(
+            execute(&mut db, "XCLAIM mystream missing bob 0 1-0")?,
+            RedisValue::SimpleError(_)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_keys_max_results() -> Result<()> {
+        let clock = MockClock::install();
+        let mut db = setup_db();
+        execute(&mut db, "SET a 1")?;
+        execute(&mut db, "SET b 2")?;
+        assert_eq!(bulkstrings(execute(&mut db, "KEYS *")?).len(), 2);
+
+        db.info.keys_max_results = Some(2);
+        assert_eq!(bulkstrings(execute(&mut db, "KEYS *")?).len(), 2);
+
+        execute(&mut db, "SET c 3")?;
+        assert!(matches!(
+            execute(&mut db, "KEYS *")?,
+            RedisValue::SimpleError(e) if e.contains("SCAN")
+        ));
+
+        // only the non expired matching keys are counted
+        execute(&mut db, "SET ab 4")?;
+        execute(&mut db, "SET ac 5 PX 1")?;
+        clock.advance(Duration::from_millis(2));
+        let mut keys = bulkstrings(execute(&mut db, "KEYS a*")?);
+        keys.sort();
+        assert_eq!(keys, vec!["a", "ab"]);
+        assert_eq!(bulkstrings(execute(&mut db, "KEYS [bc]")?).len(), 2);
+        Ok(())
+    }
+
+    fn execute_for_connection(
+        db: &mut RedisDb,
+        token: Token,
+        command: &str,
+    ) -> Result<Vec<RedisValue>> {
+        let redis_value = RedisValue::array_of_bulkstrings_from(command);
+        let redis_command = RedisCommand::try_from(&redis_value)?;
+        Ok(redis_command
+            .execute_for_connection(db, token)
+            .expect("Should be a pub/sub command"))
+    }
+
+    /// (kind, channel, count) of each confirmation message
+    fn confirmations(redis_values: Vec<RedisValue>) -> Vec<(String, Option<String>, i64)> {
+        redis_values
+            .into_iter()
+            .map(|redis_value| match redis_value {
+                RedisValue::Array(3, values) => match &values[..] {
+                    [RedisValue::BulkString(_, kind), channel, RedisValue::Integer(count)] => {
+                        let channel = match channel {
+                            RedisValue::BulkString(_, channel) => Some(lossy_string(channel)),
+                            _ => None,
+                        };
+                        (lossy_string(kind), channel, *count)
+                    }
+                    _ => panic!("Invalid confirmation message"),
+                },
+                _ => panic!("Invalid confirmation message"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_subscribe_counts() -> Result<()> {
+        let mut db = setup_db();
+        let token = Token(20);
+        let some = |x: &str| Some(x.to_string());
+
+        let res = execute_for_connection(&mut db, token, "SUBSCRIBE a b c")?;
+        assert_eq!(
+            confirmations(res),
+            vec![
+                ("subscribe".to_string(), some("a"), 1),
+                ("subscribe".to_string(), some("b"), 2),
+                ("subscribe".to_string(), some("c"), 3),
+            ]
+        );
+
+        // patterns are counted with the channels
+        let res = execute_for_connection(&mut db, token, "PSUBSCRIBE news.*")?;
+        assert_eq!(
+            confirmations(res),
+            vec![("psubscribe".to_string(), some("news.*"), 4)]
+        );
+
+        let res = execute_for_connection(&mut db, token, "UNSUBSCRIBE")?;
+        assert_eq!(
+            confirmations(res),
+            vec![
+                ("unsubscribe".to_string(), some("a"), 3),
+                ("unsubscribe".to_string(), some("b"), 2),
+                ("unsubscribe".to_string(), some("c"), 1),
+            ]
+        );
+
+        let res = execute_for_connection(&mut db, token, "PUNSUBSCRIBE")?;
+        assert_eq!(
+            confirmations(res),
+            vec![("punsubscribe".to_string(), some("news.*"), 0)]
+        );
+
+        // nothing left to unsubscribe from
+        let res = execute_for_connection(&mut db, token, "UNSUBSCRIBE")?;
+        assert_eq!(
+            confirmations(res),
+            vec![("unsubscribe".to_string(), None, 0)]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_pubsub_introspection() -> Result<()> {
+        let mut db = setup_db();
+        execute_for_connection(&mut db, Token(20), "SUBSCRIBE news.tech news.sport weather")?;
+        execute_for_connection(&mut db, Token(21), "SUBSCRIBE news.tech")?;
+        execute_for_connection(&mut db, Token(21), "PSUBSCRIBE news.* weather.*")?;
+        execute_for_connection(&mut db, Token(22), "PSUBSCRIBE news.*")?;
+
+        let res = execute(&mut db, "PUBSUB CHANNELS")?;
+        assert_eq!(bulkstrings(res), vec!["news.sport", "news.tech", "weather"]);
+        let res = execute(&mut db, "PUBSUB CHANNELS news.*")?;
+        assert_eq!(bulkstrings(res), vec!["news.sport", "news.tech"]);
+
+        let res = execute(&mut db, "PUBSUB NUMSUB news.tech weather unknown")?;
+        assert_eq!(
+            res,
+            RedisValue::Array(
+                6,
+                vec![
+                    RedisValue::bulkstring_from("news.tech"),
+                    RedisValue::Integer(2),
+                    RedisValue::bulkstring_from("weather"),
+                    RedisValue::Integer(1),
+                    RedisValue::bulkstring_from("unknown"),
+                    RedisValue::Integer(0),
+                ]
+            )
+        );
+        assert_eq!(execute(&mut db, "PUBSUB NUMPAT")?, RedisValue::Integer(2));
+
+        execute_for_connection(&mut db, Token(20), "UNSUBSCRIBE")?;
+        let res = execute(&mut db, "PUBSUB CHANNELS")?;
+        assert_eq!(bulkstrings(res), vec!["news.tech"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_sharded_pubsub() -> Result<()> {
+        let mut db = setup_db();
+        let some = |x: &str| Some(x.to_string());
+        execute_for_connection(&mut db, Token(20), "SUBSCRIBE orders")?;
+        let res = execute_for_connection(&mut db, Token(20), "SSUBSCRIBE orders")?;
+        // shard channels have their own count
+        assert_eq!(
+            confirmations(res),
+            vec![("ssubscribe".to_string(), some("orders"), 1)]
+        );
+        execute_for_connection(&mut db, Token(21), "SSUBSCRIBE orders")?;
+
+        assert_eq!(
+            execute(&mut db, "SPUBLISH orders hello")?,
+            RedisValue::Integer(2)
+        );
+        let mut replies = db.take_due_replies(Instant::now());
+        replies.sort_by_key(|reply| reply.connection_token);
+        assert_eq!(
+            replies
+                .iter()
+                .map(|reply| reply.connection_token)
+                .collect::<Vec<_>>(),
+            vec![Token(20), Token(21)]
+        );
+        assert_eq!(
+            bulkstrings(replies[0].redis_value.clone()),
+            vec!["smessage", "orders", "hello"]
+        );
+        // the message is a push for RESP3 connections and an array for RESP2 ones
+        db.set_protocol(Token(21), 3);
+        let message = "3\r\n$8\r\nsmessage\r\n$6\r\norders\r\n$5\r\nhello\r\n";
+        assert_eq!(
+            db.encode_for(Token(20), replies[0].redis_value.clone())
+                .to_bytes(),
+            format!("*{message}").into_bytes()
+        );
+        assert_eq!(
+            db.encode_for(Token(21), replies[1].redis_value.clone())
+                .to_bytes(),
+            format!(">{message}").into_bytes()
+        );
+
+        let res = execute_for_connection(&mut db, Token(20), "SUNSUBSCRIBE")?;
+        assert_eq!(
+            confirmations(res),
+            vec![("sunsubscribe".to_string(), some("orders"), 0)]
+        );
+        assert_eq!(
+            execute(&mut db, "SPUBLISH orders hello")?,
+            RedisValue::Integer(1)
+        );
+        // the regular subscription is untouched
+        assert_eq!(
+            execute(&mut db, "PUBSUB NUMSUB orders")?,
+            RedisValue::Array(
+                2,
+                vec![
+                    RedisValue::bulkstring_from("orders"),
+                    RedisValue::Integer(1)
+                ]
+            )
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_command_info() -> Result<()> {
+        let mut db = setup_db();
+        let res = execute(&mut db, "COMMAND INFO get SET unknown")?;
+        assert_eq!(
+            res,
+            RedisValue::Array(
+                3,
+                vec![
+                    RedisValue::Array(
+                        6,
+                        vec![
+                            RedisValue::bulkstring_from("get"),
+                            RedisValue::Integer(2),
+                            RedisValue::Array(
+                                2,
+                                vec![
+                                    RedisValue::SimpleString("readonly".to_string()),
+                                    RedisValue::SimpleString("fast".to_string()),
+                                ]
+                            ),
+                            RedisValue::Integer(1),
+                            RedisValue::Integer(1),
+                            RedisValue::Integer(1),
+                        ]
+                    ),
+                    command_info_to_redis_value(command_table::lookup("set").unwrap()),
+                    RedisValue::NullBulkString,
+                ]
+            )
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_command_getkeys() -> Result<()> {
+        let mut db = setup_db();
+        assert_eq!(
+            execute(&mut db, "COMMAND GETKEYS MSET a 1 b 2")?,
+            RedisValue::array_of_bulkstrings_from("a b")
+        );
+        assert_eq!(
+            execute(&mut db, "COMMAND GETKEYS XREAD COUNT 2 STREAMS s1 s2 0 0")?,
+            RedisValue::array_of_bulkstrings_from("s1 s2")
+        );
+
+        let flags = |flags: &[&str]| {
+            RedisValue::Array(
+                flags.len(),
+                flags
+                    .iter()
+                    .map(|flag| RedisValue::SimpleString(flag.to_string()))
+                    .collect(),
+            )
+        };
+        assert_eq!(
+            execute(&mut db, "COMMAND GETKEYSANDFLAGS sinterstore dest s1 s2")?,
+            RedisValue::Array(
+                3,
+                vec![
+                    RedisValue::Array(
+                        2,
+                        vec![
+                            RedisValue::bulkstring_from("dest"),
+                            flags(&["OW", "update"])
+                        ]
+                    ),
+                    RedisValue::Array(
+                        2,
+                        vec![RedisValue::bulkstring_from("s1"), flags(&["RO", "access"])]
+                    ),
+                    RedisValue::Array(
+                        2,
+                        vec![RedisValue::bulkstring_from("s2"), flags(&["RO", "access"])]
+                    ),
+                ]
+            )
+        );
+        assert_eq!(
+            execute(
+                &mut db,
+                "COMMAND GETKEYSANDFLAGS XREADGROUP GROUP g c STREAMS s >"
+            )?,
+            RedisValue::Array(
+                1,
+                vec![RedisValue::Array(
+                    2,
+                    vec![
+                        RedisValue::bulkstring_from("s"),
+                        flags(&["RW", "access", "update"])
+                    ]
+                )]
+            )
+        );
+
+        for (command, error) in [
+            ("COMMAND GETKEYS foo a", "ERR Invalid command specified"),
+            (
+                "COMMAND GETKEYSANDFLAGS get a b",
+                "ERR Invalid number of arguments specified for command",
+            ),
+            (
+                "COMMAND GETKEYS ping",
+                "ERR The command has no key arguments",
+            ),
+        ] {
+            assert_eq!(
+                execute(&mut db, command)?,
+                RedisValue::SimpleError(error.to_string())
+            );
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_command_docs() -> Result<()> {
+        let mut db = setup_db();
+        let res = execute(&mut db, "COMMAND DOCS get unknown")?;
+        assert_eq!(
+            res,
+            RedisValue::Array(
+                2,
+                vec![
+                    RedisValue::bulkstring_from("get"),
+                    RedisValue::Array(
+                        4,
+                        vec![
+                            RedisValue::bulkstring_from("summary"),
+                            RedisValue::bulkstring_from("Returns the string value of a key."),
+                            RedisValue::bulkstring_from("arity"),
+                            RedisValue::Integer(2),
+                        ]
+                    ),
+                ]
+            )
+        );
+
+        // without names, all the commands are documented
+        let RedisValue::Array(len, _) = execute(&mut db, "COMMAND DOCS")? else {
+            panic!("COMMAND DOCS should return an array");
+        };
+        assert_eq!(len, 2 * command_table::COMMAND_TABLE.len());
+        Ok(())
+    }
+
+    #[test]
+    fn test_del() -> Result<()> {
+        let mut db = setup_db();
+        execute(&mut db, "SET a 1")?;
+        execute(&mut db, "SET b 2")?;
+        assert_eq!(execute(&mut db, "DEL a b c")?, RedisValue::Integer(2));
+        assert_eq!(execute(&mut db, "GET a")?, RedisValue::NullBulkString);
+        Ok(())
+    }
+
+    #[test]
+    fn test_exists() -> Result<()> {
+        let mut db = setup_db();
+        execute(&mut db, "SET a 1")?;
+        execute(&mut db, "SET b 2")?;
+        // repeated keys are counted each time
+        assert_eq!(execute(&mut db, "EXISTS a b a c")?, RedisValue::Integer(3));
+
+        db.set(
+            "expired".to_string(),
+            ValueType::String("1".into()),
+            Some(0),
+        );
+        assert_eq!(execute(&mut db, "EXISTS expired")?, RedisValue::Integer(0));
+        assert_eq!(db.dbsize(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_dispatch_checks_the_table_arity() {
+        let parse =
+            |command: &str| RedisCommand::try_from(&RedisValue::array_of_bulkstrings_from(command));
+        assert!(matches!(parse("GET a b"), Err(Error::WrongArity(name)) if name == "get"));
+        assert!(matches!(parse("mget"), Err(Error::WrongArity(name)) if name == "mget"));
+        assert!(matches!(
+            parse("NOPE a"),
+            Err(Error::UnknownCommand { name, .. }) if name == "NOPE"
+        ));
+        assert!(matches!(parse("Get a"), Ok(RedisCommand::Get(key)) if key == "a"));
+        assert!(matches!(parse("CONFIG help"), Ok(RedisCommand::Help(name)) if name == "config"));
+    }
+
+    #[test]
+    fn test_command_list() -> Result<()> {
+        let mut db = setup_db();
+        let RedisValue::Array(count, names) = execute(&mut db, "COMMAND LIST")? else {
+            panic!("COMMAND LIST should return an array");
+        };
+        assert_eq!(
+            execute(&mut db, "COMMAND COUNT")?,
+            RedisValue::Integer(count as i64)
+        );
+        assert_eq!(count, command_table::COMMAND_TABLE.len());
+        let names = names
+            .iter()
+            .map(|name| name.inner_string())
+            .collect::<Result<HashSet<_>>>()?;
+
+        // one invocation of each variant (HELP excepted)
+        let invocations = [
+            "PING",
+            "ECHO a",
+            "HELLO",
+            "CLIENT ID",
+            "SET a 1",
+            "GET a",
+            "GETDEL a",
+            "GETEX a",
+            "APPEND a b",
+            "SETRANGE a 0 b",
+            "STRLEN a",
+            "GETRANGE a 0 -1",
+            "SETBIT a 0 1",
+            "EXPIREAT a 1",
+            "PEXPIREAT a 1",
+            "DEL a",
+            "EXISTS a",
+            "DBSIZE",
+            "OBJECT ENCODING a",
+            "SAVE",
+            "BGSAVE",
+            "SELECT 0",
+            "COPY a b",
+            "INCR a",
+            "DECR a",
+            "INCRBY a 2",
+            "DECRBY a 2",
+            "INCRBYFLOAT a 1.5",
+            "INFO replication",
+            "REPLCONF listening-port 6380",
+            "PSYNC ? -1",
+            "WAIT 1 0",
+            "CONFIG GET dir",
+            "CLUSTER INFO",
+            "KEYS *",
+            "SCAN 0",
+            "TYPE a",
+            "COMMAND LIST",
+            "XADD s * a 1",
+            "XDEL s 1-1",
+            "XRANGE s - +",
+            "XREVRANGE s + -",
+            "XREAD streams s 0",
+            "XINFO STREAM s",
+            "XGROUP CREATE s g $",
+            "XREADGROUP GROUP g c STREAMS s >",
+            "XACK s g 0-1",
+            "XCLAIM s g c 0 0-1",
+            "XPENDING s g",
+            "LPUSH l a",
+            "RPUSH l a",
+            "LPOP l",
+            "RPOP l",
+            "LLEN l",
+            "LRANGE l 0 -1",
+            "BLPOP l 0",
+            "BRPOP l 0",
+            "LPOS l a",
+            "HRANDFIELD h",
+            "HSET h f v",
+            "HGET h f",
+            "HDEL h f",
+            "HGETALL h",
+            "HKEYS h",
+            "HVALS h",
+            "HLEN h",
+            "HINCRBY h f 1",
+            "HINCRBYFLOAT h f 1.5",
+            "HMGET h f",
+            "MGET a",
+            "MSET a b",
+            "HEXPIRE h 10 FIELDS 1 f",
+            "HTTL h FIELDS 1 f",
+            "ZRANDMEMBER z",
+            "ZSCORE z a",
+            "ZRANK z a",
+            "ZRANGE z 0 -1",
+            "ZREM z a",
+            "ZADD z 1 a",
+            "GEOADD g 1 2 a",
+            "GEOPOS g a",
+            "GEODIST g a b",
+            "GEOSEARCH g FROMLONLAT 1 2 BYRADIUS 10 km",
+            "PFADD h a",
+            "PFCOUNT h",
+            "PFMERGE h2 h",
+            "SADD s a",
+            "SREM s b",
+            "SMEMBERS s",
+            "SRANDMEMBER s",
+            "SPOP s 2",
+            "SINTER s",
+            "SUNION s",
+            "SDIFF s",
+            "SINTERSTORE d s",
+            "SUNIONSTORE d s",
+            "SDIFFSTORE d s",
+            "BITPOS a 1",
+            "BITOP AND d a",
+            "DEBUG SLEEP 0",
+            "SUBSCRIBE c",
+            "PSUBSCRIBE c*",
+            "SSUBSCRIBE c",
+            "UNSUBSCRIBE",
+            "PUNSUBSCRIBE",
+            "SUNSUBSCRIBE",
+            "PUBSUB NUMPAT",
+            "PUBLISH c m",
+            "SPUBLISH c m",
+            "MULTI",
+            "EXEC",
+            "DISCARD",
+            "WATCH a",
+            "UNWATCH",
+        ];
+        assert_eq!(count, invocations.len());
+        for invocation in invocations {
+            let redis_value = RedisValue::array_of_bulkstrings_from(invocation);
+            let name = RedisCommand::try_from(&redis_value)?.name();
+            assert!(names.contains(name), "{} is not in COMMAND LIST", name);
+        }
+        Ok(())
+    }
+
+    /// Runs SCAN with the cursor and returns the next cursor and the keys
+    fn scan(db: &mut RedisDb, cursor: &str, options: &str) -> Result<(String, Vec<String>)> {
+        match execute(db, &format!("SCAN {} {}", cursor, options))? {
+            RedisValue::Array(2, mut values) => {
+                let keys = bulkstrings(values.pop().unwrap());
+                Ok((values[0].inner_string()?, keys))
+            }
+            _ => panic!("SCAN should return the cursor and the keys"),
+        }
+    }
+
+    #[test]
+    fn test_scan() -> Result<()> {
+        let mut db = setup_db();
+        for i in 0..20 {
+            execute(&mut db, &format!("SET key{} {}", i, i))?;
+        }
+        setup_hash(&db);
+
+        let mut cursor = "0".to_string();
+        let mut seen = Vec::new();
+        loop {
+            let keys;
+            (cursor, keys) = scan(&mut db, &cursor, "COUNT 3")?;
+            assert!(keys.len() <= 3);
+            seen.extend(keys);
+            if cursor == "0" {
+                break;
+            }
+        }
+        assert_eq!(seen.len(), 21);
+
+        let (cursor, keys) = scan(&mut db, "0", "COUNT 100 MATCH key1*")?;
+        assert_eq!(cursor, "0");
+        assert_eq!(keys.len(), 11);
+        let (_, keys) = scan(&mut db, "0", "COUNT 100 TYPE hash")?;
+        assert_eq!(keys, vec!["myhash"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_scan_with_concurrent_mutations() -> Result<()> {
+        let mut db = setup_db();
+        let persistent = (0..50)
+            .map(|i| format!("persistent{}", i))
+            .collect::<Vec<_>>();
+        for key in persistent.iter() {
+            execute(&mut db, &format!("SET {} 1", key))?;
+        }
+        for i in 0..50 {
+            execute(&mut db, &format!("SET volatile{} 1", i))?;
+        }
+
+        let mut cursor = "0".to_string();
+        let mut seen = HashSet::new();
+        let mut iteration = 0;
+        loop {
+            let keys;
+            (cursor, keys) = scan(&mut db, &cursor, "COUNT 5")?;
+            seen.extend(keys);
+            if cursor == "0" {
+                break;
+            }
+
+            // between two calls, some keys are removed and others are added
+            execute(&mut db, &format!("DEL volatile{}", iteration))?;
+            for i in 0..3 {
+                execute(&mut db, &format!("SET added{}-{} 1", iteration, i))?;
+            }
+            iteration += 1;
+            assert!(iteration < 1000, "SCAN should terminate");
+        }
+
+        for key in persistent.iter() {
+            assert!(seen.contains(key), "{} was skipped", key);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_debug_flushall() -> Result<()> {
+        let mut db = setup_db();
+        execute(&mut db, "SET a 1")?;
+        execute(&mut db, "COPY a a DB 1")?;
+        assert_eq!(
+            execute(&mut db, "DEBUG FLUSHALL")?,
+            RedisValue::SimpleString("OK".to_string())
+        );
+        assert_eq!(execute(&mut db, "DBSIZE")?, RedisValue::Integer(0));
+        db.select(1)?;
+        assert_eq!(execute(&mut db, "DBSIZE")?, RedisValue::Integer(0));
+        Ok(())
+    }
+
+    #[test]
+    fn test_object_encoding() -> Result<()> {
+        let mut db = setup_db();
+        let encoding =
+            |db: &mut RedisDb, key: &str| execute(db, &format!("OBJECT ENCODING {}", key));
+        execute(&mut db, "SET int 12345")?;
+        execute(&mut db, "SET negative -12345")?;
+        execute(&mut db, "SET padded 012345")?;
+        execute(&mut db, &format!("SET max {}", i64::MAX))?;
+        execute(&mut db, &format!("SET too_big {}0", i64::MAX))?;
+        execute(&mut db, &format!("SET embstr {}", "a".repeat(44)))?;
+        execute(&mut db, &format!("SET raw {}", "a".repeat(45)))?;
+        execute(&mut db, "RPUSH list a")?;
+        execute(&mut db, "SADD intset 1 2 -3")?;
+        execute(&mut db, "SADD set 1 a")?;
+        execute(&mut db, "HSET hash a 1")?;
+        execute(&mut db, "ZADD zset 1 a")?;
+
+        let bulk = RedisValue::bulkstring_from;
+        assert_eq!(encoding(&mut db, "int")?, bulk("int"));
+        assert_eq!(encoding(&mut db, "negative")?, bulk("int"));
+        assert_eq!(encoding(&mut db, "padded")?, bulk("embstr"));
+        assert_eq!(encoding(&mut db, "max")?, bulk("int"));
+        assert_eq!(encoding(&mut db, "too_big")?, bulk("embstr"));
+        assert_eq!(encoding(&mut db, "embstr")?, bulk("embstr"));
+        assert_eq!(encoding(&mut db, "raw")?, bulk("raw"));
+        assert_eq!(encoding(&mut db, "list")?, bulk("quicklist"));
+        assert_eq!(encoding(&mut db, "missing")?, RedisValue::NullBulkString);
+        assert_eq!(encoding(&mut db, "intset")?, bulk("intset"));
+        assert_eq!(encoding(&mut db, "set")?, bulk("listpack"));
+        assert_eq!(encoding(&mut db, "hash")?, bulk("listpack"));
+        assert_eq!(encoding(&mut db, "zset")?, bulk("listpack"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_object_encoding_thresholds() -> Result<()> {
+        let mut db = setup_db();
+        db.info.encoding_thresholds = EncodingThresholds {
+            set_max_intset_entries: 2,
+            set_max_listpack_entries: 3,
+            set_max_listpack_value: 4,
+            hash_max_listpack_entries: 2,
+            hash_max_listpack_value: 4,
+            zset_max_listpack_entries: 2,
+            zset_max_listpack_value: 4,
+        };
+        let encoding = |db: &mut RedisDb, key: &str| -> Result<String> {
+            execute(db, &format!("OBJECT ENCODING {}", key))?.inner_string()
+        };
+
+        execute(&mut db, "SADD s 1 2")?;
+        assert_eq!(encoding(&mut db, "s")?, "intset");
+        execute(&mut db, "SADD s 3")?;
+        assert_eq!(encoding(&mut db, "s")?, "listpack");
+        execute(&mut db, "SADD s 4")?;
+        assert_eq!(encoding(&mut db, "s")?, "hashtable");
+        execute(&mut db, "SADD t 1 abcde")?;
+        assert_eq!(encoding(&mut db, "t")?, "hashtable");
+
+        execute(&mut db, "HSET h a 1 b 2")?;
+        assert_eq!(encoding(&mut db, "h")?, "listpack");
+        execute(&mut db, "HSET h c 3")?;
+        assert_eq!(encoding(&mut db, "h")?, "hashtable");
+        execute(&mut db, "HSET i a abcde")?;
+        assert_eq!(encoding(&mut db, "i")?, "hashtable");
+
+        execute(&mut db, "ZADD z 1 a 2 b")?;
+        assert_eq!(encoding(&mut db, "z")?, "listpack");
+        execute(&mut db, "ZADD z 3 c")?;
+        assert_eq!(encoding(&mut db, "z")?, "skiplist");
+        execute(&mut db, "ZADD y 1 abcde")?;
+        assert_eq!(encoding(&mut db, "y")?, "skiplist");
+
+        assert_eq!(
+            execute(&mut db, "CONFIG GET set-max-intset-entries")?,
+            RedisValue::array_of_bulkstrings_from("set-max-intset-entries 2")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_cluster() -> Result<()> {
+        let mut db = setup_db();
+        let RedisValue::VerbatimString(_, info) = execute(&mut db, "CLUSTER INFO")? else {
+            panic!("CLUSTER INFO should return a verbatim string");
+        };
+        assert!(info.starts_with("cluster_enabled:0\r\ncluster_state:ok\r\n"));
+        assert_eq!(
+            execute(&mut db, "cluster myid")?,
+            RedisValue::bulkstring_from(&db.info.master_replid)
+        );
+        for subcommand in ["SLOTS", "SHARDS"] {
+            assert_eq!(
+                execute(&mut db, &format!("CLUSTER {}", subcommand))?,
+                RedisValue::Array(0, Vec::new())
+            );
+        }
+        assert!(
+            RedisCommand::try_from(&RedisValue::array_of_bulkstrings_from("CLUSTER NODES"))
+                .is_err()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_config_set() -> Result<()> {
+        let mut db = setup_db();
+        assert_eq!(
+            execute(&mut db, "CONFIG GET maxmemory")?,
+            RedisValue::array_of_bulkstrings_from("maxmemory 0")
+        );
+        assert_eq!(
+            execute(&mut db, "CONFIG SET maxmemory 100mb")?,
+            RedisValue::SimpleString("OK".to_string())
+        );
+        assert_eq!(
+            execute(&mut db, "CONFIG GET maxmemory")?,
+            RedisValue::array_of_bulkstrings_from("maxmemory 104857600")
+        );
+
+        assert_eq!(
+            execute(&mut db, "CONFIG SET MAXMEMORY-POLICY allkeys-LRU")?,
+            RedisValue::SimpleString("OK".to_string())
+        );
+        assert_eq!(
+            execute(&mut db, "CONFIG GET maxmemory-policy")?,
+            RedisValue::array_of_bulkstrings_from("maxmemory-policy allkeys-lru")
+        );
+
+        // nothing is set if one of the values is invalid
+        assert_eq!(
+            execute(&mut db, "CONFIG SET maxmemory 1kb maxmemory-policy foo")?,
+            RedisValue::SimpleError(
+                "ERR CONFIG SET failed (possibly related to argument 'maxmemory-policy') - \
+                 argument(s) must be one of the following: volatile-lru, volatile-lfu, \
+                 volatile-random, volatile-ttl, allkeys-lru, allkeys-lfu, allkeys-random, \
+                 noeviction"
+                    .to_string()
+            )
+        );
+        assert_eq!(
+            execute(&mut db, "CONFIG GET maxmemory")?,
+            RedisValue::array_of_bulkstrings_from("maxmemory 104857600")
+        );
+        assert!(matches!(
+            execute(&mut db, "CONFIG SET maxmemory 1tb")?,
+            RedisValue::SimpleError(_)
+        ));
+
+        execute(&mut db, "CONFIG SET proto-max-bulk-len 1k")?;
+        assert_eq!(
+            execute(&mut db, "CONFIG GET proto-max-bulk-len")?,
+            RedisValue::array_of_bulkstrings_from("proto-max-bulk-len 1000")
+        );
+
+        assert_eq!(
+            execute(&mut db, "CONFIG GET appendonly")?,
+            RedisValue::array_of_bulkstrings_from("appendonly no")
+        );
+        assert_eq!(
+            execute(&mut db, "CONFIG GET MAXMEMORY*")?,
+            RedisValue::array_of_bulkstrings_from(
+                "maxmemory 104857600 maxmemory-policy allkeys-lru"
+            )
+        );
+        assert_eq!(bulkstrings(execute(&mut db, "CONFIG GET *max*")?).len(), 20);
+        assert_eq!(
+            execute(&mut db, "CONFIG GET unknown")?,
+            RedisValue::Array(0, vec![])
+        );
+        assert_eq!(
+            execute(&mut db, "CONFIG SET appendonly yes")?,
+            RedisValue::SimpleError(
+                "ERR CONFIG SET failed (possibly related to argument 'appendonly') - can't set \
+                 immutable config"
+                    .to_string()
+            )
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_debug_object() -> Result<()> {
+        let mut db = setup_db();
+        execute(&mut db, "SET a 10")?;
+        execute(&mut db, "RPUSH list a bc")?;
+
+        let RedisValue::SimpleString(debug) = execute(&mut db, "DEBUG OBJECT a")? else {
+            panic!("DEBUG OBJECT should return a simple string");
+        };
+        assert!(debug.contains(" encoding:int "));
+        assert!(!debug.contains("ql_nodes"));
+
+        let RedisValue::SimpleString(debug) = execute(&mut db, "DEBUG OBJECT list")? else {
+            panic!("DEBUG OBJECT should return a simple string");
+        };
+        assert!(debug.contains(" encoding:quicklist "));
+        assert!(debug.contains(" ql_nodes:1 "));
+        assert!(debug.contains(" ql_avg_node:2.00 "));
+
+        assert_eq!(
+            execute(&mut db, "DEBUG OBJECT missing")?,
+            RedisValue::SimpleError("ERR no such key".to_string())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_set_active_expire() -> Result<()> {
+        let clock = MockClock::install();
+        let mut db = setup_db();
+        execute(&mut db, "DEBUG SET-ACTIVE-EXPIRE 0")?;
+        execute(&mut db, "SET a 1 PX 1")?;
+        clock.advance(Duration::from_millis(5));
+
+        // the expired key is only evicted when it is accessed
+        assert_eq!(db.active_expire_cycle(), 0);
+        assert_eq!(execute(&mut db, "DBSIZE")?, RedisValue::Integer(1));
+        assert_eq!(execute(&mut db, "GET a")?, RedisValue::NullBulkString);
+        assert_eq!(execute(&mut db, "DBSIZE")?, RedisValue::Integer(0));
+
+        execute(&mut db, "SET b 1 PX 1")?;
+        execute(&mut db, "DEBUG SET-ACTIVE-EXPIRE 1")?;
+        clock.advance(Duration::from_millis(5));
+        assert_eq!(db.active_expire_cycle(), 1);
+        assert_eq!(execute(&mut db, "DBSIZE")?, RedisValue::Integer(0));
+
+        // replicas never expire keys on their own, whatever the flag
+        let db_info = DbInfo::build("slave", 6380, "/tmp/redis-files", "dump.rdb");
+        let mut replica_db = RedisDb::build(db_info, ConnectionState::Ready);
+        execute(&mut replica_db, "SET a 1 PX 1")?;
+        clock.advance(Duration::from_millis(5));
+        assert_eq!(replica_db.active_expire_cycle(), 0);
+        assert_eq!(execute(&mut replica_db, "DBSIZE")?, RedisValue::Integer(1));
+        Ok(())
+    }
+
+    #[test]
+    fn test_copy_to_other_db() -> Result<()> {
+        let mut db = setup_db();
+        let token = Token(20);
+        execute(&mut db, "SET a 1")?;
+        assert_eq!(execute(&mut db, "COPY a b DB 1")?, RedisValue::Integer(1));
+        assert_eq!(execute(&mut db, "COPY a b DB 1")?, RedisValue::Integer(0));
+        assert_eq!(
+            execute(&mut db, "COPY a b DB 1 REPLACE")?,
+            RedisValue::Integer(1)
+        );
+        assert!(matches!(
+            execute(&mut db, "COPY a b DB 16")?,
+            RedisValue::SimpleError(_)
+        ));
+        assert!(matches!(
+            execute(&mut db, "COPY a a")?,
+            RedisValue::SimpleError(_)
+        ));
+        // the copy is only visible in the destination db
+        assert_eq!(execute(&mut db, "GET b")?, RedisValue::NullBulkString);
+
+        execute_for_connection(&mut db, token, "SELECT 1")?;
+        assert_eq!(execute(&mut db, "GET b")?, RedisValue::bulkstring_from("1"));
+        assert_eq!(execute(&mut db, "GET a")?, RedisValue::NullBulkString);
+
+        // unless it is copied back
+        assert_eq!(execute(&mut db, "COPY b b DB 0")?, RedisValue::Integer(1));
+        db.restore_connection_db(Token(21));
+        assert_eq!(execute(&mut db, "GET b")?, RedisValue::bulkstring_from("1"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_getdel_getex() -> Result<()> {
+        let clock = MockClock::install();
+        let mut db = setup_db();
+        execute(&mut db, "SET a 1")?;
+        assert_eq!(
+            execute(&mut db, "GETEX a")?,
+            RedisValue::bulkstring_from("1")
+        );
+        assert_eq!(
+            execute(&mut db, "GETDEL a")?,
+            RedisValue::bulkstring_from("1")
+        );
+        assert_eq!(execute(&mut db, "GETDEL a")?, RedisValue::NullBulkString);
+
+        execute(&mut db, "SET b 2")?;
+        assert_eq!(
+            execute(&mut db, "GETEX b PX 1")?,
+            RedisValue::bulkstring_from("2")
+        );
+        clock.advance(Duration::from_millis(5));
+        assert_eq!(execute(&mut db, "GET b")?, RedisValue::NullBulkString);
+        Ok(())
+    }
+
+    #[test]
+    fn test_getex_options() -> Result<()> {
+        let clock = MockClock::install();
+        let mut db = setup_db();
+        execute(&mut db, "SET a 1 PX 20")?;
+        assert_eq!(
+            execute(&mut db, "GETEX a PERSIST")?,
+            RedisValue::bulkstring_from("1")
+        );
+        execute(&mut db, "SET b 1 PX 20")?;
+        // without option, the expiration is left untouched
+        execute(&mut db, "GETEX b")?;
+        clock.advance(Duration::from_millis(30));
+        assert_eq!(execute(&mut db, "GET a")?, RedisValue::bulkstring_from("1"));
+        assert_eq!(execute(&mut db, "GET b")?, RedisValue::NullBulkString);
+
+        let in_one_hour = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("time should not go backward")
+            + Duration::from_secs(3600);
+        execute(&mut db, &format!("GETEX a EXAT {}", in_one_hour.as_secs()))?;
+        assert_eq!(execute(&mut db, "GET a")?, RedisValue::bulkstring_from("1"));
+        execute(&mut db, "GETEX a PXAT 1000")?;
+        assert_eq!(execute(&mut db, "GET a")?, RedisValue::NullBulkString);
+
+        execute(&mut db, "SET c 1")?;
+        assert_eq!(
+            execute(&mut db, "GETEX c EX 0")?,
+            RedisValue::SimpleError("ERR invalid expire time in 'getex' command".to_string())
+        );
+        assert_eq!(
+            execute(&mut db, "GETEX missing PERSIST")?,
+            RedisValue::NullBulkString
+        );
+        assert_eq!(
+            execute(&mut db, "GETDEL missing")?,
+            RedisValue::NullBulkString
+        );
+        assert_eq!(db.dbsize(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_set_nx_xx_get() -> Result<()> {
+        let mut db = setup_db();
+        let ok = RedisValue::SimpleString("OK".to_string());
+        assert_eq!(execute(&mut db, "SET a 1 XX")?, RedisValue::NullBulkString);
+        assert_eq!(execute(&mut db, "GET a")?, RedisValue::NullBulkString);
+        assert_eq!(execute(&mut db, "SET a 1 nx")?, ok);
+        assert_eq!(execute(&mut db, "SET a 2 NX")?, RedisValue::NullBulkString);
+        assert_eq!(execute(&mut db, "SET a 3 XX")?, ok);
+
+        // GET replies with the old value even if the key is not set
+        assert_eq!(
+            execute(&mut db, "SET a 4 NX GET")?,
+            RedisValue::bulkstring_from("3")
+        );
+        assert_eq!(
+            execute(&mut db, "SET a 5 GET")?,
+            RedisValue::bulkstring_from("3")
+        );
+        assert_eq!(execute(&mut db, "SET b 1 GET")?, RedisValue::NullBulkString);
+        assert_eq!(execute(&mut db, "GET b")?, RedisValue::bulkstring_from("1"));
+
+        setup_hash(&db);
+        assert_eq!(
+            execute(&mut db, "SET myhash 1 GET")?,
+            RedisValue::SimpleError(Error::WrongTypeOperation.to_string())
+        );
+        assert_eq!(execute(&mut db, "SET myhash 1")?, ok);
+        Ok(())
+    }
+
+    #[test]
+    fn test_set_expiry_options() -> Result<()> {
+        let clock = MockClock::install();
+        let mut db = setup_db();
+        let ok = RedisValue::SimpleString("OK".to_string());
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("time should not go backward");
+        let in_one_hour = now + Duration::from_secs(3600);
+
+        execute(&mut db, &format!("SET a 1 EXAT {}", in_one_hour.as_secs()))?;
+        execute(
+            &mut db,
+            &format!("SET b 1 pxat {}", in_one_hour.as_millis()),
+        )?;
+        execute(&mut db, "SET c 1 EX 3600")?;
+        assert_eq!(db.dbsize(), 3);
+
+        // a time in the past expires the key right away
+        execute(&mut db, "SET a 1 PXAT 1000")?;
+        assert_eq!(execute(&mut db, "GET a")?, RedisValue::NullBulkString);
+
+        execute(&mut db, "SET d 1 PX 20")?;
+        assert_eq!(execute(&mut db, "SET d 2 KEEPTTL")?, ok);
+        execute(&mut db, "SET e 1 PX 20")?;
+        execute(&mut db, "SET e 2")?;
+        clock.advance(Duration::from_millis(30));
+        assert_eq!(execute(&mut db, "GET d")?, RedisValue::NullBulkString);
+        assert_eq!(execute(&mut db, "GET e")?, RedisValue::bulkstring_from("2"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_set_conflicting_options() -> Result<()> {
+        let mut db = setup_db();
+        let syntax_error = RedisValue::SimpleError("ERR syntax error".to_string());
+        assert_eq!(execute(&mut db, "SET a 1 NX XX")?, syntax_error);
+        assert_eq!(execute(&mut db, "SET a 1 EX 10 PX 100")?, syntax_error);
+        assert_eq!(execute(&mut db, "SET a 1 PX 100 KEEPTTL")?, syntax_error);
+        assert_eq!(
+            execute(&mut db, "SET a 1 EX 0")?,
+            RedisValue::SimpleError("ERR invalid expire time in 'set' command".to_string())
+        );
+        assert_eq!(db.dbsize(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_getex_forwarded_only_when_it_mutates() -> Result<()> {
+        let parse =
+            |input: &str| RedisCommand::try_from(&RedisValue::array_of_bulkstrings_from(input));
+        assert!(!parse("GETEX a")?.should_forward_to_replicas());
+        assert!(parse("GETEX a EX 10")?.should_forward_to_replicas());
+        assert!(parse("GETEX a PERSIST")?.should_forward_to_replicas());
+        assert!(parse("GETDEL a")?.should_forward_to_replicas());
+        Ok(())
+    }
+
+    #[test]
+    fn test_debug_lenient() -> Result<()> {
+        let mut db = setup_db();
+        assert!(matches!(
+            execute(&mut db, "DEBUG QUICKLIST-PACKED-THRESHOLD 100")?,
+            RedisValue::SimpleError(_)
+        ));
+
+        db.info.debug_lenient = true;
+        assert_eq!(
+            execute(&mut db, "DEBUG QUICKLIST-PACKED-THRESHOLD 100")?,
+            RedisValue::SimpleString("OK".to_string())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_help() -> Result<()> {
+        let mut db = setup_db();
+        for container in [
+            "CONFIG", "COMMAND", "DEBUG", "PUBSUB", "XGROUP", "CLIENT", "XINFO",
+        ] {
+            match execute(&mut db, &format!("{} HELP", container))? {
+                RedisValue::Array(_, lines) => {
+                    assert!(
+                        matches!(&lines[0], RedisValue::SimpleString(x) if x.starts_with(container))
+                    );
+                    assert!(lines.contains(&RedisValue::SimpleString("HELP".to_string())));
+                }
+                _ => panic!("Expected an array of help lines"),
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_client_id() -> Result<()> {
+        let mut db = setup_db();
+        assert_eq!(
+            execute_for_connection(&mut db, Token(21), "CLIENT ID")?,
+            vec![RedisValue::Integer(21)]
+        );
+        assert!(matches!(
+            execute(&mut db, "CLIENT KILL 1"),
+            Err(Error::InvalidRedisValue(_))
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_xinfo_stream() -> Result<()> {
+        let mut db = setup_db();
+        assert_eq!(
+            execute(&mut db, "XINFO STREAM s")?,
+            RedisValue::SimpleError("ERR no such key".to_string())
+        );
+        execute(&mut db, "XADD s 1-1 a 1")?;
+        execute(&mut db, "XADD s 2-1 b 2")?;
+        execute(&mut db, "XGROUP CREATE s g $")?;
+
+        let RedisValue::Map(fields) = execute(&mut db, "XINFO STREAM s")? else {
+            panic!("XINFO STREAM should return a map");
+        };
+        let entry = |id: &str, field: &str, value: &str| {
+            RedisValue::Array(
+                2,
+                vec![
+                    RedisValue::bulkstring_from(id),
+                    RedisValue::array_of_bulkstrings_from(&format!("{field} {value}")),
+                ],
+            )
+        };
+        let values = fields
+            .into_iter()
+            .map(|(_, value)| value)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            values,
+            vec![
+                RedisValue::Integer(2),
+                RedisValue::bulkstring_from("2-1"),
+                RedisValue::Integer(1),
+                entry("1-1", "a", "1"),
+                entry("2-1", "b", "2"),
+            ]
+        );
+
+        execute(&mut db, "SET a 1")?;
+        assert_eq!(
+            execute(&mut db, "XINFO STREAM a")?,
+            RedisValue::SimpleError(Error::WrongTypeOperation.to_string())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_hello() -> Result<()> {
+        let mut db = setup_db();
+        let token = Token(20);
+        assert_eq!(db.protocol(token), 2);
+
+        let res = execute_for_connection(&mut db, token, "HELLO 3")?;
+        match &res[0] {
+            RedisValue::Map(fields) if fields.len() == 7 => {
+                assert_eq!(
+                    fields[2],
+                    (RedisValue::bulkstring_from("proto"), RedisValue::Integer(3))
+                );
+            }
+            _ => panic!("Expected the HELLO fields"),
+        }
+        assert_eq!(db.protocol(token), 3);
+        assert!(db
+            .encode_for(token, res[0].clone())
+            .to_bytes()
+            .starts_with(b"%7\r\n"));
+
+        // RESP2 connections receive a flat array of fields and values
+        let res = execute_for_connection(&mut db, Token(21), "HELLO")?;
+        match db.encode_for(Token(21), res[0].clone()) {
+            RedisValue::Array(14, fields) => {
+                assert_eq!(fields[4], RedisValue::bulkstring_from("proto"));
+                assert_eq!(fields[5], RedisValue::Integer(2));
+            }
+            _ => panic!("Expected the HELLO fields"),
+        }
+
+        // RESP3 values are only sent as is on RESP3 connections
+        let info = execute(&mut db, "INFO replication")?;
+        assert!(matches!(
+            db.encode_for(token, info.clone()),
+            RedisValue::VerbatimString(_, _)
+        ));
+        assert!(matches!(
+            db.encode_for(Token(21), info),
+            RedisValue::BulkString(_, _)
+        ));
+
+        let res = execute_for_connection(&mut db, token, "HELLO 4")?;
+        assert!(matches!(&res[0], RedisValue::SimpleError(e) if e.starts_with("NOPROTO")));
+        assert_eq!(db.protocol(token), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_psync_reports_offset() -> Result<()> {
+        let mut db = setup_db();
+        db.info.master_repl_offset = 42;
+        assert_eq!(
+            execute(&mut db, "PSYNC ? -1")?,
+            RedisValue::SimpleString(format!("FULLRESYNC {} 42", db.info.master_replid))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_xadd_auto_id_round_trip() -> Result<()> {
+        let mut db = setup_db();
+        let mut stream_ids = Vec::new();
+        // several ids generated within the same millisecond must stay increasing
+        for i in 0..5 {
+            let res = execute(&mut db, &format!("XADD mystream * field {}", i))?;
+            stream_ids.push(res.inner_string()?);
+        }
+        let mut sorted_stream_ids = stream_ids.clone();
+        sorted_stream_ids.sort_by_key(|stream_id| {
+            let (ts, seq) = stream_id.split_once('-').unwrap();
+            (ts.parse::<u64>().unwrap(), seq.parse::<u64>().unwrap())
+        });
+        sorted_stream_ids.dedup();
+        assert_eq!(sorted_stream_ids, stream_ids);
+
+        for (i, stream_id) in stream_ids.iter().enumerate() {
+            let res = execute(
+                &mut db,
+                &format!("XRANGE mystream {} {}", stream_id, stream_id),
+            )?;
+            assert_eq!(
+                res,
+                stream_entries_to_redis_value(&[(
+                    stream_id.clone(),
+                    HashMap::from([("field".to_string(), i.to_string())])
+                )])
+            );
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,1173 @@
+use crate::command::{self, RedisCommand};
+use crate::parser::RedisValue;
+use crate::Result;
+
+/// Parses the arguments of a command: its lowercase name, the arguments after the name
+/// and the whole value, for the errors
+pub type Parser = fn(&str, &[RedisValue], &RedisValue) -> Result<RedisCommand>;
+
+/// Finds the positions of the keys among the arguments of a command whose keys can't
+/// be described by first_key, last_key and key_step
+pub type KeyFinder = fn(&[String]) -> Vec<usize>;
+
+/// Access flags of a key, with the names of the redis key specs: RO, RW, OW (overwrite)
+/// or RM (remove), then what is done to the value
+type KeyFlags = &'static [&'static str];
+
+const READ: KeyFlags = &["RO", "access"];
+const UPDATE: KeyFlags = &["RW", "access", "update"];
+const INSERT: KeyFlags = &["RW", "insert"];
+const DELETE: KeyFlags = &["RW", "access", "delete"];
+const OVERWRITE: KeyFlags = &["OW", "update"];
+
+/// Static description of a command, as returned by COMMAND INFO and COMMAND DOCS, and
+/// the parser that turns it into a RedisCommand
+#[derive(Debug, Clone)]
+pub struct CommandMetadata {
+    pub name: &'static str,
+    /// One line description, as returned by COMMAND DOCS
+    pub summary: &'static str,
+    /// Number of arguments including the command name. A negative arity -N means at
+    /// least N arguments.
+    pub arity: i64,
+    pub flags: &'static [&'static str],
+    /// Position of the first key in the arguments, 0 if the command has no keys
+    pub first_key: i64,
+    /// Position of the last key, -1 if the keys go until the end of the arguments
+    pub last_key: i64,
+    pub key_step: i64,
+    /// Access flags of the keys, in order, the last ones apply to the remaining keys.
+    /// When empty, the keys are read, or updated for the write commands
+    pub key_specs: &'static [KeyFlags],
+    /// For the commands whose keys depend on the arguments, such as XREAD
+    pub find_keys: Option<KeyFinder>,
+    pub parse: Parser,
+}
+
+impl CommandMetadata {
+    const fn new(
+        name: &'static str,
+        summary: &'static str,
+        arity: i64,
+        flags: &'static [&'static str],
+        (first_key, last_key, key_step): (i64, i64, i64),
+        parse: Parser,
+    ) -> Self {
+        Self {
+            name,
+            summary,
+            arity,
+            flags,
+            first_key,
+            last_key,
+            key_step,
+            key_specs: &[],
+            find_keys: None,
+            parse,
+        }
+    }
+
+    const fn with_key_specs(mut self, key_specs: &'static [KeyFlags]) -> Self {
+        self.key_specs = key_specs;
+        self
+    }
+
+    const fn with_key_finder(mut self, find_keys: KeyFinder) -> Self {
+        self.find_keys = Some(find_keys);
+        self
+    }
+
+    /// Whether the arity allows this number of arguments, including the command name
+    pub fn accepts(&self, nb_arguments: usize) -> bool {
+        if self.arity < 0 {
+            nb_arguments as i64 >= -self.arity
+        } else {
+            nb_arguments as i64 == self.arity
+        }
+    }
+
+    /// Positions of the keys among the arguments, the command name being at 0
+    pub fn key_positions(&self, args: &[String]) -> Vec<usize> {
+        if let Some(find_keys) = self.find_keys {
+            return find_keys(args);
+        }
+        let nb_arguments = args.len();
+        if self.first_key == 0 {
+            return Vec::new();
+        }
+        let last_key = if self.last_key < 0 {
+            nb_arguments as i64 + self.last_key
+        } else {
+            self.last_key.min(nb_arguments as i64 - 1)
+        };
+        (self.first_key..=last_key)
+            .step_by(self.key_step as usize)
+            .map(|position| position as usize)
+            .collect()
+    }
+
+    /// Access flags of the key at this index among the keys of the command
+    pub fn key_flags(&self, index: usize) -> KeyFlags {
+        let key_specs: &[KeyFlags] = if !self.key_specs.is_empty() {
+            self.key_specs
+        } else if self.flags.contains(&"write") {
+            &[UPDATE]
+        } else {
+            &[READ]
+        };
+        key_specs[index.min(key_specs.len() - 1)]
+    }
+}
+
+/// Keys of XREAD and XREADGROUP: the first half of the arguments after STREAMS. As in
+/// redis, the options before it are skipped so that a group named STREAMS is not
+/// mistaken for it
+fn streams_keys(args: &[String]) -> Vec<usize> {
+    let mut position = 1;
+    while position < args.len() {
+        match args[position].to_lowercase().as_str() {
+            "count" | "block" => position += 2,
+            "group" => position += 3,
+            "streams" => {
+                let nb_arguments = args.len() - position - 1;
+                if nb_arguments % 2 != 0 {
+                    return Vec::new();
+                }
+                return (position + 1..=position + nb_arguments / 2).collect();
+            }
+            _ => position += 1,
+        }
+    }
+    Vec::new()
+}
+
+const NO_KEYS: (i64, i64, i64) = (0, 0, 0);
+const FIRST_KEY: (i64, i64, i64) = (1, 1, 1);
+
+/// Every command understood by the server. Must be kept in sync with the parsing of
+/// RedisCommand.
+pub const COMMAND_TABLE: &[CommandMetadata] = &[
+    CommandMetadata::new(
+        "ping",
+        "Returns the server's liveliness response.",
+        -1,
+        &["fast", "stale"],
+        NO_KEYS,
+        command::parse_ping,
+    ),
+    CommandMetadata::new(
+        "echo",
+        "Returns the given string.",
+        2,
+        &["fast"],
+        NO_KEYS,
+        command::parse_echo,
+    ),
+    CommandMetadata::new(
+        "hello",
+        "Handshakes with the Redis server.",
+        -1,
+        &["noscript", "loading", "stale", "fast"],
+        NO_KEYS,
+        command::parse_hello,
+    ),
+    CommandMetadata::new(
+        "client",
+        "A container for client connection commands.",
+        -2,
+        &["noscript", "loading", "stale"],
+        NO_KEYS,
+        command::parse_client,
+    ),
+    CommandMetadata::new(
+        "set",
+        "Sets the string value of a key, ignoring its type. The key is created if it doesn't exist.",
+        -3,
+        &["write", "denyoom"],
+        FIRST_KEY,
+        command::parse_set,
+    ),
+    CommandMetadata::new(
+        "get",
+        "Returns the string value of a key.",
+        2,
+        &["readonly", "fast"],
+        FIRST_KEY,
+        command::parse_get,
+    ),
+    CommandMetadata::new(
+        "getdel",
+        "Returns the string value of a key after deleting the key.",
+        2,
+        &["write", "fast"],
+        FIRST_KEY,
+        command::parse_getdel,
+    )
+    .with_key_specs(&[DELETE]),
+    CommandMetadata::new(
+        "getex",
+        "Returns the string value of a key after setting its expiration time.",
+        -2,
+        &["write", "fast"],
+        FIRST_KEY,
+        command::parse_getex,
+    ),
+    CommandMetadata::new(
+        "del",
+        "Deletes one or more keys.",
+        -2,
+        &["write"],
+        (1, -1, 1),
+        command::parse_del,
+    )
+    .with_key_specs(&[&["RM", "delete"]]),
+    CommandMetadata::new(
+        "append",
+        "Appends a string to the value of a key. Creates the key if it doesn't exist.",
+        3,
+        &["write", "denyoom", "fast"],
+        FIRST_KEY,
+        command::parse_append,
+    )
+    .with_key_specs(&[INSERT]),
+    CommandMetadata::new(
+        "setrange",
+        "Overwrites a part of a string value with another by an offset. Creates the key if it doesn't exist.",
+        4,
+        &["write", "denyoom"],
+        FIRST_KEY,
+        command::parse_setrange,
+    ),
+    CommandMetadata::new(
+        "strlen",
+        "Returns the length of a string value.",
+        2,
+        &["readonly", "fast"],
+        FIRST_KEY,
+        command::parse_strlen,
+    ),
+    CommandMetadata::new(
+        "getrange",
+        "Returns a substring of the string stored at a key.",
+        4,
+        &["readonly"],
+        FIRST_KEY,
+        command::parse_getrange,
+    ),
+    CommandMetadata::new(
+        "setbit",
+        "Sets or clears the bit at offset of the string value. Creates the key if it doesn't exist.",
+        4,
+        &["write", "denyoom"],
+        FIRST_KEY,
+        command::parse_setbit,
+    ),
+    CommandMetadata::new(
+        "expireat",
+        "Sets the expiration time of a key to a Unix timestamp.",
+        3,
+        &["write", "fast"],
+        FIRST_KEY,
+        command::parse_expireat,
+    )
+    .with_key_specs(&[&["RW", "update"]]),
+    CommandMetadata::new(
+        "pexpireat",
+        "Sets the expiration time of a key to a Unix milliseconds timestamp.",
+        3,
+        &["write", "fast"],
+        FIRST_KEY,
+        command::parse_expireat,
+    )
+    .with_key_specs(&[&["RW", "update"]]),
+    CommandMetadata::new(
+        "mget",
+        "Atomically returns the string values of one or more keys.",
+        -2,
+        &["readonly", "fast"],
+        (1, -1, 1),
+        command::parse_mget,
+    ),
+    CommandMetadata::new(
+        "mset",
+        "Atomically creates or modifies the string values of one or more keys.",
+        -3,
+        &["write", "denyoom"],
+        (1, -1, 2),
+        command::parse_mset,
+    )
+    .with_key_specs(&[OVERWRITE]),
+    CommandMetadata::new(
+        "exists",
+        "Determines whether one or more keys exist.",
+        -2,
+        &["readonly", "fast"],
+        (1, -1, 1),
+        command::parse_exists,
+    ),
+    CommandMetadata::new(
+        "select",
+        "Changes the selected database.",
+        2,
+        &["loading", "stale", "fast"],
+        NO_KEYS,
+        command::parse_select,
+    ),
+    CommandMetadata::new(
+        "copy",
+        "Copies the value of a key to a new key.",
+        -3,
+        &["write", "denyoom"],
+        (1, 2, 1),
+        command::parse_copy,
+    )
+    .with_key_specs(&[READ, OVERWRITE]),
+    CommandMetadata::new(
+        "dbsize",
+        "Returns the number of keys in the database.",
+        1,
+        &["readonly", "fast"],
+        NO_KEYS,
+        command::parse_dbsize,
+    ),
+    CommandMetadata::new(
+        "save",
+        "Synchronously saves the database(s) to disk.",
+        1,
+        &["admin", "noscript", "no_async_loading", "no_multi"],
+        NO_KEYS,
+        command::parse_save,
+    ),
+    CommandMetadata::new(
+        "bgsave",
+        "Asynchronously saves the database(s) to disk.",
+        -1,
+        &["admin", "noscript", "no_async_loading"],
+        NO_KEYS,
+        command::parse_save,
+    ),
+    CommandMetadata::new(
+        "incr",
+        "Increments the integer value of a key by one. Uses 0 as initial value if the key doesn't exist.",
+        2,
+        &["write", "denyoom", "fast"],
+        FIRST_KEY,
+        command::parse_incr,
+    ),
+    CommandMetadata::new(
+        "decr",
+        "Decrements the integer value of a key by one. Uses 0 as initial value if the key doesn't exist.",
+        2,
+        &["write", "denyoom", "fast"],
+        FIRST_KEY,
+        command::parse_increment,
+    ),
+    CommandMetadata::new(
+        "incrby",
+        "Increments the integer value of a key by a number. Uses 0 as initial value if the key doesn't exist.",
+        3,
+        &["write", "denyoom", "fast"],
+        FIRST_KEY,
+        command::parse_increment,
+    ),
+    CommandMetadata::new(
+        "decrby",
+        "Decrements a number from the integer value of a key. Uses 0 as initial value if the key doesn't exist.",
+        3,
+        &["write", "denyoom", "fast"],
+        FIRST_KEY,
+        command::parse_increment,
+    ),
+    CommandMetadata::new(
+        "incrbyfloat",
+        "Increment the floating point value of a key by a number. Uses 0 as initial value if the key doesn't exist.",
+        3,
+        &["write", "denyoom", "fast"],
+        FIRST_KEY,
+        command::parse_increment,
+    ),
+    CommandMetadata::new(
+        "info",
+        "Returns information and statistics about the server.",
+        -1,
+        &["loading", "stale"],
+        NO_KEYS,
+        command::parse_info,
+    ),
+    CommandMetadata::new(
+        "replconf",
+        "An internal command for configuring the replication stream.",
+        -1,
+        &["admin", "noscript", "loading", "stale"],
+        NO_KEYS,
+        command::parse_replconf,
+    ),
+    CommandMetadata::new(
+        "psync",
+        "An internal command used in replication.",
+        -3,
+        &["admin", "noscript"],
+        NO_KEYS,
+        command::parse_psync,
+    ),
+    CommandMetadata::new(
+        "wait",
+        "Blocks until the asynchronous replication of all preceding write commands sent by the connection is completed.",
+        3,
+        &["noscript"],
+        NO_KEYS,
+        command::parse_wait,
+    ),
+    CommandMetadata::new(
+        "config",
+        "A container for server configuration commands.",
+        -2,
+        &["admin", "noscript", "loading", "stale"],
+        NO_KEYS,
+        command::parse_config,
+    ),
+    CommandMetadata::new(
+        "cluster",
+        "A container for Redis Cluster commands.",
+        -2,
+        &["loading", "stale"],
+        NO_KEYS,
+        command::parse_cluster,
+    ),
+    CommandMetadata::new(
+        "keys",
+        "Returns all key names that match a pattern.",
+        2,
+        &["readonly"],
+        NO_KEYS,
+        command::parse_keys,
+    ),
+    CommandMetadata::new(
+        "scan",
+        "Iterates over the key names in the database.",
+        -2,
+        &["readonly"],
+        NO_KEYS,
+        command::parse_scan,
+    ),
+    CommandMetadata::new(
+        "type",
+        "Determines the type of value stored at a key.",
+        2,
+        &["readonly", "fast"],
+        FIRST_KEY,
+        command::parse_type,
+    ),
+    CommandMetadata::new(
+        "command",
+        "Returns detailed information about all commands.",
+        -1,
+        &["loading", "stale"],
+        NO_KEYS,
+        command::parse_command,
+    ),
+    CommandMetadata::new(
+        "xadd",
+        "Appends a new message to a stream. Creates the key if it doesn't exist.",
+        -5,
+        &["write", "denyoom", "fast"],
+        FIRST_KEY,
+        command::parse_xadd,
+    ),
+    CommandMetadata::new(
+        "xdel",
+        "Returns the number of messages after removing them from a stream.",
+        -3,
+        &["write", "fast"],
+        FIRST_KEY,
+        command::parse_xdel,
+    )
+    .with_key_specs(&[DELETE]),
+    CommandMetadata::new(
+        "xrange",
+        "Returns the messages from a stream within a range of IDs.",
+        -4,
+        &["readonly"],
+        FIRST_KEY,
+        command::parse_xrange,
+    ),
+    CommandMetadata::new(
+        "xrevrange",
+        "Returns the messages from a stream within a range of IDs in reverse order.",
+        -4,
+        &["readonly"],
+        FIRST_KEY,
+        command::parse_xrevrange,
+    ),
+    CommandMetadata::new(
+        "xread",
+        "Returns messages from multiple streams with IDs greater than the ones requested. Blocks until a message is available otherwise.",
+        -4,
+        &["readonly", "blocking", "movablekeys"],
+        NO_KEYS,
+        command::parse_xread,
+    )
+    .with_key_finder(streams_keys),
+    CommandMetadata::new(
+        "xgroup",
+        "Creates or destroys consumer groups.",
+        -2,
+        &["write", "denyoom"],
+        (2, 2, 1),
+        command::parse_xgroup,
+    ),
+    CommandMetadata::new(
+        "xinfo",
+        "A container for stream introspection commands.",
+        -2,
+        &["readonly"],
+        (2, 2, 1),
+        command::parse_xinfo,
+    ),
+    CommandMetadata::new(
+        "xreadgroup",
+        "Returns new or historical messages from a stream for a consumer in a group. Blocks until a message is available otherwise.",
+        -7,
+        &["write", "blocking", "movablekeys"],
+        NO_KEYS,
+        command::parse_xreadgroup,
+    )
+    .with_key_finder(streams_keys),
+    CommandMetadata::new(
+        "xack",
+        "Returns the number of messages that were successfully acknowledged by the consumer group member of a stream.",
+        -4,
+        &["write", "fast"],
+        FIRST_KEY,
+        command::parse_xack,
+    ),
+    CommandMetadata::new(
+        "xclaim",
+        "Changes, or acquires, ownership of a message in a consumer group, as if the message was delivered a consumer group member.",
+        -6,
+        &["write", "fast"],
+        FIRST_KEY,
+        command::parse_xclaim,
+    ),
+    CommandMetadata::new(
+        "xpending",
+        "Returns the information and entries from a stream consumer group's pending entries list.",
+        -3,
+        &["readonly"],
+        FIRST_KEY,
+        command::parse_xpending,
+    ),
+    CommandMetadata::new(
+        "subscribe",
+        "Listens for messages published to channels.",
+        -2,
+        &["pubsub", "noscript", "loading", "stale"],
+        NO_KEYS,
+        command::parse_subscribe,
+    ),
+    CommandMetadata::new(
+        "psubscribe",
+        "Listens for messages published to channels that match one or more patterns.",
+        -2,
+        &["pubsub", "noscript", "loading", "stale"],
+        NO_KEYS,
+        command::parse_subscribe,
+    ),
+    CommandMetadata::new(
+        "ssubscribe",
+        "Listens for messages published to shard channels.",
+        -2,
+        &["pubsub", "noscript", "loading", "stale"],
+        (1, -1, 1),
+        command::parse_subscribe,
+    )
+    .with_key_specs(&[&["not_key"]]),
+    CommandMetadata::new(
+        "unsubscribe",
+        "Stops listening to messages posted to channels.",
+        -1,
+        &["pubsub", "noscript", "loading", "stale"],
+        NO_KEYS,
+        command::parse_subscribe,
+    ),
+    CommandMetadata::new(
+        "punsubscribe",
+        "Stops listening to messages published to channels that match one or more patterns.",
+        -1,
+        &["pubsub", "noscript", "loading", "stale"],
+        NO_KEYS,
+        command::parse_subscribe,
+    ),
+    CommandMetadata::new(
+        "sunsubscribe",
+        "Stops listening to messages posted to shard channels.",
+        -1,
+        &["pubsub", "noscript", "loading", "stale"],
+        (1, -1, 1),
+        command::parse_subscribe,
+    )
+    .with_key_specs(&[&["not_key"]]),
+    CommandMetadata::new(
+        "publish",
+        "Posts a message to a channel.",
+        3,
+        &["pubsub", "loading", "stale", "fast"],
+        NO_KEYS,
+        command::parse_publish,
+    ),
+    CommandMetadata::new(
+        "spublish",
+        "Post a message to a shard channel",
+        3,
+        &["pubsub", "loading", "stale", "fast"],
+        FIRST_KEY,
+        command::parse_publish,
+    )
+    .with_key_specs(&[&["not_key"]]),
+    CommandMetadata::new(
+        "pubsub",
+        "Inspects the state of the Pub/Sub subsystem.",
+        -2,
+        &["pubsub", "loading", "stale"],
+        NO_KEYS,
+        command::parse_pubsub,
+    ),
+    CommandMetadata::new(
+        "lpush",
+        "Prepends one or more elements to a list. Creates the key if it doesn't exist.",
+        -3,
+        &["write", "denyoom", "fast"],
+        FIRST_KEY,
+        command::parse_push,
+    )
+    .with_key_specs(&[INSERT]),
+    CommandMetadata::new(
+        "rpush",
+        "Appends one or more elements to a list. Creates the key if it doesn't exist.",
+        -3,
+        &["write", "denyoom", "fast"],
+        FIRST_KEY,
+        command::parse_push,
+    )
+    .with_key_specs(&[INSERT]),
+    CommandMetadata::new(
+        "lpop",
+        "Returns the first elements in a list after removing it. Deletes the list if the last element was popped.",
+        -2,
+        &["write", "fast"],
+        FIRST_KEY,
+        command::parse_pop,
+    )
+    .with_key_specs(&[DELETE]),
+    CommandMetadata::new(
+        "rpop",
+        "Returns and removes the last elements of a list. Deletes the list if the last element was popped.",
+        -2,
+        &["write", "fast"],
+        FIRST_KEY,
+        command::parse_pop,
+    )
+    .with_key_specs(&[DELETE]),
+    CommandMetadata::new(
+        "llen",
+        "Returns the length of a list.",
+        2,
+        &["readonly", "fast"],
+        FIRST_KEY,
+        command::parse_llen,
+    ),
+    CommandMetadata::new(
+        "lrange",
+        "Returns a range of elements from a list.",
+        4,
+        &["readonly"],
+        FIRST_KEY,
+        command::parse_lrange,
+    ),
+    CommandMetadata::new(
+        "blpop",
+        "Removes and returns the first element in a list. Blocks until an element is available otherwise. Deletes the list if the last element was popped.",
+        -3,
+        &["write", "blocking"],
+        (1, -2, 1),
+        command::parse_blocking_pop,
+    )
+    .with_key_specs(&[DELETE]),
+    CommandMetadata::new(
+        "brpop",
+        "Removes and returns the last element in a list. Blocks until an element is available otherwise. Deletes the list if the last element was popped.",
+        -3,
+        &["write", "blocking"],
+        (1, -2, 1),
+        command::parse_blocking_pop,
+    )
+    .with_key_specs(&[DELETE]),
+    CommandMetadata::new(
+        "lpos",
+        "Returns the index of matching elements in a list.",
+        -3,
+        &["readonly"],
+        FIRST_KEY,
+        command::parse_lpos,
+    ),
+    CommandMetadata::new(
+        "hset",
+        "Creates or modifies the value of a field in a hash.",
+        -4,
+        &["write", "denyoom", "fast"],
+        FIRST_KEY,
+        command::parse_hset,
+    ),
+    CommandMetadata::new(
+        "hget",
+        "Returns the value of a field in a hash.",
+        3,
+        &["readonly", "fast"],
+        FIRST_KEY,
+        command::parse_hash_command,
+    ),
+    CommandMetadata::new(
+        "hdel",
+        "Deletes one or more fields and their values from a hash. Deletes the hash if no fields remain.",
+        -3,
+        &["write", "fast"],
+        FIRST_KEY,
+        command::parse_hash_command,
+    )
+    .with_key_specs(&[DELETE]),
+    CommandMetadata::new(
+        "hgetall",
+        "Returns all fields and values in a hash.",
+        2,
+        &["readonly"],
+        FIRST_KEY,
+        command::parse_hash_command,
+    ),
+    CommandMetadata::new(
+        "hkeys",
+        "Returns all fields in a hash.",
+        2,
+        &["readonly"],
+        FIRST_KEY,
+        command::parse_hash_command,
+    ),
+    CommandMetadata::new(
+        "hvals",
+        "Returns all values in a hash.",
+        2,
+        &["readonly"],
+        FIRST_KEY,
+        command::parse_hash_command,
+    ),
+    CommandMetadata::new(
+        "hlen",
+        "Returns the number of fields in a hash.",
+        2,
+        &["readonly", "fast"],
+        FIRST_KEY,
+        command::parse_hash_command,
+    ),
+    CommandMetadata::new(
+        "hincrby",
+        "Increments the integer value of a field in a hash by a number. Uses 0 as initial value if the field doesn't exist.",
+        4,
+        &["write", "denyoom", "fast"],
+        FIRST_KEY,
+        command::parse_hincrby,
+    ),
+    CommandMetadata::new(
+        "hincrbyfloat",
+        "Increments the floating point value of a field by a number. Uses 0 as initial value if the field doesn't exist.",
+        4,
+        &["write", "denyoom", "fast"],
+        FIRST_KEY,
+        command::parse_hincrby,
+    ),
+    CommandMetadata::new(
+        "hmget",
+        "Returns the values of all fields in a hash.",
+        -3,
+        &["readonly", "fast"],
+        FIRST_KEY,
+        command::parse_hmget,
+    ),
+    CommandMetadata::new(
+        "hrandfield",
+        "Returns one or more random fields from a hash.",
+        -2,
+        &["readonly"],
+        FIRST_KEY,
+        command::parse_hrandfield,
+    ),
+    CommandMetadata::new(
+        "hexpire",
+        "Set expiry for hash field using relative time to expire (seconds)",
+        -6,
+        &["write", "denyoom", "fast"],
+        FIRST_KEY,
+        command::parse_hexpire,
+    ),
+    CommandMetadata::new(
+        "httl",
+        "Returns the TTL in seconds of a hash field.",
+        -5,
+        &["readonly", "fast"],
+        FIRST_KEY,
+        command::parse_httl,
+    ),
+    CommandMetadata::new(
+        "sadd",
+        "Adds one or more members to a set. Creates the key if it doesn't exist.",
+        -3,
+        &["write", "denyoom", "fast"],
+        FIRST_KEY,
+        command::parse_set_command,
+    )
+    .with_key_specs(&[INSERT]),
+    CommandMetadata::new(
+        "srem",
+        "Removes one or more members from a set. Deletes the set if the last member was removed.",
+        -3,
+        &["write", "fast"],
+        FIRST_KEY,
+        command::parse_set_command,
+    )
+    .with_key_specs(&[DELETE]),
+    CommandMetadata::new(
+        "smembers",
+        "Returns all members of a set.",
+        2,
+        &["readonly"],
+        FIRST_KEY,
+        command::parse_set_command,
+    ),
+    CommandMetadata::new(
+        "srandmember",
+        "Returns one or more random members from a set.",
+        -2,
+        &["readonly"],
+        FIRST_KEY,
+        command::parse_set_command,
+    ),
+    CommandMetadata::new(
+        "spop",
+        "Returns one or more random members from a set after removing them. Deletes the set if the last member was popped.",
+        -2,
+        &["write", "fast"],
+        FIRST_KEY,
+        command::parse_set_command,
+    )
+    .with_key_specs(&[DELETE]),
+    CommandMetadata::new(
+        "geoadd",
+        "Adds one or more members to a geospatial index. The key is created if it doesn't exist.",
+        -5,
+        &["write", "denyoom"],
+        FIRST_KEY,
+        command::parse_geoadd,
+    ),
+    CommandMetadata::new(
+        "geopos",
+        "Returns the longitude and latitude of members from a geospatial index.",
+        -2,
+        &["readonly"],
+        FIRST_KEY,
+        command::parse_geopos,
+    ),
+    CommandMetadata::new(
+        "geodist",
+        "Returns the distance between two members of a geospatial index.",
+        -4,
+        &["readonly"],
+        FIRST_KEY,
+        command::parse_geodist,
+    ),
+    CommandMetadata::new(
+        "geosearch",
+        "Queries a geospatial index for members inside an area of a box or a circle.",
+        -7,
+        &["readonly"],
+        FIRST_KEY,
+        command::parse_geosearch,
+    ),
+    CommandMetadata::new(
+        "pfadd",
+        "Adds elements to a HyperLogLog key. Creates the key if it doesn't exist.",
+        -2,
+        &["write", "denyoom", "fast"],
+        FIRST_KEY,
+        command::parse_hyperloglog_command,
+    )
+    .with_key_specs(&[INSERT]),
+    CommandMetadata::new(
+        "pfcount",
+        "Returns the approximated cardinality of the set(s) observed by the HyperLogLog key(s).",
+        -2,
+        &["readonly"],
+        (1, -1, 1),
+        command::parse_hyperloglog_command,
+    ),
+    CommandMetadata::new(
+        "pfmerge",
+        "Merges one or more HyperLogLog values into a single key.",
+        -2,
+        &["write", "denyoom"],
+        (1, -1, 1),
+        command::parse_hyperloglog_command,
+    )
+    .with_key_specs(&[&["RW", "access", "insert"], READ]),
+    CommandMetadata::new(
+        "sinter",
+        "Returns the intersect of multiple sets.",
+        -2,
+        &["readonly"],
+        (1, -1, 1),
+        command::parse_set_command,
+    ),
+    CommandMetadata::new(
+        "sunion",
+        "Returns the union of multiple sets.",
+        -2,
+        &["readonly"],
+        (1, -1, 1),
+        command::parse_set_command,
+    ),
+    CommandMetadata::new(
+        "sdiff",
+        "Returns the difference of multiple sets.",
+        -2,
+        &["readonly"],
+        (1, -1, 1),
+        command::parse_set_command,
+    ),
+    CommandMetadata::new(
+        "sinterstore",
+        "Stores the intersect of multiple sets in a key.",
+        -3,
+        &["write", "denyoom"],
+        (1, -1, 1),
+        command::parse_set_command,
+    )
+    .with_key_specs(&[OVERWRITE, READ]),
+    CommandMetadata::new(
+        "sunionstore",
+        "Stores the union of multiple sets in a key.",
+        -3,
+        &["write", "denyoom"],
+        (1, -1, 1),
+        command::parse_set_command,
+    )
+    .with_key_specs(&[OVERWRITE, READ]),
+    CommandMetadata::new(
+        "sdiffstore",
+        "Stores the difference of multiple sets in a key.",
+        -3,
+        &["write", "denyoom"],
+        (1, -1, 1),
+        command::parse_set_command,
+    )
+    .with_key_specs(&[OVERWRITE, READ]),
+    CommandMetadata::new(
+        "zadd",
+        "Adds one or more members to a sorted set, or updates their scores. Creates the key if it doesn't exist.",
+        -4,
+        &["write", "denyoom", "fast"],
+        FIRST_KEY,
+        command::parse_zadd,
+    ),
+    CommandMetadata::new(
+        "zrandmember",
+        "Returns one or more random members from a sorted set.",
+        -2,
+        &["readonly"],
+        FIRST_KEY,
+        command::parse_zrandmember,
+    ),
+    CommandMetadata::new(
+        "zscore",
+        "Returns the score of a member in a sorted set.",
+        3,
+        &["readonly", "fast"],
+        FIRST_KEY,
+        command::parse_zscore,
+    ),
+    CommandMetadata::new(
+        "zrank",
+        "Returns the index of a member in a sorted set ordered by ascending scores.",
+        3,
+        &["readonly", "fast"],
+        FIRST_KEY,
+        command::parse_zscore,
+    ),
+    CommandMetadata::new(
+        "zrange",
+        "Returns members in a sorted set within a range of indexes.",
+        -4,
+        &["readonly"],
+        FIRST_KEY,
+        command::parse_zrange,
+    ),
+    CommandMetadata::new(
+        "zrem",
+        "Removes one or more members from a sorted set. Deletes the sorted set if all members were removed.",
+        -3,
+        &["write", "fast"],
+        FIRST_KEY,
+        command::parse_zrem,
+    )
+    .with_key_specs(&[DELETE]),
+    CommandMetadata::new(
+        "bitpos",
+        "Finds the first set (1) or clear (0) bit in a string.",
+        -3,
+        &["readonly"],
+        FIRST_KEY,
+        command::parse_bitpos,
+    ),
+    CommandMetadata::new(
+        "bitop",
+        "Performs bitwise operations on multiple strings, and stores the result.",
+        -4,
+        &["write", "denyoom"],
+        (2, -1, 1),
+        command::parse_bitop,
+    )
+    .with_key_specs(&[OVERWRITE, READ]),
+    CommandMetadata::new(
+        "object",
+        "A container for object introspection commands.",
+        -2,
+        &[],
+        NO_KEYS,
+        command::parse_object,
+    ),
+    CommandMetadata::new(
+        "debug",
+        "A container for debugging commands.",
+        -2,
+        &["admin", "noscript", "loading", "stale"],
+        NO_KEYS,
+        command::parse_debug,
+    ),
+    CommandMetadata::new(
+        "multi",
+        "Starts a transaction.",
+        1,
+        &["noscript", "loading", "stale", "fast"],
+        NO_KEYS,
+        command::parse_multi,
+    ),
+    CommandMetadata::new(
+        "exec",
+        "Executes all commands in a transaction.",
+        1,
+        &["noscript", "loading", "stale", "skip_slowlog"],
+        NO_KEYS,
+        command::parse_exec,
+    ),
+    CommandMetadata::new(
+        "discard",
+        "Discards a transaction.",
+        1,
+        &["noscript", "loading", "stale", "fast"],
+        NO_KEYS,
+        command::parse_discard,
+    ),
+    CommandMetadata::new(
+        "watch",
+        "Monitors changes to keys to determine the execution of a transaction.",
+        -2,
+        &["noscript", "loading", "stale", "fast"],
+        (1, -1, 1),
+        command::parse_watch,
+    )
+    .with_key_specs(&[&["RO"]]),
+    CommandMetadata::new(
+        "unwatch",
+        "Forgets about watched keys of a transaction.",
+        1,
+        &["noscript", "loading", "stale", "fast"],
+        NO_KEYS,
+        command::parse_unwatch,
+    ),
+];
+
+/// Case insensitive lookup in the command table
+pub fn lookup(name: &str) -> Option<&'static CommandMetadata> {
+    COMMAND_TABLE
+        .iter()
+        .find(|metadata| metadata.name.eq_ignore_ascii_case(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    #[test]
+    fn test_lookup() {
+        let set = lookup("SET").expect("set should be in the table");
+        assert_eq!(set.arity, -3);
+        assert!(set.flags.contains(&"write"));
+        assert!(lookup("get").unwrap().flags.contains(&"readonly"));
+        assert!(lookup("unknown").is_none());
+    }
+
+    #[test]
+    fn test_key_positions_and_flags() {
+        let args = |command: &str| {
+            command
+                .split_whitespace()
+                .map(String::from)
+                .collect::<Vec<_>>()
+        };
+        let mset = lookup("mset").unwrap();
+        assert_eq!(mset.key_positions(&args("mset a 1 b 2")), vec![1, 3]);
+        assert_eq!(mset.key_flags(1), &["OW", "update"]);
+        // the timeout is not a key
+        let blpop = lookup("blpop").unwrap();
+        assert_eq!(blpop.key_positions(&args("blpop a b 0")), vec![1, 2]);
+        let xgroup = lookup("xgroup").unwrap();
+        assert_eq!(xgroup.key_positions(&args("xgroup create s g $")), vec![2]);
+        assert!(lookup("ping")
+            .unwrap()
+            .key_positions(&args("ping"))
+            .is_empty());
+
+        let copy = lookup("copy").unwrap();
+        assert_eq!(copy.key_positions(&args("copy a b db 1")), vec![1, 2]);
+        assert_eq!(copy.key_flags(0), &["RO", "access"]);
+        assert_eq!(copy.key_flags(1), &["OW", "update"]);
+        let sinterstore = lookup("sinterstore").unwrap();
+        assert_eq!(sinterstore.key_flags(0), &["OW", "update"]);
+        assert_eq!(sinterstore.key_flags(2), &["RO", "access"]);
+        assert_eq!(
+            lookup("incr").unwrap().key_flags(0),
+            &["RW", "access", "update"]
+        );
+
+        // the keys of XREAD come after STREAMS, with as many ids
+        let xread = lookup("xread").unwrap();
+        assert_eq!(
+            xread.key_positions(&args("xread block 0 streams a b 0 0")),
+            vec![4, 5]
+        );
+        assert!(xread.key_positions(&args("xread streams a b 0")).is_empty());
+        let xreadgroup = lookup("xreadgroup").unwrap();
+        assert_eq!(
+            xreadgroup.key_positions(&args("xreadgroup group streams c streams s >")),
+            vec![5]
+        );
+        assert_eq!(xreadgroup.key_flags(0), &["RW", "access", "update"]);
+    }
+
+    #[test]
+    fn test_command_table_names_are_unique() {
+        let names = COMMAND_TABLE
+            .iter()
+            .map(|metadata| metadata.name)
+            .collect::<HashSet<_>>();
+        assert_eq!(names.len(), COMMAND_TABLE.len());
+    }
+}
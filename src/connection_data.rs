@@ -1,6 +1,10 @@
 use mio::net::TcpStream;
 use std::io::{ErrorKind, Read, Result};
 
+/// Initial size of the receive buffer. It is doubled each time it is full so that large
+/// pipelines are read in a few syscalls.
+const INITIAL_BUFFER_SIZE: usize = 4096;
+
 /// Helper struct to help receiving data with mio
 pub struct ConnectionData {
     pub bytes_read: usize,
@@ -10,7 +14,7 @@ pub struct ConnectionData {
 impl ConnectionData {
     pub fn receive_data(connection: &mut TcpStream) -> Result<ConnectionData> {
         let mut connection_closed = false;
-        let mut received_data = vec![0; 512];
+        let mut received_data = vec![0; INITIAL_BUFFER_SIZE];
         let mut bytes_read = 0;
         loop {
             match connection.read(&mut received_data[bytes_read..]) {
@@ -23,7 +27,7 @@ impl ConnectionData {
                 Ok(n) => {
                     bytes_read += n;
                     if bytes_read == received_data.len() {
-                        received_data.resize(received_data.len() + 512, 0);
+                        received_data.resize(received_data.len() * 2, 0);
                     }
                 }
                 // Would block "errors" are the OS's way of saying that the
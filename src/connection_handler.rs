@@ -4,17 +4,17 @@ use crate::{Error, Result};
 use std::io::{Cursor, Write};
 use std::time::{Duration, Instant};
 
-use crate::command::RedisCommand;
+use crate::command::{DebugSubcommand, RedisCommand};
 use crate::connection_data::ConnectionData;
-use crate::db::{ConnectionState, RedisDb};
-use crate::parser::parse_redis_value;
+use crate::db::{BlockedPop, ConnectionState, RedisDb};
+use crate::parser::{finish_streaming, parse_request};
 
 use binrw::BinRead;
 use mio::net::TcpStream;
 use mio::Token;
 use nom::Finish;
-/// When a client connects to the server
 
+/// When a client connects to the server
 pub fn handle_connection(
     connection: &mut TcpStream,
     token: Token,
@@ -25,43 +25,87 @@ pub fn handle_connection(
 
     let connection_data = ConnectionData::receive_data(connection)?;
 
-    if connection_data.bytes_read == 0 {
+    // input left over by a blocking command or a partial value comes before the new data
+    let mut received_data = db.unprocessed_input.remove(&token).unwrap_or_default();
+    received_data.extend_from_slice(connection_data.get_received_data());
+
+    // a client blocked by BLPOP or BRPOP only gets its input processed once unblocked
+    if received_data.is_empty() || db.is_blocked(token) {
+        if !received_data.is_empty() {
+            db.unprocessed_input.insert(token, received_data);
+        }
         return Ok((connection_data.connection_closed, false));
     }
 
+    handle_received_data(connection, token, db, silent, &received_data)
+}
+
+/// Processes the data received on the connection. The replies of all the pipelined
+/// commands are written to the connection at once instead of one write per command.
+fn handle_received_data(
+    connection: &mut TcpStream,
+    token: Token,
+    db: &mut RedisDb,
+    silent: bool,
+    received_data: &[u8],
+) -> Result<(bool, bool)> {
+    let mut output = Vec::new();
+    let result = process_received_data(&mut output, token, db, silent, received_data);
+    // replies to the commands processed before an error are still sent
+    if !output.is_empty() {
+        connection.write_all(&output)?;
+    }
+    result
+}
+
+/// Processes the data received on the connection according to the current state and
+/// appends the replies to the output buffer
+fn process_received_data(
+    output: &mut Vec<u8>,
+    token: Token,
+    db: &mut RedisDb,
+    silent: bool,
+    received_data: &[u8],
+) -> Result<(bool, bool)> {
     // Whether we should register the replica stream or not
     let mut register = false;
 
-    let input_string;
-    match db.state {
-        ConnectionState::BeforeRdbFile => {
-            // if we are waiting for rdb file, the input we get is not a redis value.
-            // However, after the rdb, the stream can contain other redis values.
-            let received_data = connection_data.get_received_data();
-            let position = find_crlf_position(received_data).unwrap();
-            let begin = String::from_utf8_lossy(&received_data[..position + 2]).to_string();
-            let (_begin, length) = parse_rdb_length(&begin).finish()?;
-
-            // Uncomment to Parse rdb
-            let rdb_bytes = &received_data[position + 2..position + 2 + length as usize];
-            let rdb = Rdb::read(&mut Cursor::new(rdb_bytes))?;
-            db.load_rdb(&rdb);
-
-            let end_bytes = &received_data[position + 2 + length as usize..];
-            input_string = String::from_utf8_lossy(end_bytes).to_string();
-            db.state = ConnectionState::Ready;
-        }
-        _ => {
-            // For all other states, we expect to receive a standard redis value.
-            input_string = String::from_utf8_lossy(connection_data.get_received_data()).to_string();
-        }
+    if let ConnectionState::BeforeRdbFile = db.state {
+        // if we are waiting for rdb file, the input we get is not a redis value.
+        // The rdb can be split across several reads so we buffer it until it is
+        // complete. After the rdb, the stream can contain other redis values.
+        db.rdb_buffer.extend_from_slice(received_data);
+        return match take_buffered_rdb(db)? {
+            None => Ok((false, false)),
+            Some(end_bytes) => process_received_data(output, token, db, silent, &end_bytes),
+        };
     }
 
-    let mut input = input_string.as_str();
-    let mut redis_value;
+    // For all other states, we expect to receive a standard redis value.
+    let mut input = received_data;
+    let mut nb_values = 0;
 
     while !input.is_empty() {
-        (input, redis_value) = parse_redis_value(input).finish()?;
+        // a long pipeline would starve the other connections, the rest of the input is
+        // processed at the next iteration of the event loop
+        if nb_values == db.info.max_commands_per_event {
+            db.unprocessed_input.insert(token, input.to_vec());
+            db.yielded_connections.push(token);
+            break;
+        }
+        nb_values += 1;
+
+        // a value can be split across several reads, the bytes received so far are
+        // kept and parsed again with the next read
+        let Some((rest, redis_value)) = finish_streaming(parse_request(input))? else {
+            db.unprocessed_input.insert(token, input.to_vec());
+            break;
+        };
+        input = rest;
+        // empty inline commands are ignored
+        if let RedisValue::Array(0, _) = redis_value {
+            continue;
+        }
 
         match db.state {
             ConnectionState::BeforeRdbFile => {
@@ -76,7 +120,7 @@ pub fn handle_connection(
                         port
                     ));
                     db.state = ConnectionState::BeforeReplConf1;
-                    connection.write_all(redis_value.to_string().as_bytes())?;
+                    output.write_all(&redis_value.to_bytes())?;
                 }
                 _ => Err(Error::InvalidAnswerDuringHandshake(redis_value.clone()))?,
             },
@@ -84,7 +128,7 @@ pub fn handle_connection(
                 RedisValue::SimpleString(x) if x == *"OK" => {
                     let redis_value = RedisValue::array_of_bulkstrings_from("REPLCONF capa psync2");
                     db.state = ConnectionState::BeforeReplConf2;
-                    connection.write_all(redis_value.to_string().as_bytes())?;
+                    output.write_all(&redis_value.to_bytes())?;
                 }
                 _ => Err(Error::InvalidAnswerDuringHandshake(redis_value.clone()))?,
             },
@@ -92,101 +136,195 @@ pub fn handle_connection(
                 RedisValue::SimpleString(x) if x == *"OK" => {
                     let redis_value = RedisValue::array_of_bulkstrings_from("PSYNC ? -1");
                     db.state = ConnectionState::BeforePsync;
-                    connection.write_all(redis_value.to_string().as_bytes())?;
+                    output.write_all(&redis_value.to_bytes())?;
                 }
                 _ => Err(Error::InvalidAnswerDuringHandshake(redis_value.clone()))?,
             },
             ConnectionState::BeforePsync => {
+                // FULLRESYNC <replid> <offset>: we start counting processed bytes from the
+                // master offset so that our acks are comparable with it
+                if let RedisValue::SimpleString(x) = &redis_value {
+                    if let [_, replid, offset] = x.split_whitespace().collect::<Vec<_>>()[..] {
+                        db.info.master_replid = replid.to_string();
+                        db.info.master_repl_offset = offset.parse()?;
+                        db.processed_bytes = offset.parse()?;
+                    }
+                }
                 db.state = ConnectionState::BeforeRdbFile;
+
+                // the rdb can arrive in the same read as the FULLRESYNC reply
+                let consumed = received_data.len() - input.len();
+                return process_received_data(
+                    output,
+                    token,
+                    db,
+                    silent,
+                    &received_data[consumed..],
+                );
             }
-            ConnectionState::Waiting(_, _, _, _) => {
-                // TODO: handle commands launched while waiting
+            ConnectionState::Waiting(_, _, _) => {
+                // the main loop turns the state into a pending wait as soon as WAIT is
+                // received, so other connections are processed in the Ready state
             }
             ConnectionState::BlockingStreams(_, _, _) => {}
-            ConnectionState::InitiatingTransaction => {}
             ConnectionState::Ready => {
-                let redis_command = RedisCommand::try_from(&redis_value)?;
-
-                if let RedisCommand::Multi = redis_command {
-                    db.state = ConnectionState::InitiatingTransaction;
-                    return Ok((true, false));
-                }
-
-                // check if we are within a transaction
-                if db.ongoing_transacations.contains_key(&token) {
-                    match redis_command {
-                        RedisCommand::Discard => {
-                            db.ongoing_transacations.remove(&token);
-                            connection.write_all(
-                                RedisValue::SimpleString("OK".to_string())
-                                    .to_string()
-                                    .as_bytes(),
-                            )?;
+                // a command that can't be parsed gets an error reply, the connection
+                // is only closed if the value is not a command at all
+                let redis_command = match RedisCommand::try_from(&redis_value) {
+                    Ok(redis_command) => redis_command,
+                    Err(e) => {
+                        let reply = e.to_resp().ok_or(e)?;
+                        if !silent {
+                            write_redis_value(output, &reply)?;
                         }
-                        RedisCommand::Exec => {
-                            let commands = db.ongoing_transacations.remove(&token).unwrap();
-
+                        db.processed_bytes += redis_value.to_bytes().len();
+                        continue;
+                    }
+                };
+                db.restore_connection_db(token);
+
+                // Transactions: the commands are queued until EXEC. As for the other
+                // commands, the rest of the input is processed afterwards.
+                let in_transaction = db.ongoing_transacations.contains_key(&token);
+                let transaction_reply = match &redis_command {
+                    RedisCommand::Multi if in_transaction => Some(RedisValue::SimpleError(
+                        "ERR MULTI calls can not be nested".to_string(),
+                    )),
+                    RedisCommand::Multi => {
+                        db.ongoing_transacations.insert(token, Vec::new());
+                        Some(RedisValue::SimpleString("OK".to_string()))
+                    }
+                    RedisCommand::Discard if in_transaction => {
+                        db.ongoing_transacations.remove(&token);
+                        db.unwatch(token);
+                        Some(RedisValue::SimpleString("OK".to_string()))
+                    }
+                    RedisCommand::Exec if in_transaction => {
+                        let commands = db.ongoing_transacations.remove(&token).unwrap();
+
+                        // the transaction is aborted if a watched key was modified
+                        let aborted = db.watched_keys_modified(token);
+                        db.unwatch(token);
+                        if aborted {
+                            Some(RedisValue::NullBulkString)
+                        } else {
                             let mut result = Vec::new();
-                            for command in commands {
-                                let value = command.execute(db)?;
+                            let mut writes = Vec::new();
+                            for (command, command_value) in commands {
+                                let value = db.execute_command(&command)?;
                                 result.push(value);
+                                if command.should_forward_to_replicas() {
+                                    writes.push((db.selected_db(), command_value));
+                                }
                             }
+                            propagate_transaction(db, writes)?;
                             let redis_value = RedisValue::Array(result.len(), result);
-                            connection.write_all(redis_value.to_string().as_bytes())?;
-                        }
-                        redis_command => {
-                            db.ongoing_transacations
-                                .get_mut(&token)
-                                .unwrap()
-                                .push(redis_command);
-
-                            let redis_value = RedisValue::SimpleString("QUEUED".to_string());
-                            connection.write_all(redis_value.to_string().as_bytes())?;
+                            Some(db.encode_for(token, redis_value))
                         }
                     }
-
-                    return Ok((false, false));
+                    RedisCommand::Exec => Some(RedisValue::SimpleError(
+                        "ERR EXEC without MULTI".to_string(),
+                    )),
+                    RedisCommand::Discard => Some(RedisValue::SimpleError(
+                        "ERR DISCARD without MULTI".to_string(),
+                    )),
+                    _ if in_transaction => {
+                        db.ongoing_transacations
+                            .get_mut(&token)
+                            .unwrap()
+                            .push((redis_command.clone(), redis_value.clone()));
+                        Some(RedisValue::SimpleString("QUEUED".to_string()))
+                    }
+                    _ => None,
+                };
+                if let Some(reply) = transaction_reply {
+                    if !silent {
+                        write_redis_value(output, &reply)?;
+                    }
+                    db.processed_bytes += redis_value.to_bytes().len();
+                    continue;
                 }
 
-                // handling of exec and discard outside of transaction
-                if let RedisCommand::Exec = redis_command {
-                    connection.write_all(
-                        RedisValue::SimpleError("ERR EXEC without MULTI".to_string())
-                            .to_string()
-                            .as_bytes(),
-                    )?;
-                    return Ok((false, false));
-                }
-                if let RedisCommand::Discard = redis_command {
-                    connection.write_all(
-                        RedisValue::SimpleError("ERR DISCARD without MULTI".to_string())
-                            .to_string()
-                            .as_bytes(),
-                    )?;
-                    return Ok((false, false));
+                // Pub/sub commands depend on the connection and can reply with several
+                // messages. SELECT is one of them, which master sends before the writes
+                // to another database.
+                if let Some(redis_values) = redis_command.execute_for_connection(db, token) {
+                    if !silent {
+                        for redis_value in redis_values {
+                            output.write_all(&redis_value.to_bytes())?;
+                        }
+                    }
+                    db.processed_bytes += redis_value.to_bytes().len();
+                    continue;
                 }
 
-                // Special handling of WAIT command
+                // Special handling of WAIT command. The commands pipelined after WAIT
+                // are kept until WAIT returns.
                 if let RedisCommand::Wait(nb_replicas, timeout) = redis_command {
+                    if !input.is_empty() {
+                        db.unprocessed_input.insert(token, input.to_vec());
+                    }
                     db.state = ConnectionState::Waiting(
                         Instant::now(),
                         Duration::from_millis(timeout),
                         nb_replicas,
-                        db.get_nb_uptodate_replicas() as u64,
                     );
-                    let redis_value = RedisValue::array_of_bulkstrings_from("REPLCONF GETACK *");
-                    db.send_to_replicas(redis_value, true)?;
 
                     return Ok((true, false));
                 }
 
+                // Special handling of DEBUG SLEEP: the server is single threaded so instead of
+                // sleeping, we let the main loop send the reply once the deadline is reached.
+                // Until then the connection is blocked and, as for BLPOP, the commands
+                // pipelined after it are kept.
+                if let RedisCommand::Debug(DebugSubcommand::Sleep(duration)) = redis_command {
+                    db.defer_reply(
+                        token,
+                        Instant::now() + duration,
+                        RedisValue::SimpleString("OK".to_string()),
+                    );
+                    if !input.is_empty() {
+                        db.unprocessed_input.insert(token, input.to_vec());
+                    }
+                    return Ok((false, false));
+                }
+
+                // Special handling of BLPOP and BRPOP. If all the lists are empty, the client
+                // is blocked until a push serves it or the timeout is reached. As for WAIT,
+                // the commands pipelined after it are kept until then.
+                if let RedisCommand::BLPop(keys, timeout) | RedisCommand::BRPop(keys, timeout) =
+                    &redis_command
+                {
+                    let response_redis_value = db.execute_command(&redis_command)?;
+                    if let RedisValue::NullBulkString = response_redis_value {
+                        if !silent {
+                            db.block_pop(BlockedPop {
+                                connection_token: token,
+                                db_index: db.selected_db(),
+                                keys: keys.clone(),
+                                from_left: matches!(redis_command, RedisCommand::BLPop(_, _)),
+                                initial_time: Instant::now(),
+                                timeout: *timeout,
+                            });
+                            if !input.is_empty() {
+                                db.unprocessed_input.insert(token, input.to_vec());
+                            }
+                            return Ok((false, false));
+                        }
+                    } else if !silent {
+                        write_redis_value(output, &response_redis_value)?;
+                    }
+                    db.propagate_pops()?;
+                    continue;
+                }
+
                 // Special handling of BLOCK command
                 if let RedisCommand::Xread {
                     block: Some(block),
                     key_offset_pairs,
                 } = redis_command
                 {
-                    let key_offset_pairs = key_offset_pairs
+                    let key_offset_pairs = match key_offset_pairs
                         .iter()
                         .map(|(stream_key, stream_id)| {
                             if stream_id == "$" {
@@ -198,7 +336,18 @@ pub fn handle_connection(
                                 Ok((stream_key.clone(), stream_id.clone()))
                             }
                         })
-                        .collect::<Result<Vec<_>>>()?;
+                        .collect::<Result<Vec<_>>>()
+                    {
+                        Ok(key_offset_pairs) => key_offset_pairs,
+                        Err(e) => {
+                            let reply = e.to_resp().ok_or(e)?;
+                            if !silent {
+                                write_redis_value(output, &reply)?;
+                            }
+                            db.processed_bytes += redis_value.to_bytes().len();
+                            continue;
+                        }
+                    };
 
                     db.state = ConnectionState::BlockingStreams(
                         Instant::now(),
@@ -206,52 +355,420 @@ pub fn handle_connection(
                         key_offset_pairs,
                     );
 
-                    let processed_bytes = redis_value.to_string().as_bytes().len();
+                    let processed_bytes = redis_value.to_bytes().len();
                     db.processed_bytes += processed_bytes;
                     return Ok((true, false));
                 }
 
-                let response_redis_value = redis_command.execute(db)?;
-                let processed_bytes = redis_value.to_string().as_bytes().len();
+                let response_redis_value = db.execute_command(&redis_command)?;
+                let response_redis_value = db.encode_for(token, response_redis_value);
+                let processed_bytes = redis_value.to_bytes().len();
 
                 // For replicas, only answer master if an ack is requested
                 if silent {
                     if let RedisCommand::ReplConfGetAck = redis_command {
-                        connection.write_all(response_redis_value.to_string().as_bytes())?;
+                        write_redis_value(output, &response_redis_value)?;
                     }
                 } else {
-                    connection.write_all(response_redis_value.to_string().as_bytes())?;
+                    write_redis_value(output, &response_redis_value)?;
                 }
 
                 db.processed_bytes += processed_bytes;
                 if let RedisCommand::Psync = redis_command {
                     register = true;
-                    // TODO: use actual rdb instead
-                    let bytes = hex::decode("524544495330303131fa0972656469732d76657205372e322e30fa0a72656469732d62697473c040fa056374696d65c26d08bc65fa08757365642d6d656dc2b0c41000fa08616f662d62617365c000fff06e3bfec0ff5aa2")?;
-
-                    // Add a small delay after sending the previous command
-                    std::thread::sleep(Duration::from_millis(200));
-
-                    connection.write_all(format!("${}\r\n", bytes.len()).as_bytes())?;
-                    connection.write_all(&bytes)?;
+                    // The snapshot of the dataset is taken in the same event as the
+                    // FULLRESYNC reply so that it matches the reported offset. The replica
+                    // reads the rdb from the stream, there is no need to wait between the two.
+                    let bytes = db.to_rdb()?.to_bytes()?;
+                    output.write_all(format!("${}\r\n", bytes.len()).as_bytes())?;
+                    output.write_all(&bytes)?;
 
                     // NOTE: In fact, replconf getack * is a command launched by the cli,
                     // it is not automatically sent by master so we must handle it after
 
                     // let redis_value = RedisValue::array_of_bulkstrings_from("REPLCONF GETACK *");
-                    // connection.write_all(redis_value.to_string().as_bytes())?;
+                    // output.write_all(&redis_value.to_bytes())?;
                 }
 
+                // keys evicted while executing the command must be deleted on the
+                // replicas before the command is applied there
+                db.propagate_evictions()?;
                 if redis_command.should_forward_to_replicas() {
-                    db.mark_replicas_as_outdated();
-                    db.send_to_replicas(redis_value, false)?;
+                    db.propagate(db.selected_db(), redis_value)?;
                 }
+                // the pops of the blocked clients served by a push come after it
+                db.propagate_pops()?;
             }
         }
     }
     Ok((false, register))
 }
 
+/// Propagates the writes of a transaction to the append only file and to the replicas.
+/// As in redis, they are wrapped in MULTI and EXEC so that they are applied at once.
+fn propagate_transaction(db: &mut RedisDb, writes: Vec<(usize, RedisValue)>) -> Result<()> {
+    // keys evicted while executing the commands are deleted before the writes
+    db.propagate_evictions()?;
+    if let (Some((first_db, _)), Some((last_db, _))) = (writes.first(), writes.last()) {
+        let (first_db, last_db) = (*first_db, *last_db);
+        db.propagate(first_db, RedisValue::array_of_bulkstrings_from("MULTI"))?;
+        for (db_index, redis_value) in writes {
+            db.propagate(db_index, redis_value)?;
+        }
+        db.propagate(last_db, RedisValue::array_of_bulkstrings_from("EXEC"))?;
+    }
+    db.propagate_pops()
+}
+
+/// Writes the value without serializing it to a string first
+fn write_redis_value<W: Write>(writer: &mut W, redis_value: &RedisValue) -> Result<()> {
+    redis_value.write_to(writer)?;
+    Ok(())
+}
+
+/// Loads the rdb ($<length>\r\n<bytes>) from the buffered data if it is complete and
+/// returns the bytes received after it
+fn take_buffered_rdb(db: &mut RedisDb) -> Result<Option<Vec<u8>>> {
+    let Some(position) = find_crlf_position(&db.rdb_buffer) else {
+        return Ok(None);
+    };
+    let (_begin, length) = parse_rdb_length(&db.rdb_buffer[..position + 2]).finish()?;
+
+    let rdb_end = position + 2 + length as usize;
+    if db.rdb_buffer.len() < rdb_end {
+        return Ok(None);
+    }
+
+    let rdb = Rdb::read(&mut Cursor::new(&db.rdb_buffer[position + 2..rdb_end]))?;
+    db.load_rdb(&rdb);
+
+    let end_bytes = db.rdb_buffer.split_off(rdb_end);
+    db.rdb_buffer.clear();
+    db.state = ConnectionState::Ready;
+    Ok(Some(end_bytes))
+}
+
 fn find_crlf_position(buffer: &[u8]) -> Option<usize> {
     buffer.windows(2).position(|window| window == b"\r\n")
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{DbInfo, ValueType};
+
+    /// Server whose files go to a directory of the test
+    fn setup_db(role: &str, test_name: &str) -> RedisDb {
+        let dir = std::env::temp_dir().join(format!("test-{}-{}", test_name, std::process::id()));
+        let port = if role == "master" { 6379 } else { 6380 };
+        let info = DbInfo::build(role, port, &dir.to_string_lossy(), "dump.rdb");
+        RedisDb::build(info, ConnectionState::Ready)
+    }
+
+    /// Commands serialized as a client sends them
+    fn input(commands: &[&str]) -> Vec<u8> {
+        commands
+            .iter()
+            .flat_map(|command| RedisValue::array_of_bulkstrings_from(command).to_bytes())
+            .collect()
+    }
+
+    /// Processes the commands received by the connection and returns the replies
+    fn process(db: &mut RedisDb, token: Token, commands: &[&str]) -> Result<Vec<u8>> {
+        let mut output = Vec::new();
+        process_received_data(&mut output, token, db, false, &input(commands))?;
+        Ok(output)
+    }
+
+    #[test]
+    fn test_take_buffered_rdb() -> Result<()> {
+        let mut db = setup_db("slave", "take-buffered-rdb");
+        db.state = ConnectionState::BeforeRdbFile;
+
+        let rdb = hex::decode("524544495330303131fa0972656469732d76657205372e322e30fa0a72656469732d62697473c040fa056374696d65c26d08bc65fa08757365642d6d656dc2b0c41000fa08616f662d62617365c000fff06e3bfec0ff5aa2")?;
+        let mut data = format!("${}\r\n", rdb.len()).into_bytes();
+        data.extend_from_slice(&rdb);
+        let command = RedisValue::array_of_bulkstrings_from("REPLCONF GETACK *").to_bytes();
+        data.extend_from_slice(&command);
+
+        // the length line and the rdb arrive in several reads
+        for chunk in [&data[..2], &data[2..20]] {
+            db.rdb_buffer.extend_from_slice(chunk);
+            assert!(take_buffered_rdb(&mut db)?.is_none());
+        }
+        db.rdb_buffer.extend_from_slice(&data[20..]);
+        let end_bytes = take_buffered_rdb(&mut db)?.expect("Rdb should be complete");
+
+        assert_eq!(end_bytes, command);
+        assert!(db.rdb_buffer.is_empty());
+        assert!(matches!(db.state, ConnectionState::Ready));
+        Ok(())
+    }
+
+    #[test]
+    fn test_full_resync_skips_the_keys_not_in_the_rdb() -> Result<()> {
+        let mut master = setup_db("master", "full-resync-skips-keys");
+        process(
+            &mut master,
+            Token(20),
+            &["SET a 1", "ZADD zset 1 one", "XADD stream 1-1 f v"],
+        )?;
+
+        // the sorted set and the stream can't be encoded but the handshake goes on
+        let output = process(&mut master, Token(21), &["PSYNC ? -1"])?;
+        assert!(output.starts_with(b"+FULLRESYNC "));
+        let rdb_start = find_crlf_position(&output).expect("FULLRESYNC reply") + 2;
+
+        let mut replica = setup_db("slave", "full-resync-skips-keys-replica");
+        replica.state = ConnectionState::BeforeRdbFile;
+        replica.rdb_buffer.extend_from_slice(&output[rdb_start..]);
+        assert!(take_buffered_rdb(&mut replica)?.is_some());
+        assert!(matches!(replica.get("a"), Some(ValueType::String(value)) if value == b"1"));
+        assert!(replica.get("stream").is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_inline_ping() -> Result<()> {
+        let mut db = setup_db("master", "inline-ping");
+
+        let mut output = Vec::new();
+        process_received_data(
+            &mut output,
+            Token(20),
+            &mut db,
+            false,
+            b"PING\r\n\r\nPING hello\r\n",
+        )?;
+        assert_eq!(output, b"+PONG\r\n$5\r\nhello\r\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_commands_after_wait_are_kept() -> Result<()> {
+        let mut db = setup_db("master", "commands-after-wait-are-kept");
+
+        // the command before WAIT is executed, the one after is kept for later
+        assert_eq!(
+            process(&mut db, Token(20), &["SET a 1", "WAIT 1 100", "SET b 2"])?,
+            b"+OK\r\n"
+        );
+        assert!(matches!(db.state, ConnectionState::Waiting(_, _, 1)));
+        assert_eq!(db.unprocessed_input[&Token(20)], input(&["SET b 2"]));
+        assert!(db.get("b").is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_replica_applies_select_from_master() -> Result<()> {
+        let mut db = setup_db("slave", "replica-applies-select-from-master");
+
+        let received = input(&["SELECT 3", "SET a 1"]);
+        let mut output = Vec::new();
+        process_received_data(&mut output, crate::token::MASTER, &mut db, true, &received)?;
+        // nothing is sent back to master but the SELECT counts in the offset
+        assert!(output.is_empty());
+        assert_eq!(db.processed_bytes, received.len());
+
+        // the next writes of master still go to the selected database
+        let received = input(&["SET b 1"]);
+        process_received_data(&mut output, crate::token::MASTER, &mut db, true, &received)?;
+        db.select(0)?;
+        assert_eq!(db.dbsize(), 0);
+        db.select(3)?;
+        assert_eq!(db.dbsize(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_pipelined_replies_are_coalesced() -> Result<()> {
+        let mut db = setup_db("master", "pipelined-replies-are-coalesced");
+
+        let output = process(
+            &mut db,
+            Token(20),
+            &["SET key0 0", "SET key1 1", "SET key2 2", "GET key1"],
+        )?;
+        assert_eq!(output, b"+OK\r\n+OK\r\n+OK\r\n$1\r\n1\r\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_long_pipeline_yields_to_other_connections() -> Result<()> {
+        let mut db = setup_db("master", "long-pipeline-yields");
+        db.info.max_commands_per_event = 2;
+
+        let mut output = process(
+            &mut db,
+            Token(20),
+            &["INCR a", "INCR a", "INCR a", "INCR a", "INCR a"],
+        )?;
+        assert_eq!(output, b":1\r\n:2\r\n");
+        assert_eq!(db.yielded_connections, vec![Token(20)]);
+
+        // the event loop resumes the connection with the rest of its input
+        let mut nb_events = 1;
+        while let Some(token) = db.yielded_connections.pop() {
+            let rest = db.unprocessed_input.remove(&token).unwrap();
+            process_received_data(&mut output, token, &mut db, false, &rest)?;
+            nb_events += 1;
+        }
+        assert_eq!(nb_events, 3);
+        assert_eq!(output, b":1\r\n:2\r\n:3\r\n:4\r\n:5\r\n");
+        assert!(!db.unprocessed_input.contains_key(&Token(20)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_values_split_across_reads() -> Result<()> {
+        let received = input(&["SET a 1", "INCR a", "GET a"]);
+
+        // the pipeline is cut at every possible position
+        for split in 1..received.len() {
+            let mut db = setup_db("master", "values-split-across-reads");
+            let mut output = Vec::new();
+            for chunk in [&received[..split], &received[split..]] {
+                let mut received_data = db.unprocessed_input.remove(&Token(20)).unwrap_or_default();
+                received_data.extend_from_slice(chunk);
+                process_received_data(&mut output, Token(20), &mut db, false, &received_data)?;
+            }
+            assert_eq!(output, b"+OK\r\n:2\r\n$1\r\n2\r\n");
+            assert!(!db.unprocessed_input.contains_key(&Token(20)));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_complete_commands_before_a_partial_one() -> Result<()> {
+        let mut db = setup_db("master", "complete-commands-before-a-partial-one");
+        let received = [input(&["SET a 1", "SET b 2"]), b"*3\r\n$3\r\nSET".to_vec()].concat();
+        let mut output = Vec::new();
+        process_received_data(&mut output, Token(20), &mut db, false, &received)?;
+
+        // the two complete commands are executed, the partial one is kept for later
+        assert_eq!(output, b"+OK\r\n+OK\r\n");
+        assert!(matches!(db.get("a"), Some(ValueType::String(val)) if val == b"1"));
+        assert!(matches!(db.get("b"), Some(ValueType::String(val)) if val == b"2"));
+        assert_eq!(
+            db.unprocessed_input.get(&Token(20)),
+            Some(&b"*3\r\n$3\r\nSET".to_vec())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_binary_values_round_trip() -> Result<()> {
+        let mut db = setup_db("master", "binary-values-round-trip");
+
+        let value = b"\xff\x00\r\nbin";
+        let set = RedisValue::Array(
+            3,
+            vec![
+                RedisValue::bulkstring_from("SET"),
+                RedisValue::bulkstring_from("key"),
+                RedisValue::bulkstring_from_bytes(value),
+            ],
+        );
+        let received = [set.to_bytes(), input(&["GET key"])].concat();
+
+        let mut output = Vec::new();
+        process_received_data(&mut output, Token(20), &mut db, false, &received)?;
+        assert_eq!(
+            output,
+            [b"+OK\r\n$7\r\n".as_slice(), value, b"\r\n"].concat()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_exec_aborted_by_watched_key() -> Result<()> {
+        let mut db = setup_db("master", "exec-aborted-by-watched-key");
+
+        assert_eq!(process(&mut db, Token(20), &["WATCH a"])?, b"+OK\r\n");
+        // another client modifies the key before the transaction
+        process(&mut db, Token(21), &["SET a 2"])?;
+        db.ongoing_transacations.insert(Token(20), Vec::new());
+        assert_eq!(process(&mut db, Token(20), &["SET b 1"])?, b"+QUEUED\r\n");
+        assert_eq!(process(&mut db, Token(20), &["EXEC"])?, b"$-1\r\n");
+        assert!(db.get("b").is_none());
+
+        // the keys are no longer watched after EXEC
+        db.ongoing_transacations.insert(Token(20), Vec::new());
+        process(&mut db, Token(20), &["SET b 1"])?;
+        assert_eq!(process(&mut db, Token(20), &["EXEC"])?, b"*1\r\n+OK\r\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_pipelined_transaction() -> Result<()> {
+        let mut db = setup_db("master", "pipelined-transaction");
+
+        let output = process(
+            &mut db,
+            Token(20),
+            &[
+                "MULTI", "SET a 1", "MULTI", "SET b 2", "EXEC", "GET a", "MULTI", "SET c 3",
+                "DISCARD", "EXEC", "GET c",
+            ],
+        )?;
+        assert_eq!(
+            output,
+            [
+                &b"+OK\r\n+QUEUED\r\n-ERR MULTI calls can not be nested\r\n+QUEUED\r\n"[..],
+                b"*2\r\n+OK\r\n+OK\r\n$1\r\n1\r\n",
+                b"+OK\r\n+QUEUED\r\n+OK\r\n-ERR EXEC without MULTI\r\n$-1\r\n",
+            ]
+            .concat()
+        );
+        assert!(!db.ongoing_transacations.contains_key(&Token(20)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_client_errors_keep_the_connection() -> Result<()> {
+        let mut db = setup_db("master", "client-errors-keep-the-connection");
+        db.push("list", &[b"a".to_vec()], true)?;
+
+        let output = process(
+            &mut db,
+            Token(20),
+            &[
+                "GET list",
+                "INCR list",
+                "XREAD BLOCK 0 streams list $",
+                "FOO",
+                "GET",
+                "PING",
+            ],
+        )?;
+        let wrong_type = RedisValue::SimpleError(Error::WrongTypeOperation.to_string()).to_bytes();
+        assert_eq!(
+            output,
+            [
+                &wrong_type[..],
+                &wrong_type,
+                &wrong_type,
+                b"-ERR unknown command 'FOO', with args beginning with: \r\n",
+                b"-ERR wrong number of arguments for 'get' command\r\n",
+                b"+PONG\r\n"
+            ]
+            .concat()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_panicking_command_replies_an_error() -> Result<()> {
+        let mut db = setup_db("master", "panicking-command-replies-an-error");
+
+        // WAIT is only handled by the connection, executing it in a transaction panics
+        db.ongoing_transacations.insert(Token(20), Vec::new());
+        assert_eq!(process(&mut db, Token(20), &["WAIT 0 0"])?, b"+QUEUED\r\n");
+        assert_eq!(
+            process(&mut db, Token(20), &["EXEC"])?,
+            b"*1\r\n-ERR internal error while executing 'wait'\r\n"
+        );
+        assert_eq!(process(&mut db, Token(20), &["PING"])?, b"+PONG\r\n");
+        Ok(())
+    }
+}
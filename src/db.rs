@@ -1,26 +1,44 @@
 use mio::net::TcpStream;
 use mio::Token;
 
+use crate::aof::Aof;
+use crate::bitops;
+use crate::clock::{self, unix_time_ms};
 use crate::command::RedisCommand;
-use crate::rdb::{Rdb, ValueTypeEncoding};
+use crate::connection_data::ConnectionData;
+use crate::glob::glob_match;
+use crate::hash::Hash;
+use crate::hyperloglog::HyperLogLog;
+use crate::log::{self, log_warning};
+use crate::parser::{finish_streaming, lossy_string, parse_redis_value};
+use crate::pubsub::{keyspace_events_enabled, PubSub};
+use crate::random;
+use crate::rdb::{DatabaseField, DatabaseSection, DatabaseValue, Rdb, StringEncodedField};
 use crate::replica::Replica;
-use crate::stream::{PendingStreamXread, Stream};
+use crate::sorted_set::{format_score, SortedSet, ZAddOptions, ZAddOutcome};
+use crate::stream::{
+    ClaimOptions, PendingRange, PendingStreamXread, PendingSummary, Stream, StreamInfo, StreamTrim,
+};
 use crate::token::TokenTrack;
 use crate::{Error, Result};
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::hash::{Hash as _, Hasher};
 use std::io::Write;
+use std::ops::RangeInclusive;
+use std::panic::{self, AssertUnwindSafe};
 use std::rc::Rc;
-use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use std::time::{Duration, Instant};
 
 use crate::parser::RedisValue;
 
 #[derive(Debug, Clone)]
 pub enum ConnectionState {
     Ready,
-    Waiting(Instant, Duration, u64, u64),
+    /// WAIT was received: initial time, timeout and number of replicas
+    Waiting(Instant, Duration, u64),
     BlockingStreams(Instant, Duration, Vec<(String, String)>),
-    InitiatingTransaction,
     BeforePing,
     BeforeReplConf1,
     BeforeReplConf2,
@@ -28,28 +46,158 @@ pub enum ConnectionState {
     BeforeRdbFile,
 }
 
+/// WAIT command waiting for the replicas to acknowledge the previous writes
+#[derive(Debug, Clone)]
+pub struct PendingWait {
+    pub connection_token: Token,
+    pub initial_time: Instant,
+    pub timeout: Duration,
+    pub nb_replicas: u64,
+}
+
+/// Operation of SINTER, SUNION and SDIFF across the sets of several keys
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SetOperation {
+    Inter,
+    Union,
+    Diff,
+}
+
+/// BLPOP or BRPOP waiting for an element to be pushed to one of its lists
+#[derive(Debug, Clone)]
+pub struct BlockedPop {
+    pub connection_token: Token,
+    /// Database selected by the connection when it blocked
+    pub db_index: usize,
+    pub keys: Vec<String>,
+    /// BLPOP pops from the head of the list, BRPOP from the tail
+    pub from_left: bool,
+    pub initial_time: Instant,
+    /// Blocks forever if zero
+    pub timeout: Duration,
+}
+
+/// A reply that must only be sent to the connection once respond_at is reached.
+/// The main loop flushes them so that the event loop is never blocked.
+#[derive(Debug, Clone)]
+pub struct DeferredReply {
+    pub connection_token: Token,
+    pub respond_at: Instant,
+    pub redis_value: RedisValue,
+}
+
 #[derive(Debug, Clone)]
 pub struct DbValue {
     pub value: ValueType,
     pub expires_at: Option<Instant>,
+    /// Version of the last write to the key, see RedisDb::key_version
+    pub version: u64,
 }
 
 // TODO: rename
 #[derive(Debug, Clone)]
 pub enum ValueType {
-    String(String),
+    String(Vec<u8>),
     Stream(Stream),
+    List(VecDeque<Vec<u8>>),
+    Hash(Hash),
+    Set(HashSet<String>),
+    SortedSet(SortedSet),
+}
+
+impl ValueType {
+    /// Name of the type, as returned by TYPE
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::String(_) => "string",
+            Self::Stream(_) => "stream",
+            Self::List(_) => "list",
+            Self::Hash(_) => "hash",
+            Self::Set(_) => "set",
+            Self::SortedSet(_) => "zset",
+        }
+    }
+
+    /// Name of the internal encoding in redis. We have a single representation per type
+    /// so this is the encoding redis would use for a value of this size. Unlike redis, a
+    /// collection that shrinks goes back to the compact encoding.
+    pub fn encoding(&self, thresholds: &EncodingThresholds) -> &'static str {
+        match self {
+            Self::String(val) if canonical_integer(val).is_some() => "int",
+            // the string fits in the same allocation as its header
+            Self::String(val) if val.len() <= 44 => "embstr",
+            Self::String(_) => "raw",
+            Self::Stream(_) => "stream",
+            Self::List(_) => "quicklist",
+            Self::Hash(hash)
+                if fits_listpack(
+                    hash.len(),
+                    thresholds.hash_max_listpack_entries,
+                    thresholds.hash_max_listpack_value,
+                    hash.iter()
+                        .flat_map(|(field, value)| [field.len(), value.len()]),
+                ) =>
+            {
+                "listpack"
+            }
+            Self::Hash(_) => "hashtable",
+            Self::Set(set)
+                if set.len() <= thresholds.set_max_intset_entries
+                    && set
+                        .iter()
+                        .all(|member| canonical_integer(member.as_bytes()).is_some()) =>
+            {
+                "intset"
+            }
+            Self::Set(set)
+                if fits_listpack(
+                    set.len(),
+                    thresholds.set_max_listpack_entries,
+                    thresholds.set_max_listpack_value,
+                    set.iter().map(|member| member.len()),
+                ) =>
+            {
+                "listpack"
+            }
+            Self::Set(_) => "hashtable",
+            Self::SortedSet(sorted_set)
+                if fits_listpack(
+                    sorted_set.len(),
+                    thresholds.zset_max_listpack_entries,
+                    thresholds.zset_max_listpack_value,
+                    sorted_set.iter().map(|(member, _)| member.len()),
+                ) =>
+            {
+                "listpack"
+            }
+            Self::SortedSet(_) => "skiplist",
+        }
+    }
+}
+
+/// Whether a collection is small enough for the listpack encoding
+fn fits_listpack(
+    len: usize,
+    max_entries: usize,
+    max_value: usize,
+    mut sizes: impl Iterator<Item = usize>,
+) -> bool {
+    len <= max_entries && sizes.all(|size| size <= max_value)
 }
 
 impl DbValue {
     fn new(value: ValueType, expires_in: Option<Duration>) -> Self {
-        let expires_at = expires_in.map(|dur| Instant::now() + dur);
-        Self { value, expires_at }
+        let expires_at = expires_in.map(|dur| clock::now() + dur);
+        Self {
+            value,
+            expires_at,
+            version: 0,
+        }
     }
 
     fn is_expired(&self) -> bool {
         if let Some(expires_at) = self.expires_at {
-            Instant::now() >= expires_at
+            clock::now() >= expires_at
         } else {
             false
         }
@@ -65,6 +213,29 @@ pub struct DbInfo {
     pub master_repl_offset: u64,
     pub dir: String,
     pub dbfilename: String,
+    /// Maximum number of keys KEYS is allowed to collect, unlimited if None
+    pub keys_max_results: Option<usize>,
+    /// Classes of keyspace events to publish, same format as redis
+    pub notify_keyspace_events: String,
+    /// Unknown DEBUG subcommands reply OK instead of an error
+    pub debug_lenient: bool,
+    /// Whether expired keys are periodically removed, see DEBUG SET-ACTIVE-EXPIRE
+    pub active_expire: bool,
+    /// Maximum length of the strings grown by APPEND, SETRANGE and SETBIT
+    pub proto_max_bulk_len: u64,
+    /// The rdb is saved when one of them is reached, never if there is none
+    pub save_points: Vec<SavePoint>,
+    /// Sizes reported by OBJECT ENCODING as compact encodings
+    pub encoding_thresholds: EncodingThresholds,
+    /// Memory limit in bytes, 0 for no limit. Only reported, nothing is evicted
+    pub maxmemory: u64,
+    /// One of MAXMEMORY_POLICIES
+    pub maxmemory_policy: String,
+    /// Whether the write commands are logged to the append only file. Only set at
+    /// startup with --appendonly, see `config_set`
+    pub appendonly: bool,
+    /// Commands processed for a connection before the other ones get their turn
+    pub max_commands_per_event: usize,
 }
 
 impl DbInfo {
@@ -79,6 +250,212 @@ impl DbInfo {
             master_repl_offset,
             dir: dir.to_string(),
             dbfilename: dbfilename.to_string(),
+            keys_max_results: None,
+            notify_keyspace_events: String::new(),
+            debug_lenient: false,
+            active_expire: true,
+            proto_max_bulk_len: 512 * 1024 * 1024,
+            save_points: Vec::new(),
+            encoding_thresholds: EncodingThresholds::default(),
+            maxmemory: 0,
+            maxmemory_policy: "noeviction".to_string(),
+            appendonly: false,
+            max_commands_per_event: 1000,
+        }
+    }
+
+    /// Parameters matching the glob-style pattern with their values, in the order of
+    /// CONFIG_PARAMETERS
+    pub fn config_get_matching(&self, pattern: &str) -> Vec<(&'static str, String)> {
+        CONFIG_PARAMETERS
+            .iter()
+            .filter(|parameter| glob_match(pattern, parameter))
+            .filter_map(|parameter| Some((*parameter, self.config_get(parameter)?)))
+            .collect()
+    }
+
+    /// Value of the parameter as returned by CONFIG GET, None if it is unknown
+    pub fn config_get(&self, parameter: &str) -> Option<String> {
+        match parameter {
+            "dir" => Some(self.dir.clone()),
+            "dbfilename" => Some(self.dbfilename.clone()),
+            "loglevel" => Some(log::log_level().name().to_string()),
+            "notify-keyspace-events" => Some(self.notify_keyspace_events.clone()),
+            "proto-max-bulk-len" => Some(self.proto_max_bulk_len.to_string()),
+            "save" => Some(
+                self.save_points
+                    .iter()
+                    .map(|save_point| format!("{} {}", save_point.seconds, save_point.changes))
+                    .collect::<Vec<_>>()
+                    .join(" "),
+            ),
+            "maxmemory" => Some(self.maxmemory.to_string()),
+            "maxmemory-policy" => Some(self.maxmemory_policy.clone()),
+            "appendonly" => Some(if self.appendonly { "yes" } else { "no" }.to_string()),
+            parameter => self
+                .encoding_thresholds
+                .get(parameter)
+                .map(|value| value.to_string()),
+        }
+    }
+
+    /// Changes the parameter as CONFIG SET does. Only the parameters that can be
+    /// changed while the server runs are accepted. appendonly is immutable: unlike
+    /// redis, the server can't rewrite the append only file from the dataset, so a
+    /// file opened at runtime would miss the keys written before it.
+    pub fn config_set(&mut self, parameter: &str, value: &str) -> Result<()> {
+        let failed = |reason: &str| Error::ConfigSetFailed {
+            parameter: parameter.to_string(),
+            reason: reason.to_string(),
+        };
+        match parameter {
+            "maxmemory" => self.maxmemory = parse_memory(value).map_err(|e| failed(&e))?,
+            "proto-max-bulk-len" => {
+                self.proto_max_bulk_len = parse_memory(value).map_err(|e| failed(&e))?
+            }
+            "maxmemory-policy" => {
+                self.maxmemory_policy = parse_maxmemory_policy(value).map_err(|e| failed(&e))?
+            }
+            "appendonly" => return Err(failed("can't set immutable config")),
+            _ => return Err(Error::UnknownConfigParameter(parameter.to_string())),
+        }
+        Ok(())
+    }
+}
+
+/// Parameters known to CONFIG GET
+pub const CONFIG_PARAMETERS: [&str; 16] = [
+    "dir",
+    "dbfilename",
+    "loglevel",
+    "notify-keyspace-events",
+    "proto-max-bulk-len",
+    "save",
+    "maxmemory",
+    "maxmemory-policy",
+    "appendonly",
+    "set-max-intset-entries",
+    "set-max-listpack-entries",
+    "set-max-listpack-value",
+    "hash-max-listpack-entries",
+    "hash-max-listpack-value",
+    "zset-max-listpack-entries",
+    "zset-max-listpack-value",
+];
+
+/// Accepted values of maxmemory-policy, in the order redis lists them
+pub const MAXMEMORY_POLICIES: [&str; 8] = [
+    "volatile-lru",
+    "volatile-lfu",
+    "volatile-random",
+    "volatile-ttl",
+    "allkeys-lru",
+    "allkeys-lfu",
+    "allkeys-random",
+    "noeviction",
+];
+
+/// One of MAXMEMORY_POLICIES, case insensitive
+pub fn parse_maxmemory_policy(value: &str) -> std::result::Result<String, String> {
+    let policy = value.to_lowercase();
+    if MAXMEMORY_POLICIES.contains(&policy.as_str()) {
+        Ok(policy)
+    } else {
+        Err(format!(
+            "argument(s) must be one of the following: {}",
+            MAXMEMORY_POLICIES.join(", ")
+        ))
+    }
+}
+
+/// Number of bytes of a memory value such as 100mb, with the units of the redis
+/// configuration: k, m and g are powers of 1000, kb, mb and gb powers of 1024
+pub fn parse_memory(value: &str) -> std::result::Result<u64, String> {
+    let value = value.to_lowercase();
+    let digits_end = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(digits_end);
+    let multiplier: u64 = match unit {
+        "" | "b" => 1,
+        "k" => 1000,
+        "kb" => 1024,
+        "m" => 1000 * 1000,
+        "mb" => 1024 * 1024,
+        "g" => 1000 * 1000 * 1000,
+        "gb" => 1024 * 1024 * 1024,
+        _ => return Err("argument must be a memory value".to_string()),
+    };
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|number| number.checked_mul(multiplier))
+        .ok_or_else(|| "argument must be a memory value".to_string())
+}
+
+/// Limits up to which redis keeps small collections in compact encodings, with the
+/// same names and defaults as its configuration. The entries are counted, the values
+/// are lengths in bytes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EncodingThresholds {
+    pub set_max_intset_entries: usize,
+    pub set_max_listpack_entries: usize,
+    pub set_max_listpack_value: usize,
+    pub hash_max_listpack_entries: usize,
+    pub hash_max_listpack_value: usize,
+    pub zset_max_listpack_entries: usize,
+    pub zset_max_listpack_value: usize,
+}
+
+impl EncodingThresholds {
+    /// Value of the configuration parameter, as returned by CONFIG GET
+    pub fn get(&self, parameter: &str) -> Option<usize> {
+        match parameter {
+            "set-max-intset-entries" => Some(self.set_max_intset_entries),
+            "set-max-listpack-entries" => Some(self.set_max_listpack_entries),
+            "set-max-listpack-value" => Some(self.set_max_listpack_value),
+            "hash-max-listpack-entries" => Some(self.hash_max_listpack_entries),
+            "hash-max-listpack-value" => Some(self.hash_max_listpack_value),
+            "zset-max-listpack-entries" => Some(self.zset_max_listpack_entries),
+            "zset-max-listpack-value" => Some(self.zset_max_listpack_value),
+            _ => None,
+        }
+    }
+}
+
+impl Default for EncodingThresholds {
+    fn default() -> Self {
+        Self {
+            set_max_intset_entries: 512,
+            set_max_listpack_entries: 128,
+            set_max_listpack_value: 64,
+            hash_max_listpack_entries: 128,
+            hash_max_listpack_value: 64,
+            zset_max_listpack_entries: 128,
+            zset_max_listpack_value: 64,
+        }
+    }
+}
+
+/// Automatic save of the rdb after a number of changes within a number of seconds,
+/// `<seconds> <changes>` as in the save directive of redis
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SavePoint {
+    pub seconds: u64,
+    pub changes: u64,
+}
+
+impl std::str::FromStr for SavePoint {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let parse = |value: &str| value.parse::<u64>().map_err(|e| e.to_string());
+        match s.split_whitespace().collect::<Vec<_>>()[..] {
+            [seconds, changes] => Ok(Self {
+                seconds: parse(seconds)?,
+                changes: parse(changes)?,
+            }),
+            _ => Err("expected <seconds> <changes>".to_string()),
         }
     }
 }
@@ -92,17 +469,49 @@ impl std::fmt::Display for DbInfo {
     }
 }
 
+/// Number of logical databases, selected with SELECT
+pub const NB_DATABASES: usize = 16;
+/// Keys with an expiration sampled to compute the avg_ttl of INFO keyspace
+const AVG_TTL_SAMPLES: usize = 1000;
+
 #[derive(Debug, Clone)]
 struct InnerRedisDb {
-    store: HashMap<String, DbValue>,
+    databases: Vec<HashMap<String, DbValue>>,
+    /// Index of the database the commands apply to
+    selected: usize,
+    /// Incremented on each write, shared by all the keys of all the databases
+    last_version: u64,
 }
 
 impl InnerRedisDb {
     pub fn build() -> Self {
         Self {
-            store: HashMap::new(),
+            databases: vec![HashMap::new(); NB_DATABASES],
+            selected: 0,
+            last_version: 0,
+        }
+    }
+
+    fn next_version(&mut self) -> u64 {
+        self.last_version += 1;
+        self.last_version
+    }
+
+    /// Gives a new version to the key of the selected database after a write
+    fn touch(&mut self, key: &str) {
+        let version = self.next_version();
+        if let Some(db_value) = self.store_mut().get_mut(key) {
+            db_value.version = version;
         }
     }
+
+    fn store(&self) -> &HashMap<String, DbValue> {
+        &self.databases[self.selected]
+    }
+
+    fn store_mut(&mut self) -> &mut HashMap<String, DbValue> {
+        &mut self.databases[self.selected]
+    }
 }
 
 #[derive(Debug)]
@@ -115,7 +524,45 @@ pub struct RedisDb {
     pub token_track: TokenTrack,
     // NOTE: only one pending xread allowed
     pub pending_stream_xread: Option<PendingStreamXread>,
-    pub ongoing_transacations: HashMap<Token, Vec<RedisCommand>>,
+    /// Commands queued by each connection within MULTI, with the values they were parsed
+    /// from so that the writes can be propagated on EXEC
+    pub ongoing_transacations: HashMap<Token, Vec<(RedisCommand, RedisValue)>>,
+    pub deferred_replies: Vec<DeferredReply>,
+    pub pubsub: PubSub,
+    /// Connections that negotiated RESP3 with HELLO, the others use RESP2
+    pub resp3_connections: HashSet<Token>,
+    /// Bytes of the rdb received from master so far, for replicas
+    pub rdb_buffer: Vec<u8>,
+    // NOTE: only one pending wait allowed
+    pub pending_wait: Option<PendingWait>,
+    /// Keys evicted since the last propagation to the replicas, with their database
+    pending_evictions: Vec<(usize, String)>,
+    /// Clients blocked by BLPOP or BRPOP, the longest waiting first
+    blocked_pops: VecDeque<BlockedPop>,
+    /// Pops of the blocking commands and of SPOP since the last propagation to the
+    /// replicas, with their database
+    pending_pops: Vec<(usize, RedisValue)>,
+    /// Keys watched by each connection with their database and version at WATCH time
+    watched_keys: HashMap<Token, Vec<(usize, String, u64)>>,
+    /// Database selected by each connection, 0 if absent
+    selected_dbs: HashMap<Token, usize>,
+    /// Input received after a blocking command such as WAIT, processed once the
+    /// connection is unblocked
+    pub unprocessed_input: HashMap<Token, Vec<u8>>,
+    /// Connections that reached max_commands_per_event with input left, resumed at the
+    /// next iteration of the event loop
+    pub yielded_connections: Vec<Token>,
+    /// Append only file the writes are logged to, if enabled
+    pub aof: Option<Aof>,
+    /// Number of writes since the last save of the rdb
+    pub dirty: u64,
+    /// Last save of the rdb, or last failed automatic save so that it is not retried
+    /// on every tick
+    pub last_save: Instant,
+    /// Database the last propagated write applies to, so that a SELECT is sent before a
+    /// write to another one. None when the replicas may not agree, after a new replica
+    /// started from database 0
+    pub replicated_db: Option<usize>,
 }
 
 impl RedisDb {
@@ -129,22 +576,237 @@ impl RedisDb {
             token_track: TokenTrack::new(),
             pending_stream_xread: None,
             ongoing_transacations: HashMap::new(),
+            deferred_replies: Vec::new(),
+            pubsub: PubSub::new(),
+            resp3_connections: HashSet::new(),
+            rdb_buffer: Vec::new(),
+            pending_wait: None,
+            pending_evictions: Vec::new(),
+            blocked_pops: VecDeque::new(),
+            pending_pops: Vec::new(),
+            watched_keys: HashMap::new(),
+            selected_dbs: HashMap::new(),
+            unprocessed_input: HashMap::new(),
+            yielded_connections: Vec::new(),
+            aof: None,
+            dirty: 0,
+            last_save: Instant::now(),
+            replicated_db: Some(0),
+        }
+    }
+
+    /// Executes the command and returns its reply without going through a connection,
+    /// which allows driving the database in process. A panic, such as a todo! in a command
+    /// path that is not implemented, is turned into an error reply instead of bringing
+    /// down the server and all the connections with it. So are the errors caused by the
+    /// command, such as an operation on a key holding the wrong kind of value.
+    pub fn execute_command(&mut self, redis_command: &RedisCommand) -> Result<RedisValue> {
+        match panic::catch_unwind(AssertUnwindSafe(|| redis_command.execute(self))) {
+            Ok(Err(e)) => e.to_resp().ok_or(e),
+            Ok(result) => result,
+            Err(payload) => {
+                let message = payload
+                    .downcast_ref::<&str>()
+                    .map(|message| message.to_string())
+                    .or_else(|| payload.downcast_ref::<String>().cloned())
+                    .unwrap_or_default();
+                log_warning!("{} panicked: {}", redis_command.name(), message);
+                Ok(RedisValue::SimpleError(format!(
+                    "ERR internal error while executing '{}'",
+                    redis_command.name()
+                )))
+            }
         }
     }
 
+    pub fn defer_reply(
+        &mut self,
+        connection_token: Token,
+        respond_at: Instant,
+        redis_value: RedisValue,
+    ) {
+        self.deferred_replies.push(DeferredReply {
+            connection_token,
+            respond_at,
+            redis_value,
+        });
+    }
+
+    /// Removes and returns the deferred replies that should be sent at `now`,
+    /// in the order they were deferred
+    pub fn take_due_replies(&mut self, now: Instant) -> Vec<DeferredReply> {
+        let (due, pending) = self
+            .deferred_replies
+            .drain(..)
+            .partition(|reply| reply.respond_at <= now);
+        self.deferred_replies = pending;
+        due
+    }
+
     pub fn set(&self, key: String, value: ValueType, px: Option<u64>) {
         let expires_in = px.map(Duration::from_millis);
-        let db_value = DbValue::new(value, expires_in);
-        self.inner.borrow_mut().store.insert(key, db_value);
+        let mut db_value = DbValue::new(value, expires_in);
+        let mut inner = self.inner.borrow_mut();
+        db_value.version = inner.next_version();
+        inner.store_mut().insert(key, db_value);
+    }
+
+    /// Version of the key in the selected database, 0 if it does not exist. It changes on
+    /// each write to the key, which lets WATCH detect the keys modified before EXEC.
+    /// A key deleted then recreated gets a new version.
+    pub fn key_version(&self, key: &str) -> u64 {
+        self.inner
+            .borrow()
+            .store()
+            .get(key)
+            .map_or(0, |db_value| db_value.version)
+    }
+
+    /// Remembers the version of the keys so that EXEC can check they were not modified
+    pub fn watch(&mut self, token: Token, keys: &[String]) {
+        let db_index = self.selected_db();
+        let watched = keys
+            .iter()
+            .map(|key| (db_index, key.clone(), self.key_version(key)))
+            .collect::<Vec<_>>();
+        self.watched_keys.entry(token).or_default().extend(watched);
+    }
+
+    pub fn unwatch(&mut self, token: Token) {
+        self.watched_keys.remove(&token);
+    }
+
+    /// Whether one of the keys watched by the connection was written since WATCH
+    pub fn watched_keys_modified(&self, token: Token) -> bool {
+        let Some(watched) = self.watched_keys.get(&token) else {
+            return false;
+        };
+        let inner = self.inner.borrow();
+        watched.iter().any(|(db_index, key, version)| {
+            inner.databases[*db_index]
+                .get(key)
+                .map_or(0, |db_value| db_value.version)
+                != *version
+        })
+    }
+
+    /// Selects the database the next commands apply to
+    pub fn select(&self, index: usize) -> Result<()> {
+        if index >= NB_DATABASES {
+            return Err(Error::DbIndexOutOfRange);
+        }
+        self.inner.borrow_mut().selected = index;
+        Ok(())
+    }
+
+    pub fn selected_db(&self) -> usize {
+        self.inner.borrow().selected
+    }
+
+    /// Selects the database of the connection and remembers it for its next commands
+    pub fn select_for_connection(&mut self, token: Token, index: usize) -> Result<()> {
+        self.select(index)?;
+        self.selected_dbs.insert(token, index);
+        Ok(())
+    }
+
+    /// Selects the database previously selected by the connection before executing its
+    /// commands
+    pub fn restore_connection_db(&self, token: Token) {
+        let index = self.selected_dbs.get(&token).copied().unwrap_or(0);
+        self.inner.borrow_mut().selected = index;
+    }
+
+    /// Copies the value (and its expiration) of the source key of the selected database to
+    /// the destination key of the destination database, the selected one by default.
+    /// Returns whether the key was copied
+    pub fn copy(
+        &self,
+        source: &str,
+        destination: &str,
+        destination_db: Option<usize>,
+        replace: bool,
+    ) -> Result<bool> {
+        let mut inner = self.inner.borrow_mut();
+        let destination_db = destination_db.unwrap_or(inner.selected);
+        if destination_db >= NB_DATABASES {
+            return Err(Error::DbIndexOutOfRange);
+        }
+        if destination_db == inner.selected && source == destination {
+            return Err(Error::SameSourceAndDestination);
+        }
+        let Some(mut db_value) = inner
+            .store()
+            .get(source)
+            .filter(|db_value| !db_value.is_expired())
+            .cloned()
+        else {
+            return Ok(false);
+        };
+        db_value.version = inner.next_version();
+
+        let destination_store = &mut inner.databases[destination_db];
+        let destination_exists = destination_store
+            .get(destination)
+            .is_some_and(|db_value| !db_value.is_expired());
+        if destination_exists && !replace {
+            return Ok(false);
+        }
+        destination_store.insert(destination.to_string(), db_value);
+        Ok(true)
+    }
+
+    /// Same as set but the key keeps the expiration it had, if any
+    pub fn set_keepttl(&self, key: String, value: ValueType) {
+        let mut inner = self.inner.borrow_mut();
+        let expires_at = inner
+            .store()
+            .get(&key)
+            .filter(|db_value| !db_value.is_expired())
+            .and_then(|db_value| db_value.expires_at);
+        let mut db_value = DbValue::new(value, None);
+        db_value.expires_at = expires_at;
+        db_value.version = inner.next_version();
+        inner.store_mut().insert(key, db_value);
+    }
+
+    /// Sets the expiration of an existing key in milliseconds, or removes it
+    pub fn set_expiry(&self, key: &str, px: Option<u64>) {
+        let mut inner = self.inner.borrow_mut();
+        if let Some(db_value) = inner.store_mut().get_mut(key) {
+            db_value.expires_at = px.map(|px| clock::now() + Duration::from_millis(px));
+        }
+        inner.touch(key);
+    }
+
+    /// Sets the expiration of an existing key to a unix time in milliseconds. The key is
+    /// deleted right away if the time is already past. Returns whether the key exists
+    pub fn expire_at(&mut self, key: &str, unix_time_ms_expire: u64) -> bool {
+        if !self.contains_key(key) {
+            // evicts the key if it is expired
+            self.get(key);
+            return false;
+        }
+        let current_timestamp_in_ms = unix_time_ms();
+        if unix_time_ms_expire <= current_timestamp_in_ms {
+            self.del(&[key.to_string()]);
+        } else {
+            self.set_expiry(key, Some(unix_time_ms_expire - current_timestamp_in_ms));
+        }
+        true
     }
 
-    pub fn get(&self, key: &str) -> Option<ValueType> {
-        let db_value = self.inner.borrow().store.get(key).cloned();
+    pub fn get(&mut self, key: &str) -> Option<ValueType> {
+        let db_value = self.inner.borrow().store().get(key).cloned();
         match db_value {
             None => None,
             Some(db_value) => {
                 if db_value.is_expired() {
-                    self.inner.borrow_mut().store.remove(key);
+                    // Replicas keep logically expired keys until the master sends the DEL,
+                    // otherwise they could diverge from the master
+                    if !self.is_replica() {
+                        self.evict_expired(key);
+                    }
                     None
                 } else {
                     Some(db_value.value)
@@ -153,72 +815,508 @@ impl RedisDb {
         }
     }
 
-    pub fn incr(&self, key: &str) -> Result<i64> {
-        let mut db = self.inner.borrow_mut();
-        let db_value = db.store.get_mut(key);
-        match db_value {
+    /// Counts the keys that exist, a key given several times is counted each time.
+    /// As with get, the expired keys are evicted.
+    pub fn exists(&mut self, keys: &[String]) -> i64 {
+        let mut count = 0;
+        for key in keys {
+            if self.contains_key(key) {
+                count += 1;
+            } else {
+                self.get(key);
+            }
+        }
+        count
+    }
+
+    /// Whether the key exists and is not expired
+    fn contains_key(&self, key: &str) -> bool {
+        self.inner
+            .borrow()
+            .store()
+            .get(key)
+            .is_some_and(|db_value| !db_value.is_expired())
+    }
+
+    /// Removes an expired key. Replicas do not expire keys on their own so the eviction
+    /// is propagated to them as a DEL, and an expired event is published if keyspace
+    /// notifications are enabled.
+    pub fn evict_expired(&mut self, key: &str) {
+        if self.inner.borrow_mut().store_mut().remove(key).is_none() {
+            return;
+        }
+        let db_index = self.selected_db();
+        self.pending_evictions.push((db_index, key.to_string()));
+        self.notify_keyspace_event('x', "expired", key);
+    }
+
+    /// Evicts the key if it is expired, before a write takes its entry. As in get,
+    /// replicas keep it until the master sends the DEL
+    fn evict_if_expired(&mut self, key: &str) {
+        let expired = self
+            .inner
+            .borrow()
+            .store()
+            .get(key)
+            .is_some_and(|db_value| db_value.is_expired());
+        if expired && !self.is_replica() {
+            self.evict_expired(key);
+        }
+    }
+
+    /// Removes all the expired keys, which would otherwise stay in memory until they
+    /// are accessed. As for lazy expiration, replicas wait for the DEL of the master.
+    /// Returns the number of evicted keys
+    pub fn active_expire_cycle(&mut self) -> usize {
+        if !self.info.active_expire || self.is_replica() {
+            return 0;
+        }
+        let selected = self.selected_db();
+        let mut nb_evicted = 0;
+        for index in 0..NB_DATABASES {
+            self.inner.borrow_mut().selected = index;
+            let expired_keys = self
+                .inner
+                .borrow()
+                .store()
+                .iter()
+                .filter(|(_, db_value)| db_value.is_expired())
+                .map(|(key, _)| key.clone())
+                .collect::<Vec<_>>();
+            for key in expired_keys.iter() {
+                self.evict_expired(key);
+            }
+            nb_evicted += expired_keys.len();
+        }
+        self.inner.borrow_mut().selected = selected;
+        nb_evicted
+    }
+
+    /// Removes all the keys of all the databases
+    pub fn flushall(&self) {
+        for store in self.inner.borrow_mut().databases.iter_mut() {
+            store.clear();
+        }
+    }
+
+    /// Number of keys, including the expired keys that were not evicted yet
+    pub fn dbsize(&self) -> usize {
+        self.inner.borrow().store().len()
+    }
+
+    /// Keyspace section of INFO: number of keys, of keys with an expiration and their
+    /// average remaining time to live in ms for each database with keys. The average
+    /// is computed on a sample of the keys to bound its cost.
+    pub fn keyspace_info(&self) -> String {
+        let now = clock::now();
+        let mut info = "# Keyspace\r\n".to_string();
+        for (index, store) in self.inner.borrow().databases.iter().enumerate() {
+            if store.is_empty() {
+                continue;
+            }
+            let expires = store
+                .values()
+                .filter(|db_value| db_value.expires_at.is_some())
+                .count();
+            let ttls = store
+                .values()
+                .filter_map(|db_value| db_value.expires_at)
+                .filter(|expires_at| *expires_at > now)
+                .take(AVG_TTL_SAMPLES)
+                .map(|expires_at| (expires_at - now).as_millis())
+                .collect::<Vec<_>>();
+            let avg_ttl = if ttls.is_empty() {
+                0
+            } else {
+                ttls.iter().sum::<u128>() / ttls.len() as u128
+            };
+            info.push_str(&format!(
+                "db{}:keys={},expires={},avg_ttl={}\r\n",
+                index,
+                store.len(),
+                expires,
+                avg_ttl
+            ));
+        }
+        info
+    }
+
+    /// Sends the DEL of the evicted keys to the replicas. Must be called before
+    /// forwarding a command so that replicas apply the eviction first.
+    pub fn propagate_evictions(&mut self) -> Result<()> {
+        if self.pending_evictions.is_empty() {
+            return Ok(());
+        }
+        for (db_index, key) in std::mem::take(&mut self.pending_evictions) {
+            let redis_value = RedisValue::Array(
+                2,
+                vec![
+                    RedisValue::bulkstring_from("DEL"),
+                    RedisValue::bulkstring_from(&key),
+                ],
+            );
+            self.propagate(db_index, redis_value)?;
+        }
+        Ok(())
+    }
+
+    /// Sends the pops of the blocking commands as LPOP or RPOP, and those of SPOP as
+    /// SREM, to the replicas. Must be called after forwarding the command as the pops
+    /// served by a push come after it.
+    pub fn propagate_pops(&mut self) -> Result<()> {
+        for (db_index, redis_value) in std::mem::take(&mut self.pending_pops) {
+            self.propagate(db_index, redis_value)?;
+        }
+        Ok(())
+    }
+
+    /// Logs the write to the append only file and sends it to the replicas. As in redis,
+    /// it is preceded by a SELECT if it applies to another database than the previous one
+    pub fn propagate(&mut self, db_index: usize, redis_value: RedisValue) -> Result<()> {
+        if self.replicated_db != Some(db_index) {
+            let select = RedisValue::Array(
+                2,
+                vec![
+                    RedisValue::bulkstring_from("SELECT"),
+                    RedisValue::bulkstring_from(&db_index.to_string()),
+                ],
+            );
+            self.append_to_aof(&select)?;
+            self.send_to_replicas(select, false)?;
+            self.replicated_db = Some(db_index);
+        }
+        self.dirty += 1;
+        self.append_to_aof(&redis_value)?;
+        self.send_to_replicas(redis_value, false)
+    }
+
+    /// Rdb of the keys of all the databases. Streams and sorted sets can't be encoded for
+    /// now, their keys are skipped with a warning so that a full resync still succeeds. The
+    /// expirations of the fields of the hashes are not saved
+    pub fn to_rdb(&self) -> Result<Rdb> {
+        let now = clock::now();
+        let now_unix_ms = unix_time_ms();
+        let inner = self.inner.borrow();
+        let mut database_sections = Vec::new();
+        for (index, store) in inner.databases.iter().enumerate() {
+            let mut fields = Vec::new();
+            for (key, db_value) in store.iter() {
+                if db_value.is_expired() {
+                    continue;
+                }
+                let value = match &db_value.value {
+                    ValueType::String(value) => {
+                        DatabaseValue::String(StringEncodedField::new(value))
+                    }
+                    ValueType::List(list) => DatabaseValue::List(
+                        list.iter()
+                            .map(|element| StringEncodedField::new(element))
+                            .collect(),
+                    ),
+                    ValueType::Set(set) => DatabaseValue::Set(
+                        set.iter()
+                            .map(|member| StringEncodedField::new(member.as_bytes()))
+                            .collect(),
+                    ),
+                    ValueType::Hash(hash) => DatabaseValue::Hash(
+                        hash.iter()
+                            .map(|(field, value)| {
+                                (
+                                    StringEncodedField::new(field.as_bytes()),
+                                    StringEncodedField::new(value),
+                                )
+                            })
+                            .collect(),
+                    ),
+                    value => {
+                        log_warning!(
+                            "Can't save {} in the rdb, {} values are not supported",
+                            key,
+                            value.name()
+                        );
+                        continue;
+                    }
+                };
+                let expires_at_ms = db_value.expires_at.map(|expires_at| {
+                    now_unix_ms + expires_at.saturating_duration_since(now).as_millis() as u64
+                });
+                fields.push(DatabaseField::new(key, value, expires_at_ms));
+            }
+            if !fields.is_empty() {
+                database_sections.push(DatabaseSection::new(index as u32, fields));
+            }
+        }
+        Rdb::with_databases(database_sections)
+    }
+
+    /// Saves the rdb to dir/dbfilename, as SAVE does
+    pub fn save(&mut self) -> Result<()> {
+        let path = std::path::Path::new(&self.info.dir).join(&self.info.dbfilename);
+        self.to_rdb()?.save(path)?;
+        self.dirty = 0;
+        self.last_save = Instant::now();
+        Ok(())
+    }
+
+    /// Called on every tick of the main loop: saves the rdb if one of the save points is
+    /// reached. Returns whether it was saved
+    pub fn save_if_due(&mut self, now: Instant) -> bool {
+        let elapsed = now.saturating_duration_since(self.last_save);
+        let due = self.info.save_points.iter().any(|save_point| {
+            self.dirty >= save_point.changes && elapsed.as_secs() >= save_point.seconds
+        });
+        if !due || self.dirty == 0 {
+            return false;
+        }
+        match self.save() {
+            Ok(()) => true,
+            Err(e) => {
+                log_warning!("Background saving error: {}", e);
+                self.last_save = now;
+                false
+            }
+        }
+    }
+
+    /// Logs the write command to the append only file if it is enabled
+    pub fn append_to_aof(&mut self, redis_value: &RedisValue) -> Result<()> {
+        match self.aof.as_mut() {
+            Some(aof) => aof.append(redis_value),
+            None => Ok(()),
+        }
+    }
+
+    /// Removes the keys and returns how many existed
+    pub fn del(&self, keys: &[String]) -> usize {
+        let mut inner = self.inner.borrow_mut();
+        keys.iter()
+            .filter(|key| {
+                inner
+                    .store_mut()
+                    .remove(key.as_str())
+                    .is_some_and(|db_value| !db_value.is_expired())
+            })
+            .count()
+    }
+
+    /// Publishes the message to the subscribers of the channel and to the subscribers of
+    /// the matching patterns. Returns the number of receivers
+    pub fn publish(&mut self, channel: &str, message: &str) -> usize {
+        let now = Instant::now();
+        let receivers = self.pubsub.receivers(channel);
+        for (token, pattern) in receivers.iter() {
+            let redis_value = match pattern {
+                None => RedisValue::Push(
+                    3,
+                    vec![
+                        RedisValue::bulkstring_from("message"),
+                        RedisValue::bulkstring_from(channel),
+                        RedisValue::bulkstring_from(message),
+                    ],
+                ),
+                Some(pattern) => RedisValue::Push(
+                    4,
+                    vec![
+                        RedisValue::bulkstring_from("pmessage"),
+                        RedisValue::bulkstring_from(pattern),
+                        RedisValue::bulkstring_from(channel),
+                        RedisValue::bulkstring_from(message),
+                    ],
+                ),
+            };
+            self.defer_reply(*token, now, redis_value);
+        }
+        receivers.len()
+    }
+
+    /// Publishes the keyspace and keyevent notifications of the event if its class is
+    /// enabled in notify-keyspace-events
+    fn notify_keyspace_event(&mut self, class: char, event: &str, key: &str) {
+        let (keyspace, keyevent) =
+            keyspace_events_enabled(&self.info.notify_keyspace_events, class);
+        let db_index = self.selected_db();
+        if keyspace {
+            self.publish(&format!("__keyspace@{}__:{}", db_index, key), event);
+        }
+        if keyevent {
+            self.publish(&format!("__keyevent@{}__:{}", db_index, event), key);
+        }
+    }
+
+    pub fn incr(&mut self, key: &str) -> Result<i64> {
+        self.incr_by(key, 1)
+    }
+
+    pub fn decr(&mut self, key: &str) -> Result<i64> {
+        self.incr_by(key, -1)
+    }
+
+    pub fn incrby(&mut self, key: &str, increment: i64) -> Result<i64> {
+        self.incr_by(key, increment)
+    }
+
+    pub fn decrby(&mut self, key: &str, decrement: i64) -> Result<i64> {
+        self.incr_by(key, decrement.checked_neg().ok_or(Error::IncrOverflow)?)
+    }
+
+    /// Adds the delta to the integer stored at the key, 0 if it does not exist
+    fn incr_by(&mut self, key: &str, delta: i64) -> Result<i64> {
+        self.update_number(key, |current| increment_integer(current, delta))
+    }
+
+    /// Adds the delta to the float stored at the key, 0 if it does not exist. As in redis,
+    /// the result is stored without exponent nor trailing zeros
+    pub fn incrbyfloat(&mut self, key: &str, delta: f64) -> Result<f64> {
+        self.update_number(key, |current| increment_float(current, delta))
+    }
+
+    /// Same as INCRBY for a field of the hash, which is created if needed
+    pub fn hincrby(&mut self, key: &str, field: &str, delta: i64) -> Result<i64> {
+        self.update_hash_field(key, field, |current| increment_integer(current, delta))
+    }
+
+    /// Same as INCRBYFLOAT for a field of the hash, which is created if needed
+    pub fn hincrbyfloat(&mut self, key: &str, field: &str, delta: f64) -> Result<f64> {
+        self.update_hash_field(key, field, |current| increment_float(current, delta))
+    }
+
+    /// Replaces the value of the field of the hash by the one computed from its current
+    /// value, None if the field does not exist. The hash is created if needed
+    fn update_hash_field<T>(
+        &mut self,
+        key: &str,
+        field: &str,
+        update: impl FnOnce(Option<&[u8]>) -> Result<(Vec<u8>, T)>,
+    ) -> Result<T> {
+        self.evict_if_expired(key);
+        let mut inner = self.inner.borrow_mut();
+        let store = inner.store_mut();
+        let result = match store.get_mut(key) {
+            None => {
+                let mut hash = Hash::new();
+                let result = hash.update(field, update)?;
+                store.insert(key.to_string(), DbValue::new(ValueType::Hash(hash), None));
+                result
+            }
+            Some(DbValue {
+                value: ValueType::Hash(hash),
+                ..
+            }) => hash.update(field, update)?,
+            Some(_) => return Err(Error::WrongTypeOperation),
+        };
+        inner.touch(key);
+        Ok(result)
+    }
+
+    /// Replaces the string of the key by the one computed from its current value, None if
+    /// the key does not exist. The expiration of the key is kept
+    fn update_number<T>(
+        &mut self,
+        key: &str,
+        update: impl FnOnce(Option<&[u8]>) -> Result<(Vec<u8>, T)>,
+    ) -> Result<T> {
+        self.evict_if_expired(key);
+        let mut inner = self.inner.borrow_mut();
+        let store = inner.store_mut();
+        let result = match store.get_mut(key) {
             None => {
-                db.store.insert(
+                let (string, result) = update(None)?;
+                store.insert(
                     key.to_string(),
-                    DbValue {
-                        value: ValueType::String("1".to_string()),
-                        expires_at: None,
-                    },
+                    DbValue::new(ValueType::String(string), None),
                 );
-                Ok(1)
+                result
             }
             Some(DbValue {
-                value: ValueType::String(ref mut val),
-                expires_at: _,
+                value: ValueType::String(current),
+                ..
             }) => {
-                let incremented = val.parse::<i64>()? + 1;
-                *val = format!("{}", incremented);
-                Ok(incremented)
+                let (string, result) = update(Some(current))?;
+                *current = string;
+                result
             }
-            _ => Err(Error::WrongTypeOperation),
-        }
+            Some(_) => return Err(Error::WrongTypeOperation),
+        };
+        inner.touch(key);
+        Ok(result)
     }
 
+    /// Adds the entry and returns its id. None if the stream does not exist and
+    /// no_mk_stream forbids to create it
     pub fn xadd(
         &mut self,
         key: &str,
         stream_id: &str,
         store: HashMap<String, String>,
-    ) -> Result<String> {
+        trim: Option<&StreamTrim>,
+        no_mk_stream: bool,
+    ) -> Result<Option<String>> {
         let mut inner = self.inner.borrow_mut();
+        let exists = inner
+            .store()
+            .get(key)
+            .is_some_and(|db_value| !db_value.is_expired());
+        if no_mk_stream && !exists {
+            return Ok(None);
+        }
+        let db_value = inner
+            .store_mut()
+            .entry(key.to_string())
+            .or_insert_with(|| DbValue::new(ValueType::Stream(Stream::new()), None));
+
+        let returned_stream_id = match &mut db_value.value {
+            ValueType::Stream(stream) => {
+                let stream_id = stream.create_new_entry_id(stream_id)?;
+                let returned_stream_id = stream.xadd(store, Some(stream_id))?;
+                if let Some(trim) = trim {
+                    stream.trim(trim);
+                }
+                inner.touch(key);
+                returned_stream_id
+            }
+            _ => Err(Error::WrongTypeOperation)?,
+        };
 
-        // NOTE: Here we just handle the case where we set a blocking connection with no
-        // timeout
+        // The XREAD blocked on this key is answered at the next iteration of the main loop,
+        // whatever its timeout: its deadline is moved to now. A zero timeout means no
+        // deadline, hence the minimum of 1 ms.
         if let Some(PendingStreamXread {
-            connection_token: _,
-            initial_time: _,
+            initial_time,
             ref mut timeout,
             ref key_offset_pairs,
+            ..
         }) = self.pending_stream_xread
         {
-            // we set the timeout to 1 ms so that it returns directly
-            if *timeout == Duration::from_millis(0)
-                && key_offset_pairs
-                    .iter()
-                    .any(|(stream_key, _)| key == stream_key)
+            if key_offset_pairs
+                .iter()
+                .any(|(stream_key, _)| key == stream_key)
             {
-                *timeout = Duration::from_millis(1);
+                *timeout = initial_time.elapsed().max(Duration::from_millis(1));
             }
         }
+        Ok(Some(returned_stream_id.to_string()))
+    }
 
-        let db_value = inner
-            .store
-            .entry(key.to_string())
-            .or_insert_with(|| DbValue::new(ValueType::Stream(Stream::new()), None));
+    /// Removes the entries with these ids from the stream and returns how many existed
+    pub fn xdel(&mut self, key: &str, ids: &[String]) -> Result<usize> {
+        let mut inner = self.inner.borrow_mut();
+        let stream = match inner.store_mut().get_mut(key) {
+            Some(db_value) if !db_value.is_expired() => match &mut db_value.value {
+                ValueType::Stream(stream) => stream,
+                _ => Err(Error::WrongTypeOperation)?,
+            },
+            _ => return Ok(0),
+        };
 
-        match &mut db_value.value {
-            ValueType::Stream(stream) => {
-                let stream_id = stream.create_stream_id(stream_id)?;
-                let returned_stream_id = stream.xadd(store, Some(stream_id))?;
-                Ok(returned_stream_id.to_string())
-            }
-            _ => Err(Error::WrongTypeOperation)?,
+        let ids = ids
+            .iter()
+            .map(|id| stream.create_stream_id(id))
+            .collect::<Result<Vec<_>>>()?;
+        let deleted = stream.xdel(&ids);
+        if deleted > 0 {
+            inner.touch(key);
         }
+        Ok(deleted)
     }
 
     pub fn xrange(
@@ -231,7 +1329,7 @@ impl RedisDb {
 
         // Actually creates a stream if does not exist. Not sure if correct
         let db_value = inner
-            .store
+            .store_mut()
             .entry(key.to_string())
             .or_insert_with(|| DbValue::new(ValueType::Stream(Stream::new()), None));
 
@@ -241,7 +1339,23 @@ impl RedisDb {
         }
     }
 
-    pub fn xread(
+    pub fn xrevrange(
+        &self,
+        key: &str,
+        stream_id_end: &str,
+        stream_id_start: &str,
+    ) -> Result<Vec<(String, HashMap<String, String>)>> {
+        let mut inner = self.inner.borrow_mut();
+        match inner.store_mut().get_mut(key) {
+            Some(db_value) if !db_value.is_expired() => match &mut db_value.value {
+                ValueType::Stream(stream) => stream.xrevrange(stream_id_end, stream_id_start),
+                _ => Err(Error::WrongTypeOperation)?,
+            },
+            _ => Ok(vec![]),
+        }
+    }
+
+    pub fn xread(
         &self,
         key: &str,
         stream_id_start: &str,
@@ -250,7 +1364,7 @@ impl RedisDb {
 
         // Actually creates a stream if does not exist. Not sure if correct
         let db_value = inner
-            .store
+            .store_mut()
             .entry(key.to_string())
             .or_insert_with(|| DbValue::new(ValueType::Stream(Stream::new()), None));
 
@@ -260,11 +1374,159 @@ impl RedisDb {
         }
     }
 
+    /// Same as with_stream for the functions modifying the stream
+    fn with_stream_mut<T>(
+        &self,
+        key: &str,
+        f: impl FnOnce(&mut Stream) -> Result<T>,
+    ) -> Result<Option<T>> {
+        let result = self.with_stream(key, f)?;
+        if result.is_some() {
+            self.inner.borrow_mut().touch(key);
+        }
+        Ok(result)
+    }
+
+    /// Runs f on the stream stored at key. Returns None if the key does not exist
+    fn with_stream<T>(
+        &self,
+        key: &str,
+        f: impl FnOnce(&mut Stream) -> Result<T>,
+    ) -> Result<Option<T>> {
+        let mut inner = self.inner.borrow_mut();
+        match inner.store_mut().get_mut(key) {
+            Some(db_value) if !db_value.is_expired() => match &mut db_value.value {
+                ValueType::Stream(stream) => Ok(Some(f(stream)?)),
+                _ => Err(Error::WrongTypeOperation)?,
+            },
+            _ => Ok(None),
+        }
+    }
+
+    pub fn xgroup_create(
+        &self,
+        key: &str,
+        group: &str,
+        stream_id: &str,
+        mk_stream: bool,
+    ) -> Result<()> {
+        if mk_stream && !self.contains_key(key) {
+            self.set(key.to_string(), ValueType::Stream(Stream::new()), None);
+        }
+        self.with_stream_mut(key, |stream| stream.create_group(group, stream_id))?
+            .ok_or(Error::XGroupKeyMissing)
+    }
+
+    /// Returns whether the group existed
+    pub fn xgroup_destroy(&self, key: &str, group: &str) -> Result<bool> {
+        Ok(self
+            .with_stream_mut(key, |stream| Ok(stream.destroy_group(group)))?
+            .unwrap_or(false))
+    }
+
+    /// Returns whether the consumer was created
+    pub fn xgroup_create_consumer(&self, key: &str, group: &str, consumer: &str) -> Result<bool> {
+        let no_such_group = || Error::NoSuchConsumerGroup {
+            key: key.to_string(),
+            group: group.to_string(),
+        };
+        self.with_stream_mut(key, |stream| {
+            let group = stream.groups.get_mut(group).ok_or_else(no_such_group)?;
+            Ok(group.create_consumer(consumer))
+        })?
+        .ok_or_else(no_such_group)
+    }
+
+    pub fn xreadgroup(
+        &self,
+        key: &str,
+        group: &str,
+        consumer: &str,
+        stream_id_start: &str,
+        count: Option<usize>,
+        no_ack: bool,
+    ) -> Result<Vec<(String, HashMap<String, String>)>> {
+        self.with_stream_mut(key, |stream| {
+            stream.read_group(key, group, consumer, stream_id_start, count, no_ack)
+        })?
+        .ok_or_else(|| Error::NoSuchConsumerGroup {
+            key: key.to_string(),
+            group: group.to_string(),
+        })
+    }
+
+    pub fn xinfo_stream(&self, key: &str) -> Result<StreamInfo> {
+        self.with_stream(key, |stream| Ok(stream.info()))?
+            .ok_or(Error::NoSuchKey)
+    }
+
+    pub fn xpending_summary(&self, key: &str, group: &str) -> Result<PendingSummary> {
+        self.with_stream(key, |stream| stream.pending_summary(key, group))?
+            .ok_or_else(|| Error::NoSuchConsumerGroup {
+                key: key.to_string(),
+                group: group.to_string(),
+            })
+    }
+
+    /// Returns the id, consumer, idle time in ms and delivery count of each pending entry
+    pub fn xpending_entries(
+        &self,
+        key: &str,
+        group: &str,
+        range: &PendingRange,
+    ) -> Result<Vec<(String, String, u64, u64)>> {
+        let now = clock::now();
+        self.with_stream(key, |stream| {
+            let entries = stream.pending_entries(key, group, range)?;
+            Ok(entries
+                .iter()
+                .map(|(stream_id, pending_entry)| {
+                    (
+                        stream_id.to_string(),
+                        pending_entry.consumer.clone(),
+                        pending_entry.idle_ms(now),
+                        pending_entry.delivery_count,
+                    )
+                })
+                .collect())
+        })?
+        .ok_or_else(|| Error::NoSuchConsumerGroup {
+            key: key.to_string(),
+            group: group.to_string(),
+        })
+    }
+
+    pub fn xclaim(
+        &self,
+        key: &str,
+        group: &str,
+        consumer: &str,
+        stream_ids: &[String],
+        options: &ClaimOptions,
+    ) -> Result<Vec<(String, HashMap<String, String>)>> {
+        let stream_ids = stream_ids.iter().map(|x| x.as_str()).collect::<Vec<_>>();
+        self.with_stream_mut(key, |stream| {
+            stream.claim(key, group, consumer, &stream_ids, options)
+        })?
+        .ok_or_else(|| Error::NoSuchConsumerGroup {
+            key: key.to_string(),
+            group: group.to_string(),
+        })
+    }
+
+    /// Returns the number of acknowledged entries
+    pub fn xack(&self, key: &str, group: &str, stream_ids: &[String]) -> Result<usize> {
+        let stream_ids = stream_ids.iter().map(|x| x.as_str()).collect::<Vec<_>>();
+        Ok(self
+            .with_stream_mut(key, |stream| stream.ack(group, &stream_ids))?
+            .unwrap_or(0))
+    }
+
     pub fn get_last_stream_id(&self, key: &str) -> Result<String> {
         let mut inner = self.inner.borrow_mut();
         // Actually creates a stream if does not exist. Not sure if correct
         let db_value = inner
-            .store
+            .store_mut()
             .entry(key.to_string())
             .or_insert_with(|| DbValue::new(ValueType::Stream(Stream::new()), None));
 
@@ -274,39 +1536,1069 @@ impl RedisDb {
         }
     }
 
-    pub fn keys(&self, _pat: &str) -> Vec<String> {
-        self.inner
-            .borrow()
-            .store
+    /// Pushes the values one after the other to the head (or the tail) of the list and
+    /// returns its length. LPUSH key a b c thus gives c b a, RPUSH key a b c gives a b c.
+    /// As XADD does for the pending XREAD, the push then serves the clients blocked on
+    /// the key.
+    pub fn push(&mut self, key: &str, values: &[Vec<u8>], from_left: bool) -> Result<usize> {
+        self.evict_if_expired(key);
+        let len = {
+            let mut inner = self.inner.borrow_mut();
+            let store = inner.store_mut();
+            let db_value = store
+                .entry(key.to_string())
+                .or_insert_with(|| DbValue::new(ValueType::List(VecDeque::new()), None));
+
+            let len = match &mut db_value.value {
+                ValueType::List(list) => {
+                    for value in values {
+                        if from_left {
+                            list.push_front(value.clone());
+                        } else {
+                            list.push_back(value.clone());
+                        }
+                    }
+                    list.len()
+                }
+                _ => Err(Error::WrongTypeOperation)?,
+            };
+            inner.touch(key);
+            len
+        };
+        self.serve_blocked_pops(key);
+        Ok(len)
+    }
+
+    /// Removes and returns the first (or last) element of the list. The key is removed
+    /// with its last element.
+    pub fn pop(&self, key: &str, from_left: bool) -> Result<Option<Vec<u8>>> {
+        Ok(self
+            .pop_count(key, from_left, 1)?
+            .and_then(|mut elements| elements.pop()))
+    }
+
+    /// Removes and returns up to count elements from the head (or the tail) of the list,
+    /// in the order they are popped. None if the key does not exist.
+    pub fn pop_count(
+        &self,
+        key: &str,
+        from_left: bool,
+        count: usize,
+    ) -> Result<Option<Vec<Vec<u8>>>> {
+        let mut inner = self.inner.borrow_mut();
+        let store = inner.store_mut();
+        let list = match store.get_mut(key) {
+            Some(db_value) if !db_value.is_expired() => match &mut db_value.value {
+                ValueType::List(list) => list,
+                _ => Err(Error::WrongTypeOperation)?,
+            },
+            _ => return Ok(None),
+        };
+
+        let count = count.min(list.len());
+        let elements = if from_left {
+            list.drain(..count).collect()
+        } else {
+            list.drain(list.len() - count..).rev().collect()
+        };
+        if list.is_empty() {
+            store.remove(key);
+        }
+        inner.touch(key);
+        Ok(Some(elements))
+    }
+
+    /// Returns the length of the list, 0 if the key does not exist
+    pub fn llen(&self, key: &str) -> Result<usize> {
+        let inner = self.inner.borrow();
+        match inner.store().get(key) {
+            Some(db_value) if !db_value.is_expired() => match &db_value.value {
+                ValueType::List(list) => Ok(list.len()),
+                _ => Err(Error::WrongTypeOperation)?,
+            },
+            _ => Ok(0),
+        }
+    }
+
+    /// Returns the elements between start and stop included. Negative indices count
+    /// from the tail and out of range indices are clamped, as in redis.
+    pub fn lrange(&self, key: &str, start: i64, stop: i64) -> Result<Vec<Vec<u8>>> {
+        let inner = self.inner.borrow();
+        let list = match inner.store().get(key) {
+            Some(db_value) if !db_value.is_expired() => match &db_value.value {
+                ValueType::List(list) => list,
+                _ => Err(Error::WrongTypeOperation)?,
+            },
+            _ => return Ok(vec![]),
+        };
+
+        Ok(match index_range(list.len(), start, stop) {
+            Some(range) => list.range(range).cloned().collect(),
+            None => vec![],
+        })
+    }
+
+    /// Pops an element from the first non empty list, as BLPOP and BRPOP do before
+    /// blocking. Returns the key and the element
+    pub fn pop_first(
+        &mut self,
+        keys: &[String],
+        from_left: bool,
+    ) -> Result<Option<(String, Vec<u8>)>> {
+        for key in keys {
+            if let Some(element) = self.pop(key, from_left)? {
+                let db_index = self.selected_db();
+                self.pending_pops
+                    .push((db_index, pop_command(key, from_left)));
+                return Ok(Some((key.clone(), element)));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Registers a client that waits for an element to be pushed to one of its lists
+    pub fn block_pop(&mut self, blocked_pop: BlockedPop) {
+        self.blocked_pops.push_back(blocked_pop);
+    }
+
+    /// Whether the connection waits in BLPOP or BRPOP, or for a deferred reply such as
+    /// the one of DEBUG SLEEP. Its input is only processed once it is unblocked so that
+    /// the replies keep the order of the commands
+    pub fn is_blocked(&self, token: Token) -> bool {
+        self.blocked_pops
+            .iter()
+            .any(|blocked_pop| blocked_pop.connection_token == token)
+            || self
+                .deferred_replies
+                .iter()
+                .any(|deferred_reply| deferred_reply.connection_token == token)
+    }
+
+    /// Unblocks the clients whose timeout is reached and returns their connections
+    pub fn take_timed_out_pops(&mut self, now: Instant) -> Vec<Token> {
+        let (timed_out, blocked) = self.blocked_pops.drain(..).partition(|blocked_pop| {
+            blocked_pop.timeout > Duration::ZERO
+                && blocked_pop.initial_time + blocked_pop.timeout <= now
+        });
+        self.blocked_pops = blocked;
+        timed_out
+            .into_iter()
+            .map(|blocked_pop: BlockedPop| blocked_pop.connection_token)
+            .collect()
+    }
+
+    /// Serves the clients blocked on the key of the selected database with one element
+    /// each, the longest waiting first, while the list is not empty.
+    /// Replicas receive the pops from their master instead.
+    fn serve_blocked_pops(&mut self, key: &str) {
+        if self.is_replica() {
+            return;
+        }
+        let db_index = self.selected_db();
+        let now = Instant::now();
+        let mut index = 0;
+        while index < self.blocked_pops.len() {
+            let blocked_pop = &self.blocked_pops[index];
+            if blocked_pop.db_index != db_index || !blocked_pop.keys.iter().any(|k| k == key) {
+                index += 1;
+                continue;
+            }
+            let from_left = blocked_pop.from_left;
+            let Ok(Some(element)) = self.pop(key, from_left) else {
+                break;
+            };
+
+            let connection_token = blocked_pop.connection_token;
+            self.blocked_pops.remove(index);
+            self.pending_pops
+                .push((db_index, pop_command(key, from_left)));
+            self.defer_reply(
+                connection_token,
+                now,
+                RedisValue::Array(
+                    2,
+                    vec![
+                        RedisValue::bulkstring_from(key),
+                        RedisValue::bulkstring_from_bytes(&element),
+                    ],
+                ),
+            );
+        }
+    }
+
+    /// Returns the indices of the elements of the list equal to `element`.
+    /// A negative rank starts the search from the tail, a count of 0 returns all the matches.
+    pub fn lpos(&self, key: &str, element: &[u8], rank: i64, count: usize) -> Result<Vec<usize>> {
+        let inner = self.inner.borrow();
+        let db_value = match inner.store().get(key) {
+            Some(db_value) if !db_value.is_expired() => db_value,
+            _ => return Ok(vec![]),
+        };
+
+        match &db_value.value {
+            ValueType::List(list) => {
+                let skip = (rank.unsigned_abs() - 1) as usize;
+                let take = if count == 0 { usize::MAX } else { count };
+                let matches = list
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, el)| *el == element)
+                    .map(|(index, _)| index);
+
+                if rank > 0 {
+                    Ok(matches.skip(skip).take(take).collect())
+                } else {
+                    Ok(matches.rev().skip(skip).take(take).collect())
+                }
+            }
+            _ => Err(Error::WrongTypeOperation)?,
+        }
+    }
+
+    /// Returns random field value pairs from the hash. See random::sample for the meaning
+    /// of count
+    pub fn hrandfield(&self, key: &str, count: i64) -> Result<Vec<(String, Vec<u8>)>> {
+        let inner = self.inner.borrow();
+        let db_value = match inner.store().get(key) {
+            Some(db_value) if !db_value.is_expired() => db_value,
+            _ => return Ok(vec![]),
+        };
+
+        match &db_value.value {
+            ValueType::Hash(hash) => {
+                let fields = hash.iter().collect::<Vec<_>>();
+                Ok(random::sample(&fields, count)?
+                    .into_iter()
+                    .map(|(field, value)| (field.to_string(), value.to_vec()))
+                    .collect())
+            }
+            _ => Err(Error::WrongTypeOperation)?,
+        }
+    }
+
+    /// Sets the fields of the hash, creating the key if needed. Returns the number of
+    /// fields that were added, not those only updated.
+    pub fn hset(&mut self, key: &str, pairs: &[(String, Vec<u8>)]) -> Result<usize> {
+        self.evict_if_expired(key);
+        let mut inner = self.inner.borrow_mut();
+        let store = inner.store_mut();
+        let db_value = store
+            .entry(key.to_string())
+            .or_insert_with(|| DbValue::new(ValueType::Hash(Hash::new()), None));
+
+        let added = match &mut db_value.value {
+            ValueType::Hash(hash) => pairs
+                .iter()
+                .filter(|(field, value)| hash.insert(field, value))
+                .count(),
+            _ => Err(Error::WrongTypeOperation)?,
+        };
+        inner.touch(key);
+        Ok(added)
+    }
+
+    /// Value of the field of the hash, None if the key or the field does not exist
+    pub fn hget(&self, key: &str, field: &str) -> Result<Option<Vec<u8>>> {
+        let inner = self.inner.borrow();
+        match inner.store().get(key) {
+            Some(db_value) if !db_value.is_expired() => match &db_value.value {
+                ValueType::Hash(hash) => Ok(hash.get(field).map(|value| value.to_vec())),
+                _ => Err(Error::WrongTypeOperation),
+            },
+            _ => Ok(None),
+        }
+    }
+
+    /// Removes the fields from the hash and returns how many existed. The key is deleted
+    /// when it has no field left
+    pub fn hdel(&mut self, key: &str, fields: &[String]) -> Result<usize> {
+        let mut inner = self.inner.borrow_mut();
+        let hash = match inner.store_mut().get_mut(key) {
+            Some(db_value) if !db_value.is_expired() => match &mut db_value.value {
+                ValueType::Hash(hash) => hash,
+                _ => return Err(Error::WrongTypeOperation),
+            },
+            _ => return Ok(0),
+        };
+
+        let removed = fields
+            .iter()
+            .filter(|field| hash.get(field).is_some() && hash.remove(field).is_some())
+            .count();
+        if hash.is_empty() {
+            inner.store_mut().remove(key);
+        }
+        if removed > 0 {
+            inner.touch(key);
+        }
+        Ok(removed)
+    }
+
+    /// All the (field, value) pairs of the hash, in no particular order
+    pub fn hgetall(&self, key: &str) -> Result<Vec<(String, Vec<u8>)>> {
+        let inner = self.inner.borrow();
+        match inner.store().get(key) {
+            Some(db_value) if !db_value.is_expired() => match &db_value.value {
+                ValueType::Hash(hash) => Ok(hash
+                    .iter()
+                    .map(|(field, value)| (field.to_string(), value.to_vec()))
+                    .collect()),
+                _ => Err(Error::WrongTypeOperation),
+            },
+            _ => Ok(vec![]),
+        }
+    }
+
+    /// Number of fields of the hash, 0 if the key does not exist
+    pub fn hlen(&self, key: &str) -> Result<usize> {
+        let inner = self.inner.borrow();
+        match inner.store().get(key) {
+            Some(db_value) if !db_value.is_expired() => match &db_value.value {
+                ValueType::Hash(hash) => Ok(hash.len()),
+                _ => Err(Error::WrongTypeOperation),
+            },
+            _ => Ok(0),
+        }
+    }
+
+    /// Sets the expiration of the fields of the hash in seconds. Returns the status of
+    /// each field, see Hash::expire. The key is deleted when it has no field left
+    pub fn hexpire(&mut self, key: &str, seconds: u64, fields: &[String]) -> Result<Vec<i64>> {
+        let mut inner = self.inner.borrow_mut();
+        let hash = match inner.store_mut().get_mut(key) {
+            Some(db_value) if !db_value.is_expired() => match &mut db_value.value {
+                ValueType::Hash(hash) => hash,
+                _ => return Err(Error::WrongTypeOperation),
+            },
+            _ => return Ok(vec![-2; fields.len()]),
+        };
+
+        let statuses = fields
+            .iter()
+            .map(|field| hash.expire(field, Duration::from_secs(seconds)))
+            .collect();
+        if hash.is_empty() {
+            inner.store_mut().remove(key);
+        }
+        inner.touch(key);
+        Ok(statuses)
+    }
+
+    /// Remaining time to live of the fields of the hash in seconds, see Hash::ttl
+    pub fn httl(&self, key: &str, fields: &[String]) -> Result<Vec<i64>> {
+        let inner = self.inner.borrow();
+        match inner.store().get(key) {
+            Some(db_value) if !db_value.is_expired() => match &db_value.value {
+                ValueType::Hash(hash) => Ok(fields.iter().map(|field| hash.ttl(field)).collect()),
+                _ => Err(Error::WrongTypeOperation),
+            },
+            _ => Ok(vec![-2; fields.len()]),
+        }
+    }
+
+    /// Adds the (score, member) pairs to the sorted set according to the ZADD flags.
+    /// The key is only created if a member is added.
+    pub fn zadd(
+        &mut self,
+        key: &str,
+        options: &ZAddOptions,
+        pairs: &[(f64, String)],
+    ) -> Result<Vec<ZAddOutcome>> {
+        self.evict_if_expired(key);
+        let mut inner = self.inner.borrow_mut();
+        let store = inner.store_mut();
+        if options.xx && !store.contains_key(key) {
+            return Ok(vec![ZAddOutcome::Skipped; pairs.len()]);
+        }
+        let db_value = store
+            .entry(key.to_string())
+            .or_insert_with(|| DbValue::new(ValueType::SortedSet(SortedSet::new()), None));
+        let ValueType::SortedSet(sorted_set) = &mut db_value.value else {
+            return Err(Error::WrongTypeOperation);
+        };
+
+        let outcomes = pairs
+            .iter()
+            .map(|(score, member)| sorted_set.add(member, *score, options))
+            .collect::<Result<Vec<_>>>();
+        if sorted_set.is_empty() {
+            store.remove(key);
+        }
+        let outcomes = outcomes?;
+        if outcomes
+            .iter()
+            .any(|outcome| matches!(outcome, ZAddOutcome::Added(_) | ZAddOutcome::Updated(_)))
+        {
+            inner.touch(key);
+        }
+        Ok(outcomes)
+    }
+
+    pub fn zscore(&self, key: &str, member: &str) -> Result<Option<f64>> {
+        Ok(self
+            .sorted_set(key, |sorted_set| sorted_set.score(member))?
+            .flatten())
+    }
+
+    pub fn zrank(&self, key: &str, member: &str) -> Result<Option<usize>> {
+        Ok(self
+            .sorted_set(key, |sorted_set| sorted_set.rank(member))?
+            .flatten())
+    }
+
+    /// (member, score) pairs between the start and stop ranks included
+    pub fn zrange(&self, key: &str, start: i64, stop: i64) -> Result<Vec<(String, f64)>> {
+        Ok(self
+            .sorted_set(key, |sorted_set| {
+                match index_range(sorted_set.len(), start, stop) {
+                    Some(range) => sorted_set
+                        .iter()
+                        .skip(*range.start())
+                        .take(range.count())
+                        .map(|(member, score)| (member.to_string(), score))
+                        .collect(),
+                    None => vec![],
+                }
+            })?
+            .unwrap_or_default())
+    }
+
+    /// Removes the members from the sorted set, and the key once it is empty. Returns the
+    /// number of removed members
+    pub fn zrem(&mut self, key: &str, members: &[String]) -> Result<usize> {
+        self.evict_if_expired(key);
+        let mut inner = self.inner.borrow_mut();
+        let store = inner.store_mut();
+        let sorted_set = match store.get_mut(key) {
+            Some(db_value) if !db_value.is_expired() => match &mut db_value.value {
+                ValueType::SortedSet(sorted_set) => sorted_set,
+                _ => Err(Error::WrongTypeOperation)?,
+            },
+            _ => return Ok(0),
+        };
+
+        let removed = members
+            .iter()
+            .filter(|member| sorted_set.remove(member))
+            .count();
+        if sorted_set.is_empty() {
+            store.remove(key);
+        }
+        if removed > 0 {
+            inner.touch(key);
+        }
+        Ok(removed)
+    }
+
+    /// Applies f to the sorted set of the key, None if the key does not exist
+    fn sorted_set<T>(&self, key: &str, f: impl FnOnce(&SortedSet) -> T) -> Result<Option<T>> {
+        let inner = self.inner.borrow();
+        match inner.store().get(key) {
+            Some(db_value) if !db_value.is_expired() => match &db_value.value {
+                ValueType::SortedSet(sorted_set) => Ok(Some(f(sorted_set))),
+                _ => Err(Error::WrongTypeOperation),
+            },
+            _ => Ok(None),
+        }
+    }
+
+    /// Adds the members to the set, created if needed. Returns the number of members that
+    /// were not already in the set
+    pub fn sadd(&mut self, key: &str, members: &[String]) -> Result<usize> {
+        self.evict_if_expired(key);
+        let mut inner = self.inner.borrow_mut();
+        let store = inner.store_mut();
+        let db_value = store
+            .entry(key.to_string())
+            .or_insert_with(|| DbValue::new(ValueType::Set(HashSet::new()), None));
+        let ValueType::Set(set) = &mut db_value.value else {
+            return Err(Error::WrongTypeOperation);
+        };
+
+        let added = members
+            .iter()
+            .filter(|member| set.insert(member.to_string()))
+            .count();
+        inner.touch(key);
+        Ok(added)
+    }
+
+    /// Removes the members from the set, deleted when it becomes empty. Returns the
+    /// number of members that were in the set
+    pub fn srem(&mut self, key: &str, members: &[String]) -> Result<usize> {
+        self.evict_if_expired(key);
+        let mut inner = self.inner.borrow_mut();
+        let store = inner.store_mut();
+        let set = match store.get_mut(key) {
+            Some(db_value) if !db_value.is_expired() => match &mut db_value.value {
+                ValueType::Set(set) => set,
+                _ => return Err(Error::WrongTypeOperation),
+            },
+            _ => return Ok(0),
+        };
+
+        let removed = members.iter().filter(|member| set.remove(*member)).count();
+        if set.is_empty() {
+            store.remove(key);
+        }
+        if removed > 0 {
+            inner.touch(key);
+        }
+        Ok(removed)
+    }
+
+    /// Random members of the set, see random::sample for the meaning of count. Empty if
+    /// the key does not exist
+    pub fn srandmember(&self, key: &str, count: i64) -> Result<Vec<String>> {
+        let inner = self.inner.borrow();
+        match inner.store().get(key) {
+            Some(db_value) if !db_value.is_expired() => match &db_value.value {
+                ValueType::Set(set) => {
+                    let members = set.iter().collect::<Vec<_>>();
+                    Ok(random::sample(&members, count)?
+                        .into_iter()
+                        .map(|member| member.to_string())
+                        .collect())
+                }
+                _ => Err(Error::WrongTypeOperation),
+            },
+            _ => Ok(vec![]),
+        }
+    }
+
+    /// Removes and returns up to count distinct random members of the set. The removal
+    /// is propagated as SREM since the replicas would pick other members
+    pub fn spop(&mut self, key: &str, count: usize) -> Result<Vec<String>> {
+        let members = self.srandmember(key, count.min(i64::MAX as usize) as i64)?;
+        if members.is_empty() {
+            return Ok(members);
+        }
+        self.srem(key, &members)?;
+
+        let srem = ["SREM", key]
+            .into_iter()
+            .chain(members.iter().map(String::as_str))
+            .map(RedisValue::bulkstring_from)
+            .collect::<Vec<_>>();
+        let db_index = self.selected_db();
+        self.pending_pops
+            .push((db_index, RedisValue::Array(srem.len(), srem)));
+        Ok(members)
+    }
+
+    /// Members of the set, empty if the key does not exist
+    pub fn smembers(&self, key: &str) -> Result<HashSet<String>> {
+        let inner = self.inner.borrow();
+        match inner.store().get(key) {
+            Some(db_value) if !db_value.is_expired() => match &db_value.value {
+                ValueType::Set(set) => Ok(set.clone()),
+                _ => Err(Error::WrongTypeOperation),
+            },
+            _ => Ok(HashSet::new()),
+        }
+    }
+
+    /// Intersection, union or difference of the sets of the keys. Missing keys are empty
+    /// sets and the difference is the members of the first set that are in none of the
+    /// others.
+    pub fn set_operation(
+        &self,
+        operation: SetOperation,
+        keys: &[String],
+    ) -> Result<HashSet<String>> {
+        let sets = keys
+            .iter()
+            .map(|key| self.smembers(key))
+            .collect::<Result<Vec<_>>>()?;
+        let mut sets = sets.into_iter();
+        let first = sets.next().unwrap_or_default();
+        Ok(sets.fold(first, |result, set| match operation {
+            SetOperation::Inter => result.intersection(&set).cloned().collect(),
+            SetOperation::Union => result.union(&set).cloned().collect(),
+            SetOperation::Diff => result.difference(&set).cloned().collect(),
+        }))
+    }
+
+    /// Stores the result of the set operation at destination, which is deleted if the
+    /// result is empty. Returns the number of members of the result
+    pub fn set_operation_store(
+        &mut self,
+        operation: SetOperation,
+        destination: &str,
+        keys: &[String],
+    ) -> Result<usize> {
+        let result = self.set_operation(operation, keys)?;
+        let len = result.len();
+        let mut inner = self.inner.borrow_mut();
+        if result.is_empty() {
+            inner.store_mut().remove(destination);
+        } else {
+            inner.store_mut().insert(
+                destination.to_string(),
+                DbValue::new(ValueType::Set(result), None),
+            );
+        }
+        inner.touch(destination);
+        Ok(len)
+    }
+
+    /// Returns random member score pairs from the sorted set. See random::sample for the
+    /// meaning of count
+    pub fn zrandmember(&self, key: &str, count: i64) -> Result<Vec<(String, f64)>> {
+        let inner = self.inner.borrow();
+        let db_value = match inner.store().get(key) {
+            Some(db_value) if !db_value.is_expired() => db_value,
+            _ => return Ok(vec![]),
+        };
+
+        match &db_value.value {
+            ValueType::SortedSet(sorted_set) => {
+                let members = sorted_set.iter().collect::<Vec<_>>();
+                Ok(random::sample(&members, count)?
+                    .into_iter()
+                    .map(|(member, score)| (member.to_string(), *score))
+                    .collect())
+            }
+            _ => Err(Error::WrongTypeOperation)?,
+        }
+    }
+
+    /// Adds the elements to the HyperLogLog of the key, created if needed. Returns true if
+    /// the estimated cardinality may have changed
+    pub fn pfadd(&mut self, key: &str, elements: &[String]) -> Result<bool> {
+        self.evict_if_expired(key);
+        let mut inner = self.inner.borrow_mut();
+        let store = inner.store_mut();
+        let changed = match store.get_mut(key) {
+            None => {
+                let mut hll = HyperLogLog::new();
+                for element in elements {
+                    hll.add(element.as_bytes());
+                }
+                store.insert(
+                    key.to_string(),
+                    DbValue::new(ValueType::String(hll.to_bytes()), None),
+                );
+                true
+            }
+            Some(DbValue {
+                value: ValueType::String(string),
+                ..
+            }) => {
+                let mut hll = HyperLogLog::from_bytes(string)?;
+                let changed = elements
+                    .iter()
+                    .filter(|element| hll.add(element.as_bytes()))
+                    .count()
+                    > 0;
+                if changed {
+                    *string = hll.to_bytes();
+                }
+                changed
+            }
+            Some(_) => return Err(Error::WrongTypeOperation),
+        };
+        if changed {
+            inner.touch(key);
+        }
+        Ok(changed)
+    }
+
+    /// Estimated cardinality of the union of the HyperLogLogs of the keys
+    pub fn pfcount(&self, keys: &[String]) -> Result<u64> {
+        Ok(self.merged_hyperloglog(keys)?.count())
+    }
+
+    /// Stores at destination the union of its HyperLogLog and the ones of the keys. The
+    /// expiration of destination is kept
+    pub fn pfmerge(&mut self, destination: &str, keys: &[String]) -> Result<()> {
+        let mut merged = self.merged_hyperloglog(keys)?;
+        if let Some(hll) = self.hyperloglog(destination)? {
+            merged.merge(&hll);
+        }
+        let mut inner = self.inner.borrow_mut();
+        let store = inner.store_mut();
+        match store.get_mut(destination) {
+            Some(db_value) if !db_value.is_expired() => {
+                db_value.value = ValueType::String(merged.to_bytes());
+            }
+            _ => {
+                store.insert(
+                    destination.to_string(),
+                    DbValue::new(ValueType::String(merged.to_bytes()), None),
+                );
+            }
+        }
+        inner.touch(destination);
+        Ok(())
+    }
+
+    /// HyperLogLog stored at the key, None if the key does not exist
+    fn hyperloglog(&self, key: &str) -> Result<Option<HyperLogLog>> {
+        let inner = self.inner.borrow();
+        match inner.store().get(key) {
+            Some(db_value) if !db_value.is_expired() => match &db_value.value {
+                ValueType::String(string) => Ok(Some(HyperLogLog::from_bytes(string)?)),
+                _ => Err(Error::WrongTypeOperation),
+            },
+            _ => Ok(None),
+        }
+    }
+
+    /// Union of the HyperLogLogs of the keys, the missing keys counting as empty ones
+    fn merged_hyperloglog(&self, keys: &[String]) -> Result<HyperLogLog> {
+        let mut merged = HyperLogLog::new();
+        for key in keys {
+            if let Some(hll) = self.hyperloglog(key)? {
+                merged.merge(&hll);
+            }
+        }
+        Ok(merged)
+    }
+
+    pub fn bitpos(&self, key: &str, bit: u8, start: Option<i64>, end: Option<i64>) -> Result<i64> {
+        let inner = self.inner.borrow();
+        let db_value = match inner.store().get(key) {
+            Some(db_value) if !db_value.is_expired() => db_value,
+            // non existing keys are considered as empty strings
+            _ => return Ok(bitops::bitpos(&[], bit, start, end)),
+        };
+
+        match &db_value.value {
+            ValueType::String(val) => Ok(bitops::bitpos(val, bit, start, end)),
+            _ => Err(Error::WrongTypeOperation)?,
+        }
+    }
+
+    /// Stores at dest the result of the bitwise operation across the string values of
+    /// srckeys and returns the length of the result.
+    pub fn bitop(&self, op: &str, dest: &str, srckeys: &[String]) -> Result<usize> {
+        let mut inner = self.inner.borrow_mut();
+        let sources = srckeys
+            .iter()
+            .map(|key| match inner.store().get(key) {
+                Some(db_value) if !db_value.is_expired() => match &db_value.value {
+                    ValueType::String(val) => Ok(val.clone()),
+                    _ => Err(Error::WrongTypeOperation),
+                },
+                // non existing keys are considered as empty strings
+                _ => Ok(vec![]),
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let result = bitops::bitop(op, &sources);
+        if result.is_empty() {
+            inner.store_mut().remove(dest);
+        } else {
+            let value = ValueType::String(result.clone());
+            inner
+                .store_mut()
+                .insert(dest.to_string(), DbValue::new(value, None));
+            inner.touch(dest);
+        }
+        Ok(result.len())
+    }
+
+    /// Appends the value to the string, created if needed. Returns the new length
+    pub fn append(&mut self, key: &str, value: &[u8]) -> Result<usize> {
+        self.grow_string(
+            key,
+            |len| (len + value.len()) as u64,
+            |string| {
+                // the string was grown to make room for the value
+                let start = string.len() - value.len();
+                string[start..].copy_from_slice(value);
+                string.len()
+            },
+        )
+    }
+
+    /// Length of the string, 0 if the key does not exist
+    pub fn strlen(&mut self, key: &str) -> Result<usize> {
+        match self.get(key) {
+            Some(ValueType::String(string)) => Ok(string.len()),
+            Some(_) => Err(Error::WrongTypeOperation),
+            None => Ok(0),
+        }
+    }
+
+    /// Bytes of the string between start and end included. Negative indices count from
+    /// the end and are clamped the same way as redis
+    pub fn getrange(&mut self, key: &str, start: i64, end: i64) -> Result<Vec<u8>> {
+        let string = match self.get(key) {
+            Some(ValueType::String(string)) => string,
+            Some(_) => return Err(Error::WrongTypeOperation),
+            None => return Ok(vec![]),
+        };
+        let len = string.len() as i64;
+        if start < 0 && end < 0 && start > end {
+            return Ok(vec![]);
+        }
+        let start = if start < 0 { len + start } else { start }.max(0);
+        let end = if end < 0 { len + end } else { end }.clamp(0, (len - 1).max(0));
+        if len == 0 || start > end {
+            return Ok(vec![]);
+        }
+        Ok(string[start as usize..=end as usize].to_vec())
+    }
+
+    /// Overwrites the string from the offset, padded with zeros if it is too short.
+    /// Returns the new length. An empty value does not create the key.
+    pub fn setrange(&mut self, key: &str, offset: usize, value: &[u8]) -> Result<usize> {
+        if value.is_empty() {
+            return match self.get(key) {
+                Some(ValueType::String(string)) => Ok(string.len()),
+                Some(_) => Err(Error::WrongTypeOperation),
+                None => Ok(0),
+            };
+        }
+        self.grow_string(
+            key,
+            |len| len.max(offset + value.len()) as u64,
+            |string| {
+                string[offset..offset + value.len()].copy_from_slice(value);
+                string.len()
+            },
+        )
+    }
+
+    /// Sets the bit at the offset, counted from the most significant bit of the first
+    /// byte, and returns its previous value
+    pub fn setbit(&mut self, key: &str, offset: u64, bit: bool) -> Result<u8> {
+        if offset / 8 >= self.info.proto_max_bulk_len {
+            return Err(Error::BitOffsetOutOfRange);
+        }
+        let index = (offset / 8) as usize;
+        let mask = 0b1000_0000 >> (offset % 8);
+        self.grow_string(
+            key,
+            |len| len.max(index + 1) as u64,
+            |string| {
+                let previous = u8::from(string[index] & mask != 0);
+                if bit {
+                    string[index] |= mask;
+                } else {
+                    string[index] &= !mask;
+                }
+                previous
+            },
+        )
+    }
+
+    /// Applies the update to the string of the key, created empty if needed and padded
+    /// with zeros to `new_len(current length)` before the update. Fails without
+    /// allocating anything if the new length exceeds proto-max-bulk-len.
+    fn grow_string<T>(
+        &mut self,
+        key: &str,
+        new_len: impl FnOnce(usize) -> u64,
+        update: impl FnOnce(&mut Vec<u8>) -> T,
+    ) -> Result<T> {
+        let max_len = self.info.proto_max_bulk_len;
+        self.evict_if_expired(key);
+        let mut inner = self.inner.borrow_mut();
+        let store = inner.store_mut();
+        let current_len = match store.get(key).map(|db_value| &db_value.value) {
+            None => 0,
+            Some(ValueType::String(string)) => string.len(),
+            Some(_) => return Err(Error::WrongTypeOperation),
+        };
+        let new_len = new_len(current_len);
+        if new_len > max_len {
+            return Err(Error::StringTooLong);
+        }
+
+        let db_value = store
+            .entry(key.to_string())
+            .or_insert_with(|| DbValue::new(ValueType::String(Vec::new()), None));
+        let ValueType::String(string) = &mut db_value.value else {
+            unreachable!("the type was checked above");
+        };
+        if (string.len() as u64) < new_len {
+            string.resize(new_len as usize, 0);
+        }
+        let result = update(string);
+        inner.touch(key);
+        Ok(result)
+    }
+
+    /// Returns the keys of the db. Stops as soon as the configured cap is exceeded so
+    /// that an accidental KEYS * does not build a huge array.
+    /// Non expired keys matching the glob-style pattern. Only the matching keys count
+    /// against `keys_max_results`.
+    pub fn keys(&self, pattern: &str) -> Result<Vec<String>> {
+        let inner = self.inner.borrow();
+        let mut keys = Vec::new();
+        for (key, db_value) in inner.store().iter() {
+            if db_value.is_expired() || !glob_match(pattern, key) {
+                continue;
+            }
+            if let Some(max_results) = self.info.keys_max_results {
+                if keys.len() >= max_results {
+                    return Err(Error::TooManyKeys(max_results));
+                }
+            }
+            keys.push(key.to_string());
+        }
+        Ok(keys)
+    }
+
+    /// Returns about `count` keys from the cursor and the cursor of the next call, 0 when
+    /// the iteration is over. Keys are visited in the order of a fixed hash of their name
+    /// and the cursor is the hash to resume from, so a key present during the whole
+    /// iteration is always returned, whatever is added or removed in between.
+    pub fn scan(
+        &self,
+        cursor: u64,
+        pattern: Option<&str>,
+        count: usize,
+        type_name: Option<&str>,
+    ) -> (u64, Vec<String>) {
+        let inner = self.inner.borrow();
+        let mut candidates = inner
+            .store()
             .keys()
-            .map(|x| x.to_string())
-            .collect::<Vec<_>>()
+            .map(|key| (scan_hash(key), key))
+            .filter(|(hash, _)| *hash >= cursor)
+            .collect::<Vec<_>>();
+        candidates.sort();
+
+        // keys with the same hash can not be separated by the cursor so they are
+        // returned together
+        let mut end = count.max(1).min(candidates.len());
+        while end < candidates.len() && candidates[end].0 == candidates[end - 1].0 {
+            end += 1;
+        }
+        let next_cursor = match candidates.get(end) {
+            Some((hash, _)) => *hash,
+            None => 0,
+        };
+
+        let keys = candidates[..end]
+            .iter()
+            .filter(|(_, key)| {
+                let db_value = &inner.store()[key.as_str()];
+                !db_value.is_expired()
+                    && pattern.map_or(true, |pattern| glob_match(pattern, key))
+                    && type_name.map_or(true, |type_name| db_value.value.name() == type_name)
+            })
+            .map(|(_, key)| key.to_string())
+            .collect();
+        (next_cursor, keys)
+    }
+
+    /// Protocol version of the connection
+    pub fn protocol(&self, token: Token) -> u8 {
+        if self.resp3_connections.contains(&token) {
+            3
+        } else {
+            2
+        }
+    }
+
+    pub fn set_protocol(&mut self, token: Token, protover: u8) {
+        if protover == 3 {
+            self.resp3_connections.insert(token);
+        } else {
+            self.resp3_connections.remove(&token);
+        }
+    }
+
+    /// Converts the value to what the connection understands
+    pub fn encode_for(&self, token: Token, redis_value: RedisValue) -> RedisValue {
+        if self.protocol(token) == 3 {
+            redis_value
+        } else {
+            redis_value.into_resp2()
+        }
+    }
+
+    /// Cleans up everything related to a closed connection
+    pub fn remove_connection(&mut self, token: Token) {
+        self.pubsub.remove_connection(token);
+        self.resp3_connections.remove(&token);
+        self.selected_dbs.remove(&token);
+        self.watched_keys.remove(&token);
+        self.unprocessed_input.remove(&token);
+        self.blocked_pops
+            .retain(|blocked_pop| blocked_pop.connection_token != token);
+        self.deferred_replies
+            .retain(|deferred_reply| deferred_reply.connection_token != token);
     }
 
     pub fn is_replica(&self) -> bool {
         self.info.role == "slave"
     }
 
+    /// The replica starts counting from the offset sent in the FULLRESYNC reply
     pub fn register_replica(&mut self, replica_stream: TcpStream, replica_token: Token) {
-        self.replicas
-            .push(Replica::new(replica_stream, replica_token));
+        let mut replica = Replica::new(replica_stream, replica_token);
+        let offset = self.info.master_repl_offset as usize;
+        replica.offset = offset;
+        replica.write_offset = offset;
+        replica.acked_offset = offset;
+        self.replicas.push(replica);
+        // the new replica starts from database 0, unlike the others
+        if self.replicated_db != Some(0) {
+            self.replicated_db = None;
+        }
     }
 
     pub fn get_nb_uptodate_replicas(&self) -> usize {
-        self.replicas.iter().filter(|r| r.up_to_date).count()
+        self.replicas.iter().filter(|r| r.is_up_to_date()).count()
     }
-    pub fn mark_replicas_as_outdated(&mut self) {
+
+    /// Number of replicas that acknowledged all the writes sent before the pending WAIT.
+    /// Writes received while waiting do not change what the replicas must reach.
+    pub fn get_nb_acked_replicas(&self) -> usize {
+        self.replicas
+            .iter()
+            .filter(|r| r.acked_offset >= r.wait_offset)
+            .count()
+    }
+
+    /// Records the offsets the replicas must acknowledge and asks the replicas that are
+    /// not up to date for an ack.
+    pub fn start_wait(&mut self, pending_wait: PendingWait) -> Result<()> {
         for replica in self.replicas.iter_mut() {
-            replica.up_to_date = false;
+            replica.wait_offset = replica.write_offset;
         }
+        self.pending_wait = Some(pending_wait);
+
+        let redis_value = RedisValue::array_of_bulkstrings_from("REPLCONF GETACK *");
+        self.send_to_replicas(redis_value, true)
     }
 
-    pub fn mark_replica_as_uptodate(&mut self, token: Token) {
-        self.replicas
-            .iter_mut()
-            .find(|replica| replica.token == token)
-            .expect("Replica should exist")
-            .up_to_date = true;
+    /// Reads the data sent by a replica, which only consists of REPLCONF ACK <offset>
+    /// answers to our getacks
+    pub fn receive_replica_acks(&mut self, token: Token) -> Result<()> {
+        let Some(replica) = self.replicas.iter_mut().find(|r| r.token == token) else {
+            return Ok(());
+        };
+        let connection_data = ConnectionData::receive_data(&mut replica.stream.borrow_mut())?;
+        let mut received_data = std::mem::take(&mut replica.partial_ack);
+        received_data.extend_from_slice(connection_data.get_received_data());
+
+        let mut input = received_data.as_slice();
+        while !input.is_empty() {
+            let Some((rest, redis_value)) = finish_streaming(parse_redis_value(input))? else {
+                replica.partial_ack = input.to_vec();
+                break;
+            };
+            input = rest;
+            if let RedisValue::Array(3, values) = redis_value {
+                let values = values
+                    .iter()
+                    .map(|value| value.inner_string())
+                    .collect::<Result<Vec<_>>>()?;
+                if values[0].eq_ignore_ascii_case("replconf")
+                    && values[1].eq_ignore_ascii_case("ack")
+                {
+                    replica.acked_offset = replica.acked_offset.max(values[2].parse()?);
+                }
+            }
+        }
+        Ok(())
     }
 
     /// Starts the handshake process: A replica sends a ping to the master
@@ -315,60 +2607,675 @@ impl RedisDb {
         // let port = self.inner.borrow().info.port;
 
         let redis_value = RedisValue::array_of_bulkstrings_from("PING");
-        stream.write_all(redis_value.to_string().as_bytes())?;
+        stream.write_all(&redis_value.to_bytes())?;
         Ok(())
     }
 
-    pub fn send_to_replicas(&self, redis_value: RedisValue, ignore_up_to_date: bool) -> Result<()> {
-        for replica in self.replicas.iter() {
-            if replica.up_to_date && ignore_up_to_date {
+    /// Sends the value to the replicas and advances their offsets. Write commands are
+    /// sent to all replicas while getacks (ignore_up_to_date) are only sent to the
+    /// replicas that did not acknowledge all the writes. Getacks are part of the
+    /// replication stream, so both advance the master offset.
+    pub fn send_to_replicas(
+        &mut self,
+        redis_value: RedisValue,
+        ignore_up_to_date: bool,
+    ) -> Result<()> {
+        let bytes = redis_value.to_bytes();
+        self.info.master_repl_offset += bytes.len() as u64;
+
+        for replica in self.replicas.iter_mut() {
+            if replica.is_up_to_date() && ignore_up_to_date {
                 continue;
             }
-            replica
-                .stream
-                .borrow_mut()
-                .write_all(redis_value.to_string().as_bytes())?;
+            replica.stream.borrow_mut().write_all(&bytes)?;
+            replica.offset += bytes.len();
+            if !ignore_up_to_date {
+                replica.write_offset = replica.offset;
+            }
         }
 
         Ok(())
     }
 
     pub fn load_rdb(&self, rdb: &Rdb) {
-        let db_section = rdb
-            .database_sections
-            .iter()
-            .find(|x| x.db_number.length == 0);
-        match db_section {
-            None => {}
-            Some(db_section) => {
-                for field in &db_section.fields_with_expiry {
-                    let unix_timestamp_ms_expire = field.get_unix_timestamp_expiration_ms();
-
-                    let value = match field.value_type {
-                        ValueTypeEncoding::String => ValueType::String(field.value.field.clone()),
-                        _ => todo!("Only string implemented with rdb"),
-                    };
-
-                    match unix_timestamp_ms_expire {
-                        None => {
-                            self.set(field.key.field.clone(), value, None);
+        let selected = self.selected_db();
+        for db_section in rdb.database_sections.iter() {
+            let Ok(index) = usize::try_from(db_section.db_number.length) else {
+                continue;
+            };
+            if self.select(index).is_err() {
+                continue;
+            }
+            for field in &db_section.fields_with_expiry {
+                let unix_timestamp_ms_expire = field.get_unix_timestamp_expiration_ms();
+
+                let value = match &field.value {
+                    DatabaseValue::String(value) => ValueType::String(value.field.clone()),
+                    DatabaseValue::List(elements) => ValueType::List(
+                        elements
+                            .iter()
+                            .map(|element| element.field.clone())
+                            .collect(),
+                    ),
+                    // the members of the sets and the fields of the hashes are strings
+                    DatabaseValue::Set(members) => ValueType::Set(
+                        members
+                            .iter()
+                            .map(|member| lossy_string(&member.field))
+                            .collect(),
+                    ),
+                    DatabaseValue::Hash(fields) => {
+                        let mut hash = Hash::new();
+                        for (field, value) in fields {
+                            hash.insert(&lossy_string(&field.field), &value.field);
                         }
-                        Some(unix_timestamp_ms_expire) => {
-                            let since_epoch = SystemTime::now()
-                                .duration_since(UNIX_EPOCH)
-                                .expect("time should not go backward");
-
-                            let current_timestamp_in_ms = since_epoch.as_secs() * 1000
-                                + since_epoch.subsec_nanos() as u64 / 1000000;
-
-                            if current_timestamp_in_ms < unix_timestamp_ms_expire {
-                                let px = unix_timestamp_ms_expire - current_timestamp_in_ms;
-                                self.set(field.key.field.clone(), value, Some(px));
-                            }
+                        ValueType::Hash(hash)
+                    }
+                };
+                let key = lossy_string(&field.key.field);
+
+                match unix_timestamp_ms_expire {
+                    None => {
+                        self.set(key, value, None);
+                    }
+                    Some(unix_timestamp_ms_expire) => {
+                        let current_timestamp_in_ms = unix_time_ms();
+                        if current_timestamp_in_ms < unix_timestamp_ms_expire {
+                            let px = unix_timestamp_ms_expire - current_timestamp_in_ms;
+                            self.set(key, value, Some(px));
                         }
                     }
                 }
             }
         }
+        self.inner.borrow_mut().selected = selected;
+    }
+}
+
+/// Integer represented by the string if it is written the way redis would write it: no
+/// sign for positive numbers, no leading zeros nor spaces. Only such strings are
+/// stored as integers, so that they are returned unchanged
+pub fn canonical_integer(val: &[u8]) -> Option<i64> {
+    let integer = std::str::from_utf8(val).ok()?.parse::<i64>().ok()?;
+    (integer.to_string().as_bytes() == val).then_some(integer)
+}
+
+/// Range of positions between start and stop included, clamped to the length. Negative
+/// indices count from the end, as in LRANGE and ZRANGE. None if the range is empty
+fn index_range(len: usize, start: i64, stop: i64) -> Option<RangeInclusive<usize>> {
+    let len = len as i64;
+    let start = if start < 0 {
+        (len + start).max(0)
+    } else {
+        start
+    };
+    let stop = if stop < 0 {
+        len + stop
+    } else {
+        stop.min(len - 1)
+    };
+    (start <= stop).then_some(start as usize..=stop as usize)
+}
+
+/// Integer stored after adding delta to the current value, 0 if there is none
+fn increment_integer(current: Option<&[u8]>, delta: i64) -> Result<(Vec<u8>, i64)> {
+    let current = match current {
+        None => 0,
+        Some(current) => std::str::from_utf8(current)
+            .ok()
+            .and_then(|current| current.parse::<i64>().ok())
+            .ok_or(Error::NotAnInteger)?,
+    };
+    let incremented = current.checked_add(delta).ok_or(Error::IncrOverflow)?;
+    Ok((incremented.to_string().into_bytes(), incremented))
+}
+
+/// Float stored after adding delta to the current value, 0 if there is none
+fn increment_float(current: Option<&[u8]>, delta: f64) -> Result<(Vec<u8>, f64)> {
+    let current = match current {
+        None => 0.0,
+        Some(current) => std::str::from_utf8(current)
+            .ok()
+            .and_then(|current| current.parse::<f64>().ok())
+            .filter(|current| !current.is_nan())
+            .ok_or(Error::NotAFloat)?,
+    };
+    let incremented = current + delta;
+    if !incremented.is_finite() {
+        return Err(Error::NanOrInfinity);
+    }
+    Ok((format_score(incremented).into_bytes(), incremented))
+}
+
+/// Hash giving the position of a key in a SCAN iteration. It must not depend on the
+/// process so that cursors stay valid
+fn scan_hash(key: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    key.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// LPOP or RPOP propagating the pop of a blocking command to the replicas
+fn pop_command(key: &str, from_left: bool) -> RedisValue {
+    let name = if from_left { "LPOP" } else { "RPOP" };
+    RedisValue::Array(
+        2,
+        vec![
+            RedisValue::bulkstring_from(name),
+            RedisValue::bulkstring_from(key),
+        ],
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pubsub::SubscriptionKind;
+    use binrw::BinRead;
+
+    #[test]
+    fn test_take_due_replies() {
+        let db_info = DbInfo::build("master", 6379, "/tmp/redis-files", "dump.rdb");
+        let mut db = RedisDb::build(db_info, ConnectionState::Ready);
+        let now = Instant::now();
+
+        db.defer_reply(
+            Token(21),
+            now + Duration::from_millis(100),
+            RedisValue::Integer(1),
+        );
+        db.defer_reply(Token(22), now, RedisValue::Integer(2));
+        db.defer_reply(Token(23), now, RedisValue::Integer(3));
+
+        let due = db.take_due_replies(now);
+        assert_eq!(
+            due.iter().map(|r| r.connection_token).collect::<Vec<_>>(),
+            vec![Token(22), Token(23)]
+        );
+        assert_eq!(db.deferred_replies.len(), 1);
+
+        assert!(db.take_due_replies(now).is_empty());
+        let due = db.take_due_replies(now + Duration::from_millis(100));
+        assert_eq!(due[0].redis_value, RedisValue::Integer(1));
+        assert!(db.deferred_replies.is_empty());
+    }
+
+    fn setup_master_db() -> RedisDb {
+        let info = DbInfo::build("master", 6379, "/tmp/redis-files", "dump.rdb");
+        RedisDb::build(info, ConnectionState::Ready)
+    }
+
+    #[test]
+    fn test_evict_expired() {
+        let mut info = DbInfo::build("master", 6379, "/tmp/redis-files", "dump.rdb");
+        info.notify_keyspace_events = "Ex".to_string();
+        let mut db = RedisDb::build(info, ConnectionState::Ready);
+        db.pubsub.subscribe(
+            Token(20),
+            SubscriptionKind::Channel,
+            "__keyevent@0__:expired",
+        );
+        db.pubsub
+            .subscribe(Token(21), SubscriptionKind::Channel, "__keyspace@0__:key");
+
+        db.set(
+            "key".to_string(),
+            ValueType::String("value".into()),
+            Some(0),
+        );
+        assert!(db.get("key").is_none());
+        assert_eq!(db.pending_evictions, vec![(0, "key".to_string())]);
+
+        // only the keyevent notification is enabled
+        let replies = db.take_due_replies(Instant::now());
+        assert_eq!(replies.len(), 1);
+        assert_eq!(replies[0].connection_token, Token(20));
+
+        // the key is already gone, nothing else to evict
+        db.evict_expired("key");
+        assert_eq!(db.pending_evictions.len(), 1);
+        db.propagate_evictions().unwrap();
+        assert!(db.pending_evictions.is_empty());
+
+        // the channel has the index of the database of the key
+        db.pubsub.subscribe(
+            Token(22),
+            SubscriptionKind::Channel,
+            "__keyevent@3__:expired",
+        );
+        db.select(3).unwrap();
+        db.set(
+            "key".to_string(),
+            ValueType::String("value".into()),
+            Some(0),
+        );
+        assert!(db.get("key").is_none());
+        let replies = db.take_due_replies(Instant::now());
+        assert_eq!(replies.len(), 1);
+        assert_eq!(replies[0].connection_token, Token(22));
+    }
+
+    #[test]
+    fn test_replica_does_not_evict_expired() {
+        let info = DbInfo::build("slave", 6380, "/tmp/redis-files", "dump.rdb");
+        let mut db = RedisDb::build(info, ConnectionState::Ready);
+
+        db.set(
+            "key".to_string(),
+            ValueType::String("value".into()),
+            Some(0),
+        );
+        assert!(db.get("key").is_none());
+        assert!(db.inner.borrow().store().contains_key("key"));
+        assert!(db.pending_evictions.is_empty());
+
+        // the key is only removed by the DEL coming from master
+        assert_eq!(db.del(&["key".to_string()]), 0);
+        assert!(!db.inner.borrow().store().contains_key("key"));
+    }
+
+    /// Sets an expired stream at key, a write of another type only succeeds if it is
+    /// evicted
+    fn set_expired(db: &RedisDb, key: &str) {
+        db.set(key.to_string(), ValueType::Stream(Stream::new()), Some(0));
+    }
+
+    #[test]
+    fn test_writes_evict_expired_keys() -> Result<()> {
+        let mut db = setup_master_db();
+        set_expired(&db, "list");
+        assert_eq!(db.push("list", &[b"a".to_vec()], true)?, 1);
+        set_expired(&db, "counter");
+        assert_eq!(db.incr("counter")?, 1);
+        set_expired(&db, "hash");
+        assert_eq!(db.hset("hash", &[("f".to_string(), b"v".to_vec())])?, 1);
+        set_expired(&db, "zset");
+        db.zadd("zset", &ZAddOptions::default(), &[(1.0, "one".to_string())])?;
+        // a removal from an expired key also evicts it
+        set_expired(&db, "removed");
+        assert_eq!(db.zrem("removed", &["one".to_string()])?, 0);
+        set_expired(&db, "hash_counter");
+        assert_eq!(db.hincrby("hash_counter", "f", 2)?, 2);
+        set_expired(&db, "string");
+        assert_eq!(db.append("string", b"abc")?, 3);
+        set_expired(&db, "hll");
+        assert!(db.pfadd("hll", &["a".to_string()])?);
+        set_expired(&db, "set");
+        assert_eq!(db.sadd("set", &["a".to_string()])?, 1);
+        set_expired(&db, "set_removed");
+        assert_eq!(db.srem("set_removed", &["a".to_string()])?, 0);
+
+        let evicted = [
+            "list",
+            "counter",
+            "hash",
+            "zset",
+            "removed",
+            "hash_counter",
+            "string",
+            "hll",
+            "set",
+            "set_removed",
+        ];
+        assert_eq!(
+            db.pending_evictions,
+            evicted.map(|key| (0, key.to_string()))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_memory() {
+        assert_eq!(parse_memory("100"), Ok(100));
+        assert_eq!(parse_memory("100b"), Ok(100));
+        // like in redis, the suffixes without b are powers of 1000
+        assert_eq!(parse_memory("1k"), Ok(1000));
+        assert_eq!(parse_memory("1kb"), Ok(1024));
+        assert_eq!(parse_memory("1KB"), Ok(1024));
+        assert_eq!(parse_memory("3m"), Ok(3000000));
+        assert_eq!(parse_memory("100mb"), Ok(104857600));
+        assert_eq!(parse_memory("2g"), Ok(2000000000));
+        assert_eq!(parse_memory("2Gb"), Ok(2147483648));
+        assert!(parse_memory("mb").is_err());
+        assert!(parse_memory("1tb").is_err());
+        assert!(parse_memory("-1").is_err());
+    }
+
+    #[test]
+    fn test_save_point_parsing() {
+        assert_eq!(
+            "900 1".parse::<SavePoint>(),
+            Ok(SavePoint {
+                seconds: 900,
+                changes: 1
+            })
+        );
+        assert!("900".parse::<SavePoint>().is_err());
+        assert!("900 a".parse::<SavePoint>().is_err());
+    }
+
+    #[test]
+    fn test_save_points() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("test-save-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let mut info = DbInfo::build("master", 6379, dir.to_str().unwrap(), "dump.rdb");
+        info.save_points = vec![
+            SavePoint {
+                seconds: 60,
+                changes: 1,
+            },
+            SavePoint {
+                seconds: 0,
+                changes: 3,
+            },
+        ];
+        let mut db = RedisDb::build(info, ConnectionState::Ready);
+
+        let write = RedisValue::array_of_bulkstrings_from("SET a 1");
+        db.set("a".to_string(), ValueType::String(b"1".to_vec()), None);
+        db.propagate(0, write.clone())?;
+        db.select(2)?;
+        db.set(
+            "b".to_string(),
+            ValueType::String(b"2".to_vec()),
+            Some(60_000),
+        );
+        db.propagate(2, write.clone())?;
+        let now = Instant::now();
+        assert!(!db.save_if_due(now));
+        // enough time passed for the first save point
+        assert!(db.save_if_due(now + Duration::from_secs(61)));
+        assert_eq!(db.dirty, 0);
+        assert!(!db.save_if_due(now + Duration::from_secs(200)));
+
+        // enough changes for the second one
+        for _ in 0..3 {
+            db.propagate(2, write.clone())?;
+        }
+        assert!(db.save_if_due(Instant::now()));
+
+        let mut loaded = setup_master_db();
+        loaded.load_rdb(&Rdb::new(dir.join("dump.rdb"))?);
+        assert!(matches!(loaded.get("a"), Some(ValueType::String(value)) if value == b"1"));
+        loaded.select(2)?;
+        assert!(matches!(loaded.get("b"), Some(ValueType::String(value)) if value == b"2"));
+        let expires_at = loaded.inner.borrow().store()["b"].expires_at.unwrap();
+        assert!(expires_at > Instant::now() + Duration::from_secs(50));
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_rdb_round_trip_of_collections() -> Result<()> {
+        let db = setup_master_db();
+        // values are not necessarily valid utf8
+        db.set(
+            "binary".to_string(),
+            ValueType::String(b"\xff\x00bin".to_vec()),
+            None,
+        );
+        let list = VecDeque::from([b"a".to_vec(), b"1".to_vec(), b"\xfe".to_vec()]);
+        db.set("list".to_string(), ValueType::List(list.clone()), None);
+        let set = HashSet::from(["x".to_string(), "42".to_string()]);
+        db.set("set".to_string(), ValueType::Set(set.clone()), None);
+        let mut hash = Hash::new();
+        hash.insert("f1", b"v1");
+        hash.insert("f2", b"\x80");
+        db.set("hash".to_string(), ValueType::Hash(hash), Some(60_000));
+
+        let rdb = Rdb::read(&mut std::io::Cursor::new(db.to_rdb()?.to_bytes()?))?;
+        let mut loaded = setup_master_db();
+        loaded.load_rdb(&rdb);
+        assert_eq!(loaded.dbsize(), 4);
+        assert!(
+            matches!(loaded.get("binary"), Some(ValueType::String(value)) if value == b"\xff\x00bin")
+        );
+        assert!(matches!(loaded.get("list"), Some(ValueType::List(value)) if value == list));
+        assert!(matches!(loaded.get("set"), Some(ValueType::Set(value)) if value == set));
+        let Some(ValueType::Hash(hash)) = loaded.get("hash") else {
+            panic!("hash should be loaded as a hash");
+        };
+        let mut fields: Vec<_> = hash.iter().collect();
+        fields.sort();
+        assert_eq!(fields, [("f1", &b"v1"[..]), ("f2", &b"\x80"[..])]);
+        assert!(loaded.inner.borrow().store()["hash"].expires_at.is_some());
+
+        // streams can't be saved yet, they are skipped and the other keys are still written
+        db.set("stream".to_string(), ValueType::Stream(Stream::new()), None);
+        let rdb = Rdb::read(&mut std::io::Cursor::new(db.to_rdb()?.to_bytes()?))?;
+        let mut loaded = setup_master_db();
+        loaded.load_rdb(&rdb);
+        assert_eq!(loaded.dbsize(), 4);
+        assert!(loaded.get("stream").is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_propagate_selects_the_database() -> Result<()> {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+        let stream = std::net::TcpStream::connect(listener.local_addr()?)?;
+        stream.set_nonblocking(true)?;
+        let (mut replica_side, _) = listener.accept()?;
+
+        let mut db = setup_master_db();
+        db.register_replica(TcpStream::from_std(stream), Token(2));
+        let write = RedisValue::array_of_bulkstrings_from("SET a 1");
+        db.propagate(0, write.clone())?;
+        db.propagate(3, write.clone())?;
+        db.propagate(3, write.clone())?;
+        db.propagate(0, write.clone())?;
+
+        let select =
+            |index: &str| RedisValue::array_of_bulkstrings_from(&format!("SELECT {}", index));
+        let expected = [
+            write.to_bytes(),
+            select("3").to_bytes(),
+            write.to_bytes(),
+            write.to_bytes(),
+            select("0").to_bytes(),
+            write.to_bytes(),
+        ]
+        .concat();
+        let mut received = vec![0; expected.len()];
+        std::io::Read::read_exact(&mut replica_side, &mut received)?;
+        assert_eq!(received, expected);
+        assert_eq!(db.info.master_repl_offset, expected.len() as u64);
+
+        // a new replica starts from database 0 while the others are in database 3
+        db.propagate(3, write.clone())?;
+        let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+        let stream = std::net::TcpStream::connect(listener.local_addr()?)?;
+        db.register_replica(TcpStream::from_std(stream), Token(3));
+        assert_eq!(db.replicated_db, None);
+        db.propagate(3, write)?;
+        assert_eq!(db.replicated_db, Some(3));
+        Ok(())
+    }
+
+    #[test]
+    fn test_wait_with_intervening_writes() -> Result<()> {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0")?;
+        let stream = std::net::TcpStream::connect(listener.local_addr()?)?;
+        stream.set_nonblocking(true)?;
+        let (mut replica_side, _) = listener.accept()?;
+
+        let mut db = setup_master_db();
+        db.register_replica(TcpStream::from_std(stream), Token(2));
+        assert_eq!(db.get_nb_uptodate_replicas(), 1);
+
+        let write = RedisValue::array_of_bulkstrings_from("SET a 1");
+        db.send_to_replicas(write.clone(), false)?;
+        assert_eq!(db.get_nb_uptodate_replicas(), 0);
+        let wait_offset = write.to_bytes().len();
+
+        db.start_wait(PendingWait {
+            connection_token: Token(20),
+            initial_time: Instant::now(),
+            timeout: Duration::from_secs(1),
+            nb_replicas: 1,
+        })?;
+        let getack = RedisValue::array_of_bulkstrings_from("REPLCONF GETACK *");
+        assert_eq!(
+            db.info.master_repl_offset,
+            (wait_offset + getack.to_bytes().len()) as u64
+        );
+        // a write received during the wait is forwarded but does not change the target
+        db.send_to_replicas(write, false)?;
+        assert_eq!(db.get_nb_acked_replicas(), 0);
+
+        let ack = format!("REPLCONF ACK {}", wait_offset);
+        replica_side.write_all(&RedisValue::array_of_bulkstrings_from(&ack).to_bytes())?;
+        // the ack is not always immediately readable on the other side
+        for _ in 0..100 {
+            db.receive_replica_acks(Token(2))?;
+            if db.get_nb_acked_replicas() == 1 {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        assert_eq!(db.get_nb_acked_replicas(), 1);
+        // the second write is still not acknowledged
+        assert_eq!(db.get_nb_uptodate_replicas(), 0);
+        Ok(())
+    }
+
+    fn blocked_pop(token: Token, keys: &[&str], from_left: bool, timeout: Duration) -> BlockedPop {
+        BlockedPop {
+            connection_token: token,
+            db_index: 0,
+            keys: keys.iter().map(|key| key.to_string()).collect(),
+            from_left,
+            initial_time: Instant::now(),
+            timeout,
+        }
+    }
+
+    #[test]
+    fn test_push_serves_blocked_pops_in_order() -> Result<()> {
+        let mut db = setup_master_db();
+        db.block_pop(blocked_pop(Token(20), &["list"], true, Duration::ZERO));
+        db.block_pop(blocked_pop(Token(21), &["other"], true, Duration::ZERO));
+        db.block_pop(blocked_pop(
+            Token(22),
+            &["other", "list"],
+            false,
+            Duration::ZERO,
+        ));
+
+        // the length includes the elements popped by the blocked clients
+        assert_eq!(
+            db.push("list", &["a".into(), "b".into(), "c".into()], false)?,
+            3
+        );
+
+        let replies = db.take_due_replies(Instant::now());
+        assert_eq!(replies.len(), 2);
+        assert_eq!(replies[0].connection_token, Token(20));
+        assert_eq!(
+            replies[0].redis_value,
+            RedisValue::array_of_bulkstrings_from("list a")
+        );
+        assert_eq!(replies[1].connection_token, Token(22));
+        assert_eq!(
+            replies[1].redis_value,
+            RedisValue::array_of_bulkstrings_from("list c")
+        );
+
+        // the remaining element stays in the list
+        assert!(!db.is_blocked(Token(20)));
+        assert!(db.is_blocked(Token(21)));
+        assert_eq!(db.pop("list", true)?, Some(b"b".to_vec()));
+        assert_eq!(db.pop("list", true)?, None);
+
+        assert_eq!(db.pending_pops.len(), 2);
+        db.propagate_pops()?;
+        assert!(db.pending_pops.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_spop_is_propagated_as_srem() -> Result<()> {
+        let mut db = setup_master_db();
+        db.sadd("s", &["a".into(), "b".into(), "c".into()])?;
+        db.select(2)?;
+        db.sadd("s", &["a".into()])?;
+
+        db.select(0)?;
+        let popped = db.spop("s", 2)?;
+        assert_eq!(popped.len(), 2);
+        db.select(2)?;
+        assert_eq!(db.spop("s", 1)?, vec!["a".to_string()]);
+        assert!(db.spop("missing", 1)?.is_empty());
+
+        assert_eq!(
+            db.pending_pops,
+            vec![
+                (
+                    0,
+                    RedisValue::array_of_bulkstrings_from(&format!("SREM s {}", popped.join(" ")))
+                ),
+                (2, RedisValue::array_of_bulkstrings_from("SREM s a")),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_take_timed_out_pops() {
+        let mut db = setup_master_db();
+        db.block_pop(blocked_pop(Token(20), &["list"], true, Duration::ZERO));
+        db.block_pop(blocked_pop(
+            Token(21),
+            &["list"],
+            true,
+            Duration::from_millis(100),
+        ));
+
+        assert!(db.take_timed_out_pops(Instant::now()).is_empty());
+        let timed_out = db.take_timed_out_pops(Instant::now() + Duration::from_millis(100));
+        assert_eq!(timed_out, vec![Token(21)]);
+        assert!(db.is_blocked(Token(20)));
+
+        db.remove_connection(Token(20));
+        assert!(!db.is_blocked(Token(20)));
+    }
+
+    #[test]
+    fn test_key_version() -> Result<()> {
+        let mut db = setup_master_db();
+        assert_eq!(db.key_version("key"), 0);
+        db.set("key".to_string(), ValueType::String("1".into()), None);
+        let version = db.key_version("key");
+        assert!(version > 0);
+
+        db.watch(Token(20), &["key".to_string(), "other".to_string()]);
+        assert!(!db.watched_keys_modified(Token(20)));
+        // reads don't change the version
+        db.get("key");
+        assert!(!db.watched_keys_modified(Token(20)));
+
+        db.incr("key")?;
+        assert!(db.key_version("key") > version);
+        assert!(db.watched_keys_modified(Token(20)));
+
+        db.unwatch(Token(20));
+        db.watch(Token(20), &["key".to_string()]);
+        db.del(&["key".to_string()]);
+        assert_eq!(db.key_version("key"), 0);
+        assert!(db.watched_keys_modified(Token(20)));
+        Ok(())
+    }
+
+    #[test]
+    fn test_execute_command() -> Result<()> {
+        let mut db = setup_master_db();
+        let mut execute = |input: &str| {
+            let redis_value = RedisValue::array_of_bulkstrings_from(input);
+            db.execute_command(&RedisCommand::try_from(&redis_value)?)
+        };
+        assert_eq!(execute("SET key 1")?, RedisValue::SimpleString("OK".into()));
+        assert_eq!(execute("INCR key")?, RedisValue::Integer(2));
+        assert_eq!(execute("GET key")?, RedisValue::bulkstring_from("2"));
+        Ok(())
     }
 }
@@ -1,6 +1,10 @@
 use thiserror::Error;
 
-use crate::{command::RedisCommand, parser::RedisValue};
+use crate::{
+    command::RedisCommand,
+    command_table,
+    parser::{lossy_string, RedisValue},
+};
 
 #[derive(Debug, Error)]
 pub enum Error {
@@ -17,7 +21,7 @@ pub enum Error {
     InvalidRedisValue(RedisValue),
 
     #[error("Invalid redis command")]
-    InvalidRedisCommand(RedisCommand),
+    InvalidRedisCommand(Box<RedisCommand>),
 
     #[error("Redis value cant be converted to string")]
     CantConvertToString(RedisValue),
@@ -31,15 +35,105 @@ pub enum Error {
         got: String,
     },
 
-    #[error("WRONGTYPE Operation agains a key holding the wrong kind of value")]
+    #[error("BUSYGROUP Consumer Group name already exists")]
+    ConsumerGroupAlreadyExists,
+
+    #[error("NOGROUP No such key '{key}' or consumer group '{group}'")]
+    NoSuchConsumerGroup { key: String, group: String },
+
+    #[error("ERR The XGROUP subcommand requires the key to exist. Note that for CREATE you may want to use the MKSTREAM option to create an empty stream automatically.")]
+    XGroupKeyMissing,
+
+    #[error("ERR KEYS would return more than {0} keys, use SCAN instead")]
+    TooManyKeys(usize),
+
+    #[error("ERR DB index is out of range")]
+    DbIndexOutOfRange,
+
+    #[error("ERR source and destination objects are the same")]
+    SameSourceAndDestination,
+
+    #[error("WRONGTYPE Operation against a key holding the wrong kind of value")]
     WrongTypeOperation,
 
+    #[error("ERR resulting score is not a number (NaN)")]
+    NanScore,
+
+    #[error("ERR string exceeds maximum allowed size (proto-max-bulk-len)")]
+    StringTooLong,
+
+    #[error("ERR bit offset is not an integer or out of range")]
+    BitOffsetOutOfRange,
+
+    #[error("ERR value is not an integer or out of range")]
+    NotAnInteger,
+
+    #[error("ERR value is out of range")]
+    ValueOutOfRange,
+
+    #[error("ERR value is not a valid float")]
+    NotAFloat,
+
+    #[error("ERR increment or decrement would overflow")]
+    IncrOverflow,
+
+    #[error("ERR increment would produce NaN or Infinity")]
+    NanOrInfinity,
+
+    #[error("ERR syntax error")]
+    SyntaxError,
+
+    #[error("ERR wrong number of arguments for '{0}' command")]
+    WrongArity(String),
+
+    #[error("ERR unknown command '{name}', with args beginning with: {args}")]
+    UnknownCommand { name: String, args: String },
+
+    #[error("ERR invalid expire time in '{0}' command")]
+    InvalidExpireTime(&'static str),
+
+    #[error("WRONGTYPE Key is not a valid HyperLogLog string value.")]
+    InvalidHyperLogLog,
+
+    #[error("ERR invalid longitude,latitude pair {0:.6},{1:.6}")]
+    InvalidLonLat(f64, f64),
+
+    #[error("ERR unsupported unit provided. please use M, KM, FT, MI")]
+    UnsupportedUnit,
+
+    #[error("ERR could not decode requested zset member")]
+    GeoMemberMissing,
+
+    #[error("ERR Invalid command specified")]
+    InvalidCommandSpecified,
+
+    #[error("ERR Invalid number of arguments specified for command")]
+    InvalidNumberOfArgumentsSpecified,
+
+    #[error("ERR The command has no key arguments")]
+    NoKeyArguments,
+
+    #[error("ERR no such key")]
+    NoSuchKey,
+
+    #[error("ERR Unknown option or number of arguments for CONFIG SET - '{0}'")]
+    UnknownConfigParameter(String),
+
+    #[error("ERR CONFIG SET failed (possibly related to argument '{parameter}') - {reason}")]
+    ConfigSetFailed { parameter: String, reason: String },
+
+    #[error("Wrong RDB checksum expected: ({expected:x}) got: ({actual:x})")]
+    RdbChecksumMismatch { expected: u64, actual: u64 },
+
     #[error(transparent)]
     IoError(#[from] std::io::Error),
 
     #[error(transparent)]
     ParseIntError(#[from] std::num::ParseIntError),
 
+    #[error(transparent)]
+    ParseFloatError(#[from] std::num::ParseFloatError),
+
     #[error(transparent)]
     NetAddrParseError(#[from] std::net::AddrParseError),
 
@@ -57,14 +151,150 @@ pub enum Error {
 
 impl<I> From<nom::error::Error<I>> for Error
 where
-    I: ToString,
+    I: AsRef<[u8]>,
 {
     fn from(err: nom::error::Error<I>) -> Self {
         Self::NomParseError(nom::error::Error {
-            input: err.input.to_string(),
+            input: String::from_utf8_lossy(err.input.as_ref()).to_string(),
             code: err.code,
         })
     }
 }
 
+impl Error {
+    /// Reply to the client for the errors caused by its command, after which the
+    /// connection stays open. None for the errors that close the connection, such as
+    /// I/O errors.
+    pub fn to_resp(&self) -> Option<RedisValue> {
+        let message = match self {
+            Self::InvalidRedisValue(redis_value) => {
+                return Self::invalid_command(redis_value)?.to_resp()
+            }
+            Self::InvalidRedisCommand(_) => Self::SyntaxError.to_string(),
+            Self::ParseIntError(_) => Self::NotAnInteger.to_string(),
+            Self::ParseFloatError(_) => Self::NotAFloat.to_string(),
+            Self::InvalidStreamId { .. } | Self::CantConvertToMsTimestamp(_) => {
+                "ERR Invalid stream ID specified as stream command argument".to_string()
+            }
+            Self::ConsumerGroupAlreadyExists
+            | Self::NoSuchConsumerGroup { .. }
+            | Self::XGroupKeyMissing
+            | Self::TooManyKeys(_)
+            | Self::DbIndexOutOfRange
+            | Self::SameSourceAndDestination
+            | Self::WrongTypeOperation
+            | Self::NanScore
+            | Self::StringTooLong
+            | Self::BitOffsetOutOfRange
+            | Self::NotAnInteger
+            | Self::NotAFloat
+            | Self::IncrOverflow
+            | Self::NanOrInfinity
+            | Self::SyntaxError
+            | Self::WrongArity(_)
+            | Self::UnknownCommand { .. }
+            | Self::InvalidExpireTime(_)
+            | Self::InvalidHyperLogLog
+            | Self::InvalidLonLat(_, _)
+            | Self::UnsupportedUnit
+            | Self::GeoMemberMissing
+            | Self::InvalidCommandSpecified
+            | Self::InvalidNumberOfArgumentsSpecified
+            | Self::NoKeyArguments
+            | Self::NoSuchKey
+            | Self::ValueOutOfRange
+            | Self::UnknownConfigParameter(_)
+            | Self::ConfigSetFailed { .. } => self.to_string(),
+            Self::InvaldMasterAddr
+            | Self::InvalidAnswerDuringHandshake(_)
+            | Self::EmptyCommand
+            | Self::CantConvertToString(_)
+            | Self::IoError(_)
+            | Self::RdbChecksumMismatch { .. }
+            | Self::NetAddrParseError(_)
+            | Self::FromHexError(_)
+            | Self::BinRwError(_)
+            | Self::NomParseError(_) => return None,
+        };
+        Some(RedisValue::SimpleError(message))
+    }
+
+    /// Error for a command missing from the command table
+    pub fn unknown_command(name: String, args: &[RedisValue]) -> Self {
+        Self::UnknownCommand {
+            name,
+            args: args
+                .iter()
+                .map(|arg| match arg {
+                    RedisValue::BulkString(_, arg) => format!("'{}' ", lossy_string(arg)),
+                    _ => String::new(),
+                })
+                .collect(),
+        }
+    }
+
+    /// Error for a command that could not be parsed: unknown, called with the wrong
+    /// number of arguments according to the command table, or with invalid arguments.
+    /// None if the value is not a command at all.
+    fn invalid_command(redis_value: &RedisValue) -> Option<Self> {
+        let RedisValue::Array(nb_elements, values) = redis_value else {
+            return None;
+        };
+        let (RedisValue::BulkString(_, name), args) = values.split_first()? else {
+            return None;
+        };
+        let name = lossy_string(name);
+        match command_table::lookup(&name) {
+            None => Some(Self::unknown_command(name, args)),
+            Some(metadata) if !metadata.accepts(*nb_elements) => {
+                Some(Self::WrongArity(metadata.name.to_string()))
+            }
+            Some(_) => Some(Self::SyntaxError),
+        }
+    }
+}
+
 pub type Result<T> = core::result::Result<T, Error>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn to_resp(command: &str) -> Option<RedisValue> {
+        Error::InvalidRedisValue(RedisValue::array_of_bulkstrings_from(command)).to_resp()
+    }
+
+    #[test]
+    fn test_to_resp() {
+        assert_eq!(
+            to_resp("FOO a b"),
+            Some(RedisValue::SimpleError(
+                "ERR unknown command 'FOO', with args beginning with: 'a' 'b' ".to_string()
+            ))
+        );
+        assert_eq!(
+            to_resp("GET"),
+            Some(RedisValue::SimpleError(
+                "ERR wrong number of arguments for 'get' command".to_string()
+            ))
+        );
+        assert_eq!(
+            to_resp("SET a b FOO"),
+            Some(RedisValue::SimpleError("ERR syntax error".to_string()))
+        );
+        assert_eq!(
+            Error::from("a".parse::<i64>().unwrap_err()).to_resp(),
+            Some(RedisValue::SimpleError(Error::NotAnInteger.to_string()))
+        );
+
+        // not a command, or not caused by the client
+        assert_eq!(
+            Error::InvalidRedisValue(RedisValue::SimpleString("PING".to_string())).to_resp(),
+            None
+        );
+        assert_eq!(
+            Error::from(std::io::Error::other("broken pipe")).to_resp(),
+            None
+        );
+    }
+}
@@ -0,0 +1,182 @@
+use crate::{Error, Result};
+
+/// Latitudes are limited to the ones of the EPSG:3857 projection, like in redis
+const LAT_MIN: f64 = -85.05112878;
+const LAT_MAX: f64 = 85.05112878;
+const LON_MIN: f64 = -180.0;
+const LON_MAX: f64 = 180.0;
+/// Bits per coordinate in the geohash, which gives a 52 bits score that can be stored
+/// exactly in a f64
+const STEP: u32 = 26;
+const EARTH_RADIUS_IN_METERS: f64 = 6372797.560856;
+
+/// Distance unit of the GEO commands
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Unit {
+    Meters,
+    Kilometers,
+    Feet,
+    Miles,
+}
+
+impl Unit {
+    pub fn parse(unit: &str) -> Result<Self> {
+        match unit.to_lowercase().as_str() {
+            "m" => Ok(Self::Meters),
+            "km" => Ok(Self::Kilometers),
+            "ft" => Ok(Self::Feet),
+            "mi" => Ok(Self::Miles),
+            _ => Err(Error::UnsupportedUnit),
+        }
+    }
+
+    /// Number of meters in one unit
+    pub fn meters(&self) -> f64 {
+        match self {
+            Self::Meters => 1.0,
+            Self::Kilometers => 1000.0,
+            Self::Feet => 0.3048,
+            Self::Miles => 1609.34,
+        }
+    }
+}
+
+/// Center of GEOSEARCH
+#[derive(Debug, Clone, PartialEq)]
+pub enum GeoOrigin {
+    Member(String),
+    /// Longitude and latitude
+    LonLat(f64, f64),
+}
+
+/// Options of GEOSEARCH. Only searches by radius are supported
+#[derive(Debug, Clone, PartialEq)]
+pub struct GeoSearchOptions {
+    pub origin: GeoOrigin,
+    pub radius: f64,
+    pub unit: Unit,
+    /// Sorts from the farthest member
+    pub descending: bool,
+    /// Maximum number of members to return
+    pub count: Option<usize>,
+    pub with_dist: bool,
+    pub with_coord: bool,
+}
+
+/// Member found by GEOSEARCH, with its distance in meters to the center and its position
+#[derive(Debug, Clone, PartialEq)]
+pub struct GeoMatch {
+    pub member: String,
+    pub distance: f64,
+    pub position: (f64, f64),
+}
+
+/// Same check as redis before adding a position
+pub fn validate(lon: f64, lat: f64) -> Result<()> {
+    if (LON_MIN..=LON_MAX).contains(&lon) && (LAT_MIN..=LAT_MAX).contains(&lat) {
+        Ok(())
+    } else {
+        Err(Error::InvalidLonLat(lon, lat))
+    }
+}
+
+/// Score of the position in the sorted set: the geohash interleaving the bits of the
+/// latitude (even bits) and longitude (odd bits)
+pub fn encode(lon: f64, lat: f64) -> f64 {
+    let scale = (1u64 << STEP) as f64;
+    let lat_offset = ((lat - LAT_MIN) / (LAT_MAX - LAT_MIN) * scale) as u64;
+    let lon_offset = ((lon - LON_MIN) / (LON_MAX - LON_MIN) * scale) as u64;
+    (spread(lat_offset) | spread(lon_offset) << 1) as f64
+}
+
+/// Position (longitude, latitude) at the center of the cell of the geohash
+pub fn decode(score: f64) -> (f64, f64) {
+    let bits = score as u64;
+    let scale = (1u64 << STEP) as f64;
+    let center = |offset: u64, min: f64, max: f64| {
+        let cell_min = min + offset as f64 / scale * (max - min);
+        let cell_max = min + (offset + 1) as f64 / scale * (max - min);
+        ((cell_min + cell_max) / 2.0).clamp(min, max)
+    };
+    (
+        center(squash(bits >> 1), LON_MIN, LON_MAX),
+        center(squash(bits), LAT_MIN, LAT_MAX),
+    )
+}
+
+/// Distance in meters between two positions with the haversine formula
+pub fn distance((lon1, lat1): (f64, f64), (lon2, lat2): (f64, f64)) -> f64 {
+    let (lat1, lat2) = (lat1.to_radians(), lat2.to_radians());
+    let u = ((lat2 - lat1) / 2.0).sin();
+    let v = ((lon2 - lon1).to_radians() / 2.0).sin();
+    2.0 * EARTH_RADIUS_IN_METERS * (u * u + lat1.cos() * lat2.cos() * v * v).sqrt().asin()
+}
+
+/// Members within radius meters of the center, from the closest one
+pub fn search(members: &[(String, f64)], center: (f64, f64), radius: f64) -> Vec<GeoMatch> {
+    let mut matches = members
+        .iter()
+        .filter_map(|(member, score)| {
+            let position = decode(*score);
+            let distance = distance(center, position);
+            (distance <= radius).then(|| GeoMatch {
+                member: member.clone(),
+                distance,
+                position,
+            })
+        })
+        .collect::<Vec<_>>();
+    matches.sort_by(|a, b| a.distance.total_cmp(&b.distance));
+    matches
+}
+
+/// Spreads the STEP lower bits of x on the even bits
+fn spread(x: u64) -> u64 {
+    (0..STEP).fold(0, |acc, i| acc | ((x >> i) & 1) << (2 * i))
+}
+
+/// Gathers the even bits of x on the STEP lower bits
+fn squash(x: u64) -> u64 {
+    (0..STEP).fold(0, |acc, i| acc | ((x >> (2 * i)) & 1) << i)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_decode() {
+        // values returned by redis for the GEOADD example of its documentation
+        let score = encode(13.361389, 38.115556);
+        assert_eq!(score, 3479099956230698.0);
+        let (lon, lat) = decode(score);
+        assert!((lon - 13.361_389_338_970_184).abs() < 1e-12, "{}", lon);
+        assert!((lat - 38.115_556_395_496_3).abs() < 1e-12, "{}", lat);
+    }
+
+    #[test]
+    fn test_distance() {
+        let palermo = (13.361389, 38.115556);
+        let catania = (15.087269, 37.502669);
+        let distance = distance(palermo, catania);
+        assert!((distance - 166274.1516).abs() < 1.0, "{}", distance);
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(validate(180.0, 85.0).is_ok());
+        assert!(matches!(
+            validate(1.0, 86.0),
+            Err(Error::InvalidLonLat(_, _))
+        ));
+        assert!(validate(-181.0, 0.0).is_err());
+    }
+
+    #[test]
+    fn test_units() -> Result<()> {
+        assert_eq!(Unit::parse("KM")?, Unit::Kilometers);
+        assert_eq!(Unit::parse("mi")?.meters(), 1609.34);
+        assert!(matches!(Unit::parse("yd"), Err(Error::UnsupportedUnit)));
+        Ok(())
+    }
+}
@@ -0,0 +1,88 @@
+/// Glob-style matching as done by redis: `*` matches any sequence, `?` any character,
+/// `[abc]`, `[^abc]` and `[a-z]` match character classes and `\` escapes the next
+/// character.
+pub fn glob_match(pattern: &str, s: &str) -> bool {
+    let pattern = pattern.chars().collect::<Vec<_>>();
+    let s = s.chars().collect::<Vec<_>>();
+    matches(&pattern, &s)
+}
+
+fn matches(pattern: &[char], s: &[char]) -> bool {
+    match pattern.first() {
+        None => s.is_empty(),
+        Some('*') => {
+            // consecutive stars are equivalent to a single one
+            let rest = &pattern[1..];
+            (0..=s.len()).any(|i| matches(rest, &s[i..]))
+        }
+        Some(_) if s.is_empty() => false,
+        Some('?') => matches(&pattern[1..], &s[1..]),
+        Some('[') => match match_class(&pattern[1..], s[0]) {
+            Some((true, rest)) => matches(rest, &s[1..]),
+            Some((false, _)) => false,
+            // unclosed class, the bracket is considered as a literal
+            None => s[0] == '[' && matches(&pattern[1..], &s[1..]),
+        },
+        Some('\\') if pattern.len() > 1 => pattern[1] == s[0] && matches(&pattern[2..], &s[1..]),
+        Some(c) => *c == s[0] && matches(&pattern[1..], &s[1..]),
+    }
+}
+
+/// Matches a character class (pattern starts after the opening bracket). Returns whether
+/// the character matched and the rest of the pattern after the closing bracket
+fn match_class(pattern: &[char], c: char) -> Option<(bool, &[char])> {
+    let (negate, mut i) = match pattern.first() {
+        Some('^') => (true, 1),
+        _ => (false, 0),
+    };
+
+    let mut matched = false;
+    while i < pattern.len() {
+        match pattern[i] {
+            ']' => return Some((matched != negate, &pattern[i + 1..])),
+            '\\' if i + 1 < pattern.len() => {
+                matched |= pattern[i + 1] == c;
+                i += 2;
+            }
+            start if i + 2 < pattern.len() && pattern[i + 1] == '-' && pattern[i + 2] != ']' => {
+                let end = pattern[i + 2];
+                let (start, end) = if start <= end {
+                    (start, end)
+                } else {
+                    (end, start)
+                };
+                matched |= start <= c && c <= end;
+                i += 3;
+            }
+            other => {
+                matched |= other == c;
+                i += 1;
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("*", ""));
+        assert!(glob_match("*", "anything"));
+        assert!(glob_match("news.*", "news.tech"));
+        assert!(!glob_match("news.*", "sport.tech"));
+        assert!(glob_match("h?llo", "hello"));
+        assert!(!glob_match("h?llo", "hllo"));
+        assert!(glob_match("h[ae]llo", "hallo"));
+        assert!(!glob_match("h[ae]llo", "hillo"));
+        assert!(glob_match("h[^e]llo", "hallo"));
+        assert!(!glob_match("h[^e]llo", "hello"));
+        assert!(glob_match("h[a-c]llo", "hbllo"));
+        assert!(glob_match("h\\*llo", "h*llo"));
+        assert!(!glob_match("h\\*llo", "hello"));
+        assert!(glob_match("a*b*c", "aXXbYYc"));
+        assert!(!glob_match("a*b*c", "aXXbYY"));
+    }
+}
@@ -0,0 +1,162 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::clock;
+
+/// Hash with an optional expiration per field, as set by HEXPIRE. Expired fields are
+/// invisible to the reads and removed by the writes.
+#[derive(Debug, Clone, Default)]
+pub struct Hash {
+    fields: HashMap<String, Vec<u8>>,
+    expirations: HashMap<String, Instant>,
+}
+
+impl Hash {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn is_expired(&self, field: &str) -> bool {
+        self.expirations
+            .get(field)
+            .is_some_and(|expires_at| clock::now() >= *expires_at)
+    }
+
+    fn remove_expired(&mut self) {
+        let now = clock::now();
+        let expired = self
+            .expirations
+            .iter()
+            .filter(|(_, expires_at)| now >= **expires_at)
+            .map(|(field, _)| field.clone())
+            .collect::<Vec<_>>();
+        for field in expired {
+            self.remove(&field);
+        }
+    }
+
+    pub fn get(&self, field: &str) -> Option<&[u8]> {
+        self.fields
+            .get(field)
+            .filter(|_| !self.is_expired(field))
+            .map(|value| value.as_slice())
+    }
+
+    /// Sets the value of the field and clears its expiration. Returns true if the field
+    /// is new
+    pub fn insert(&mut self, field: &str, value: &[u8]) -> bool {
+        self.remove_expired();
+        self.expirations.remove(field);
+        self.fields
+            .insert(field.to_string(), value.to_vec())
+            .is_none()
+    }
+
+    /// Replaces the value of the field with the one computed from its current value, None
+    /// if the field does not exist. Unlike insert, the expiration of the field is kept.
+    pub fn update<T>(
+        &mut self,
+        field: &str,
+        update: impl FnOnce(Option<&[u8]>) -> crate::Result<(Vec<u8>, T)>,
+    ) -> crate::Result<T> {
+        self.remove_expired();
+        let (value, result) = update(self.get(field))?;
+        self.fields.insert(field.to_string(), value);
+        Ok(result)
+    }
+
+    pub fn remove(&mut self, field: &str) -> Option<Vec<u8>> {
+        self.expirations.remove(field);
+        self.fields.remove(field)
+    }
+
+    pub fn len(&self) -> usize {
+        self.iter().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Iterates over the (field, value) pairs that are not expired
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &[u8])> {
+        self.fields
+            .iter()
+            .filter(|(field, _)| !self.is_expired(field))
+            .map(|(field, value)| (field.as_str(), value.as_slice()))
+    }
+
+    /// Sets the expiration of the field and returns the HEXPIRE status: -2 if the field
+    /// does not exist, 2 if it was deleted because the duration is 0, 1 otherwise
+    pub fn expire(&mut self, field: &str, duration: Duration) -> i64 {
+        self.remove_expired();
+        if !self.fields.contains_key(field) {
+            return -2;
+        }
+        if duration.is_zero() {
+            self.remove(field);
+            return 2;
+        }
+        self.expirations
+            .insert(field.to_string(), clock::now() + duration);
+        1
+    }
+
+    /// Remaining time to live of the field in seconds, -1 if it has no expiration and -2
+    /// if it does not exist
+    pub fn ttl(&self, field: &str) -> i64 {
+        if self.get(field).is_none() {
+            return -2;
+        }
+        match self.expirations.get(field) {
+            None => -1,
+            Some(expires_at) => {
+                let remaining = expires_at.saturating_duration_since(clock::now());
+                // rounded like the TTL command
+                ((remaining.as_millis() + 500) / 1000) as i64
+            }
+        }
+    }
+}
+
+impl FromIterator<(String, Vec<u8>)> for Hash {
+    fn from_iter<T: IntoIterator<Item = (String, Vec<u8>)>>(iter: T) -> Self {
+        Self {
+            fields: iter.into_iter().collect(),
+            expirations: HashMap::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+
+    #[test]
+    fn test_field_expiration() {
+        let clock = MockClock::install();
+        let mut hash = Hash::new();
+        assert!(hash.insert("f1", b"v1"));
+        assert!(hash.insert("f2", b"v2"));
+
+        assert_eq!(hash.ttl("f1"), -1);
+        assert_eq!(hash.ttl("missing"), -2);
+        assert_eq!(hash.expire("missing", Duration::from_secs(10)), -2);
+        assert_eq!(hash.expire("f1", Duration::from_secs(10)), 1);
+        assert_eq!(hash.ttl("f1"), 10);
+
+        // setting the value again clears the expiration
+        assert!(!hash.insert("f1", b"v1"));
+        assert_eq!(hash.ttl("f1"), -1);
+
+        assert_eq!(hash.expire("f2", Duration::ZERO), 2);
+        assert_eq!(hash.get("f2"), None);
+
+        assert_eq!(hash.expire("f1", Duration::from_millis(1)), 1);
+        clock.advance(Duration::from_millis(5));
+        assert_eq!(hash.get("f1"), None);
+        assert_eq!(hash.ttl("f1"), -2);
+        assert!(hash.is_empty());
+    }
+}
@@ -0,0 +1,337 @@
+use crate::{Error, Result};
+
+/// Number of bits of the hash used to select a register
+const P: u32 = 14;
+/// Number of registers
+const REGISTERS: usize = 1 << P;
+/// Number of bits of the hash used to count the leading zeros
+const Q: usize = 64 - P as usize;
+/// Bits per register in the dense encoding
+const REGISTER_BITS: usize = 6;
+const REGISTER_MAX: u8 = (1 << REGISTER_BITS) - 1;
+/// Magic, encoding, 3 unused bytes and the cached cardinality
+const HEADER_LEN: usize = 16;
+const DENSE_LEN: usize = HEADER_LEN + (REGISTERS * REGISTER_BITS).div_ceil(8);
+const DENSE: u8 = 0;
+const SPARSE: u8 = 1;
+const HASH_SEED: u64 = 0xadc83b19;
+const ALPHA_INF: f64 = 0.721_347_520_444_481_7;
+
+/// HyperLogLog with 16384 registers, stored in a string with the same format as redis so
+/// that the values can be exchanged with it. Sparse representations are read but only
+/// dense ones are written.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HyperLogLog {
+    /// One register per byte, decoded from the 6 bits representation
+    registers: Vec<u8>,
+}
+
+impl HyperLogLog {
+    pub fn new() -> Self {
+        Self {
+            registers: vec![0; REGISTERS],
+        }
+    }
+
+    /// Decodes a string holding a HyperLogLog in the dense or sparse encoding
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() < HEADER_LEN || &bytes[..4] != b"HYLL" {
+            return Err(Error::InvalidHyperLogLog);
+        }
+        let payload = &bytes[HEADER_LEN..];
+        let hll = match bytes[4] {
+            DENSE if bytes.len() == DENSE_LEN => Self {
+                registers: (0..REGISTERS)
+                    .map(|index| dense_register(payload, index))
+                    .collect(),
+            },
+            SPARSE => Self::from_sparse(payload)?,
+            _ => Err(Error::InvalidHyperLogLog)?,
+        };
+        // a register holds at most a run of Q zeros plus one, the 6 bits of the dense
+        // encoding can represent more
+        if hll
+            .registers
+            .iter()
+            .any(|register| *register as usize > Q + 1)
+        {
+            return Err(Error::InvalidHyperLogLog);
+        }
+        Ok(hll)
+    }
+
+    /// The sparse encoding is a sequence of runs of registers: ZERO (00xxxxxx) and
+    /// XZERO (01xxxxxx xxxxxxxx) for runs of empty registers, VAL (1vvvvvxx) for runs of
+    /// registers holding the same value
+    fn from_sparse(payload: &[u8]) -> Result<Self> {
+        let mut registers = Vec::with_capacity(REGISTERS);
+        let mut bytes = payload.iter();
+        while let Some(&byte) = bytes.next() {
+            let (value, len) = match byte >> 6 {
+                0b00 => (0, (byte & 0x3f) as usize + 1),
+                0b01 => {
+                    let next = bytes.next().ok_or(Error::InvalidHyperLogLog)?;
+                    (0, ((byte as usize & 0x3f) << 8 | *next as usize) + 1)
+                }
+                _ => (((byte >> 2) & 0x1f) + 1, (byte & 0x3) as usize + 1),
+            };
+            // the runs can't cover more than the registers, whatever the payload length
+            if registers.len() + len > REGISTERS {
+                return Err(Error::InvalidHyperLogLog);
+            }
+            registers.extend(std::iter::repeat(value).take(len));
+        }
+        if registers.len() != REGISTERS {
+            return Err(Error::InvalidHyperLogLog);
+        }
+        Ok(Self { registers })
+    }
+
+    /// Dense encoding of the HyperLogLog. The cached cardinality is marked as invalid so
+    /// that redis computes it again
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![0; DENSE_LEN];
+        bytes[..4].copy_from_slice(b"HYLL");
+        bytes[4] = DENSE;
+        bytes[HEADER_LEN - 1] = 1 << 7;
+        let payload = &mut bytes[HEADER_LEN..];
+        for (index, register) in self.registers.iter().enumerate() {
+            set_dense_register(payload, index, *register);
+        }
+        bytes
+    }
+
+    /// Adds the element. Returns true if a register changed, hence the estimation
+    pub fn add(&mut self, element: &[u8]) -> bool {
+        let hash = murmurhash64a(element, HASH_SEED);
+        let index = hash as usize & (REGISTERS - 1);
+        // the bit set at Q guarantees a run of at most Q zeros
+        let count = ((hash >> P) | 1 << Q).trailing_zeros() as u8 + 1;
+        if count > self.registers[index] {
+            self.registers[index] = count;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Union with the other HyperLogLog
+    pub fn merge(&mut self, other: &Self) {
+        for (register, other) in self.registers.iter_mut().zip(other.registers.iter()) {
+            *register = (*register).max(*other);
+        }
+    }
+
+    /// Estimated cardinality, with the same estimator as redis (Otmar Ertl, "New
+    /// cardinality estimation algorithms for HyperLogLog sketches")
+    pub fn count(&self) -> u64 {
+        // indexed by any value of the 6 bits registers
+        let mut histogram = [0usize; 1 << REGISTER_BITS];
+        for register in self.registers.iter() {
+            histogram[*register as usize] += 1;
+        }
+
+        let m = REGISTERS as f64;
+        let mut z = m * tau((m - histogram[Q + 1] as f64) / m);
+        for count in histogram[1..=Q].iter().rev() {
+            z += *count as f64;
+            z *= 0.5;
+        }
+        z += m * sigma(histogram[0] as f64 / m);
+        (ALPHA_INF * m * m / z).round() as u64
+    }
+}
+
+impl Default for HyperLogLog {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Value of the register in the dense encoding, where registers are packed on 6 bits
+/// starting from the least significant bits of each byte
+fn dense_register(payload: &[u8], index: usize) -> u8 {
+    let bit = index * REGISTER_BITS;
+    let (byte, shift) = (bit / 8, bit % 8);
+    let word = payload[byte] as u16 | (payload.get(byte + 1).copied().unwrap_or(0) as u16) << 8;
+    (word >> shift) as u8 & REGISTER_MAX
+}
+
+fn set_dense_register(payload: &mut [u8], index: usize, value: u8) {
+    let bit = index * REGISTER_BITS;
+    let (byte, shift) = (bit / 8, bit % 8);
+    payload[byte] &= !(REGISTER_MAX << shift);
+    payload[byte] |= value << shift;
+    if shift + REGISTER_BITS > 8 {
+        payload[byte + 1] &= !(REGISTER_MAX >> (8 - shift));
+        payload[byte + 1] |= value >> (8 - shift);
+    }
+}
+
+fn sigma(mut x: f64) -> f64 {
+    if x == 1.0 {
+        return f64::INFINITY;
+    }
+    let mut y = 1.0;
+    let mut z = x;
+    loop {
+        x *= x;
+        let previous = z;
+        z += x * y;
+        y += y;
+        if previous == z {
+            return z;
+        }
+    }
+}
+
+fn tau(mut x: f64) -> f64 {
+    if x == 0.0 || x == 1.0 {
+        return 0.0;
+    }
+    let mut y = 1.0;
+    let mut z = 1.0 - x;
+    loop {
+        x = x.sqrt();
+        let previous = z;
+        y *= 0.5;
+        z -= (1.0 - x).powi(2) * y;
+        if previous == z {
+            return z / 3.0;
+        }
+    }
+}
+
+/// 64 bits version of MurmurHash2 by Austin Appleby, the hash function of redis
+fn murmurhash64a(key: &[u8], seed: u64) -> u64 {
+    const M: u64 = 0xc6a4a7935bd1e995;
+    const R: u32 = 47;
+    let mut h = seed ^ (key.len() as u64).wrapping_mul(M);
+
+    let mut chunks = key.chunks_exact(8);
+    for chunk in chunks.by_ref() {
+        let mut k = u64::from_le_bytes(chunk.try_into().expect("chunks have 8 bytes"));
+        k = k.wrapping_mul(M);
+        k ^= k >> R;
+        k = k.wrapping_mul(M);
+        h ^= k;
+        h = h.wrapping_mul(M);
+    }
+
+    let tail = chunks.remainder();
+    if !tail.is_empty() {
+        for (i, byte) in tail.iter().enumerate() {
+            h ^= (*byte as u64) << (8 * i);
+        }
+        h = h.wrapping_mul(M);
+    }
+
+    h ^= h >> R;
+    h = h.wrapping_mul(M);
+    h ^= h >> R;
+    h
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add_and_count() {
+        let mut hll = HyperLogLog::new();
+        assert_eq!(hll.count(), 0);
+        assert!(hll.add(b"a"));
+        assert!(!hll.add(b"a"));
+        assert_eq!(hll.count(), 1);
+
+        for i in 0..10000 {
+            hll.add(i.to_string().as_bytes());
+        }
+        let count = hll.count() as f64;
+        // the standard error is 0.81%
+        assert!((count - 10001.0).abs() < 10001.0 * 0.03, "{}", count);
+    }
+
+    #[test]
+    fn test_dense_round_trip() -> Result<()> {
+        let mut hll = HyperLogLog::new();
+        for element in ["a", "b", "c", "foo", "bar"] {
+            hll.add(element.as_bytes());
+        }
+        let bytes = hll.to_bytes();
+        assert_eq!(bytes.len(), DENSE_LEN);
+        assert_eq!(&bytes[..5], b"HYLL\x00");
+        assert_eq!(HyperLogLog::from_bytes(&bytes)?, hll);
+        assert!(matches!(
+            HyperLogLog::from_bytes(b"HYLL"),
+            Err(Error::InvalidHyperLogLog)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_from_sparse() -> Result<()> {
+        // XZERO run of 1000 registers, VAL 3 repeated twice, XZERO for the remaining ones
+        let remaining = REGISTERS - 1002 - 1;
+        let mut bytes = b"HYLL\x01\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00".to_vec();
+        bytes.extend([0x40 | (999 >> 8) as u8, (999 & 0xff) as u8]);
+        bytes.push(0x80 | (2 << 2) | 1);
+        bytes.extend([0x40 | (remaining >> 8) as u8, (remaining & 0xff) as u8]);
+        let hll = HyperLogLog::from_bytes(&bytes)?;
+        assert_eq!(hll.registers[999], 0);
+        assert_eq!(hll.registers[1000], 3);
+        assert_eq!(hll.registers[1001], 3);
+        assert_eq!(hll.registers[1002], 0);
+
+        bytes.pop();
+        assert!(HyperLogLog::from_bytes(&bytes).is_err());
+
+        // runs covering more than the registers are refused before being expanded
+        let mut bytes = b"HYLL\x01\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00\x00".to_vec();
+        bytes.extend([0x7f, 0xff].repeat(1000));
+        assert!(matches!(
+            HyperLogLog::from_bytes(&bytes),
+            Err(Error::InvalidHyperLogLog)
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_dense_register() {
+        let mut bytes = HyperLogLog::new().to_bytes();
+        set_dense_register(&mut bytes[HEADER_LEN..], 5, REGISTER_MAX);
+        assert!(matches!(
+            HyperLogLog::from_bytes(&bytes),
+            Err(Error::InvalidHyperLogLog)
+        ));
+        set_dense_register(&mut bytes[HEADER_LEN..], 5, Q as u8 + 1);
+        assert!(HyperLogLog::from_bytes(&bytes).is_ok());
+    }
+
+    #[test]
+    fn test_merge() {
+        let mut first = HyperLogLog::new();
+        let mut second = HyperLogLog::new();
+        for i in 0..100 {
+            first.add(format!("a{}", i).as_bytes());
+            second.add(format!("b{}", i).as_bytes());
+        }
+        first.merge(&second);
+        let count = first.count() as f64;
+        assert!((count - 200.0).abs() < 10.0, "{}", count);
+    }
+
+    #[test]
+    fn test_murmurhash64a() {
+        assert_eq!(murmurhash64a(b"", 0), 0);
+        // same values as the reference implementation
+        assert_eq!(murmurhash64a(b"hello", 0), 0x1e68d17c457bf117);
+        assert_eq!(murmurhash64a(b"foo", HASH_SEED), 0xe64609b8b0141cb4);
+        assert_eq!(murmurhash64a(b"12345678a", HASH_SEED), 0xf83f4e5fbcf6e38c);
+        // the tail is mixed in
+        assert_ne!(
+            murmurhash64a(b"12345678a", HASH_SEED),
+            murmurhash64a(b"12345678b", HASH_SEED)
+        );
+    }
+}
@@ -0,0 +1,103 @@
+use std::fmt;
+use std::io::Write;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Verbosity of the server logs, from the most to the least verbose
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, clap::ValueEnum)]
+pub enum LogLevel {
+    Debug,
+    Notice,
+    Warning,
+}
+
+impl LogLevel {
+    fn from_u8(value: u8) -> Self {
+        match value {
+            0 => Self::Debug,
+            1 => Self::Notice,
+            _ => Self::Warning,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Debug => "debug",
+            Self::Notice => "notice",
+            Self::Warning => "warning",
+        }
+    }
+
+    /// Same markers as the redis logs
+    fn marker(&self) -> char {
+        match self {
+            Self::Debug => '.',
+            Self::Notice => '*',
+            Self::Warning => '#',
+        }
+    }
+}
+
+static LOG_LEVEL: AtomicU8 = AtomicU8::new(LogLevel::Notice as u8);
+
+pub fn set_log_level(level: LogLevel) {
+    LOG_LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+pub fn log_level() -> LogLevel {
+    LogLevel::from_u8(LOG_LEVEL.load(Ordering::Relaxed))
+}
+
+/// Writes `<unix time in ms> <marker> <message>` to stderr if the level is enabled.
+/// Use the log_debug, log_notice and log_warning macros instead of calling it directly.
+pub fn log(level: LogLevel, args: fmt::Arguments) {
+    if level < log_level() {
+        return;
+    }
+    let since_epoch = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("time should not go backward");
+    // Logging must never make the server fail
+    let _ = writeln!(
+        std::io::stderr().lock(),
+        "{} {} {}",
+        since_epoch.as_millis(),
+        level.marker(),
+        args
+    );
+}
+
+macro_rules! log_debug {
+    ($($arg:tt)*) => {
+        $crate::log::log($crate::log::LogLevel::Debug, format_args!($($arg)*))
+    };
+}
+
+macro_rules! log_notice {
+    ($($arg:tt)*) => {
+        $crate::log::log($crate::log::LogLevel::Notice, format_args!($($arg)*))
+    };
+}
+
+macro_rules! log_warning {
+    ($($arg:tt)*) => {
+        $crate::log::log($crate::log::LogLevel::Warning, format_args!($($arg)*))
+    };
+}
+
+pub(crate) use {log_debug, log_notice, log_warning};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_log_level_order() {
+        assert!(LogLevel::Debug < LogLevel::Notice);
+        assert!(LogLevel::Notice < LogLevel::Warning);
+        assert_eq!(
+            LogLevel::from_u8(LogLevel::Warning as u8),
+            LogLevel::Warning
+        );
+    }
+}
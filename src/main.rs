@@ -1,19 +1,35 @@
+mod aof;
+mod bitops;
+mod clock;
 mod command;
+mod command_table;
 mod connection_data;
 mod connection_handler;
 mod db;
 mod error;
+mod geo;
+mod glob;
+mod hash;
+mod hyperloglog;
+mod log;
 mod parser;
+mod pubsub;
+mod random;
 mod rdb;
 mod replica;
+mod sorted_set;
 mod stream;
 mod token;
 
-use crate::db::{ConnectionState, DbInfo, RedisDb};
+use crate::db::{
+    parse_maxmemory_policy, parse_memory, ConnectionState, DbInfo, EncodingThresholds, PendingWait,
+    RedisDb, SavePoint,
+};
 pub use crate::error::{Error, Result};
 use crate::parser::RedisValue;
 use crate::token::{FIRST_UNIQUE_TOKEN, MASTER, SERVER};
 
+use aof::{Aof, AppendFsync};
 use command::RedisCommand;
 use connection_handler::handle_connection;
 use mio::net::{TcpListener, TcpStream};
@@ -27,6 +43,10 @@ use std::time::{Duration, Instant};
 use stream::PendingStreamXread;
 
 use clap::Parser;
+use log::{log_debug, log_notice, log_warning, LogLevel};
+
+/// Interval between two removals of the expired keys that were not accessed
+const ACTIVE_EXPIRE_INTERVAL: Duration = Duration::from_millis(100);
 
 #[derive(Parser)]
 #[command(version, about="Custom redis", long_about=None )]
@@ -39,6 +59,123 @@ struct Cli {
     dir: String,
     #[arg(long, default_value_t = String::from("dump.rdb"))]
     dbfilename: String,
+    /// Maximum number of keys returned by KEYS, unlimited by default
+    #[arg(long)]
+    keys_max_results: Option<usize>,
+    /// Classes of keyspace events to publish, for instance Ex for expired events
+    #[arg(long, default_value_t = String::new())]
+    notify_keyspace_events: String,
+    /// Replies OK to the DEBUG subcommands that are not implemented
+    #[arg(long)]
+    debug_lenient: bool,
+    /// Maximum length of the strings grown by APPEND, SETRANGE and SETBIT
+    #[arg(long, value_parser = parse_memory, default_value = "512mb")]
+    proto_max_bulk_len: u64,
+    #[arg(long, value_enum, default_value_t = LogLevel::Notice)]
+    loglevel: LogLevel,
+    /// Memory limit reported by CONFIG GET, for instance 100mb. Nothing is evicted
+    #[arg(long, value_parser = parse_memory, default_value = "0")]
+    maxmemory: u64,
+    #[arg(long, value_parser = parse_maxmemory_policy, default_value = "noeviction")]
+    maxmemory_policy: String,
+    /// Commands of a connection processed in one go before serving the other ones
+    #[arg(long, default_value_t = 1000)]
+    max_commands_per_event: usize,
+    /// Logs the write commands to the append only file, replayed at startup
+    #[arg(long)]
+    appendonly: bool,
+    #[arg(long, default_value_t = String::from("appendonly.aof"))]
+    appendfilename: String,
+    #[arg(long, value_enum, default_value_t = AppendFsync::Everysec)]
+    appendfsync: AppendFsync,
+    /// Saves the rdb after `<changes>` writes within `<seconds>`, for instance
+    /// --save "900 1" --save "300 10". Unlike redis, there is no save point by default
+    #[arg(long, value_name = "SECONDS CHANGES")]
+    save: Vec<SavePoint>,
+    /// Maximum number of members of the sets reported as intset
+    #[arg(long, default_value_t = 512)]
+    set_max_intset_entries: usize,
+    /// Maximum number of members of the sets reported as listpack
+    #[arg(long, default_value_t = 128)]
+    set_max_listpack_entries: usize,
+    /// Maximum length of the members of the sets reported as listpack
+    #[arg(long, default_value_t = 64)]
+    set_max_listpack_value: usize,
+    /// Maximum number of fields of the hashes reported as listpack
+    #[arg(long, default_value_t = 128)]
+    hash_max_listpack_entries: usize,
+    /// Maximum length of the fields and values of the hashes reported as listpack
+    #[arg(long, default_value_t = 64)]
+    hash_max_listpack_value: usize,
+    /// Maximum number of members of the sorted sets reported as listpack
+    #[arg(long, default_value_t = 128)]
+    zset_max_listpack_entries: usize,
+    /// Maximum length of the members of the sorted sets reported as listpack
+    #[arg(long, default_value_t = 64)]
+    zset_max_listpack_value: usize,
+}
+
+/// Everything needed to start a server. Built from the command line arguments by the
+/// binary, tests can build it directly to start a server on a chosen port.
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    pub port: u16,
+    /// `<host> <port>` of the master, the server is a master if it is not set
+    pub replicaof: Option<String>,
+    pub dir: String,
+    pub dbfilename: String,
+    pub keys_max_results: Option<usize>,
+    pub notify_keyspace_events: String,
+    pub debug_lenient: bool,
+    pub proto_max_bulk_len: u64,
+    pub maxmemory: u64,
+    pub maxmemory_policy: String,
+    pub max_commands_per_event: usize,
+    pub loglevel: LogLevel,
+    pub appendonly: bool,
+    pub appendfilename: String,
+    pub appendfsync: AppendFsync,
+    pub save: Vec<SavePoint>,
+    pub encoding_thresholds: EncodingThresholds,
+}
+
+impl Default for ServerConfig {
+    /// Same defaults as the command line arguments
+    fn default() -> Self {
+        Self::from(Cli::parse_from(["redis-starter-rust"]))
+    }
+}
+
+impl From<Cli> for ServerConfig {
+    fn from(args: Cli) -> Self {
+        Self {
+            port: args.port,
+            replicaof: args.replicaof,
+            dir: args.dir,
+            dbfilename: args.dbfilename,
+            keys_max_results: args.keys_max_results,
+            notify_keyspace_events: args.notify_keyspace_events,
+            debug_lenient: args.debug_lenient,
+            proto_max_bulk_len: args.proto_max_bulk_len,
+            maxmemory: args.maxmemory,
+            maxmemory_policy: args.maxmemory_policy,
+            max_commands_per_event: args.max_commands_per_event,
+            loglevel: args.loglevel,
+            appendonly: args.appendonly,
+            appendfilename: args.appendfilename,
+            appendfsync: args.appendfsync,
+            save: args.save,
+            encoding_thresholds: EncodingThresholds {
+                set_max_intset_entries: args.set_max_intset_entries,
+                set_max_listpack_entries: args.set_max_listpack_entries,
+                set_max_listpack_value: args.set_max_listpack_value,
+                hash_max_listpack_entries: args.hash_max_listpack_entries,
+                hash_max_listpack_value: args.hash_max_listpack_value,
+                zset_max_listpack_entries: args.zset_max_listpack_entries,
+                zset_max_listpack_value: args.zset_max_listpack_value,
+            },
+        }
+    }
 }
 
 // heavily inspired by
@@ -46,14 +183,19 @@ struct Cli {
 // but simplified a lot the writing of data part.
 
 fn main() -> Result<()> {
-    let args = Cli::parse();
+    run_server(Cli::parse().into())
+}
+
+/// Starts the server and serves the connections until an error occurs
+fn run_server(config: ServerConfig) -> Result<()> {
+    log::set_log_level(config.loglevel);
 
     let mut role = "master".to_string();
 
     // For replicas, we save the connection stream to master
     let mut master_stream = None;
     let mut state = ConnectionState::Ready;
-    match args.replicaof {
+    match config.replicaof {
         None => {}
         Some(s) => {
             role = "slave".to_string();
@@ -71,11 +213,46 @@ fn main() -> Result<()> {
     }
 
     // Creates the redis db
-    let db_info = DbInfo::build(&role, args.port, &args.dir, &args.dbfilename);
+    let mut db_info = DbInfo::build(&role, config.port, &config.dir, &config.dbfilename);
+    db_info.keys_max_results = config.keys_max_results;
+    db_info.notify_keyspace_events = config.notify_keyspace_events;
+    db_info.debug_lenient = config.debug_lenient;
+    db_info.proto_max_bulk_len = config.proto_max_bulk_len;
+    db_info.save_points = config.save;
+    db_info.encoding_thresholds = config.encoding_thresholds;
+    db_info.maxmemory = config.maxmemory;
+    db_info.maxmemory_policy = config.maxmemory_policy;
+    db_info.appendonly = config.appendonly;
+    db_info.max_commands_per_event = config.max_commands_per_event;
     let mut db = RedisDb::build(db_info, state);
-    let rdb_path = Path::new(&args.dir).join(&args.dbfilename);
-    if rdb_path.exists() {
-        db.load_rdb(&Rdb::new(rdb_path)?);
+    let rdb_path = Path::new(&config.dir).join(&config.dbfilename);
+    let aof_path = Path::new(&config.dir).join(&config.appendfilename);
+    // As in redis, the append only file has priority over the rdb when it is enabled
+    if config.appendonly && aof_path.exists() {
+        for redis_value in Aof::load(&aof_path)? {
+            match RedisCommand::try_from(&redis_value)? {
+                // the writes of a transaction are replayed in order, as EXEC applied them
+                RedisCommand::Multi | RedisCommand::Exec => {}
+                redis_command => {
+                    db.execute_command(&redis_command)?;
+                }
+            }
+        }
+        // the next writes are appended after the last SELECT of the file
+        db.replicated_db = Some(db.selected_db());
+    } else if rdb_path.exists() {
+        match Rdb::new(&rdb_path) {
+            Ok(rdb) => db.load_rdb(&rdb),
+            // unlike redis, a corrupt rdb does not prevent the server from starting
+            Err(e) => log_warning!(
+                "Can't load {}, starting with an empty dataset: {}",
+                rdb_path.display(),
+                e
+            ),
+        }
+    }
+    if config.appendonly {
+        db.aof = Some(Aof::open(&aof_path, config.appendfsync)?);
     }
 
     // Create a poll instance.
@@ -84,10 +261,12 @@ fn main() -> Result<()> {
     let mut events = Events::with_capacity(128);
 
     // Setup the server socket.
-    let addr: std::net::SocketAddr = format!("127.0.0.1:{}", args.port).parse()?;
+    let addr: std::net::SocketAddr = format!("127.0.0.1:{}", config.port).parse()?;
 
     let mut server = TcpListener::bind(addr)?;
 
+    log_notice!("Ready to accept connections on port {}", config.port);
+
     // Start listening for incoming connections.
     poll.registry()
         .register(&mut server, SERVER, Interest::READABLE)?;
@@ -103,17 +282,28 @@ fn main() -> Result<()> {
         db.send_ping_to_master(master_stream)?;
     }
 
-    // tracks client calling wait. Note that we can only handle one wait.
-    // TODO: improve WAIT flow
-    let mut waiting_token = None;
+    let mut last_active_expire = Instant::now();
+    let mut resumed_connections: Vec<Token> = Vec::new();
 
     loop {
-        // Poll Mio for events, blocking until we get an event or for 50 ms.
-        poll.poll(&mut events, Some(Duration::from_millis(50)))?;
+        // Poll Mio for events, blocking until we get an event or for 50 ms. Connections
+        // with input left to process don't wait.
+        let timeout = if resumed_connections.is_empty() {
+            Duration::from_millis(50)
+        } else {
+            Duration::ZERO
+        };
+        poll.poll(&mut events, Some(timeout))?;
 
-        // Process each event.
-        for event in events.iter() {
-            match event.token() {
+        // Process each event. The connections unblocked at the previous iteration are
+        // processed as if they were readable.
+        let tokens = events
+            .iter()
+            .map(|event| event.token())
+            .chain(resumed_connections.drain(..))
+            .collect::<Vec<_>>();
+        for token in tokens {
+            match token {
                 SERVER => {
                     // If this is an event for the server, it means a connection is ready to be accepted.
                     loop {
@@ -140,6 +330,7 @@ fn main() -> Result<()> {
                             token,
                             Interest::READABLE.add(Interest::WRITABLE),
                         )?;
+                        log_debug!("Accepted connection {}", token.0);
                         connections.insert(token, connection);
                     }
                 }
@@ -151,37 +342,32 @@ fn main() -> Result<()> {
                         .as_mut()
                         .expect("Should have a connection to master");
                     let (_, _) = handle_connection(master_stream_mut, MASTER, &mut db, true)
-                        .map_err(|e| dbg!(e))
+                        .map_err(|e| log_warning!("Error on the connection to master: {:?}", e))
                         .unwrap_or((true, false));
                 }
                 token => {
-                    // if we are in waiting state and receive an event,
-                    // if it comes from a replica, it means we received an ack so we
-                    // can increase the nb of obtained replicas and mark it as up to date
-                    // If it comes from a new connection, it is just ignored
-                    if let ConnectionState::Waiting(
-                        intitial_time,
-                        timeout,
-                        requested_replicas,
-                        obtained_replicas,
-                    ) = db.state
+                    // Replicas only send acks, which update their acknowledged offset
+                    if token.0 < FIRST_UNIQUE_TOKEN.0 {
+                        db.receive_replica_acks(token)
+                            .map_err(|e| log_warning!("Error reading replica acks: {:?}", e))
+                            .unwrap_or(());
+                        continue;
+                    }
+
+                    // The client waiting for replicas is blocked until WAIT returns. Other
+                    // clients are processed normally, including their writes.
+                    if db
+                        .pending_wait
+                        .as_ref()
+                        .is_some_and(|pending_wait| pending_wait.connection_token == token)
                     {
-                        if token.0 < FIRST_UNIQUE_TOKEN.0 {
-                            db.state = ConnectionState::Waiting(
-                                intitial_time,
-                                timeout,
-                                requested_replicas,
-                                obtained_replicas + 1,
-                            );
-                            db.mark_replica_as_uptodate(token);
-                        }
                         continue;
                     }
 
                     // Handle events for a connection
                     let (done, register) = if let Some(connection) = connections.get_mut(&token) {
                         handle_connection(connection, token, &mut db, false)
-                            .map_err(|e| dbg!(e))
+                            .map_err(|e| log_warning!("Closing connection {}: {:?}", token.0, e))
                             // here we force close the connection on error
                             .unwrap_or((true, false))
                     } else {
@@ -192,33 +378,33 @@ fn main() -> Result<()> {
                     if done || register {
                         // Ugly patch to handle waiting state. Note that the deregister
                         // process is not really robust
-                        if let ConnectionState::Waiting(_, _, _, _) = db.state {
-                            waiting_token = Some(token);
-                        } else if let ConnectionState::InitiatingTransaction = db.state {
-                            // Don't allow for nested multi
-                            if db.ongoing_transacations.contains_key(&token) {
-                                let mut connection = connections
-                                    .get(&token)
-                                    .expect("Token should be in connections");
-                                connection.write_all(
-                                    RedisValue::SimpleError(
-                                        "ERR MULTI calls can not be nested".to_string(),
-                                    )
-                                    .to_string()
-                                    .as_bytes(),
-                                )?;
-                                db.state = ConnectionState::Ready;
-                                continue;
-                            }
-
-                            db.ongoing_transacations.insert(token, Vec::new());
-
-                            connections.get_mut(&token).unwrap().write_all(
-                                RedisValue::SimpleString("OK".to_string())
-                                    .to_string()
-                                    .as_bytes(),
-                            )?;
+                        if let ConnectionState::Waiting(initial_time, timeout, nb_replicas) =
+                            db.state
+                        {
                             db.state = ConnectionState::Ready;
+                            if db.pending_wait.is_some() {
+                                // Only one WAIT can be pending, the others return directly
+                                let redis_value =
+                                    RedisValue::Integer(db.get_nb_uptodate_replicas() as i64);
+                                if let Err(e) = connections
+                                    .get_mut(&token)
+                                    .unwrap()
+                                    .write_all(&redis_value.to_bytes())
+                                {
+                                    log_warning!(
+                                        "Can't send the reply to connection {}: {}",
+                                        token.0,
+                                        e
+                                    );
+                                }
+                            } else {
+                                db.start_wait(PendingWait {
+                                    connection_token: token,
+                                    initial_time,
+                                    timeout,
+                                    nb_replicas,
+                                })?;
+                            }
                         } else if let ConnectionState::BlockingStreams(
                             initial_time,
                             timeout,
@@ -245,15 +431,20 @@ fn main() -> Result<()> {
                                     replica_token,
                                     Interest::READABLE.add(Interest::WRITABLE),
                                 )?;
+                                log_notice!("Replica {} registered", replica_token.0);
                                 db.register_replica(connection, replica_token);
                             } else if done {
                                 poll.registry().deregister(&mut connection)?;
+                                db.remove_connection(token);
                             }
                         }
                     }
                 }
             }
         }
+        // the connections that stopped after max_commands_per_event go on with the rest
+        // of their input at the next iteration, after the other ones had their turn
+        resumed_connections.append(&mut db.yielded_connections);
 
         if let Some(PendingStreamXread {
             connection_token,
@@ -269,36 +460,485 @@ fn main() -> Result<()> {
                         key_offset_pairs: key_offset_pairs.clone(),
                     };
 
-                    let response_redis_value = redis_command.execute(&mut db)?;
+                    db.restore_connection_db(connection_token);
+                    // the errors of the command are replied, they must not stop the server
+                    let response_redis_value =
+                        db.execute_command(&redis_command).unwrap_or_else(|e| {
+                            log_warning!(
+                                "XREAD of connection {} failed: {}",
+                                connection_token.0,
+                                e
+                            );
+                            RedisValue::SimpleError(
+                                "ERR internal error while executing 'XREAD'".to_string(),
+                            )
+                        });
+                    let response_redis_value =
+                        db.encode_for(connection_token, response_redis_value);
 
-                    blocking_stream_connection
-                        .write_all(response_redis_value.to_string().as_bytes())?;
+                    if let Err(e) =
+                        blocking_stream_connection.write_all(&response_redis_value.to_bytes())
+                    {
+                        log_warning!(
+                            "Can't send the reply to connection {}: {}",
+                            connection_token.0,
+                            e
+                        );
+                    }
                 }
                 db.pending_stream_xread = None;
             }
         }
 
-        // Final check on waiting state. if we are in waiting state and we either waited
-        // enough or have enough ack, we write back to the waiting connection
+        if last_active_expire.elapsed() >= ACTIVE_EXPIRE_INTERVAL {
+            db.active_expire_cycle();
+            last_active_expire = Instant::now();
+        }
+        db.propagate_evictions()?;
+        for token in db.take_timed_out_pops(Instant::now()) {
+            db.defer_reply(token, Instant::now(), RedisValue::NullBulkString);
+        }
+        db.propagate_pops()?;
+        if let Some(aof) = db.aof.as_mut() {
+            aof.fsync_if_due(Instant::now())?;
+        }
+        // the server is single threaded, the save is done in the loop rather than in the
+        // background
+        db.save_if_due(Instant::now());
 
-        if let ConnectionState::Waiting(
-            inititial_time,
-            timeout,
-            requested_replicas,
-            obtained_replicas,
-        ) = db.state
-        {
-            if obtained_replicas >= requested_replicas || inititial_time + timeout <= Instant::now()
-            {
-                let redis_value = RedisValue::Integer(obtained_replicas as i64);
+        // Sends the deferred replies whose deadline has passed
+        for deferred_reply in db.take_due_replies(Instant::now()) {
+            let token = deferred_reply.connection_token;
+            if let Some(connection) = connections.get_mut(&token) {
+                let redis_value = db.encode_for(token, deferred_reply.redis_value);
+                // a client that went away must not stop the server
+                if let Err(e) = connection.write_all(&redis_value.to_bytes()) {
+                    log_warning!("Can't send the reply to connection {}: {}", token.0, e);
+                }
+            }
+            // the input received while blocked can now be processed
+            if !db.is_blocked(token) && db.unprocessed_input.contains_key(&token) {
+                resumed_connections.push(token);
+            }
+        }
 
+        // Final check on waiting state. if a wait is pending and we either waited
+        // enough or have enough ack, we write back to the waiting connection
+        if let Some(pending_wait) = db.pending_wait.clone() {
+            let nb_acked_replicas = db.get_nb_acked_replicas();
+            if nb_acked_replicas as u64 >= pending_wait.nb_replicas
+                || pending_wait.initial_time + pending_wait.timeout <= Instant::now()
+            {
+                let redis_value = RedisValue::Integer(nb_acked_replicas as i64);
                 if let Some(waiting_connection) =
-                    connections.get_mut(&waiting_token.expect("Waiting token should be set"))
+                    connections.get_mut(&pending_wait.connection_token)
+                {
+                    if let Err(e) = waiting_connection.write_all(&redis_value.to_bytes()) {
+                        log_warning!(
+                            "Can't send the reply to connection {}: {}",
+                            pending_wait.connection_token.0,
+                            e
+                        );
+                    }
+                }
+                db.pending_wait = None;
+                // the commands pipelined after WAIT can now be processed
+                if db
+                    .unprocessed_input
+                    .contains_key(&pending_wait.connection_token)
                 {
-                    waiting_connection.write_all(redis_value.to_string().as_bytes())?;
-                    db.state = ConnectionState::Ready;
+                    resumed_connections.push(pending_wait.connection_token);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{finish_streaming, parse_redis_value};
+    use std::io::Read;
+
+    /// Directory of the files of a test, so that concurrent tests don't share them
+    fn test_dir(test_name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("test-{}-{}", test_name, std::process::id()))
+    }
+
+    /// Starts a server on a free port in a background thread and returns the port
+    fn spawn_server(test_name: &str) -> Result<u16> {
+        spawn_server_with(ServerConfig {
+            dir: test_dir(test_name).to_string_lossy().to_string(),
+            ..Default::default()
+        })
+    }
+
+    /// Same as spawn_server with the given config, its port is ignored
+    fn spawn_server_with(config: ServerConfig) -> Result<u16> {
+        let port = std::net::TcpListener::bind("127.0.0.1:0")?
+            .local_addr()?
+            .port();
+        let config = ServerConfig {
+            port,
+            loglevel: LogLevel::Warning,
+            ..config
+        };
+        std::thread::spawn(move || run_server(config).map_err(|e| e.to_string()));
+        Ok(port)
+    }
+
+    /// Connects to the server, waiting for it to be ready
+    fn connect(port: u16) -> Result<std::net::TcpStream> {
+        let deadline = Instant::now() + Duration::from_secs(2);
+        loop {
+            match std::net::TcpStream::connect(("127.0.0.1", port)) {
+                Ok(stream) => {
+                    stream.set_read_timeout(Some(Duration::from_secs(2)))?;
+                    return Ok(stream);
                 }
+                Err(_) if Instant::now() < deadline => {
+                    std::thread::sleep(Duration::from_millis(10))
+                }
+                Err(e) => Err(e)?,
+            }
+        }
+    }
+
+    /// Sends the command and reads its reply, which can span several reads
+    fn send(stream: &mut std::net::TcpStream, command: &str) -> Result<RedisValue> {
+        stream.write_all(&RedisValue::array_of_bulkstrings_from(command).to_bytes())?;
+        receive(stream)
+    }
+
+    /// Reads the next value sent by the server
+    fn receive(stream: &mut std::net::TcpStream) -> Result<RedisValue> {
+        let mut received = Vec::new();
+        let mut buffer = [0; 1024];
+        loop {
+            let n = stream.read(&mut buffer)?;
+            if n == 0 {
+                Err(std::io::Error::from(ErrorKind::UnexpectedEof))?;
+            }
+            received.extend_from_slice(&buffer[..n]);
+            if let Some((_, redis_value)) = finish_streaming(parse_redis_value(&received))? {
+                return Ok(redis_value);
+            }
+        }
+    }
+
+    /// Reads the next `nb_values` values, which can be sent in one or several writes
+    fn receive_several(
+        stream: &mut std::net::TcpStream,
+        nb_values: usize,
+    ) -> Result<Vec<RedisValue>> {
+        let mut received = Vec::new();
+        let mut buffer = [0; 1024];
+        let mut redis_values = Vec::new();
+        while redis_values.len() < nb_values {
+            let n = stream.read(&mut buffer)?;
+            if n == 0 {
+                Err(std::io::Error::from(ErrorKind::UnexpectedEof))?;
             }
+            received.extend_from_slice(&buffer[..n]);
+            while let Some((rest, redis_value)) = finish_streaming(parse_redis_value(&received))? {
+                received = rest.to_vec();
+                redis_values.push(redis_value);
+            }
+        }
+        Ok(redis_values)
+    }
+
+    #[test]
+    fn test_server_over_the_wire() -> Result<()> {
+        let port = spawn_server("server-over-the-wire")?;
+        let mut stream = connect(port)?;
+        let ok = || RedisValue::SimpleString("OK".to_string());
+
+        assert_eq!(
+            send(&mut stream, "PING")?,
+            RedisValue::SimpleString("PONG".to_string())
+        );
+        assert_eq!(send(&mut stream, "SET key 10")?, ok());
+        assert_eq!(
+            send(&mut stream, "GET key")?,
+            RedisValue::bulkstring_from("10")
+        );
+        assert_eq!(send(&mut stream, "INCR key")?, RedisValue::Integer(11));
+
+        assert_eq!(send(&mut stream, "MULTI")?, ok());
+        assert_eq!(
+            send(&mut stream, "INCR key")?,
+            RedisValue::SimpleString("QUEUED".to_string())
+        );
+        assert_eq!(
+            send(&mut stream, "SET other 1")?,
+            RedisValue::SimpleString("QUEUED".to_string())
+        );
+        assert_eq!(
+            send(&mut stream, "EXEC")?,
+            RedisValue::Array(2, vec![RedisValue::Integer(12), ok()])
+        );
+
+        // the other connections see the writes
+        let mut other_stream = connect(port)?;
+        assert_eq!(
+            send(&mut other_stream, "GET other")?,
+            RedisValue::bulkstring_from("1")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_rdb_is_loaded_on_startup() -> Result<()> {
+        let dir = test_dir("startup");
+        std::fs::create_dir_all(&dir)?;
+        let config = ServerConfig {
+            dir: dir.to_string_lossy().to_string(),
+            ..Default::default()
+        };
+
+        let mut stream = connect(spawn_server_with(config.clone())?)?;
+        send(&mut stream, "SET a 1")?;
+        send(&mut stream, "RPUSH list x y")?;
+        send(&mut stream, "SAVE")?;
+
+        // a restarted server finds the keys
+        let mut stream = connect(spawn_server_with(config.clone())?)?;
+        assert_eq!(
+            send(&mut stream, "GET a")?,
+            RedisValue::bulkstring_from("1")
+        );
+        assert_eq!(
+            send(&mut stream, "LRANGE list 0 -1")?,
+            RedisValue::array_of_bulkstrings_from("x y")
+        );
+
+        // a corrupt file is ignored
+        std::fs::write(dir.join("corrupt.rdb"), b"REDIS0011 not an rdb")?;
+        let mut stream = connect(spawn_server_with(ServerConfig {
+            dbfilename: "corrupt.rdb".to_string(),
+            ..config
+        })?)?;
+        assert_eq!(send(&mut stream, "DBSIZE")?, RedisValue::Integer(0));
+        Ok(())
+    }
+
+    #[test]
+    fn test_replica_receives_the_dataset_of_master() -> Result<()> {
+        let dir = test_dir("fullresync");
+        std::fs::create_dir_all(&dir)?;
+        let master_port = spawn_server_with(ServerConfig {
+            dir: dir.join("master").to_string_lossy().to_string(),
+            ..Default::default()
+        })?;
+        let mut stream = connect(master_port)?;
+        send(&mut stream, "SET a 1")?;
+        send(&mut stream, "RPUSH list x y")?;
+
+        let replica_port = spawn_server_with(ServerConfig {
+            dir: dir.join("replica").to_string_lossy().to_string(),
+            replicaof: Some(format!("127.0.0.1 {}", master_port)),
+            ..Default::default()
+        })?;
+        let mut replica_stream = connect(replica_port)?;
+        // the handshake happens in the background
+        let mut value = RedisValue::NullBulkString;
+        for _ in 0..100 {
+            value = send(&mut replica_stream, "GET a")?;
+            if value != RedisValue::NullBulkString {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        assert_eq!(value, RedisValue::bulkstring_from("1"));
+        assert_eq!(
+            send(&mut replica_stream, "LRANGE list 0 -1")?,
+            RedisValue::array_of_bulkstrings_from("x y")
+        );
+
+        // the writes after the snapshot are propagated on top of it
+        send(&mut stream, "SET b 2")?;
+        for _ in 0..100 {
+            value = send(&mut replica_stream, "GET b")?;
+            if value != RedisValue::NullBulkString {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        assert_eq!(value, RedisValue::bulkstring_from("2"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_aof_replays_transactions() -> Result<()> {
+        let dir = test_dir("aof-multi");
+        std::fs::create_dir_all(&dir)?;
+        let _ = std::fs::remove_file(dir.join("appendonly.aof"));
+        let config = ServerConfig {
+            dir: dir.to_string_lossy().to_string(),
+            appendonly: true,
+            ..Default::default()
+        };
+
+        let mut stream = connect(spawn_server_with(config.clone())?)?;
+        send(&mut stream, "MULTI")?;
+        send(&mut stream, "SET a 1")?;
+        send(&mut stream, "INCR a")?;
+        send(&mut stream, "GET a")?;
+        send(&mut stream, "EXEC")?;
+
+        // only the writes are logged, within MULTI and EXEC
+        let logged = Aof::load(dir.join("appendonly.aof"))?;
+        assert_eq!(
+            logged,
+            ["MULTI", "SET a 1", "INCR a", "EXEC"].map(RedisValue::array_of_bulkstrings_from)
+        );
+
+        // a restarted server replays the transaction
+        let mut stream = connect(spawn_server_with(config)?)?;
+        assert_eq!(
+            send(&mut stream, "GET a")?,
+            RedisValue::bulkstring_from("2")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_long_pipeline_is_fully_processed() -> Result<()> {
+        let port = spawn_server_with(ServerConfig {
+            dir: test_dir("long-pipeline").to_string_lossy().to_string(),
+            max_commands_per_event: 100,
+            ..Default::default()
+        })?;
+        let mut stream = connect(port)?;
+        let pipeline = (0..250)
+            .flat_map(|_| RedisValue::array_of_bulkstrings_from("INCR counter").to_bytes())
+            .collect::<Vec<_>>();
+        stream.write_all(&pipeline)?;
+
+        // the replies come in several writes
+        assert_eq!(
+            receive_several(&mut stream, 250)?,
+            (1..=250).map(RedisValue::Integer).collect::<Vec<_>>()
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_debug_sleep_blocks_the_connection() -> Result<()> {
+        let mut stream = connect(spawn_server("debug-sleep-blocks-the-connection")?)?;
+        let input = [
+            RedisValue::array_of_bulkstrings_from("DEBUG SLEEP 0.1").to_bytes(),
+            RedisValue::array_of_bulkstrings_from("SET x 1").to_bytes(),
+        ]
+        .concat();
+        stream.write_all(&input)?;
+        // sent during the sleep, answered after it
+        stream.write_all(&RedisValue::array_of_bulkstrings_from("GET x").to_bytes())?;
+
+        let ok = RedisValue::SimpleString("OK".to_string());
+        assert_eq!(
+            receive_several(&mut stream, 3)?,
+            vec![ok.clone(), ok, RedisValue::bulkstring_from("1")]
+        );
+
+        // a client leaving during the sleep does not affect the others
+        let mut leaving = connect(stream.peer_addr()?.port())?;
+        leaving.write_all(&RedisValue::array_of_bulkstrings_from("DEBUG SLEEP 0.05").to_bytes())?;
+        drop(leaving);
+        std::thread::sleep(Duration::from_millis(100));
+        assert_eq!(
+            send(&mut stream, "PING")?,
+            RedisValue::SimpleString("PONG".to_string())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_xadd_wakes_up_blocked_xread() -> Result<()> {
+        let port = spawn_server("xadd-wakes-up-blocked-xread")?;
+        let mut writer = connect(port)?;
+        send(&mut writer, "XADD s 1-1 a 1")?;
+
+        // without the wake up, the reply would only come after the timeout, longer than
+        // the read timeout of the connection
+        for (block, id) in [("0", "1-2"), ("10000", "1-3")] {
+            let mut reader = connect(port)?;
+            reader.write_all(
+                &RedisValue::array_of_bulkstrings_from(&format!(
+                    "XREAD BLOCK {} streams s $",
+                    block
+                ))
+                .to_bytes(),
+            )?;
+            // lets the server block the reader before the write
+            std::thread::sleep(Duration::from_millis(100));
+            assert_eq!(
+                send(&mut writer, &format!("XADD s {} a 2", id))?,
+                RedisValue::bulkstring_from(id)
+            );
+
+            let RedisValue::Array(1, streams) = receive(&mut reader)? else {
+                panic!("XREAD should reply with a single stream");
+            };
+            let RedisValue::Array(2, stream) = &streams[0] else {
+                panic!("the stream should have its new entry");
+            };
+            let RedisValue::Array(1, entries) = &stream[1] else {
+                panic!("only the new entry should be read");
+            };
+            assert!(matches!(
+                &entries[0],
+                RedisValue::Array(2, entry) if entry[0] == RedisValue::bulkstring_from(id)
+            ));
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_xread_timeout_on_a_replaced_stream() -> Result<()> {
+        let port = spawn_server("xread-timeout-on-a-replaced-stream")?;
+        let mut writer = connect(port)?;
+        send(&mut writer, "XADD s 1-1 a 1")?;
+
+        let mut reader = connect(port)?;
+        reader.write_all(
+            &RedisValue::array_of_bulkstrings_from("XREAD BLOCK 200 streams s $").to_bytes(),
+        )?;
+        std::thread::sleep(Duration::from_millis(50));
+        // the stream is replaced by a string before the timeout
+        send(&mut writer, "SET s 1")?;
+
+        // the XREAD of the timeout is executed as the other commands, it replies nil
+        // rather than stopping the server
+        assert_eq!(receive(&mut reader)?, RedisValue::NullBulkString);
+        assert_eq!(
+            send(&mut writer, "PING")?,
+            RedisValue::SimpleString("PONG".to_string())
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_publish_reaches_other_connections() -> Result<()> {
+        let port = spawn_server("publish-reaches-other-connections")?;
+        let mut subscribers = [connect(port)?, connect(port)?];
+        for subscriber in subscribers.iter_mut() {
+            send(subscriber, "SUBSCRIBE news")?;
+        }
+
+        // the subscribers only read, the message is pushed to them by the server
+        let mut publisher = connect(port)?;
+        assert_eq!(
+            send(&mut publisher, "PUBLISH news hello")?,
+            RedisValue::Integer(2)
+        );
+        for subscriber in subscribers.iter_mut() {
+            assert_eq!(
+                receive(subscriber)?,
+                RedisValue::array_of_bulkstrings_from("message news hello")
+            );
         }
+        Ok(())
     }
 }
@@ -1,28 +1,48 @@
 use nom::{
-    bytes::complete::{tag, take, take_until},
-    character::complete::{self, anychar},
+    bytes::streaming::{tag, take, take_until},
+    character::streaming::{self, anychar},
     sequence::terminated,
     IResult,
 };
 
+use crate::log::log_debug;
+use crate::sorted_set::format_score;
 use crate::{Error, Result};
+use std::io::Write;
 
 #[derive(Debug, PartialEq, Clone)]
 pub enum RedisValue {
     SimpleString(String),
     SimpleError(String),
     Integer(i64),
-    /// Contains size and actual string
-    BulkString(usize, String),
+    /// Contains size and actual bytes, which are not necessarily valid utf8
+    BulkString(usize, Vec<u8>),
     /// (shows up as (nil))
     NullBulkString,
     /// Contains nb of elements and actual values
     Array(usize, Vec<RedisValue>),
+    /// RESP3 error that can contain any binary data, such as large error payloads
+    BulkError(String),
+    /// RESP3 string with its format (txt or mkd), for text meant to be displayed as is
+    VerbatimString(String, String),
+    /// RESP3 floating point number, such as a score of a sorted set
+    Double(f64),
+    /// RESP3 boolean
+    Boolean(bool),
+    /// RESP3 null, sent as a null bulk string to RESP2 clients
+    Null,
+    /// RESP3 out of band data such as pub/sub messages, sent as an array to RESP2 clients
+    Push(usize, Vec<RedisValue>),
+    /// RESP3 key-value pairs, sent as a flat array of keys and values to RESP2 clients
+    Map(Vec<(RedisValue, RedisValue)>),
 }
 
 impl RedisValue {
     pub fn bulkstring_from(s: &str) -> Self {
-        Self::BulkString(s.len(), s.to_string())
+        Self::bulkstring_from_bytes(s.as_bytes())
+    }
+    pub fn bulkstring_from_bytes(bytes: &[u8]) -> Self {
+        Self::BulkString(bytes.len(), bytes.to_vec())
     }
     pub fn array_of_bulkstrings_from(s: &str) -> Self {
         let redis_values = s
@@ -37,43 +57,127 @@ impl RedisValue {
             RedisValue::SimpleString(x) => x.to_string(),
             RedisValue::SimpleError(x) => x.to_string(),
             RedisValue::Integer(x) => x.to_string(),
-            RedisValue::BulkString(_, x) => x.to_string(),
-            RedisValue::NullBulkString => "(nil)".to_string(),
+            RedisValue::BulkString(_, x) => String::from_utf8_lossy(x).to_string(),
+            RedisValue::NullBulkString | RedisValue::Null => "(nil)".to_string(),
+            RedisValue::BulkError(x) => x.to_string(),
+            RedisValue::VerbatimString(_, x) => x.to_string(),
+            RedisValue::Double(x) => format_score(*x),
+            RedisValue::Boolean(x) => x.to_string(),
             _ => Err(Error::CantConvertToString(self.clone()))?,
         };
         Ok(res)
     }
-}
 
-impl std::fmt::Display for RedisValue {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    /// Serialized value, as sent on the wire
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        self.write_to(&mut bytes)
+            .expect("writing to a vec should not fail");
+        bytes
+    }
+
+    /// Writes the serialized value to the writer. The content of bulk strings is written
+    /// as is after its header, without building an intermediate buffer, which matters
+    /// for large values.
+    pub fn write_to<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
         match self {
-            Self::SimpleString(x) => write!(f, "+{}\r\n", x),
-            Self::SimpleError(x) => write!(f, "-{}\r\n", x),
-            Self::Integer(x) => write!(f, ":{}\r\n", x),
-            Self::BulkString(size, x) => write!(f, "${}\r\n{}\r\n", size, x),
-            Self::NullBulkString => write!(f, "$-1\r\n"),
-            Self::Array(size, x) => {
-                write!(f, "*{}\r\n", size)?;
-                for redis_value in x {
-                    write!(f, "{}", redis_value)?;
+            Self::SimpleString(x) => write!(writer, "+{}\r\n", x),
+            Self::SimpleError(x) => write!(writer, "-{}\r\n", x),
+            Self::Integer(x) => write!(writer, ":{}\r\n", x),
+            Self::BulkString(size, x) => {
+                write!(writer, "${}\r\n", size)?;
+                writer.write_all(x)?;
+                writer.write_all(b"\r\n")
+            }
+            Self::NullBulkString => write!(writer, "$-1\r\n"),
+            Self::BulkError(x) => write!(writer, "!{}\r\n{}\r\n", x.len(), x),
+            Self::VerbatimString(format, x) => {
+                write!(writer, "={}\r\n{}:", format.len() + 1 + x.len(), format)?;
+                writer.write_all(x.as_bytes())?;
+                writer.write_all(b"\r\n")
+            }
+            Self::Double(x) => write!(writer, ",{}\r\n", format_score(*x)),
+            Self::Boolean(x) => write!(writer, "#{}\r\n", if *x { 't' } else { 'f' }),
+            Self::Null => write!(writer, "_\r\n"),
+            Self::Array(size, values) => {
+                write!(writer, "*{}\r\n", size)?;
+                for redis_value in values {
+                    redis_value.write_to(writer)?;
+                }
+                Ok(())
+            }
+            Self::Push(size, values) => {
+                write!(writer, ">{}\r\n", size)?;
+                for redis_value in values {
+                    redis_value.write_to(writer)?;
+                }
+                Ok(())
+            }
+            Self::Map(pairs) => {
+                write!(writer, "%{}\r\n", pairs.len())?;
+                for (key, value) in pairs {
+                    key.write_to(writer)?;
+                    value.write_to(writer)?;
                 }
                 Ok(())
             }
         }
     }
+
+    /// Converts the RESP3 only values to their RESP2 equivalent so that they can be sent
+    /// to a client that did not negotiate RESP3
+    pub fn into_resp2(self) -> Self {
+        match self {
+            // simple errors can not contain line breaks
+            Self::BulkError(x) => Self::SimpleError(x.replace(['\r', '\n'], " ")),
+            Self::VerbatimString(_, x) => Self::BulkString(x.len(), x.into_bytes()),
+            Self::Double(x) => Self::bulkstring_from(&format_score(x)),
+            Self::Boolean(x) => Self::Integer(x as i64),
+            Self::Null => Self::NullBulkString,
+            Self::Array(size, values) | Self::Push(size, values) => Self::Array(
+                size,
+                values.into_iter().map(|value| value.into_resp2()).collect(),
+            ),
+            Self::Map(pairs) => Self::Array(
+                2 * pairs.len(),
+                pairs
+                    .into_iter()
+                    .flat_map(|(key, value)| [key.into_resp2(), value.into_resp2()])
+                    .collect(),
+            ),
+            _ => self,
+        }
+    }
+}
+
+/// Values sent by clients are not necessarily valid utf8
+pub fn lossy_string(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes).to_string()
+}
+
+/// Turns the result of a parser run on data received from the network into our result
+/// type. Ok(None) means that the data ends in the middle of a value: the bytes must be
+/// kept until more data arrives, which is not an error.
+pub fn finish_streaming<T>(result: IResult<&[u8], T>) -> Result<Option<(&[u8], T)>> {
+    match result {
+        Ok(parsed) => Ok(Some(parsed)),
+        Err(nom::Err::Incomplete(_)) => Ok(None),
+        Err(nom::Err::Error(err) | nom::Err::Failure(err)) => Err(err.into()),
+    }
 }
 
-pub fn parse_redis_value(input: &str) -> IResult<&str, RedisValue> {
+/// Parses a value, the parsers are streaming ones: they fail with Incomplete instead of
+/// an error when the input is a prefix of a valid value.
+pub fn parse_redis_value(input: &[u8]) -> IResult<&[u8], RedisValue> {
     let (input, symbol) = parse_symbol(input)?;
     match symbol {
         '+' => {
             let (input, val) = parse_until_crlf(input)?;
-            Ok((input, RedisValue::SimpleString(val.to_string())))
+            Ok((input, RedisValue::SimpleString(lossy_string(val))))
         }
         '-' => {
             let (input, val) = parse_until_crlf(input)?;
-            Ok((input, RedisValue::SimpleError(val.to_string())))
+            Ok((input, RedisValue::SimpleError(lossy_string(val))))
         }
         ':' => {
             let (input, val) = parse_redis_int(input)?;
@@ -87,11 +191,11 @@ pub fn parse_redis_value(input: &str) -> IResult<&str, RedisValue> {
                 word_length => {
                     let word_length = word_length as usize;
                     let (input, word) = parse_bulkstring_word(input, word_length)?;
-                    Ok((input, RedisValue::BulkString(word_length, word.to_string())))
+                    Ok((input, RedisValue::BulkString(word_length, word.to_vec())))
                 }
             }
         }
-        '*' => {
+        '*' | '>' => {
             let (mut input, nb_elements) = parse_redis_int(input)?;
             let nb_elements = nb_elements as usize;
             let mut redis_values = Vec::new();
@@ -101,40 +205,126 @@ pub fn parse_redis_value(input: &str) -> IResult<&str, RedisValue> {
                 (input, redis_value) = parse_redis_value(input)?;
                 redis_values.push(redis_value);
             }
-            Ok((input, RedisValue::Array(nb_elements, redis_values)))
+            if symbol == '>' {
+                Ok((input, RedisValue::Push(nb_elements, redis_values)))
+            } else {
+                Ok((input, RedisValue::Array(nb_elements, redis_values)))
+            }
+        }
+        '%' => {
+            let (mut input, nb_pairs) = parse_redis_int(input)?;
+            let mut pairs = Vec::new();
+            for _ in 0..nb_pairs {
+                let (key, value);
+                (input, key) = parse_redis_value(input)?;
+                (input, value) = parse_redis_value(input)?;
+                pairs.push((key, value));
+            }
+            Ok((input, RedisValue::Map(pairs)))
+        }
+        '!' => {
+            let (input, length) = parse_redis_int(input)?;
+            let (input, error) = parse_bulkstring_word(input, length as usize)?;
+            Ok((input, RedisValue::BulkError(lossy_string(error))))
+        }
+        '=' => {
+            let (input, length) = parse_redis_int(input)?;
+            let (input, content) = parse_bulkstring_word(input, length as usize)?;
+            // the format is always 3 characters followed by a colon. The content is
+            // complete so a missing colon is an error rather than incomplete data.
+            let (input_content, format) = terminated(
+                nom::bytes::complete::take(3usize),
+                nom::bytes::complete::tag(":"),
+            )(content)?;
+            Ok((
+                input,
+                RedisValue::VerbatimString(lossy_string(format), lossy_string(input_content)),
+            ))
+        }
+        ',' => {
+            // inf, -inf and nan are valid doubles
+            let (rest, val) = parse_until_crlf(input)?;
+            match std::str::from_utf8(val).map(|val| val.parse::<f64>()) {
+                Ok(Ok(val)) => Ok((rest, RedisValue::Double(val))),
+                _ => Err(nom::Err::Error(nom::error::Error::new(
+                    input,
+                    nom::error::ErrorKind::Float,
+                ))),
+            }
+        }
+        '_' => {
+            let (input, _) = parse_crlf(input)?;
+            Ok((input, RedisValue::Null))
+        }
+        '#' => {
+            let (input, val) = terminated(anychar, parse_crlf)(input)?;
+            match val {
+                't' => Ok((input, RedisValue::Boolean(true))),
+                'f' => Ok((input, RedisValue::Boolean(false))),
+                _ => Err(nom::Err::Error(nom::error::Error::new(
+                    input,
+                    nom::error::ErrorKind::Char,
+                ))),
+            }
         }
         x => {
-            dbg!(x);
-            dbg!(input);
-            todo!()
+            log_debug!("Unsupported type {:?} before {:?}", x, input);
+            Err(nom::Err::Error(nom::error::Error::new(
+                input,
+                nom::error::ErrorKind::Char,
+            )))
         }
     }
 }
 
-fn parse_symbol(input: &str) -> IResult<&str, char> {
+/// Parses a request sent by a client. Besides RESP arrays, clients such as health
+/// checkers can send inline commands: a single line of space separated arguments, which
+/// is converted to the equivalent array of bulk strings.
+pub fn parse_request(input: &[u8]) -> IResult<&[u8], RedisValue> {
+    match input.first() {
+        Some(b'+' | b'-' | b':' | b'$' | b'*' | b'!' | b'=' | b',' | b'#' | b'_') => {
+            parse_redis_value(input)
+        }
+        _ => parse_inline_command(input),
+    }
+}
+
+/// Inline commands end with \n, optionally preceded by \r
+fn parse_inline_command(input: &[u8]) -> IResult<&[u8], RedisValue> {
+    let (input, line) = terminated(take_until("\n"), tag("\n"))(input)?;
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+    let arguments = line
+        .split(|byte| byte.is_ascii_whitespace())
+        .filter(|argument| !argument.is_empty())
+        .map(RedisValue::bulkstring_from_bytes)
+        .collect::<Vec<_>>();
+    Ok((input, RedisValue::Array(arguments.len(), arguments)))
+}
+
+fn parse_symbol(input: &[u8]) -> IResult<&[u8], char> {
     anychar(input)
 }
 
-fn parse_redis_int(input: &str) -> IResult<&str, i64> {
-    terminated(complete::i64, parse_crlf)(input)
+fn parse_redis_int(input: &[u8]) -> IResult<&[u8], i64> {
+    terminated(streaming::i64, parse_crlf)(input)
 }
 
-fn parse_until_crlf(input: &str) -> IResult<&str, &str> {
+fn parse_until_crlf(input: &[u8]) -> IResult<&[u8], &[u8]> {
     terminated(take_until("\r\n"), parse_crlf)(input)
 }
 
 /// Redis separates information with \r\n
-fn parse_crlf(input: &str) -> IResult<&str, &str> {
+fn parse_crlf(input: &[u8]) -> IResult<&[u8], &[u8]> {
     tag("\r\n")(input)
 }
 
-fn parse_bulkstring_word(input: &str, length: usize) -> IResult<&str, &str> {
+fn parse_bulkstring_word(input: &[u8], length: usize) -> IResult<&[u8], &[u8]> {
     let (input, word) = take(length)(input)?;
     let (input, _) = parse_crlf(input)?;
     Ok((input, word))
 }
 
-pub fn parse_rdb_length(input: &str) -> IResult<&str, i64> {
+pub fn parse_rdb_length(input: &[u8]) -> IResult<&[u8], i64> {
     let (input, _symbol) = parse_symbol(input)?;
     // TODO: check symbol is $
     let (input, length) = parse_redis_int(input)?;
@@ -148,98 +338,267 @@ mod tests {
     use super::*;
     use crate::Result;
 
+    #[test]
+    fn test_parse_request_inline() -> Result<()> {
+        let (input, redis_value) = parse_request(b"PING\r\nPING  hello\n").finish()?;
+        assert_eq!(redis_value, RedisValue::array_of_bulkstrings_from("PING"));
+        let (input, redis_value) = parse_request(input).finish()?;
+        assert_eq!(
+            redis_value,
+            RedisValue::array_of_bulkstrings_from("PING hello")
+        );
+        assert!(input.is_empty());
+
+        // RESP values are parsed as usual
+        let (_, redis_value) = parse_request(b"*1\r\n$4\r\nPING\r\n").finish()?;
+        assert_eq!(redis_value, RedisValue::array_of_bulkstrings_from("PING"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_finish_streaming() -> Result<()> {
+        let input = b"*2\r\n$4\r\nEcho\r\n$7\r\nbonjour\r\n";
+        // every prefix of a value is incomplete, not an error
+        for end in 1..input.len() {
+            assert!(finish_streaming(parse_redis_value(&input[..end]))?.is_none());
+        }
+        assert!(finish_streaming(parse_request(b"PING hel"))?.is_none());
+
+        let (rest, _) = finish_streaming(parse_redis_value(input))?.expect("Value is complete");
+        assert!(rest.is_empty());
+        assert!(finish_streaming(parse_redis_value(b":abc\r\n")).is_err());
+        // an unknown type byte, even nested in an array, is an error
+        assert!(finish_streaming(parse_redis_value(b"X\r\n")).is_err());
+        assert!(finish_streaming(parse_redis_value(b"*1\r\nX\r\n")).is_err());
+        Ok(())
+    }
+
     #[test]
     fn test_parse_redis_value_simplestring() -> Result<()> {
         let initial_input = "+bonjour\r\n";
-        let input = initial_input;
+        let input = initial_input.as_bytes();
         let (input, redis_value) = parse_redis_value(input).finish()?;
         assert_eq!(redis_value, RedisValue::SimpleString("bonjour".to_string()));
-        assert_eq!(input, "");
-        assert_eq!(initial_input, redis_value.to_string());
+        assert!(input.is_empty());
+        assert_eq!(redis_value.to_bytes(), initial_input.as_bytes());
         Ok(())
     }
 
     #[test]
     fn test_parse_redis_value_simpleerror() -> Result<()> {
         let initial_input = "-terrible mistake\r\n";
-        let input = initial_input;
+        let input = initial_input.as_bytes();
         let (input, redis_value) = parse_redis_value(input).finish()?;
         assert_eq!(
             redis_value,
             RedisValue::SimpleError("terrible mistake".to_string())
         );
-        assert_eq!(input, "");
-        assert_eq!(initial_input, redis_value.to_string());
+        assert!(input.is_empty());
+        assert_eq!(redis_value.to_bytes(), initial_input.as_bytes());
         Ok(())
     }
 
     #[test]
     fn test_parse_redis_value_integer() -> Result<()> {
         let initial_input = ":+65\r\n";
-        let input = initial_input;
+        let input = initial_input.as_bytes();
         let (input, redis_value) = parse_redis_value(input).finish()?;
         assert_eq!(redis_value, RedisValue::Integer(65));
-        assert_eq!(input, "");
-        assert_eq!(":65\r\n", redis_value.to_string());
+        assert!(input.is_empty());
+        assert_eq!(redis_value.to_bytes(), b":65\r\n");
 
         let initial_input = ":455\r\n";
-        let input = initial_input;
+        let input = initial_input.as_bytes();
         let (input, redis_value) = parse_redis_value(input).finish()?;
         assert_eq!(redis_value, RedisValue::Integer(455));
-        assert_eq!(input, "");
-        assert_eq!(initial_input, redis_value.to_string());
+        assert!(input.is_empty());
+        assert_eq!(redis_value.to_bytes(), initial_input.as_bytes());
 
         let initial_input = ":-879\r\n";
-        let input = initial_input;
+        let input = initial_input.as_bytes();
         let (input, redis_value) = parse_redis_value(input).finish()?;
         assert_eq!(redis_value, RedisValue::Integer(-879));
-        assert_eq!(input, "");
-        assert_eq!(initial_input, redis_value.to_string());
+        assert!(input.is_empty());
+        assert_eq!(redis_value.to_bytes(), initial_input.as_bytes());
         Ok(())
     }
 
     #[test]
     fn test_parse_redis_value_bulkstring() -> Result<()> {
         let initial_input = "$7\r\nbonjour\r\n";
-        let input = initial_input;
+        let input = initial_input.as_bytes();
         let (input, redis_value) = parse_redis_value(input).finish()?;
-        assert_eq!(
-            redis_value,
-            RedisValue::BulkString(7, "bonjour".to_string())
-        );
-        assert_eq!(input, "");
-        assert_eq!(initial_input, redis_value.to_string());
+        assert_eq!(redis_value, RedisValue::BulkString(7, b"bonjour".to_vec()));
+        assert!(input.is_empty());
+        assert_eq!(redis_value.to_bytes(), initial_input.as_bytes());
         Ok(())
     }
 
     #[test]
     fn test_parse_redis_value_nullbulkstring() -> Result<()> {
         let initial_input = "$-1\r\n";
-        let input = initial_input;
+        let input = initial_input.as_bytes();
         let (input, redis_value) = parse_redis_value(input).finish()?;
         assert_eq!(redis_value, RedisValue::NullBulkString);
-        assert_eq!(input, "");
-        assert_eq!(initial_input, redis_value.to_string());
+        assert!(input.is_empty());
+        assert_eq!(redis_value.to_bytes(), initial_input.as_bytes());
         Ok(())
     }
 
     #[test]
     fn test_parse_redis_value_array() -> Result<()> {
         let initial_input = "*2\r\n$4\r\nEcho\r\n$7\r\nbonjour\r\n";
-        let input = initial_input;
+        let input = initial_input.as_bytes();
         let (input, redis_value) = parse_redis_value(input).finish()?;
         assert_eq!(
             redis_value,
             RedisValue::Array(
                 2,
                 vec![
-                    RedisValue::BulkString(4, "Echo".to_string()),
-                    RedisValue::BulkString(7, "bonjour".to_string()),
+                    RedisValue::BulkString(4, b"Echo".to_vec()),
+                    RedisValue::BulkString(7, b"bonjour".to_vec()),
+                ]
+            )
+        );
+        assert!(input.is_empty());
+        assert_eq!(redis_value.to_bytes(), initial_input.as_bytes());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_redis_value_push() -> Result<()> {
+        let initial_input = ">3\r\n$8\r\nsmessage\r\n$2\r\nch\r\n$5\r\nhello\r\n";
+        let (input, redis_value) = parse_redis_value(initial_input.as_bytes()).finish()?;
+        let values = vec![
+            RedisValue::bulkstring_from("smessage"),
+            RedisValue::bulkstring_from("ch"),
+            RedisValue::bulkstring_from("hello"),
+        ];
+        assert_eq!(redis_value, RedisValue::Push(3, values.clone()));
+        assert!(input.is_empty());
+        assert_eq!(redis_value.to_bytes(), initial_input.as_bytes());
+        assert_eq!(redis_value.into_resp2(), RedisValue::Array(3, values));
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_redis_value_map() -> Result<()> {
+        let initial_input = "%2\r\n$5\r\nproto\r\n:3\r\n$4\r\nnull\r\n_\r\n";
+        let (input, redis_value) = parse_redis_value(initial_input.as_bytes()).finish()?;
+        assert_eq!(
+            redis_value,
+            RedisValue::Map(vec![
+                (RedisValue::bulkstring_from("proto"), RedisValue::Integer(3)),
+                (RedisValue::bulkstring_from("null"), RedisValue::Null),
+            ])
+        );
+        assert!(input.is_empty());
+        assert_eq!(redis_value.to_bytes(), initial_input.as_bytes());
+        assert_eq!(
+            redis_value.into_resp2(),
+            RedisValue::Array(
+                4,
+                vec![
+                    RedisValue::bulkstring_from("proto"),
+                    RedisValue::Integer(3),
+                    RedisValue::bulkstring_from("null"),
+                    RedisValue::NullBulkString,
+                ]
+            )
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_redis_value_bulkerror() -> Result<()> {
+        let initial_input = "!21\r\nSYNTAX invalid syntax\r\n";
+        let (input, redis_value) = parse_redis_value(initial_input.as_bytes()).finish()?;
+        assert_eq!(
+            redis_value,
+            RedisValue::BulkError("SYNTAX invalid syntax".to_string())
+        );
+        assert!(input.is_empty());
+        assert_eq!(redis_value.to_bytes(), initial_input.as_bytes());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_redis_value_verbatimstring() -> Result<()> {
+        let initial_input = "=15\r\ntxt:Some string\r\n";
+        let (input, redis_value) = parse_redis_value(initial_input.as_bytes()).finish()?;
+        assert_eq!(
+            redis_value,
+            RedisValue::VerbatimString("txt".to_string(), "Some string".to_string())
+        );
+        assert!(input.is_empty());
+        assert_eq!(redis_value.to_bytes(), initial_input.as_bytes());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_redis_value_double_and_boolean() -> Result<()> {
+        for initial_input in [
+            ",3.14\r\n",
+            ",-2\r\n",
+            ",inf\r\n",
+            ",-inf\r\n",
+            "#t\r\n",
+            "_\r\n",
+        ] {
+            let (input, redis_value) = parse_redis_value(initial_input.as_bytes()).finish()?;
+            assert!(input.is_empty());
+            assert_eq!(redis_value.to_bytes(), initial_input.as_bytes());
+        }
+        let (_, redis_value) = parse_redis_value(b",nan\r\n").finish()?;
+        assert!(matches!(redis_value, RedisValue::Double(x) if x.is_nan()));
+        assert_eq!(redis_value.to_bytes(), b",nan\r\n");
+        assert_eq!(RedisValue::Boolean(false).to_bytes(), b"#f\r\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_into_resp2() {
+        let redis_value = RedisValue::Array(
+            5,
+            vec![
+                RedisValue::BulkError("ERR line\r\nother line".to_string()),
+                RedisValue::VerbatimString("txt".to_string(), "text".to_string()),
+                RedisValue::Double(1.5),
+                RedisValue::Boolean(true),
+                RedisValue::Null,
+            ],
+        );
+        assert_eq!(
+            redis_value.into_resp2(),
+            RedisValue::Array(
+                5,
+                vec![
+                    RedisValue::SimpleError("ERR line  other line".to_string()),
+                    RedisValue::bulkstring_from("text"),
+                    RedisValue::bulkstring_from("1.5"),
+                    RedisValue::Integer(1),
+                    RedisValue::NullBulkString,
                 ]
             )
         );
-        assert_eq!(input, "");
-        assert_eq!(initial_input, redis_value.to_string());
+    }
+
+    #[test]
+    fn test_write_to() -> Result<()> {
+        let large_value = "a".repeat(100_000);
+        let redis_value = RedisValue::Array(
+            4,
+            vec![
+                RedisValue::bulkstring_from(&large_value),
+                RedisValue::Integer(3),
+                RedisValue::VerbatimString("txt".to_string(), "text".to_string()),
+                RedisValue::NullBulkString,
+            ],
+        );
+        let mut written = Vec::new();
+        redis_value.write_to(&mut written)?;
+        let expected = format!("*4\r\n$100000\r\n{large_value}\r\n:3\r\n=8\r\ntxt:text\r\n$-1\r\n");
+        assert_eq!(written, expected.into_bytes());
         Ok(())
     }
 }
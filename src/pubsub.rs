@@ -0,0 +1,300 @@
+use std::collections::{HashMap, HashSet};
+
+use mio::Token;
+
+use crate::glob::glob_match;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubscriptionKind {
+    Channel,
+    Pattern,
+    /// Shard channels live in their own namespace
+    Shard,
+}
+
+impl SubscriptionKind {
+    /// Name of the message confirming a subscription
+    pub fn subscribe_name(&self) -> &'static str {
+        match self {
+            Self::Channel => "subscribe",
+            Self::Pattern => "psubscribe",
+            Self::Shard => "ssubscribe",
+        }
+    }
+
+    /// Name of the message confirming an unsubscription
+    pub fn unsubscribe_name(&self) -> &'static str {
+        match self {
+            Self::Channel => "unsubscribe",
+            Self::Pattern => "punsubscribe",
+            Self::Shard => "sunsubscribe",
+        }
+    }
+}
+
+/// Subscriptions of a connection, kept in subscription order
+#[derive(Debug, Clone, Default)]
+struct Subscriptions {
+    channels: Vec<String>,
+    patterns: Vec<String>,
+    shard_channels: Vec<String>,
+}
+
+impl Subscriptions {
+    fn names(&self, kind: SubscriptionKind) -> &Vec<String> {
+        match kind {
+            SubscriptionKind::Channel => &self.channels,
+            SubscriptionKind::Pattern => &self.patterns,
+            SubscriptionKind::Shard => &self.shard_channels,
+        }
+    }
+
+    fn names_mut(&mut self, kind: SubscriptionKind) -> &mut Vec<String> {
+        match kind {
+            SubscriptionKind::Channel => &mut self.channels,
+            SubscriptionKind::Pattern => &mut self.patterns,
+            SubscriptionKind::Shard => &mut self.shard_channels,
+        }
+    }
+
+    /// Shard channels are counted separately from channels and patterns
+    fn count(&self, kind: SubscriptionKind) -> usize {
+        match kind {
+            SubscriptionKind::Channel | SubscriptionKind::Pattern => {
+                self.channels.len() + self.patterns.len()
+            }
+            SubscriptionKind::Shard => self.shard_channels.len(),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.channels.is_empty() && self.patterns.is_empty() && self.shard_channels.is_empty()
+    }
+}
+
+/// Keeps track of who is subscribed to what, in both directions: subscribers of a
+/// channel (or pattern) to deliver messages and subscriptions of a connection for the
+/// subscription counts.
+#[derive(Debug, Clone, Default)]
+pub struct PubSub {
+    channels: HashMap<String, HashSet<Token>>,
+    patterns: HashMap<String, HashSet<Token>>,
+    shard_channels: HashMap<String, HashSet<Token>>,
+    subscriptions: HashMap<Token, Subscriptions>,
+}
+
+impl PubSub {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn subscribers_mut(&mut self, kind: SubscriptionKind) -> &mut HashMap<String, HashSet<Token>> {
+        match kind {
+            SubscriptionKind::Channel => &mut self.channels,
+            SubscriptionKind::Pattern => &mut self.patterns,
+            SubscriptionKind::Shard => &mut self.shard_channels,
+        }
+    }
+
+    /// Number of subscriptions of the connection reported in the confirmation messages:
+    /// channels and patterns are counted together, shard channels on their own
+    pub fn subscription_count(&self, token: Token, kind: SubscriptionKind) -> usize {
+        self.subscriptions
+            .get(&token)
+            .map_or(0, |subscriptions| subscriptions.count(kind))
+    }
+
+    /// Channels (or patterns) the connection is subscribed to, in subscription order
+    pub fn subscriptions(&self, token: Token, kind: SubscriptionKind) -> Vec<String> {
+        self.subscriptions
+            .get(&token)
+            .map(|subscriptions| subscriptions.names(kind).clone())
+            .unwrap_or_default()
+    }
+
+    /// Subscribes the connection and returns its subscription count afterwards.
+    /// Subscribing twice to the same channel does not change the count.
+    pub fn subscribe(&mut self, token: Token, kind: SubscriptionKind, name: &str) -> usize {
+        let is_new = self
+            .subscribers_mut(kind)
+            .entry(name.to_string())
+            .or_default()
+            .insert(token);
+
+        let subscriptions = self.subscriptions.entry(token).or_default();
+        if is_new {
+            subscriptions.names_mut(kind).push(name.to_string());
+        }
+        subscriptions.count(kind)
+    }
+
+    /// Unsubscribes the connection and returns its subscription count afterwards
+    pub fn unsubscribe(&mut self, token: Token, kind: SubscriptionKind, name: &str) -> usize {
+        let subscribers = self.subscribers_mut(kind);
+        if let Some(tokens) = subscribers.get_mut(name) {
+            tokens.remove(&token);
+            if tokens.is_empty() {
+                subscribers.remove(name);
+            }
+        }
+
+        if let Some(subscriptions) = self.subscriptions.get_mut(&token) {
+            subscriptions.names_mut(kind).retain(|x| x != name);
+            if subscriptions.is_empty() {
+                self.subscriptions.remove(&token);
+            }
+        }
+        self.subscription_count(token, kind)
+    }
+
+    /// Channels with at least one subscriber, sorted and optionally filtered with a
+    /// glob-style pattern
+    pub fn channels(&self, pattern: Option<&str>) -> Vec<String> {
+        let mut channels = self
+            .channels
+            .keys()
+            .filter(|channel| pattern.map_or(true, |pattern| glob_match(pattern, channel)))
+            .cloned()
+            .collect::<Vec<_>>();
+        channels.sort();
+        channels
+    }
+
+    /// Connections receiving a message published to the channel, with the matching
+    /// pattern for pattern subscriptions. A connection subscribed to several matching
+    /// patterns receives the message once per pattern.
+    pub fn receivers(&self, channel: &str) -> Vec<(Token, Option<String>)> {
+        let mut receivers = self
+            .channels
+            .get(channel)
+            .map(|tokens| {
+                tokens
+                    .iter()
+                    .map(|token| (*token, None))
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        for (pattern, tokens) in self.patterns.iter() {
+            if glob_match(pattern, channel) {
+                receivers.extend(tokens.iter().map(|token| (*token, Some(pattern.clone()))));
+            }
+        }
+        receivers
+    }
+
+    /// Connections subscribed to the shard channel
+    pub fn shard_subscribers(&self, shard_channel: &str) -> Vec<Token> {
+        self.shard_channels
+            .get(shard_channel)
+            .map(|tokens| tokens.iter().copied().collect())
+            .unwrap_or_default()
+    }
+
+    /// Number of subscribers of the channel, patterns are not taken into account
+    pub fn numsub(&self, channel: &str) -> usize {
+        self.channels.get(channel).map_or(0, |tokens| tokens.len())
+    }
+
+    /// Number of distinct patterns with at least one subscriber
+    pub fn numpat(&self) -> usize {
+        self.patterns.len()
+    }
+
+    /// Removes all the subscriptions of a closed connection
+    pub fn remove_connection(&mut self, token: Token) {
+        for kind in [
+            SubscriptionKind::Channel,
+            SubscriptionKind::Pattern,
+            SubscriptionKind::Shard,
+        ] {
+            for name in self.subscriptions(token, kind) {
+                self.unsubscribe(token, kind, &name);
+            }
+        }
+    }
+}
+
+/// Whether the keyspace (`__keyspace@<db>__:<key>`) and keyevent
+/// (`__keyevent@<db>__:<event>`) notifications should be published for an event of the
+/// given class, following the notify-keyspace-events format: K and E select the
+/// notification types, A is an alias for all the classes.
+pub fn keyspace_events_enabled(flags: &str, class: char) -> (bool, bool) {
+    let class_enabled = flags.contains(class) || (flags.contains('A') && class != 'm');
+    (
+        class_enabled && flags.contains('K'),
+        class_enabled && flags.contains('E'),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_subscription_counts() {
+        let mut pubsub = PubSub::new();
+        let token = Token(20);
+        assert_eq!(pubsub.subscribe(token, SubscriptionKind::Channel, "a"), 1);
+        assert_eq!(pubsub.subscribe(token, SubscriptionKind::Channel, "b"), 2);
+        assert_eq!(pubsub.subscribe(token, SubscriptionKind::Channel, "a"), 2);
+        assert_eq!(pubsub.subscribe(token, SubscriptionKind::Pattern, "a*"), 3);
+        assert_eq!(
+            pubsub.subscribe(Token(21), SubscriptionKind::Channel, "a"),
+            1
+        );
+
+        assert_eq!(
+            pubsub.subscriptions(token, SubscriptionKind::Channel),
+            vec!["a", "b"]
+        );
+        assert_eq!(pubsub.unsubscribe(token, SubscriptionKind::Channel, "a"), 2);
+        assert_eq!(pubsub.unsubscribe(token, SubscriptionKind::Channel, "c"), 2);
+        assert_eq!(pubsub.channels["a"].len(), 1);
+
+        // shard channels are counted separately
+        assert_eq!(pubsub.subscribe(token, SubscriptionKind::Shard, "a"), 1);
+        assert_eq!(
+            pubsub.subscription_count(token, SubscriptionKind::Channel),
+            2
+        );
+        assert_eq!(pubsub.shard_subscribers("a"), vec![token]);
+
+        pubsub.remove_connection(token);
+        assert_eq!(
+            pubsub.subscription_count(token, SubscriptionKind::Channel),
+            0
+        );
+        assert!(pubsub.shard_subscribers("a").is_empty());
+        assert!(!pubsub.channels.contains_key("b"));
+        assert!(pubsub.patterns.is_empty());
+    }
+
+    #[test]
+    fn test_receivers() {
+        let mut pubsub = PubSub::new();
+        pubsub.subscribe(Token(20), SubscriptionKind::Channel, "news.tech");
+        pubsub.subscribe(Token(21), SubscriptionKind::Pattern, "news.*");
+        pubsub.subscribe(Token(21), SubscriptionKind::Pattern, "*");
+        pubsub.subscribe(Token(22), SubscriptionKind::Shard, "news.tech");
+
+        let mut receivers = pubsub.receivers("news.tech");
+        receivers.sort();
+        assert_eq!(
+            receivers,
+            vec![
+                (Token(20), None),
+                (Token(21), Some("*".to_string())),
+                (Token(21), Some("news.*".to_string())),
+            ]
+        );
+        assert_eq!(pubsub.receivers("weather").len(), 1);
+    }
+
+    #[test]
+    fn test_keyspace_events_enabled() {
+        assert_eq!(keyspace_events_enabled("", 'x'), (false, false));
+        assert_eq!(keyspace_events_enabled("Ex", 'x'), (false, true));
+        assert_eq!(keyspace_events_enabled("KA", 'x'), (true, false));
+        assert_eq!(keyspace_events_enabled("KEg", 'x'), (false, false));
+    }
+}
@@ -0,0 +1,103 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+
+use crate::{Error, Result};
+
+/// Largest number of elements returned for a negative count. The elements can repeat so
+/// the reply is not bounded by the size of the collection.
+pub const MAX_REPEATED_SAMPLES: u64 = 1 << 24;
+
+/// Small xorshift64* generator. We don't need cryptographic randomness, only
+/// something that does not depend on the iteration order of the collections.
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new() -> Self {
+        // Each RandomState is built with random keys, which gives us a random seed
+        // without pulling an external crate
+        let seed = RandomState::new().build_hasher().finish();
+        Self { state: seed | 1 }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        self.state.wrapping_mul(0x2545F4914F6CDD1D)
+    }
+
+    /// Returns a random number in 0..upper_bound
+    pub fn gen_range(&mut self, upper_bound: usize) -> usize {
+        (self.next_u64() % upper_bound as u64) as usize
+    }
+}
+
+impl Default for Rng {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Picks random elements following the redis convention for the count:
+/// a positive count returns distinct elements (at most items.len()), a negative count
+/// returns exactly |count| elements and allows repetitions. |count| is checked against
+/// MAX_REPEATED_SAMPLES before anything is allocated.
+pub fn sample<T>(items: &[T], count: i64) -> Result<Vec<&T>> {
+    let mut rng = Rng::new();
+    if count < 0 && count.unsigned_abs() > MAX_REPEATED_SAMPLES {
+        return Err(Error::ValueOutOfRange);
+    }
+    if items.is_empty() {
+        return Ok(vec![]);
+    }
+
+    if count < 0 {
+        return Ok((0..count.unsigned_abs())
+            .map(|_| &items[rng.gen_range(items.len())])
+            .collect());
+    }
+
+    // partial Fisher-Yates shuffle on the indices
+    let count = (count as usize).min(items.len());
+    let mut indices = (0..items.len()).collect::<Vec<_>>();
+    for i in 0..count {
+        let j = i + rng.gen_range(items.len() - i);
+        indices.swap(i, j);
+    }
+    Ok(indices[..count].iter().map(|&i| &items[i]).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    #[test]
+    fn test_sample() -> Result<()> {
+        let items = (0..10).collect::<Vec<_>>();
+
+        let picked = sample(&items, 5)?;
+        assert_eq!(picked.len(), 5);
+        assert_eq!(picked.iter().collect::<HashSet<_>>().len(), 5);
+
+        let picked = sample(&items, 20)?;
+        assert_eq!(picked.iter().collect::<HashSet<_>>().len(), 10);
+
+        let picked = sample(&items, -20)?;
+        assert_eq!(picked.len(), 20);
+
+        assert!(sample(&items, 0)?.is_empty());
+        assert!(sample::<i32>(&[], -3)?.is_empty());
+
+        // positive counts are bounded by the number of items
+        assert_eq!(sample(&items, i64::MAX)?.len(), 10);
+        for count in [i64::MIN, -i64::MAX, -(MAX_REPEATED_SAMPLES as i64) - 1] {
+            assert!(matches!(sample(&items, count), Err(Error::ValueOutOfRange)));
+        }
+        Ok(())
+    }
+}
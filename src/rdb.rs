@@ -1,6 +1,6 @@
-use crate::Result;
+use crate::db::canonical_integer;
+use crate::{Error, Result};
 use std::{
-    fs::File,
     io::{Cursor, SeekFrom},
     path::Path,
 };
@@ -22,9 +22,24 @@ pub struct Rdb {
 }
 
 impl Rdb {
+    /// Reads the rdb file and verifies its checksum, unless it is 0 which means that it
+    /// was not computed
     pub fn new<P: AsRef<Path>>(file_path: P) -> Result<Self> {
-        let mut file = File::open(file_path)?;
-        let rdb = Self::read(&mut file)?;
+        let bytes = std::fs::read(file_path)?;
+        let mut cursor = Cursor::new(&bytes);
+        let rdb = Self::read(&mut cursor)?;
+
+        if rdb.checksum != 0 {
+            // the checksum covers everything before it, EOF opcode included
+            let checksum_start = cursor.position() as usize - 8;
+            let actual = crc64(&bytes[..checksum_start]);
+            if actual != rdb.checksum {
+                return Err(Error::RdbChecksumMismatch {
+                    expected: rdb.checksum,
+                    actual,
+                });
+            }
+        }
         Ok(rdb)
     }
     pub fn empty() -> Result<Self> {
@@ -34,6 +49,32 @@ impl Rdb {
         let rdb = Self::read(&mut cursor)?;
         Ok(rdb)
     }
+
+    /// Rdb holding the given databases. The checksum is left to 0, which redis reads as
+    /// not computed
+    pub fn with_databases(database_sections: Vec<DatabaseSection>) -> Result<Self> {
+        let mut rdb = Self::empty()?;
+        rdb.database_sections = database_sections;
+        rdb.checksum = 0;
+        Ok(rdb)
+    }
+
+    /// Writes the rdb to a temporary file renamed to the path once complete, so that a
+    /// failed save does not corrupt the previous file
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let temp_path = path.with_extension(format!("tmp-{}", std::process::id()));
+        std::fs::write(&temp_path, self.to_bytes()?)?;
+        std::fs::rename(&temp_path, path)?;
+        Ok(())
+    }
+
+    /// Serializes the rdb with our own encoder, for instance to send it to a replica
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut cursor = Cursor::new(Vec::new());
+        self.write(&mut cursor)?;
+        Ok(cursor.into_inner())
+    }
 }
 
 // region: header
@@ -122,6 +163,25 @@ pub struct DatabaseSection {
     pub fields_with_expiry: Vec<DatabaseField>,
 }
 
+impl DatabaseSection {
+    pub fn new(db_number: u32, fields_with_expiry: Vec<DatabaseField>) -> Self {
+        let nb_expires = fields_with_expiry
+            .iter()
+            .filter(|field| field.expiration.expiry_time.is_some())
+            .count();
+        Self {
+            db_number: LengthEncoding { length: db_number },
+            hash_table_size: LengthEncoding {
+                length: fields_with_expiry.len() as u32,
+            },
+            expire_hash_table_size: LengthEncoding {
+                length: nb_expires as u32,
+            },
+            fields_with_expiry,
+        }
+    }
+}
+
 #[derive(Debug)]
 #[binrw]
 #[brw(little)]
@@ -129,11 +189,33 @@ pub struct DatabaseField {
     expiration: Expiration,
     pub value_type: ValueTypeEncoding,
     pub key: StringEncodedField,
-    // TODO: implement encoding for other types
-    pub value: StringEncodedField,
+    #[br(args(value_type))]
+    pub value: DatabaseValue,
 }
 
 impl DatabaseField {
+    /// Field expiring at the unix time in milliseconds if there is one. The value type is
+    /// the one of the value
+    pub fn new(key: &str, value: DatabaseValue, unix_timestamp_expiration_ms: Option<u64>) -> Self {
+        Self {
+            expiration: Expiration {
+                is_second: false,
+                expiry_time: unix_timestamp_expiration_ms,
+            },
+            value_type: value.value_type(),
+            key: StringEncodedField {
+                msb_11: false,
+                field: key.as_bytes().to_vec(),
+            },
+            value,
+        }
+    }
+
+    pub fn string(key: &str, value: &str, unix_timestamp_expiration_ms: Option<u64>) -> Self {
+        let value = DatabaseValue::String(StringEncodedField::new(value.as_bytes()));
+        Self::new(key, value, unix_timestamp_expiration_ms)
+    }
+
     pub fn get_unix_timestamp_expiration_ms(&self) -> Option<u64> {
         match self.expiration.expiry_time {
             None => None,
@@ -165,6 +247,109 @@ fn parse_database_sections() -> BinResult<Vec<DatabaseSection>> {
 
 // endregion: database section
 
+// region: database value
+
+/// Value of a field. Lists and sets are their length followed by their elements and
+/// hashes their number of fields followed by each field and its value
+#[derive(Debug)]
+pub enum DatabaseValue {
+    String(StringEncodedField),
+    List(Vec<StringEncodedField>),
+    Set(Vec<StringEncodedField>),
+    Hash(Vec<(StringEncodedField, StringEncodedField)>),
+}
+
+impl DatabaseValue {
+    pub fn value_type(&self) -> ValueTypeEncoding {
+        match self {
+            Self::String(_) => ValueTypeEncoding::String,
+            Self::List(_) => ValueTypeEncoding::List,
+            Self::Set(_) => ValueTypeEncoding::Set,
+            Self::Hash(_) => ValueTypeEncoding::Hash,
+        }
+    }
+}
+
+impl BinRead for DatabaseValue {
+    type Args<'a> = (ValueTypeEncoding,);
+
+    fn read_options<R: std::io::prelude::Read + std::io::prelude::Seek>(
+        reader: &mut R,
+        endian: binrw::Endian,
+        args: Self::Args<'_>,
+    ) -> BinResult<Self> {
+        let value = match args.0 {
+            ValueTypeEncoding::String => {
+                Self::String(StringEncodedField::read_options(reader, endian, ())?)
+            }
+            ValueTypeEncoding::List | ValueTypeEncoding::Set => {
+                let length = LengthEncoding::read_options(reader, endian, ())?.length;
+                let elements = (0..length)
+                    .map(|_| StringEncodedField::read_options(reader, endian, ()))
+                    .collect::<BinResult<Vec<_>>>()?;
+                match args.0 {
+                    ValueTypeEncoding::List => Self::List(elements),
+                    _ => Self::Set(elements),
+                }
+            }
+            ValueTypeEncoding::Hash => {
+                let length = LengthEncoding::read_options(reader, endian, ())?.length;
+                let fields = (0..length)
+                    .map(|_| {
+                        Ok((
+                            StringEncodedField::read_options(reader, endian, ())?,
+                            StringEncodedField::read_options(reader, endian, ())?,
+                        ))
+                    })
+                    .collect::<BinResult<Vec<_>>>()?;
+                Self::Hash(fields)
+            }
+            value_type => Err(binrw::Error::AssertFail {
+                pos: reader.stream_position()?,
+                message: format!("Value type {:?} is not supported", value_type),
+            })?,
+        };
+        Ok(value)
+    }
+}
+
+impl BinWrite for DatabaseValue {
+    type Args<'a> = ();
+
+    fn write_options<W: std::io::prelude::Write + std::io::prelude::Seek>(
+        &self,
+        writer: &mut W,
+        endian: binrw::Endian,
+        args: Self::Args<'_>,
+    ) -> BinResult<()> {
+        match self {
+            Self::String(value) => value.write_options(writer, endian, args)?,
+            Self::List(elements) | Self::Set(elements) => {
+                let length_encoding = LengthEncoding {
+                    length: elements.len() as u32,
+                };
+                length_encoding.write_options(writer, endian, args)?;
+                for element in elements {
+                    element.write_options(writer, endian, args)?;
+                }
+            }
+            Self::Hash(fields) => {
+                let length_encoding = LengthEncoding {
+                    length: fields.len() as u32,
+                };
+                length_encoding.write_options(writer, endian, args)?;
+                for (field, value) in fields {
+                    field.write_options(writer, endian, args)?;
+                    value.write_options(writer, endian, args)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+// endregion: database value
+
 // region: string encoded field
 #[derive(Debug)]
 pub struct StringEncodedField {
@@ -172,8 +357,22 @@ pub struct StringEncodedField {
     /// This is useful for writing so that we can know whether the value is a
     /// string or an actual integer
     pub msb_11: bool,
-    /// Even if the value is an integer, it is stored as a string
-    pub field: String,
+    /// Even if the value is an integer, it is stored as a string. Values are not
+    /// necessarily valid utf8
+    pub field: Vec<u8>,
+}
+
+impl StringEncodedField {
+    /// As in redis, the value is encoded as an integer if OBJECT ENCODING reports it as
+    /// int and it fits on 32 bits
+    pub fn new(value: &[u8]) -> Self {
+        let is_integer =
+            canonical_integer(value).is_some_and(|integer| i32::try_from(integer).is_ok());
+        Self {
+            msb_11: is_integer,
+            field: value.to_vec(),
+        }
+    }
 }
 
 impl BinRead for StringEncodedField {
@@ -186,14 +385,14 @@ impl BinRead for StringEncodedField {
     ) -> BinResult<Self> {
         let byte = u8::read_options(reader, endian, args)?;
         let mut msb_11 = false;
-        let field: String;
+        let field: Vec<u8>;
         match byte >> 6 {
             0..=2 => {
                 reader.seek(SeekFrom::Current(-1))?;
                 let length_encoding = LengthEncoding::read_options(reader, endian, args)?;
                 let mut buf = vec![0u8; length_encoding.length as usize];
                 reader.read_exact(&mut buf)?;
-                field = String::from_utf8_lossy(&buf).to_string();
+                field = buf;
             }
             // special case, after this there is a number on 1, 2 or 4 bytes
             // depending on the format
@@ -202,23 +401,24 @@ impl BinRead for StringEncodedField {
                 msb_11 = true;
                 let format = byte & 0b00111111;
                 match format {
+                    // the integers are signed
                     0 => {
                         let mut buf = [0u8; 1];
                         reader.read_exact(&mut buf)?;
-                        let val = u8::from_le_bytes(buf);
-                        field = format!("{}", val);
+                        let val = i8::from_le_bytes(buf);
+                        field = val.to_string().into_bytes();
                     }
                     1 => {
                         let mut buf = [0u8; 2];
                         reader.read_exact(&mut buf)?;
-                        let val = u16::from_le_bytes(buf);
-                        field = format!("{}", val);
+                        let val = i16::from_le_bytes(buf);
+                        field = val.to_string().into_bytes();
                     }
                     2 => {
                         let mut buf = [0u8; 4];
                         reader.read_exact(&mut buf)?;
-                        let val = u32::from_le_bytes(buf);
-                        field = format!("{}", val);
+                        let val = i32::from_le_bytes(buf);
+                        field = val.to_string().into_bytes();
                     }
                     _ => unreachable!(),
                 }
@@ -242,23 +442,22 @@ impl BinWrite for StringEncodedField {
         match self.msb_11 {
             true => {
                 // here we actually encoded a number as string
-                let num = self
-                    .field
-                    .parse::<u32>()
+                let num = canonical_integer(&self.field)
+                    .and_then(|num| i32::try_from(num).ok())
                     .expect("field should be an encoded integer");
-                if num < 256 {
+                if let Ok(num) = i8::try_from(num) {
                     u8::write_options(&0b11000000, writer, endian, args)?;
-                    u8::write_options(&(num as u8), writer, endian, args)?;
-                } else if num < 65536 {
+                    i8::write_options(&num, writer, endian, args)?;
+                } else if let Ok(num) = i16::try_from(num) {
                     u8::write_options(&0b11000001, writer, endian, args)?;
-                    u16::write_options(&(num as u16), writer, endian, args)?;
+                    i16::write_options(&num, writer, endian, args)?;
                 } else {
                     u8::write_options(&0b11000010, writer, endian, args)?;
-                    u32::write_options(&num, writer, endian, args)?;
+                    i32::write_options(&num, writer, endian, args)?;
                 }
             }
             false => {
-                let bytes = self.field.as_bytes();
+                let bytes = &self.field;
 
                 let len = bytes.len();
                 let length_encoding = LengthEncoding { length: len as u32 };
@@ -293,12 +492,10 @@ impl BinRead for LengthEncoding {
             1 => {
                 let first_part = (byte & 0b00111111) as u32;
                 let second_part = (u8::read_options(reader, endian, args)?) as u32;
-                first_part << 8 & second_part
-            }
-            2 => {
-                let second_part = u8::read_options(reader, endian, args)?;
-                second_part as u32
+                first_part << 8 | second_part
             }
+            // the length is on the next 4 bytes, in big endian
+            2 => u32::read_options(reader, binrw::Endian::Big, args)?,
             // NOTE: if MSB is 11, it is a special case, see StringEncodedField
             x => Err(binrw::Error::AssertFail {
                 pos: reader.stream_position()?,
@@ -320,25 +517,19 @@ impl BinWrite for LengthEncoding {
         args: Self::Args<'_>,
     ) -> BinResult<()> {
         let len = self.length;
-        if len < 192 {
-            // length fits on the rest of the byte and we are sure first two
-            // msb are 00
+        if len < 64 {
+            // length fits on the rest of the byte, the first two msb are 00
             u8::write_options(&(len as u8), writer, endian, args)?;
-        } else if len < 256 {
-            // first we write the first 2 msb: 10
-            u8::write_options(&0b10000000, writer, endian, args)?;
-
-            // then we write the actual length
-            u8::write_options(&(len as u8), writer, endian, args)?;
-        } else {
-            // we need the two bytes
+        } else if len < 16384 {
+            // 01 then the length on the next 14 bits
             let first_part = (len >> 8) | 0b01000000;
             let second_part = len & 0b11111111;
-            // first we write the first part
             u8::write_options(&(first_part as u8), writer, endian, args)?;
-
-            // then we write the second_part
             u8::write_options(&(second_part as u8), writer, endian, args)?;
+        } else {
+            // 10 then the length on the next 4 bytes, in big endian
+            u8::write_options(&0b10000000, writer, endian, args)?;
+            u32::write_options(&len, writer, binrw::Endian::Big, args)?;
         }
         Ok(())
     }
@@ -421,7 +612,7 @@ impl BinWrite for Expiration {
 // endregion: expiration
 
 // region: value type encoding
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 #[binrw]
 pub enum ValueTypeEncoding {
     #[brw(magic = 0u8)]
@@ -450,6 +641,40 @@ pub enum ValueTypeEncoding {
 
 // endregion: value type encoding
 
+// region: checksum
+
+/// Reflected form of the polynomial of the Jones CRC64 used by redis
+const CRC64_POLY: u64 = 0x95ac9329ac4bc9b5;
+
+const CRC64_TABLE: [u64; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u64;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ CRC64_POLY
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+/// CRC64 of redis: Jones polynomial, reflected input and output, initial value 0
+fn crc64(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0, |crc, byte| {
+        CRC64_TABLE[((crc ^ *byte as u64) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+// endregion: checksum
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -462,7 +687,6 @@ mod tests {
     #[test]
     pub fn test_rdb() -> Result<()> {
         let rdb = Rdb::new("test_dump.rdb")?;
-        dbg!(&rdb.database_sections);
 
         let mut cursor = Cursor::new(vec![]);
         rdb.write(&mut cursor).unwrap();
@@ -471,4 +695,199 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    pub fn test_crc64() {
+        // check value of the redis implementation
+        assert_eq!(crc64(b"123456789"), 0xe9c6d914c4b8d9ca);
+        assert_eq!(crc64(b""), 0);
+    }
+
+    #[test]
+    pub fn test_rdb_checksum() -> Result<()> {
+        let mut bytes = std::fs::read("test_dump.rdb")?;
+        let path = std::env::temp_dir().join(format!("test-checksum-{}.rdb", std::process::id()));
+
+        // the first letter of the redis-ver auxiliary field
+        let position = bytes
+            .windows(9)
+            .position(|window| window == b"redis-ver")
+            .expect("test_dump.rdb should have a redis-ver field");
+        bytes[position] = b'R';
+        std::fs::write(&path, &bytes)?;
+        let res = Rdb::new(&path);
+        assert!(
+            matches!(res, Err(Error::RdbChecksumMismatch { .. })),
+            "{:?}",
+            res
+        );
+
+        // a zero checksum is not verified
+        let len = bytes.len();
+        bytes[len - 8..].fill(0);
+        std::fs::write(&path, &bytes)?;
+        let res = Rdb::new(&path);
+        std::fs::remove_file(&path)?;
+        res?;
+        Ok(())
+    }
+
+    #[test]
+    pub fn test_length_encoding_round_trip() -> Result<()> {
+        for (length, nb_bytes) in [(0, 1), (63, 1), (64, 2), (16383, 2), (16384, 5), (70000, 5)] {
+            let mut cursor = Cursor::new(Vec::new());
+            LengthEncoding { length }.write_le(&mut cursor)?;
+            let bytes = cursor.into_inner();
+            assert_eq!(bytes.len(), nb_bytes, "length {}", length);
+            let decoded = LengthEncoding::read_le(&mut Cursor::new(&bytes))?;
+            assert_eq!(decoded.length, length);
+        }
+        Ok(())
+    }
+
+    #[test]
+    pub fn test_length_encoding_bytes() -> Result<()> {
+        for (length, bytes) in [
+            (100, vec![0x40, 0x64]),
+            (5000, vec![0x53, 0x88]),
+            (100000, vec![0x80, 0x00, 0x01, 0x86, 0xa0]),
+        ] {
+            let mut cursor = Cursor::new(Vec::new());
+            LengthEncoding { length }.write_le(&mut cursor)?;
+            assert_eq!(cursor.into_inner(), bytes, "length {}", length);
+            let decoded = LengthEncoding::read_le(&mut Cursor::new(&bytes))?;
+            assert_eq!(decoded.length, length);
+        }
+        Ok(())
+    }
+
+    #[test]
+    pub fn test_rdb_with_databases_round_trip() -> Result<()> {
+        let long_value = "x".repeat(200);
+        let rdb = Rdb::with_databases(vec![
+            DatabaseSection::new(0, vec![DatabaseField::string("a", "1", None)]),
+            DatabaseSection::new(
+                3,
+                vec![
+                    DatabaseField::string("long", &long_value, None),
+                    DatabaseField::string("volatile", "v", Some(1_700_000_000_000)),
+                ],
+            ),
+        ])?;
+        let rdb = Rdb::read(&mut Cursor::new(rdb.to_bytes()?))?;
+        assert_eq!(rdb.database_sections.len(), 2);
+        let section = &rdb.database_sections[1];
+        assert_eq!(section.db_number.length, 3);
+        assert_eq!(section.expire_hash_table_size.length, 1);
+        assert!(matches!(
+            &section.fields_with_expiry[0].value,
+            DatabaseValue::String(value) if value.field == long_value.as_bytes()
+        ));
+        assert_eq!(
+            section.fields_with_expiry[1].get_unix_timestamp_expiration_ms(),
+            Some(1_700_000_000_000)
+        );
+        Ok(())
+    }
+
+    #[test]
+    pub fn test_integer_values_encoding() -> Result<()> {
+        for (value, nb_bytes) in [
+            ("5", 2),
+            ("-1", 2),
+            ("200", 3),
+            ("-40000", 5),
+            ("2147483647", 5),
+            // too big for 32 bits or not written as redis would, kept as strings
+            ("2147483648", 11),
+            ("007", 4),
+        ] {
+            let field = DatabaseField::string("k", value, None);
+            let mut cursor = Cursor::new(Vec::new());
+            field.value.write_le(&mut cursor)?;
+            let bytes = cursor.into_inner();
+            assert_eq!(bytes.len(), nb_bytes, "value {}", value);
+            let decoded = StringEncodedField::read_le(&mut Cursor::new(&bytes))?;
+            assert_eq!(decoded.field, value.as_bytes());
+        }
+        Ok(())
+    }
+
+    #[test]
+    pub fn test_collection_fields_round_trip() -> Result<()> {
+        let elements =
+            |values: &[&[u8]]| values.iter().map(|v| StringEncodedField::new(v)).collect();
+        let rdb = Rdb::with_databases(vec![DatabaseSection::new(
+            0,
+            vec![
+                DatabaseField::new(
+                    "list",
+                    DatabaseValue::List(elements(&[b"a", b"12", b"\xff\x00"])),
+                    None,
+                ),
+                DatabaseField::new("set", DatabaseValue::Set(elements(&[b"x", b"y"])), None),
+                DatabaseField::new(
+                    "hash",
+                    DatabaseValue::Hash(vec![
+                        (
+                            StringEncodedField::new(b"f1"),
+                            StringEncodedField::new(b"v1"),
+                        ),
+                        (
+                            StringEncodedField::new(b"f2"),
+                            StringEncodedField::new(b"-3"),
+                        ),
+                    ]),
+                    Some(1_700_000_000_000),
+                ),
+            ],
+        )])?;
+        let bytes = rdb.to_bytes()?;
+        // value type, key, then the length and the elements
+        let list = [
+            &[1u8, 4][..],
+            b"list",
+            &[3, 1],
+            b"a",
+            &[0xC0, 12, 2, 0xff, 0],
+        ]
+        .concat();
+        assert!(bytes.windows(list.len()).any(|window| window == list));
+
+        let rdb = Rdb::read(&mut Cursor::new(&bytes))?;
+        assert_eq!(rdb.to_bytes()?, bytes);
+        let fields = &rdb.database_sections[0].fields_with_expiry;
+        let elements = |elements: &[StringEncodedField]| {
+            elements.iter().map(|e| e.field.clone()).collect::<Vec<_>>()
+        };
+        assert_eq!(fields[0].value_type, ValueTypeEncoding::List);
+        assert!(
+            matches!(&fields[0].value, DatabaseValue::List(l) if elements(l) == [&b"a"[..], b"12", b"\xff\x00"])
+        );
+        assert_eq!(fields[1].value_type, ValueTypeEncoding::Set);
+        assert!(matches!(&fields[1].value, DatabaseValue::Set(s) if elements(s) == [b"x", b"y"]));
+        assert_eq!(fields[2].value_type, ValueTypeEncoding::Hash);
+        let DatabaseValue::Hash(hash) = &fields[2].value else {
+            panic!("the field should be a hash");
+        };
+        let hash: Vec<_> = hash
+            .iter()
+            .map(|(field, value)| (&field.field[..], &value.field[..]))
+            .collect();
+        assert_eq!(hash, [(&b"f1"[..], &b"v1"[..]), (b"f2", b"-3")]);
+        assert_eq!(
+            fields[2].get_unix_timestamp_expiration_ms(),
+            Some(1_700_000_000_000)
+        );
+        Ok(())
+    }
+
+    #[test]
+    pub fn test_empty_rdb_round_trip() -> Result<()> {
+        let bytes = Rdb::empty()?.to_bytes()?;
+        let rdb = Rdb::read(&mut Cursor::new(&bytes))?;
+        assert_eq!(rdb.to_bytes()?, bytes);
+        assert!(rdb.database_sections.is_empty());
+        Ok(())
+    }
 }
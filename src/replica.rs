@@ -2,25 +2,42 @@ use std::{cell::RefCell, rc::Rc};
 
 use mio::{net::TcpStream, Token};
 
-/// master keeps track of current replication offset of each connected replica.
-/// At each write, it updates the values of each offset. On a wait, it compares the updated
-/// value with the current value for each replica and only sends a getack if needed. If not, it
-/// does not need to send a wait.
-/// Here, for simplication, we only keep track of whether the replica stream is uptodate.
-/// On any write command to master, we set up_to_date top false
+/// master keeps track of the replication offset of each connected replica: the number of
+/// bytes sent to it and the number of bytes it acknowledged with REPLCONF ACK.
+/// On a wait, it only sends a getack to the replicas that did not acknowledge all the
+/// writes yet.
 #[derive(Debug)]
 pub struct Replica {
     pub stream: Rc<RefCell<TcpStream>>,
-    pub up_to_date: bool,
     pub token: Token,
+    /// Replication offset of the replica: master offset at registration plus the bytes
+    /// sent to it since then
+    pub offset: usize,
+    /// Offset right after the last write command sent to the replica
+    pub write_offset: usize,
+    /// Last offset acknowledged by the replica
+    pub acked_offset: usize,
+    /// Offset the replica must acknowledge to count for the pending WAIT
+    pub wait_offset: usize,
+    /// Start of an ack that did not arrive completely in the last read
+    pub partial_ack: Vec<u8>,
 }
 
 impl Replica {
     pub fn new(stream: TcpStream, token: Token) -> Self {
         Self {
             stream: Rc::new(RefCell::new(stream)),
-            up_to_date: true,
             token,
+            offset: 0,
+            write_offset: 0,
+            acked_offset: 0,
+            wait_offset: 0,
+            partial_ack: Vec::new(),
         }
     }
+
+    /// Whether the replica acknowledged all the writes sent to it
+    pub fn is_up_to_date(&self) -> bool {
+        self.acked_offset >= self.write_offset
+    }
 }
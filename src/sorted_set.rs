@@ -0,0 +1,285 @@
+use std::{
+    cmp::Ordering,
+    collections::{BTreeSet, HashMap},
+};
+
+use crate::{Error, Result};
+
+/// f64 wrapper so that scores can be used as keys of the ordered index
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Score(pub f64);
+
+impl Eq for Score {}
+
+impl PartialOrd for Score {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Score {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+/// Flags of ZADD
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ZAddOptions {
+    /// Only adds new members
+    pub nx: bool,
+    /// Only updates existing members
+    pub xx: bool,
+    /// Only updates a member if its new score is greater
+    pub gt: bool,
+    /// Only updates a member if its new score is lower
+    pub lt: bool,
+    /// Counts the members whose score changed in the reply, not only the new ones
+    pub ch: bool,
+    /// Increments the score of the member like ZINCRBY and replies with the new score
+    pub incr: bool,
+}
+
+impl ZAddOptions {
+    /// Same errors as redis when the flags can not be used together
+    pub fn incompatibility(&self, nb_pairs: usize) -> Option<&'static str> {
+        if self.nx && self.xx {
+            Some("ERR XX and NX options at the same time are not compatible")
+        } else if (self.gt && self.lt) || ((self.gt || self.lt) && self.nx) {
+            Some("ERR GT, LT, and/or NX options at the same time are not compatible")
+        } else if self.incr && nb_pairs != 1 {
+            Some("ERR INCR option supports a single increment-element pair")
+        } else {
+            None
+        }
+    }
+}
+
+/// What ZADD did to a member
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ZAddOutcome {
+    /// The member is new, with this score
+    Added(f64),
+    /// The score of the member changed to this one
+    Updated(f64),
+    /// The member kept this score
+    Unchanged(f64),
+    /// The flags prevented the operation
+    Skipped,
+}
+
+/// Members are kept ordered by (score, member) in a BTreeSet while the HashMap gives
+/// direct access to the score of a member.
+#[derive(Debug, Clone)]
+pub struct SortedSet {
+    scores: HashMap<String, f64>,
+    ordered: BTreeSet<(Score, String)>,
+}
+
+impl SortedSet {
+    pub fn new() -> Self {
+        Self {
+            scores: HashMap::new(),
+            ordered: BTreeSet::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.scores.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.scores.is_empty()
+    }
+
+    /// Adds the member or updates its score. Returns true if the member is new
+    pub fn insert(&mut self, member: &str, score: f64) -> bool {
+        match self.scores.insert(member.to_string(), score) {
+            None => {
+                self.ordered.insert((Score(score), member.to_string()));
+                true
+            }
+            Some(previous_score) => {
+                self.ordered
+                    .remove(&(Score(previous_score), member.to_string()));
+                self.ordered.insert((Score(score), member.to_string()));
+                false
+            }
+        }
+    }
+
+    pub fn score(&self, member: &str) -> Option<f64> {
+        self.scores.get(member).copied()
+    }
+
+    /// Removes the member. Returns true if it was in the sorted set
+    pub fn remove(&mut self, member: &str) -> bool {
+        match self.scores.remove(member) {
+            Some(score) => self.ordered.remove(&(Score(score), member.to_string())),
+            None => false,
+        }
+    }
+
+    /// Position of the member, starting from 0 for the lowest score
+    pub fn rank(&self, member: &str) -> Option<usize> {
+        let score = self.score(member)?;
+        Some(
+            self.ordered
+                .range(..(Score(score), member.to_string()))
+                .count(),
+        )
+    }
+
+    /// Adds or updates the member according to the ZADD flags. With incr, the score is
+    /// an increment of the current score.
+    pub fn add(&mut self, member: &str, score: f64, options: &ZAddOptions) -> Result<ZAddOutcome> {
+        let current_score = self.score(member);
+        let score = match current_score {
+            Some(current_score) if options.incr => current_score + score,
+            _ => score,
+        };
+        if score.is_nan() {
+            return Err(Error::NanScore);
+        }
+
+        let outcome = match current_score {
+            None if options.xx => ZAddOutcome::Skipped,
+            None => ZAddOutcome::Added(score),
+            Some(_) if options.nx => ZAddOutcome::Skipped,
+            Some(current_score)
+                if (options.gt && score <= current_score)
+                    || (options.lt && score >= current_score) =>
+            {
+                // with INCR the score did not change, so there is no new score to return
+                if options.incr {
+                    ZAddOutcome::Skipped
+                } else {
+                    ZAddOutcome::Unchanged(current_score)
+                }
+            }
+            Some(current_score) if current_score == score => ZAddOutcome::Unchanged(score),
+            Some(_) => ZAddOutcome::Updated(score),
+        };
+        if let ZAddOutcome::Added(score) | ZAddOutcome::Updated(score) = outcome {
+            self.insert(member, score);
+        }
+        Ok(outcome)
+    }
+
+    /// Iterates over (member, score) from the lowest to the highest score
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = (&str, f64)> {
+        self.ordered
+            .iter()
+            .map(|(score, member)| (member.as_str(), score.0))
+    }
+}
+
+impl Default for SortedSet {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Formats a score the way redis does: integers have no decimal part
+pub fn format_score(score: f64) -> String {
+    if score.is_nan() {
+        "nan".to_string()
+    } else if score.is_infinite() {
+        if score > 0.0 {
+            "inf".to_string()
+        } else {
+            "-inf".to_string()
+        }
+    } else {
+        format!("{}", score)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sorted_set_insert() {
+        let mut sorted_set = SortedSet::new();
+        assert!(sorted_set.insert("b", 2.0));
+        assert!(sorted_set.insert("a", 2.0));
+        assert!(sorted_set.insert("c", 1.0));
+        assert!(!sorted_set.insert("c", 3.5));
+        assert_eq!(sorted_set.len(), 3);
+
+        let members = sorted_set.iter().collect::<Vec<_>>();
+        assert_eq!(members, vec![("a", 2.0), ("b", 2.0), ("c", 3.5)]);
+    }
+
+    #[test]
+    fn test_sorted_set_add() -> Result<()> {
+        let mut sorted_set = SortedSet::new();
+        let options = ZAddOptions::default();
+        assert_eq!(sorted_set.add("a", 1.0, &options)?, ZAddOutcome::Added(1.0));
+        assert_eq!(
+            sorted_set.add("a", 1.0, &options)?,
+            ZAddOutcome::Unchanged(1.0)
+        );
+        assert_eq!(
+            sorted_set.add("a", 2.0, &options)?,
+            ZAddOutcome::Updated(2.0)
+        );
+
+        let nx = ZAddOptions {
+            nx: true,
+            ..Default::default()
+        };
+        assert_eq!(sorted_set.add("a", 3.0, &nx)?, ZAddOutcome::Skipped);
+        let xx = ZAddOptions {
+            xx: true,
+            ..Default::default()
+        };
+        assert_eq!(sorted_set.add("b", 3.0, &xx)?, ZAddOutcome::Skipped);
+        let gt = ZAddOptions {
+            gt: true,
+            ..Default::default()
+        };
+        assert_eq!(sorted_set.add("a", 1.0, &gt)?, ZAddOutcome::Unchanged(2.0));
+        assert_eq!(sorted_set.add("a", 5.0, &gt)?, ZAddOutcome::Updated(5.0));
+
+        let incr = ZAddOptions {
+            incr: true,
+            ..Default::default()
+        };
+        assert_eq!(sorted_set.add("a", 1.5, &incr)?, ZAddOutcome::Updated(6.5));
+        sorted_set.add("inf", f64::INFINITY, &options)?;
+        assert!(matches!(
+            sorted_set.add("inf", f64::NEG_INFINITY, &incr),
+            Err(Error::NanScore)
+        ));
+        assert_eq!(sorted_set.score("b"), None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_sorted_set_remove_and_rank() {
+        let mut sorted_set = SortedSet::new();
+        sorted_set.insert("b", 2.0);
+        sorted_set.insert("a", 2.0);
+        sorted_set.insert("c", 1.0);
+        assert_eq!(sorted_set.rank("c"), Some(0));
+        assert_eq!(sorted_set.rank("a"), Some(1));
+        assert_eq!(sorted_set.rank("b"), Some(2));
+        assert_eq!(sorted_set.rank("d"), None);
+
+        assert!(sorted_set.remove("a"));
+        assert!(!sorted_set.remove("a"));
+        assert_eq!(sorted_set.rank("b"), Some(1));
+        assert_eq!(sorted_set.len(), 2);
+    }
+
+    #[test]
+    fn test_format_score() {
+        assert_eq!(format_score(1.0), "1");
+        assert_eq!(format_score(1.5), "1.5");
+        assert_eq!(format_score(-0.25), "-0.25");
+        assert_eq!(format_score(f64::INFINITY), "inf");
+        assert_eq!(format_score(f64::NEG_INFINITY), "-inf");
+    }
+}
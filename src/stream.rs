@@ -1,21 +1,24 @@
 use std::{
-    collections::{HashMap, VecDeque},
+    collections::{BTreeMap, BTreeSet, HashMap, VecDeque},
     fmt::Display,
-    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+    time::{Duration, Instant},
 };
 
 use mio::Token;
 
+use crate::clock;
 use crate::{Error, Result};
 #[derive(Debug, Clone)]
 pub struct Stream {
     pub entries: VecDeque<StreamEntry>,
+    pub groups: HashMap<String, ConsumerGroup>,
 }
 
 impl Stream {
     pub fn new() -> Self {
         Self {
             entries: VecDeque::from([]),
+            groups: HashMap::new(),
         }
     }
 
@@ -25,20 +28,15 @@ impl Stream {
 
     /// Generates a new stream id compatible with the stream
     pub fn next_stream_id(&self) -> StreamId {
-        let since_epoch = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .expect("time should not go backward");
-
         let last_stream_id = self.get_last_stream_id();
 
-        let current_timestamp_in_ms =
-            since_epoch.as_secs() * 1000 + since_epoch.subsec_nanos() as u64 / 1000000;
+        let current_timestamp_in_ms = clock::unix_time_ms();
         // We force timestamp_ms to be at least equal to last timestamp in stream
         let (timestamp_ms, seq_number) = {
             if current_timestamp_in_ms > last_stream_id.timestamp_ms {
                 (current_timestamp_in_ms, 0)
             } else {
-                (last_stream_id.timestamp_ms, last_stream_id.timestamp_ms + 1)
+                (last_stream_id.timestamp_ms, last_stream_id.seq_number + 1)
             }
         };
 
@@ -64,17 +62,14 @@ impl Stream {
                 let timestamp_ms = ts.parse::<u64>()?;
                 let seq_number = match seq {
                     None => 0,
-                    Some("*") => {
-                        let last_stream_id = self.get_last_stream_id();
-
-                        if last_stream_id.timestamp_ms == timestamp_ms {
-                            last_stream_id.seq_number + 1
-                        } else if timestamp_ms == 0 {
-                            1
-                        } else {
-                            0
+                    Some("*") => match self.entries.back() {
+                        Some(last) if last.stream_id.timestamp_ms == timestamp_ms => {
+                            last.stream_id.seq_number + 1
                         }
-                    }
+                        // 0-0 is never a valid id
+                        _ if timestamp_ms == 0 => 1,
+                        _ => 0,
+                    },
                     Some(seq) => seq.parse::<u64>()?,
                 };
                 Ok(StreamId {
@@ -85,6 +80,17 @@ impl Stream {
         }
     }
 
+    /// Creates the id of a new entry. A bare timestamp is completed like <ms>-*, so that
+    /// it gets the next sequence number if the last entry has the same timestamp. Redis
+    /// reads it as <ms>-0 instead and rejects it in that case.
+    pub fn create_new_entry_id(&self, value: &str) -> Result<StreamId> {
+        if value != "*" && !value.contains('-') {
+            self.create_stream_id(&format!("{}-*", value))
+        } else {
+            self.create_stream_id(value)
+        }
+    }
+
     pub fn xadd(
         &mut self,
         store: HashMap<String, String>,
@@ -109,13 +115,71 @@ impl Stream {
         Ok(stream_id)
     }
 
+    /// Removes the entries with these ids and returns how many existed
+    pub fn xdel(&mut self, ids: &[StreamId]) -> usize {
+        let len = self.entries.len();
+        self.entries.retain(|entry| !ids.contains(&entry.stream_id));
+        len - self.entries.len()
+    }
+
+    /// Removes the oldest entries until the stream fits the limit
+    pub fn trim(&mut self, trim: &StreamTrim) {
+        while self.entries.len() > trim.max_len {
+            self.entries.pop_front();
+        }
+    }
+
+    pub fn info(&self) -> StreamInfo {
+        let entry = |entry: &StreamEntry| (entry.stream_id.to_string(), entry.store.clone());
+        StreamInfo {
+            length: self.entries.len(),
+            last_generated_id: self.get_last_stream_id(),
+            groups: self.groups.len(),
+            first_entry: self.entries.front().map(entry),
+            last_entry: self.entries.back().map(entry),
+        }
+    }
+
     pub fn xrange(
         &mut self,
         stream_id_start: &str,
         stream_id_end: &str,
     ) -> Result<Vec<(String, HashMap<String, String>)>> {
+        Ok(self
+            .range_indices(stream_id_start, stream_id_end)?
+            .map(|i| {
+                let entry = &self.entries[i];
+                (entry.stream_id.to_string(), entry.store.clone())
+            })
+            .collect())
+    }
+
+    /// Same as xrange from the highest id to the lowest one. As in redis, the end comes
+    /// first
+    pub fn xrevrange(
+        &mut self,
+        stream_id_end: &str,
+        stream_id_start: &str,
+    ) -> Result<Vec<(String, HashMap<String, String>)>> {
+        Ok(self
+            .range_indices(stream_id_start, stream_id_end)?
+            .rev()
+            .map(|i| {
+                let entry = &self.entries[i];
+                (entry.stream_id.to_string(), entry.store.clone())
+            })
+            .collect())
+    }
+
+    /// Indices of the entries between the start and end ids included, "-" and "+" being
+    /// the lowest and highest ids
+    fn range_indices(
+        &mut self,
+        stream_id_start: &str,
+        stream_id_end: &str,
+    ) -> Result<std::ops::Range<usize>> {
         if self.entries.is_empty() {
-            return Ok(vec![]);
+            return Ok(0..0);
         }
 
         let start_index = {
@@ -140,16 +204,9 @@ impl Stream {
             }
         };
         match start_index {
-            None => Ok(vec![]),
-            Some(start_index) => {
-                let mut v = Vec::new();
-                // NOTE: really not optimized with vecdeque
-                for i in start_index..end_index {
-                    let entry = &self.entries[i];
-                    v.push((entry.stream_id.to_string(), entry.store.clone()));
-                }
-                Ok(v)
-            }
+            // the start may be after the end
+            Some(start_index) => Ok(start_index..end_index.max(start_index)),
+            None => Ok(0..0),
         }
     }
 
@@ -186,6 +243,233 @@ impl Stream {
             }
         }
     }
+
+    /// Creates a consumer group whose last delivered id is `stream_id` ($ for the last
+    /// entry of the stream)
+    pub fn create_group(&mut self, group: &str, stream_id: &str) -> Result<()> {
+        if self.groups.contains_key(group) {
+            Err(Error::ConsumerGroupAlreadyExists)?
+        }
+        let last_delivered_id = match stream_id {
+            "$" => self.get_last_stream_id(),
+            stream_id => self.create_stream_id(stream_id)?,
+        };
+        self.groups
+            .insert(group.to_string(), ConsumerGroup::new(last_delivered_id));
+        Ok(())
+    }
+
+    /// Returns whether the group existed
+    pub fn destroy_group(&mut self, group: &str) -> bool {
+        self.groups.remove(group).is_some()
+    }
+
+    /// Reads entries for a consumer of the group.
+    /// With `>`, delivers the entries never delivered to the group and adds them to the
+    /// pending entries list (unless no_ack is set).
+    /// With an id, returns the entries pending for this consumer with a greater id.
+    pub fn read_group(
+        &mut self,
+        key: &str,
+        group: &str,
+        consumer: &str,
+        stream_id_start: &str,
+        count: Option<usize>,
+        no_ack: bool,
+    ) -> Result<Vec<(String, HashMap<String, String>)>> {
+        let stream_id_start = match stream_id_start {
+            ">" => None,
+            stream_id_start => Some(self.create_stream_id(stream_id_start)?),
+        };
+        let count = count.unwrap_or(usize::MAX);
+        let now = clock::now();
+
+        let group = self
+            .groups
+            .get_mut(group)
+            .ok_or_else(|| Error::NoSuchConsumerGroup {
+                key: key.to_string(),
+                group: group.to_string(),
+            })?;
+        group.get_or_create_consumer(consumer).seen_at = now;
+
+        let entries = match stream_id_start {
+            None => {
+                let delivered = self
+                    .entries
+                    .iter()
+                    .filter(|entry| entry.stream_id > group.last_delivered_id)
+                    .take(count)
+                    .collect::<Vec<_>>();
+
+                for entry in delivered.iter() {
+                    group.last_delivered_id = entry.stream_id;
+                    if !no_ack {
+                        group.add_pending(entry.stream_id, consumer, now);
+                    }
+                }
+                delivered
+            }
+            Some(stream_id_start) => group.consumers[consumer]
+                .pending
+                .range(stream_id_start..)
+                .filter(|&&stream_id| stream_id > stream_id_start)
+                .take(count)
+                .filter_map(|stream_id| {
+                    self.entries
+                        .binary_search_by(|entry| entry.stream_id.cmp(stream_id))
+                        .ok()
+                        .map(|i| &self.entries[i])
+                })
+                .collect(),
+        };
+
+        Ok(entries
+            .iter()
+            .map(|entry| (entry.stream_id.to_string(), entry.store.clone()))
+            .collect())
+    }
+
+    /// Summary of the pending entries list of the group: number of pending entries,
+    /// smallest and greatest pending ids and number of pending entries per consumer
+    pub fn pending_summary(&self, key: &str, group: &str) -> Result<PendingSummary> {
+        let group = self.get_group(key, group)?;
+        let mut consumers = group
+            .consumers
+            .iter()
+            .filter(|(_, consumer)| !consumer.pending.is_empty())
+            .map(|(name, consumer)| (name.clone(), consumer.pending.len()))
+            .collect::<Vec<_>>();
+        consumers.sort();
+
+        Ok(PendingSummary {
+            count: group.pending.len(),
+            min_id: group.pending.keys().next().copied(),
+            max_id: group.pending.keys().next_back().copied(),
+            consumers,
+        })
+    }
+
+    /// Pending entries of the group within the range
+    pub fn pending_entries(
+        &self,
+        key: &str,
+        group: &str,
+        range: &PendingRange,
+    ) -> Result<Vec<(StreamId, &PendingEntry)>> {
+        let stream_id_start = match range.stream_id_start.as_str() {
+            "-" => StreamId::default(),
+            stream_id_start => self.create_stream_id(stream_id_start)?,
+        };
+        let stream_id_end = match range.stream_id_end.as_str() {
+            "+" => StreamId::MAX,
+            stream_id_end => self.create_stream_id(stream_id_end)?,
+        };
+        if stream_id_start > stream_id_end {
+            return Ok(vec![]);
+        }
+
+        let now = clock::now();
+        let group = self.get_group(key, group)?;
+        Ok(group
+            .pending
+            .range(stream_id_start..=stream_id_end)
+            .filter(|(_, pending_entry)| {
+                range
+                    .consumer
+                    .as_ref()
+                    .map_or(true, |consumer| pending_entry.consumer == *consumer)
+            })
+            .filter(|(_, pending_entry)| {
+                range
+                    .min_idle
+                    .map_or(true, |min_idle| pending_entry.idle_ms(now) >= min_idle)
+            })
+            .take(range.count)
+            .map(|(stream_id, pending_entry)| (*stream_id, pending_entry))
+            .collect())
+    }
+
+    fn get_group(&self, key: &str, group: &str) -> Result<&ConsumerGroup> {
+        self.groups
+            .get(group)
+            .ok_or_else(|| Error::NoSuchConsumerGroup {
+                key: key.to_string(),
+                group: group.to_string(),
+            })
+    }
+
+    /// Transfers the ownership of the pending entries idle for at least min_idle ms to
+    /// the consumer and returns the claimed entries.
+    pub fn claim(
+        &mut self,
+        key: &str,
+        group: &str,
+        consumer: &str,
+        stream_ids: &[&str],
+        options: &ClaimOptions,
+    ) -> Result<Vec<(String, HashMap<String, String>)>> {
+        let stream_ids = stream_ids
+            .iter()
+            .map(|stream_id| self.create_stream_id(stream_id))
+            .collect::<Result<Vec<_>>>()?;
+        let now = clock::now();
+        let delivered_at = now
+            .checked_sub(Duration::from_millis(options.idle.unwrap_or(0)))
+            .unwrap_or(now);
+
+        let group = self
+            .groups
+            .get_mut(group)
+            .ok_or_else(|| Error::NoSuchConsumerGroup {
+                key: key.to_string(),
+                group: group.to_string(),
+            })?;
+        group.get_or_create_consumer(consumer).seen_at = now;
+
+        let mut claimed = Vec::new();
+        for stream_id in stream_ids {
+            let entry = match self
+                .entries
+                .binary_search_by(|entry| entry.stream_id.cmp(&stream_id))
+            {
+                Ok(i) => &self.entries[i],
+                Err(_) => {
+                    // the entry was deleted from the stream, it can not be claimed anymore
+                    group.ack(&[stream_id]);
+                    continue;
+                }
+            };
+
+            match group.pending.get(&stream_id) {
+                None if !options.force => continue,
+                Some(pending_entry) if pending_entry.idle_ms(now) < options.min_idle => continue,
+                _ => {}
+            }
+
+            let pending_entry = group.set_owner(stream_id, consumer, delivered_at);
+            pending_entry.delivered_at = delivered_at;
+            match options.retry_count {
+                Some(retry_count) => pending_entry.delivery_count = retry_count,
+                None if !options.just_id => pending_entry.delivery_count += 1,
+                None => {}
+            }
+            claimed.push((entry.stream_id.to_string(), entry.store.clone()));
+        }
+        Ok(claimed)
+    }
+
+    /// Acknowledges the entries for the group and returns how many were actually pending
+    pub fn ack(&mut self, group: &str, stream_ids: &[&str]) -> Result<usize> {
+        let stream_ids = stream_ids
+            .iter()
+            .map(|stream_id| self.create_stream_id(stream_id))
+            .collect::<Result<Vec<_>>>()?;
+        match self.groups.get_mut(group) {
+            None => Ok(0),
+            Some(group) => Ok(group.ack(&stream_ids)),
+        }
+    }
 }
 
 impl Default for Stream {
@@ -200,6 +484,13 @@ pub struct StreamId {
     seq_number: u64,
 }
 
+impl StreamId {
+    pub const MAX: StreamId = StreamId {
+        timestamp_ms: u64::MAX,
+        seq_number: u64::MAX,
+    };
+}
+
 impl Display for StreamId {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}-{}", self.timestamp_ms, self.seq_number)
@@ -228,6 +519,169 @@ impl Display for StreamEntry {
     }
 }
 
+#[derive(Debug, Clone)]
+pub struct ConsumerGroup {
+    pub last_delivered_id: StreamId,
+    /// Pending entries list: entries delivered to a consumer but not acknowledged yet
+    pub pending: BTreeMap<StreamId, PendingEntry>,
+    pub consumers: HashMap<String, Consumer>,
+}
+
+impl ConsumerGroup {
+    pub fn new(last_delivered_id: StreamId) -> Self {
+        Self {
+            last_delivered_id,
+            pending: BTreeMap::new(),
+            consumers: HashMap::new(),
+        }
+    }
+
+    /// Returns whether the consumer was created
+    pub fn create_consumer(&mut self, consumer: &str) -> bool {
+        if self.consumers.contains_key(consumer) {
+            return false;
+        }
+        self.consumers.insert(consumer.to_string(), Consumer::new());
+        true
+    }
+
+    pub fn get_or_create_consumer(&mut self, consumer: &str) -> &mut Consumer {
+        self.consumers.entry(consumer.to_string()).or_default()
+    }
+
+    fn add_pending(&mut self, stream_id: StreamId, consumer: &str, delivered_at: Instant) {
+        self.set_owner(stream_id, consumer, delivered_at)
+            .delivery_count += 1;
+    }
+
+    /// Gives the ownership of the pending entry to the consumer, creating the pending
+    /// entry if needed. The delivery count is kept when the entry changes owner
+    fn set_owner(
+        &mut self,
+        stream_id: StreamId,
+        consumer: &str,
+        delivered_at: Instant,
+    ) -> &mut PendingEntry {
+        let previous = self.pending.remove(&stream_id);
+        if let Some(previous) = previous.as_ref() {
+            if let Some(previous_consumer) = self.consumers.get_mut(&previous.consumer) {
+                previous_consumer.pending.remove(&stream_id);
+            }
+        }
+        self.get_or_create_consumer(consumer)
+            .pending
+            .insert(stream_id);
+
+        self.pending.entry(stream_id).or_insert(PendingEntry {
+            consumer: consumer.to_string(),
+            delivered_at,
+            delivery_count: previous.map_or(0, |previous| previous.delivery_count),
+        })
+    }
+
+    /// Acknowledges the entries and returns how many were actually pending
+    pub fn ack(&mut self, stream_ids: &[StreamId]) -> usize {
+        let mut acknowledged = 0;
+        for stream_id in stream_ids {
+            if let Some(pending_entry) = self.pending.remove(stream_id) {
+                if let Some(consumer) = self.consumers.get_mut(&pending_entry.consumer) {
+                    consumer.pending.remove(stream_id);
+                }
+                acknowledged += 1;
+            }
+        }
+        acknowledged
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PendingEntry {
+    pub consumer: String,
+    pub delivered_at: Instant,
+    pub delivery_count: u64,
+}
+
+impl PendingEntry {
+    /// Time in ms since the entry was last delivered
+    pub fn idle_ms(&self, now: Instant) -> u64 {
+        now.saturating_duration_since(self.delivered_at).as_millis() as u64
+    }
+}
+
+/// Range of pending entries (ids inclusive), optionally filtered by consumer and
+/// minimum idle time in ms
+#[derive(Debug, Clone)]
+pub struct PendingRange {
+    pub min_idle: Option<u64>,
+    pub stream_id_start: String,
+    pub stream_id_end: String,
+    pub count: usize,
+    pub consumer: Option<String>,
+}
+
+/// MAXLEN option of XADD
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StreamTrim {
+    pub max_len: usize,
+    /// `~`: redis may keep a few more entries, here the stream is trimmed exactly
+    pub approximate: bool,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ClaimOptions {
+    /// Only entries idle for at least this time in ms are claimed
+    pub min_idle: u64,
+    /// Idle time in ms of the claimed entries, 0 by default
+    pub idle: Option<u64>,
+    /// Delivery count of the claimed entries, incremented by default
+    pub retry_count: Option<u64>,
+    /// Creates the pending entry if it is not already pending
+    pub force: bool,
+    /// Only returns the ids and does not increment the delivery count
+    pub just_id: bool,
+}
+
+/// Summary of the stream returned by XINFO STREAM
+#[derive(Debug, Clone)]
+pub struct StreamInfo {
+    pub length: usize,
+    pub last_generated_id: StreamId,
+    pub groups: usize,
+    pub first_entry: Option<(String, HashMap<String, String>)>,
+    pub last_entry: Option<(String, HashMap<String, String>)>,
+}
+
+#[derive(Debug, Clone)]
+pub struct PendingSummary {
+    pub count: usize,
+    pub min_id: Option<StreamId>,
+    pub max_id: Option<StreamId>,
+    /// consumer name and number of pending entries, sorted by name
+    pub consumers: Vec<(String, usize)>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Consumer {
+    pub seen_at: Instant,
+    /// ids of the entries of the group pending list owned by this consumer
+    pub pending: BTreeSet<StreamId>,
+}
+
+impl Consumer {
+    pub fn new() -> Self {
+        Self {
+            seen_at: clock::now(),
+            pending: BTreeSet::new(),
+        }
+    }
+}
+
+impl Default for Consumer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct PendingStreamXread {
     pub connection_token: Token,
@@ -240,6 +694,7 @@ pub struct PendingStreamXread {
 mod tests {
 
     use super::*;
+    use crate::clock::MockClock;
 
     #[test]
     fn test_stream_id() -> Result<()> {
@@ -275,6 +730,71 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_new_entry_id_with_bare_timestamp() -> Result<()> {
+        let mut stream = Stream::new();
+        let first = stream.create_new_entry_id("5")?;
+        assert_eq!(first.to_string(), "5-0");
+        stream.xadd(HashMap::new(), Some(first))?;
+
+        // same timestamp as the last entry, the sequence is incremented
+        let second = stream.create_new_entry_id("5")?;
+        assert_eq!(second.to_string(), "5-1");
+        stream.xadd(HashMap::new(), Some(second))?;
+        assert_eq!(stream.create_new_entry_id("5-*")?.to_string(), "5-2");
+        assert_eq!(stream.create_new_entry_id("6")?.to_string(), "6-0");
+        // explicit ids are kept as is
+        assert_eq!(stream.create_new_entry_id("5-1")?.to_string(), "5-1");
+        // the bare timestamp still means <ms>-0 outside of XADD
+        assert_eq!(stream.create_stream_id("5")?.to_string(), "5-0");
+        Ok(())
+    }
+
+    #[test]
+    fn test_zero_timestamp_with_generated_sequence() -> Result<()> {
+        let mut stream = Stream::new();
+        assert_eq!(stream.create_stream_id("0-*")?.to_string(), "0-1");
+
+        // 0-0 can't be added with XADD but can come from an rdb
+        stream
+            .entries
+            .push_back(StreamEntry::build(StreamId::default(), HashMap::new()));
+        assert_eq!(stream.create_stream_id("0-*")?.to_string(), "0-1");
+
+        stream.xadd(HashMap::new(), Some(stream.create_stream_id("0-5")?))?;
+        assert_eq!(stream.create_stream_id("0-*")?.to_string(), "0-6");
+        assert_eq!(stream.create_stream_id("1-*")?.to_string(), "1-0");
+
+        stream.xadd(HashMap::new(), Some(stream.create_stream_id("1-0")?))?;
+        // the id is generated but XADD rejects it
+        let stream_id = stream.create_stream_id("0-*")?;
+        assert_eq!(stream_id.to_string(), "0-1");
+        assert!(stream.xadd(HashMap::new(), Some(stream_id)).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_next_stream_id() -> Result<()> {
+        let clock = MockClock::install();
+        let mut stream = Stream::new();
+        let now = clock::unix_time_ms();
+        let stream_id = stream.next_stream_id();
+        assert_eq!(stream_id.to_string(), format!("{}-0", now));
+
+        // ids generated in the same millisecond only differ by their sequence
+        stream.xadd(HashMap::new(), Some(stream_id))?;
+        let stream_id = stream.next_stream_id();
+        assert_eq!(stream_id.to_string(), format!("{}-1", now));
+        stream.xadd(HashMap::new(), Some(stream_id))?;
+
+        clock.advance(Duration::from_millis(3));
+        assert_eq!(
+            stream.next_stream_id().to_string(),
+            format!("{}-0", now + 3)
+        );
+        Ok(())
+    }
+
     #[test]
     fn test_xadd() -> Result<()> {
         let mut stream = Stream::new();
@@ -311,8 +831,281 @@ mod tests {
         assert!(returned_id > stream_id);
         assert_eq!(stream.entries.len(), 4);
 
-        dbg!(stream);
+        Ok(())
+    }
+
+    #[test]
+    fn test_xdel() -> Result<()> {
+        let mut stream = Stream::new();
+        for id in ["1-1", "1-2", "2-1"] {
+            let stream_id = stream.create_stream_id(id)?;
+            stream.xadd(HashMap::new(), Some(stream_id))?;
+        }
+        let ids = ["1-2", "2-1", "3-1", "1-2"]
+            .iter()
+            .map(|id| stream.create_stream_id(id))
+            .collect::<Result<Vec<_>>>()?;
+        assert_eq!(stream.xdel(&ids), 2);
+        assert_eq!(stream.xdel(&ids), 0);
+        assert_eq!(stream.entries.len(), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_trim() -> Result<()> {
+        let mut stream = Stream::new();
+        for id in ["1-1", "1-2", "2-1"] {
+            let stream_id = stream.create_stream_id(id)?;
+            stream.xadd(HashMap::new(), Some(stream_id))?;
+        }
+        let trim = StreamTrim {
+            max_len: 5,
+            approximate: false,
+        };
+        stream.trim(&trim);
+        assert_eq!(stream.entries.len(), 3);
+
+        stream.trim(&StreamTrim { max_len: 1, ..trim });
+        assert_eq!(stream.entries.len(), 1);
+        assert_eq!(stream.get_last_stream_id().to_string(), "2-1");
+        Ok(())
+    }
+
+    #[test]
+    fn test_xrange_xrevrange() -> Result<()> {
+        let mut stream = Stream::new();
+        for id in ["1-1", "1-2", "2-1", "3-1"] {
+            let stream_id = stream.create_stream_id(id)?;
+            stream.xadd(HashMap::new(), Some(stream_id))?;
+        }
+        let ids = |entries: Vec<(String, HashMap<String, String>)>| {
+            entries.into_iter().map(|(id, _)| id).collect::<Vec<_>>()
+        };
+
+        assert_eq!(ids(stream.xrange("1-2", "2-1")?), ["1-2", "2-1"]);
+        assert_eq!(ids(stream.xrevrange("2-1", "1-2")?), ["2-1", "1-2"]);
+        assert_eq!(
+            ids(stream.xrevrange("+", "-")?),
+            ["3-1", "2-1", "1-2", "1-1"]
+        );
+        assert_eq!(ids(stream.xrevrange("1-2", "-")?), ["1-2", "1-1"]);
+        assert!(stream.xrevrange("1-1", "3-1")?.is_empty());
+        assert!(stream.xrevrange("+", "4-0")?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_consumer_group() -> Result<()> {
+        let mut stream = Stream::new();
+        for i in 1..=3 {
+            let stream_id = stream.create_stream_id(&format!("{}-0", i))?;
+            stream.xadd(
+                HashMap::from([("i".to_string(), i.to_string())]),
+                Some(stream_id),
+            )?;
+        }
+
+        stream.create_group("group", "0")?;
+        assert!(matches!(
+            stream.create_group("group", "$"),
+            Err(Error::ConsumerGroupAlreadyExists)
+        ));
+        assert!(matches!(
+            stream.read_group("key", "missing", "alice", ">", None, false),
+            Err(Error::NoSuchConsumerGroup { .. })
+        ));
+
+        let delivered = stream.read_group("key", "group", "alice", ">", Some(2), false)?;
+        assert_eq!(
+            delivered
+                .iter()
+                .map(|(id, _)| id.as_str())
+                .collect::<Vec<_>>(),
+            vec!["1-0", "2-0"]
+        );
+        let delivered = stream.read_group("key", "group", "bob", ">", None, false)?;
+        assert_eq!(delivered.len(), 1);
+        assert!(stream
+            .read_group("key", "group", "bob", ">", None, false)?
+            .is_empty());
+        assert_eq!(stream.groups["group"].pending.len(), 3);
+
+        // history only contains the entries pending for this consumer
+        let history = stream.read_group("key", "group", "alice", "0", None, false)?;
+        assert_eq!(history.len(), 2);
+
+        assert_eq!(stream.ack("group", &["1-0", "3-0", "4-0"])?, 2);
+        let group = &stream.groups["group"];
+        assert_eq!(group.pending.len(), 1);
+        assert_eq!(group.consumers["alice"].pending.len(), 1);
+        assert!(group.consumers["bob"].pending.is_empty());
+
+        let history = stream.read_group("key", "group", "alice", "0", None, false)?;
+        assert_eq!(history[0].0, "2-0");
+
+        assert!(stream.destroy_group("group"));
+        assert!(!stream.destroy_group("group"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_consumer_group_no_ack() -> Result<()> {
+        let mut stream = Stream::new();
+        let stream_id = stream.create_stream_id("1-0")?;
+        stream.xadd(HashMap::new(), Some(stream_id))?;
+        stream.create_group("group", "$")?;
+
+        assert!(stream
+            .read_group("key", "group", "alice", ">", None, false)?
+            .is_empty());
+
+        let stream_id = stream.create_stream_id("2-0")?;
+        stream.xadd(HashMap::new(), Some(stream_id))?;
+        let delivered = stream.read_group("key", "group", "alice", ">", None, true)?;
+        assert_eq!(delivered.len(), 1);
+        assert!(stream.groups["group"].pending.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_pending() -> Result<()> {
+        let mut stream = Stream::new();
+        for i in 1..=4 {
+            let stream_id = stream.create_stream_id(&format!("{}-0", i))?;
+            stream.xadd(HashMap::new(), Some(stream_id))?;
+        }
+        stream.create_group("group", "0")?;
+        stream.read_group("key", "group", "bob", ">", Some(1), false)?;
+        stream.read_group("key", "group", "alice", ">", Some(2), false)?;
+
+        let summary = stream.pending_summary("key", "group")?;
+        assert_eq!(summary.count, 3);
+        assert_eq!(summary.min_id, Some(stream.create_stream_id("1-0")?));
+        assert_eq!(summary.max_id, Some(stream.create_stream_id("3-0")?));
+        assert_eq!(
+            summary.consumers,
+            vec![("alice".to_string(), 2), ("bob".to_string(), 1)]
+        );
+
+        let range = PendingRange {
+            min_idle: None,
+            stream_id_start: "-".to_string(),
+            stream_id_end: "+".to_string(),
+            count: 10,
+            consumer: None,
+        };
+        let entries = stream.pending_entries("key", "group", &range)?;
+        assert_eq!(entries.len(), 3);
+
+        let from_second = PendingRange {
+            stream_id_start: "2".to_string(),
+            ..range.clone()
+        };
+        let entries = stream.pending_entries("key", "group", &from_second)?;
+        assert_eq!(entries[0].0.to_string(), "2-0");
+
+        let bob = PendingRange {
+            consumer: Some("bob".to_string()),
+            ..range.clone()
+        };
+        let entries = stream.pending_entries("key", "group", &bob)?;
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].1.consumer, "bob");
+        assert_eq!(entries[0].1.delivery_count, 1);
+
+        let only_one = PendingRange {
+            count: 1,
+            ..range.clone()
+        };
+        assert_eq!(stream.pending_entries("key", "group", &only_one)?.len(), 1);
+
+        let idle = PendingRange {
+            min_idle: Some(60 * 1000),
+            ..range.clone()
+        };
+        assert!(stream.pending_entries("key", "group", &idle)?.is_empty());
+
+        assert!(matches!(
+            stream.pending_summary("key", "missing"),
+            Err(Error::NoSuchConsumerGroup { .. })
+        ));
+        Ok(())
+    }
+
+    #[test]
+    fn test_claim() -> Result<()> {
+        let mut stream = Stream::new();
+        for i in 1..=3 {
+            let stream_id = stream.create_stream_id(&format!("{}-0", i))?;
+            stream.xadd(HashMap::new(), Some(stream_id))?;
+        }
+        stream.create_group("group", "0")?;
+        stream.read_group("key", "group", "alice", ">", Some(2), false)?;
+
+        // entries are not idle for long enough
+        let options = ClaimOptions {
+            min_idle: 60 * 1000,
+            ..Default::default()
+        };
+        let claimed = stream.claim("key", "group", "bob", &["1-0", "2-0"], &options)?;
+        assert!(claimed.is_empty());
+
+        let claimed = stream.claim(
+            "key",
+            "group",
+            "bob",
+            &["1-0", "3-0"],
+            &ClaimOptions::default(),
+        )?;
+        assert_eq!(claimed.len(), 1);
+        let group = &stream.groups["group"];
+        assert_eq!(
+            group.pending[&stream.create_stream_id("1-0")?].consumer,
+            "bob"
+        );
+        assert_eq!(
+            group.pending[&stream.create_stream_id("1-0")?].delivery_count,
+            2
+        );
+        assert_eq!(group.consumers["alice"].pending.len(), 1);
+        assert_eq!(group.consumers["bob"].pending.len(), 1);
+
+        // with force, an entry not pending yet can be claimed
+        let options = ClaimOptions {
+            force: true,
+            just_id: true,
+            ..Default::default()
+        };
+        let claimed = stream.claim("key", "group", "bob", &["3-0"], &options)?;
+        assert_eq!(claimed.len(), 1);
+        let group = &stream.groups["group"];
+        assert_eq!(
+            group.pending[&stream.create_stream_id("3-0")?].delivery_count,
+            0
+        );
+
+        let options = ClaimOptions {
+            idle: Some(5000),
+            retry_count: Some(7),
+            ..Default::default()
+        };
+        stream.claim("key", "group", "carol", &["2-0"], &options)?;
+        let pending_entry = &stream.groups["group"].pending[&stream.create_stream_id("2-0")?];
+        assert_eq!(pending_entry.delivery_count, 7);
+        assert!(pending_entry.idle_ms(Instant::now()) >= 5000);
+        Ok(())
+    }
+
+    #[test]
+    fn test_next_stream_id_same_timestamp() -> Result<()> {
+        let mut stream = Stream::new();
+        // an entry in the future forces the generated ids to reuse its timestamp
+        let stream_id = stream.create_stream_id("9999999999999-5")?;
+        stream.xadd(HashMap::new(), Some(stream_id))?;
 
+        let next_stream_id = stream.next_stream_id();
+        assert_eq!(next_stream_id.timestamp_ms, 9999999999999);
+        assert_eq!(next_stream_id.seq_number, 6);
         Ok(())
     }
 }